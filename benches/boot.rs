@@ -0,0 +1,57 @@
+// Instructions per second of an xv6 boot.
+// Needs kernel/kernel, the xv6 kernel built as in the README. The benchmark panics without it.
+// $ cargo bench --bench boot
+// The throughput criterion reports in Melem/s is MIPS.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use kotodori::cmd::Command;
+use kotodori::emulator::Emulator;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const KERNEL: &str = "kernel/kernel";
+const ROUNDS: u64 = 10_000; // of --quantum instructions
+
+/// A single hart with a busy WFI, so every run executes the same instructions.
+fn xv6() -> Emulator {
+    let args: Vec<String> = ["--elf", KERNEL, "--busy-wfi"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut emu = Emulator::new(Command::parse(&args));
+    emu.init();
+    emu
+}
+
+fn boot(c: &mut Criterion) {
+    if !Path::new(KERNEL).exists() {
+        panic!(
+            "{} not found. Build xv6 and copy its kernel there as in the README.",
+            KERNEL
+        );
+    }
+
+    let mut emu = xv6();
+    emu.run_rounds(ROUNDS);
+
+    let mut group = c.benchmark_group("xv6");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(emu.instret()));
+    // only the run is timed. Loading the kernel and dropping the memory are not.
+    group.bench_function("boot", |b| {
+        b.iter_custom(|iters| {
+            let mut time = Duration::ZERO;
+            for _ in 0..iters {
+                let mut emu = xv6();
+                let start = Instant::now();
+                emu.run_rounds(ROUNDS);
+                time += start.elapsed();
+            }
+            time
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, boot);
+criterion_main!(benches);
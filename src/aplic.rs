@@ -0,0 +1,405 @@
+// https://github.com/riscv/riscv-aia/blob/main/src/AdvancedPLIC.adoc
+// An M-level root domain and its S-level child domain, at the addresses of the QEMU virt machine.
+// No device drives an interrupt wire yet, so sources become pending only through
+// setip, setipnum and setipnum_le/be, whatever their source mode.
+
+use crate::imsic;
+
+pub const APLIC_M: u64 = 0xC00_0000;
+pub const APLIC_M_END: u64 = APLIC_M + 0x7FFF;
+pub const APLIC_S: u64 = 0xD00_0000;
+pub const APLIC_S_END: u64 = APLIC_S + 0x7FFF;
+
+const SOURCES: usize = 1024; // Interrupt source 1 - 1023. Source 0 does not exist.
+
+// registers of a domain
+const DOMAINCFG: u64 = 0x0000;
+const SOURCECFG: u64 = 0x0004; // 0x0004 - 0x0FFC
+const SOURCECFG_END: u64 = 0x0FFC;
+const MMSIADDRCFG: u64 = 0x1BC0;
+const MMSIADDRCFGH: u64 = 0x1BC4;
+const SMSIADDRCFG: u64 = 0x1BC8;
+const SMSIADDRCFGH: u64 = 0x1BCC;
+const SETIP: u64 = 0x1C00; // 0x1C00 - 0x1C7C
+const SETIP_END: u64 = 0x1C7C;
+const SETIPNUM: u64 = 0x1CDC;
+const IN_CLRIP: u64 = 0x1D00; // 0x1D00 - 0x1D7C
+const IN_CLRIP_END: u64 = 0x1D7C;
+const CLRIPNUM: u64 = 0x1DDC;
+const SETIE: u64 = 0x1E00; // 0x1E00 - 0x1E7C
+const SETIE_END: u64 = 0x1E7C;
+const SETIENUM: u64 = 0x1EDC;
+const CLRIE: u64 = 0x1F00; // 0x1F00 - 0x1F7C
+const CLRIE_END: u64 = 0x1F7C;
+const CLRIENUM: u64 = 0x1FDC;
+const SETIPNUM_LE: u64 = 0x2000;
+const SETIPNUM_BE: u64 = 0x2004;
+const GENMSI: u64 = 0x3000;
+const TARGET: u64 = 0x3004; // 0x3004 - 0x3FFC
+const TARGET_END: u64 = 0x3FFC;
+const IDC: u64 = 0x4000; // interrupt delivery control of each hart. 0x4000 - 0x7FFF
+const IDC_END: u64 = 0x7FFF;
+const IDC_STRIDE: u64 = 32;
+
+// registers of an IDC
+const IDELIVERY: u64 = 0x00;
+const IFORCE: u64 = 0x04;
+const ITHRESHOLD: u64 = 0x08;
+const TOPI: u64 = 0x18;
+const CLAIMI: u64 = 0x1C;
+
+const DOMAINCFG_RO: u32 = 0x8000_0000; // bits 31:24 read as 0x80
+const DOMAINCFG_IE: u32 = 1 << 8; // interrupts are enabled
+const DOMAINCFG_DM: u32 = 1 << 2; // MSI delivery mode. Direct delivery mode when clear.
+const SOURCECFG_D: u32 = 1 << 10; // delegated to the child domain
+const SOURCECFG_SM: u32 = 0b111; // source mode. 0 is inactive.
+const MSIADDRCFGH_L: u32 = 1 << 31; // locked
+const TARGET_HART: u32 = 0xFFFC_0000; // hart index
+const TARGET_IPRIO: u32 = 0xFF; // direct delivery mode
+const TARGET_EIID: u32 = 0x7FF; // MSI delivery mode
+const IPRIO_MAX: u32 = 0xFF;
+
+const M_DOMAIN: usize = 0;
+const S_DOMAIN: usize = 1;
+
+#[derive(Debug, Default, Clone)]
+struct Idc {
+    idelivery: u32,
+    iforce: u32,
+    ithreshold: u32,
+}
+
+#[derive(Debug)]
+struct Domain {
+    domaincfg: u32,
+    sourcecfg: Vec<u32>,
+    pending: Vec<u32>, // 1 bit per source
+    enable: Vec<u32>,  // 1 bit per source
+    target: Vec<u32>,
+    genmsi: u32,
+    idc: Vec<Idc>, // per hart
+}
+
+impl Domain {
+    fn new(harts: usize) -> Domain {
+        Domain {
+            domaincfg: 0,
+            sourcecfg: vec![0; SOURCES],
+            pending: vec![0; SOURCES / 32],
+            enable: vec![0; SOURCES / 32],
+            target: vec![0; SOURCES],
+            genmsi: 0,
+            idc: vec![Idc::default(); harts],
+        }
+    }
+
+    fn msi_mode(&self) -> bool {
+        self.domaincfg & DOMAINCFG_DM != 0
+    }
+
+    /// The pending and enabled source targeting the hart with the highest priority
+    /// below the threshold of its IDC. Lower IPRIO values have higher priority,
+    /// and ties go to the lowest source ID.
+    fn max_source(&self, hart: usize) -> Option<usize> {
+        let thr = self.idc[hart].ithreshold;
+        let mut max: Option<usize> = None;
+        for (i, (pending, enable)) in self.pending.iter().zip(self.enable.iter()).enumerate() {
+            let mut bits = pending & enable;
+            while bits != 0 {
+                let src = i * 32 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                let prio = self.target[src] & TARGET_IPRIO;
+                if (self.target[src] >> 18) as usize != hart || (thr != 0 && prio >= thr) {
+                    continue;
+                }
+                match max {
+                    Some(m) if prio >= self.target[m] & TARGET_IPRIO => (),
+                    _ => max = Some(src),
+                }
+            }
+        }
+        max
+    }
+
+    /// topi of the IDC of the hart. The source ID in bits 25:16 and its priority in bits 7:0.
+    fn topi(&self, hart: usize) -> u32 {
+        match self.max_source(hart) {
+            Some(src) => ((src as u32) << 16) | (self.target[src] & TARGET_IPRIO),
+            None => 0,
+        }
+    }
+
+    /// Reading claimi claims the interrupt reported by topi and clears its pending bit.
+    /// Without one, it clears iforce instead.
+    fn claimi(&mut self, hart: usize) -> u32 {
+        match self.max_source(hart) {
+            Some(src) => {
+                let topi = self.topi(hart);
+                self.pending[src / 32] &= !(1 << (src % 32));
+                topi
+            }
+            None => {
+                self.idc[hart].iforce = 0;
+                0
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Aplic {
+    domains: Vec<Domain>,    // the M-level root domain and the S-level child domain
+    msis: Vec<(usize, u64)>, // MSIs to write: interrupt file of the IMSIC and identity
+}
+
+impl Aplic {
+    pub fn new(harts: usize) -> Aplic {
+        Aplic {
+            domains: vec![Domain::new(harts), Domain::new(harts)],
+            msis: vec![],
+        }
+    }
+
+    pub fn read(&mut self, addr: u64) -> u64 {
+        if addr & 0b11 != 0 {
+            panic!("invalid reading APLIC address: 0x{:016X}", addr);
+        }
+
+        let (d, off) = domain(addr);
+        let dom = &mut self.domains[d];
+        let v = match off {
+            DOMAINCFG => DOMAINCFG_RO | dom.domaincfg,
+            SOURCECFG..=SOURCECFG_END => dom.sourcecfg[(off / 4) as usize],
+            // the MSI addresses are fixed to the interrupt files of the IMSIC, 4 KiB apart
+            MMSIADDRCFG if d == M_DOMAIN => (imsic::IMSIC_M >> 12) as u32,
+            MMSIADDRCFGH if d == M_DOMAIN => MSIADDRCFGH_L,
+            SMSIADDRCFG if d == M_DOMAIN => (imsic::IMSIC_S >> 12) as u32,
+            MMSIADDRCFG..=SMSIADDRCFGH => 0,
+            SETIP..=SETIP_END => dom.pending[((off - SETIP) / 4) as usize],
+            // the rectified inputs. No wire is driven.
+            IN_CLRIP..=IN_CLRIP_END => 0,
+            SETIE..=SETIE_END => dom.enable[((off - SETIE) / 4) as usize],
+            CLRIE..=CLRIE_END => 0,
+            SETIPNUM | CLRIPNUM | SETIENUM | CLRIENUM | SETIPNUM_LE | SETIPNUM_BE => 0,
+            GENMSI => dom.genmsi,
+            TARGET..=TARGET_END => dom.target[((off - TARGET) / 4 + 1) as usize],
+            IDC..=IDC_END => {
+                let hart = ((off - IDC) / IDC_STRIDE) as usize;
+                if hart >= dom.idc.len() {
+                    panic!("invalid reading APLIC address: 0x{:016X}", addr);
+                }
+                match (off - IDC) % IDC_STRIDE {
+                    IDELIVERY => dom.idc[hart].idelivery,
+                    IFORCE => dom.idc[hart].iforce,
+                    ITHRESHOLD => dom.idc[hart].ithreshold,
+                    TOPI => dom.topi(hart),
+                    CLAIMI => dom.claimi(hart),
+                    _ => panic!("invalid reading APLIC address: 0x{:016X}", addr),
+                }
+            }
+            _ => panic!("invalid reading APLIC address: 0x{:016X}", addr),
+        };
+        v as u64
+    }
+
+    pub fn write(&mut self, addr: u64, data: u64) {
+        if addr & 0b11 != 0 {
+            panic!("invalid writing APLIC address: 0x{:016X}", addr);
+        }
+
+        let data = data as u32;
+        let (d, off) = domain(addr);
+        match off {
+            DOMAINCFG => self.domains[d].domaincfg = data & (DOMAINCFG_IE | DOMAINCFG_DM),
+            SOURCECFG..=SOURCECFG_END => self.write_sourcecfg(d, (off / 4) as usize, data),
+            MMSIADDRCFG..=SMSIADDRCFGH => (),
+            SETIP..=SETIP_END => self.write_bits(d, ((off - SETIP) / 4) as usize, data, true, true),
+            SETIPNUM | SETIPNUM_LE => self.set_pending(d, data as usize, true),
+            SETIPNUM_BE => self.set_pending(d, data.swap_bytes() as usize, true),
+            IN_CLRIP..=IN_CLRIP_END => {
+                self.write_bits(d, ((off - IN_CLRIP) / 4) as usize, data, true, false)
+            }
+            CLRIPNUM => self.set_pending(d, data as usize, false),
+            SETIE..=SETIE_END => {
+                self.write_bits(d, ((off - SETIE) / 4) as usize, data, false, true)
+            }
+            SETIENUM => self.set_enable(d, data as usize, true),
+            CLRIE..=CLRIE_END => {
+                self.write_bits(d, ((off - CLRIE) / 4) as usize, data, false, false)
+            }
+            CLRIENUM => self.set_enable(d, data as usize, false),
+            GENMSI => {
+                let dom = &mut self.domains[d];
+                dom.genmsi = data & (TARGET_HART | TARGET_EIID);
+                if dom.msi_mode() {
+                    self.msis.push(msi(d, dom.genmsi));
+                }
+            }
+            TARGET..=TARGET_END => self.write_target(d, ((off - TARGET) / 4 + 1) as usize, data),
+            IDC..=IDC_END => {
+                let hart = ((off - IDC) / IDC_STRIDE) as usize;
+                let idc = match self.domains[d].idc.get_mut(hart) {
+                    Some(idc) => idc,
+                    None => panic!("invalid writing APLIC address: 0x{:016X}", addr),
+                };
+                match (off - IDC) % IDC_STRIDE {
+                    IDELIVERY => idc.idelivery = data & 1,
+                    IFORCE => idc.iforce = data & 1,
+                    ITHRESHOLD => idc.ithreshold = data & IPRIO_MAX,
+                    TOPI | CLAIMI => (),
+                    _ => panic!("invalid writing APLIC address: 0x{:016X}", addr),
+                }
+            }
+            _ => panic!("invalid writing APLIC address: 0x{:016X}", addr),
+        }
+    }
+
+    /// Whether the source is active in the domain.
+    /// A source delegated by the root domain is active only in the child domain.
+    fn active(&self, d: usize, src: usize) -> bool {
+        if src == 0 || src >= SOURCES {
+            return false;
+        }
+        let delegated = self.domains[M_DOMAIN].sourcecfg[src] & SOURCECFG_D != 0;
+        let sm = self.domains[d].sourcecfg[src] & SOURCECFG_SM;
+        match d {
+            M_DOMAIN => !delegated && sm != 0,
+            _ => delegated && sm != 0,
+        }
+    }
+
+    /// The pending bit, the enable bit and the target of an inactive source are read-only zero.
+    fn deactivate(&mut self, d: usize, src: usize) {
+        let dom = &mut self.domains[d];
+        dom.pending[src / 32] &= !(1 << (src % 32));
+        dom.enable[src / 32] &= !(1 << (src % 32));
+        dom.target[src] = 0;
+    }
+
+    /// The sourcecfg of the child domain is read-only zero unless the root domain delegates the source.
+    /// Only the child index 0 exists. The reserved source modes 2 and 3 are written as inactive.
+    fn write_sourcecfg(&mut self, d: usize, src: usize, data: u32) {
+        let delegated = self.domains[M_DOMAIN].sourcecfg[src] & SOURCECFG_D != 0;
+        if d == S_DOMAIN && !delegated {
+            return;
+        }
+        let cfg = match data & SOURCECFG_SM {
+            _ if d == M_DOMAIN && data & SOURCECFG_D != 0 => SOURCECFG_D,
+            2 | 3 => 0,
+            sm => sm,
+        };
+        self.domains[d].sourcecfg[src] = cfg;
+        if !self.active(d, src) {
+            self.deactivate(d, src);
+        }
+        if d == M_DOMAIN && cfg & SOURCECFG_D == 0 {
+            self.domains[S_DOMAIN].sourcecfg[src] = 0;
+            self.deactivate(S_DOMAIN, src);
+        }
+    }
+
+    /// The hart index and IPRIO in direct delivery mode, or the hart index and EIID in MSI delivery mode.
+    /// IPRIO 0 is written as 1.
+    fn write_target(&mut self, d: usize, src: usize, data: u32) {
+        if !self.active(d, src) {
+            return;
+        }
+        let dom = &mut self.domains[d];
+        dom.target[src] = if dom.msi_mode() {
+            data & (TARGET_HART | TARGET_EIID)
+        } else {
+            data & TARGET_HART | (data & TARGET_IPRIO).max(1)
+        };
+    }
+
+    fn set_pending(&mut self, d: usize, src: usize, pending: bool) {
+        if !self.active(d, src) {
+            return;
+        }
+        let bits = &mut self.domains[d].pending[src / 32];
+        if pending {
+            *bits |= 1 << (src % 32);
+        } else {
+            *bits &= !(1 << (src % 32));
+        }
+    }
+
+    fn set_enable(&mut self, d: usize, src: usize, enable: bool) {
+        if !self.active(d, src) {
+            return;
+        }
+        let bits = &mut self.domains[d].enable[src / 32];
+        if enable {
+            *bits |= 1 << (src % 32);
+        } else {
+            *bits &= !(1 << (src % 32));
+        }
+    }
+
+    /// setip, in_clrip, setie and clrie set or clear the pending or enable bits of 32 sources.
+    fn write_bits(&mut self, d: usize, word: usize, data: u32, pending: bool, set: bool) {
+        for bit in 0..32 {
+            if data & (1 << bit) == 0 {
+                continue;
+            }
+            if pending {
+                self.set_pending(d, word * 32 + bit, set);
+            } else {
+                self.set_enable(d, word * 32 + bit, set);
+            }
+        }
+    }
+
+    /// Whether the IDC of the hart raises the external interrupt in direct delivery mode.
+    /// M-level contexts are the IDCs of the root domain and S-level contexts those of the child domain,
+    /// numbered like the contexts of the PLIC.
+    pub fn pending_int(&self, ctx: usize) -> bool {
+        let dom = &self.domains[ctx % 2];
+        let hart = ctx / 2;
+        if dom.domaincfg & DOMAINCFG_IE == 0 || dom.msi_mode() || dom.idc[hart].idelivery == 0 {
+            return false;
+        }
+        dom.idc[hart].iforce != 0 || dom.max_source(hart).is_some()
+    }
+
+    /// In MSI delivery mode, the pending and enabled sources are forwarded as MSIs,
+    /// which clears their pending bits. Returns the MSIs for the IMSIC, including those of genmsi.
+    pub fn take_msis(&mut self) -> Vec<(usize, u64)> {
+        for d in [M_DOMAIN, S_DOMAIN] {
+            let dom = &mut self.domains[d];
+            if dom.domaincfg & DOMAINCFG_IE == 0 || !dom.msi_mode() {
+                continue;
+            }
+            for i in 0..dom.pending.len() {
+                let mut bits = dom.pending[i] & dom.enable[i];
+                dom.pending[i] &= !bits;
+                while bits != 0 {
+                    let src = i * 32 + bits.trailing_zeros() as usize;
+                    bits &= bits - 1;
+                    self.msis.push(msi(d, dom.target[src]));
+                }
+            }
+        }
+        std::mem::take(&mut self.msis)
+    }
+}
+
+/// The domain of the address and the offset in it.
+fn domain(addr: u64) -> (usize, u64) {
+    match addr {
+        APLIC_M..=APLIC_M_END => (M_DOMAIN, addr - APLIC_M),
+        APLIC_S..=APLIC_S_END => (S_DOMAIN, addr - APLIC_S),
+        _ => panic!("invalid APLIC address: 0x{:016X}", addr),
+    }
+}
+
+/// The MSI of a target or genmsi: the interrupt file of the hart at the level of the domain,
+/// and the EIID.
+fn msi(d: usize, target: u32) -> (usize, u64) {
+    let hart = (target >> 18) as usize;
+    let file = match d {
+        M_DOMAIN => imsic::m_file(hart),
+        _ => imsic::s_file(hart),
+    };
+    (file, (target & TARGET_EIID) as u64)
+}
@@ -1,8 +1,39 @@
+use crate::aplic::Aplic;
+use crate::clint::{self, Clint};
 use crate::dram::Dram;
+use crate::imsic::{self, Imsic};
 use crate::plic::{self, Plic};
 use crate::uart::{self, Uart};
 use crate::virtio::{self, Virtio};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
 
+const RESERVATION_GRANULE: u64 = 8; // bytes
+const NO_RESERVATION: u64 = u64::MAX;
+const CODE_PAGE: u64 = 4096; // bytes
+
+/// The external interrupt controllers of the machine.
+/// The AIA is an APLIC for wired interrupts and an IMSIC for message signaled interrupts.
+#[derive(Debug)]
+pub enum Irqchip {
+    Plic(Mutex<Plic>),
+    Aia(Mutex<Aplic>, Imsic),
+}
+
+impl Irqchip {
+    pub fn plic(harts: usize) -> Irqchip {
+        Irqchip::Plic(Mutex::new(Plic::new(harts)))
+    }
+
+    pub fn aia(harts: usize) -> Irqchip {
+        Irqchip::Aia(Mutex::new(Aplic::new(harts)), Imsic::new(harts))
+    }
+}
+
+/// The bus is shared by all harts, which may run on different host threads.
+/// Devices are locked per access. The CLINT and the external interrupt lines are atomics,
+/// so harts poll them without taking a lock. A hart halted by WFI blocks until an event instead.
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct Bus {
@@ -10,21 +41,59 @@ pub struct Bus {
     data: u32,
     control: u32,
     dram: Dram,
-    uart: Uart,
-    plic: Plic,
-    virtio: Virtio,
+    clint: Clint,
+    uart: Mutex<Uart>,
+    irqchip: Irqchip,
+    virtio: Mutex<Virtio>,
+
+    // Lines of the PLIC contexts, or of the IDCs of the APLIC numbered the same way.
+    ext_lines: Vec<AtomicBool>,
+
+    // LR/SC reservation of each hart. DRAM address reserved by LR and the value it loaded.
+    reservation: Vec<AtomicU64>,
+    reserved_value: Vec<AtomicU64>,
+
+    // Per DRAM page. Whether a hart decoded instructions from it, and the generation of its contents,
+    // bumped when a code page is written so the harts drop their decoded blocks of the page.
+    code: Vec<AtomicBool>,
+    code_gen: Vec<AtomicU32>,
+
+    // Count of the events that can wake a hart halted by WFI: device accesses that may raise
+    // an interrupt, and mtime moved forward by another hart.
+    events: Mutex<u64>,
+    event_cond: Condvar,
 }
 
 impl Bus {
-    pub fn new(dram: Dram, uart: Uart, plic: Plic, virtio: Virtio) -> Bus {
+    pub fn new(
+        dram: Dram,
+        clint: Clint,
+        uart: Uart,
+        irqchip: Irqchip,
+        virtio: Virtio,
+        harts: usize,
+    ) -> Bus {
+        let pages = (dram.size() as u64).div_ceil(CODE_PAGE);
         Bus {
             address: 0,
             data: 0,
             control: 0,
             dram,
-            uart,
-            plic,
-            virtio,
+            clint,
+            uart: Mutex::new(uart),
+            irqchip,
+            virtio: Mutex::new(virtio),
+
+            ext_lines: (0..2 * harts).map(|_| AtomicBool::new(false)).collect(),
+
+            reservation: (0..harts).map(|_| AtomicU64::new(NO_RESERVATION)).collect(),
+            reserved_value: (0..harts).map(|_| AtomicU64::new(0)).collect(),
+
+            code: (0..pages).map(|_| AtomicBool::new(false)).collect(),
+            code_gen: (0..pages).map(|_| AtomicU32::new(0)).collect(),
+
+            events: Mutex::new(0),
+            event_cond: Condvar::new(),
         }
     }
 
@@ -33,7 +102,11 @@ impl Bus {
     }
 
     pub fn puart(&self) {
-        self.uart.print();
+        self.uart.lock().unwrap().print();
+    }
+
+    pub fn dram_size(&self) -> u64 {
+        self.dram.size() as u64
     }
 
     pub fn lb_dram(&self, addr: u64) -> u8 {
@@ -52,37 +125,243 @@ impl Bus {
         self.dram.load_dword(addr)
     }
 
-    pub fn sb_dram(&mut self, addr: u64, data: u8) {
+    pub fn sb_dram(&self, addr: u64, data: u8) {
+        self.invalidate_reservations(addr, 1);
         self.dram.store_byte(addr, data);
+        self.invalidate_code(addr, 1);
     }
 
-    pub fn sh_dram(&mut self, addr: u64, data: u16) {
+    pub fn sh_dram(&self, addr: u64, data: u16) {
+        self.invalidate_reservations(addr, 2);
         self.dram.store_hword(addr, data);
+        self.invalidate_code(addr, 2);
     }
 
-    pub fn sw_dram(&mut self, addr: u64, data: u32) {
+    pub fn sw_dram(&self, addr: u64, data: u32) {
+        self.invalidate_reservations(addr, 4);
         self.dram.store_word(addr, data);
+        self.invalidate_code(addr, 4);
     }
 
-    pub fn sd_dram(&mut self, addr: u64, data: u64) {
+    pub fn sd_dram(&self, addr: u64, data: u64) {
+        self.invalidate_reservations(addr, 8);
         self.dram.store_dword(addr, data);
+        self.invalidate_code(addr, 8);
+    }
+
+    /// Atomically replace the naturally aligned `size` bytes at the DRAM address with `f(old)`.
+    /// Returns the old value.
+    pub fn amo_dram<F>(&self, addr: u64, size: u64, order: Ordering, f: F) -> u64
+    where
+        F: Fn(u64) -> u64,
+    {
+        self.invalidate_reservations(addr, size);
+        let old = self.dram.fetch_update(addr, size, order, f);
+        self.invalidate_code(addr, size);
+        old
+    }
+
+    /// Load the naturally aligned `size` bytes at the DRAM address and reserve it for the hart.
+    /// A hart holds at most one reservation, so this drops its previous one.
+    pub fn lr_dram(&self, hart: usize, addr: u64, size: u64, order: Ordering) -> u64 {
+        let v = self.dram.load_ordered(addr, size, order);
+        self.reserved_value[hart].store(v, Ordering::Relaxed);
+        self.reservation[hart].store(addr, Ordering::Release);
+        v
+    }
+
+    /// Store to the DRAM address when the hart still holds the reservation on it.
+    /// The reservation is dropped either way. Returns true on success.
+    /// Other harts break the reservation by storing to its granule. On host threads a store may
+    /// race with the check, so the write also requires the memory to still hold the value LR loaded.
+    pub fn sc_dram(&self, hart: usize, addr: u64, size: u64, data: u64, order: Ordering) -> bool {
+        if self.reservation[hart].swap(NO_RESERVATION, Ordering::Acquire) != addr {
+            return false;
+        }
+        let v = self.reserved_value[hart].load(Ordering::Relaxed);
+        if !self.dram.compare_exchange(addr, size, v, data, order) {
+            return false;
+        }
+        self.invalidate_reservations(addr, size);
+        self.invalidate_code(addr, size);
+        true
+    }
+
+    /// A store to a reserved granule breaks the reservation, whichever hart it belongs to.
+    fn invalidate_reservations(&self, addr: u64, size: u64) {
+        let first = addr & !(RESERVATION_GRANULE - 1);
+        let last = (addr + size - 1) & !(RESERVATION_GRANULE - 1);
+        for rsv in self.reservation.iter() {
+            let reserved = rsv.load(Ordering::Relaxed);
+            if reserved == NO_RESERVATION {
+                continue;
+            }
+            let granule = reserved & !(RESERVATION_GRANULE - 1);
+            if first <= granule && granule <= last {
+                let _ = rsv.compare_exchange(
+                    reserved,
+                    NO_RESERVATION,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+    }
+
+    /// Mark the DRAM page at the address as holding decoded instructions.
+    /// Returns its generation, to be read before the instructions are.
+    pub fn mark_code(&self, addr: u64) -> u32 {
+        let page = (addr / CODE_PAGE) as usize;
+        self.code[page].store(true, Ordering::SeqCst);
+        self.code_gen[page].load(Ordering::Acquire)
+    }
+
+    /// Generation of the DRAM page at the address. Decoded blocks of an older generation are stale.
+    pub fn code_gen(&self, addr: u64) -> u32 {
+        self.code_gen[(addr / CODE_PAGE) as usize].load(Ordering::Acquire)
+    }
+
+    /// A store to a code page makes the decoded blocks of every hart on it stale.
+    /// Called after the store, so a hart decoding the page again reads the new contents.
+    fn invalidate_code(&self, addr: u64, size: u64) {
+        let first = addr / CODE_PAGE;
+        let last = (addr + size - 1) / CODE_PAGE;
+        for page in first..=last {
+            let page = page as usize;
+            if self.code[page].load(Ordering::Relaxed)
+                && self.code[page].swap(false, Ordering::SeqCst)
+            {
+                self.code_gen[page].fetch_add(1, Ordering::Release);
+            }
+        }
     }
 
-    pub fn l_mm(&self, addr: u64) -> u64 {
+    pub fn l_mm(&self, addr: u64, size: u64) -> u64 {
         match addr {
-            uart::UART..=uart::UART_END => self.uart.read(addr),
-            plic::PLIC..=plic::PLIC_END => self.plic.read(addr),
-            virtio::VIRTIO..=virtio::VIRTIO_END => self.virtio.read(addr),
+            clint::CLINT..=clint::CLINT_END => self.clint.read(addr, size),
+            uart::UART..=uart::UART_END => self.uart.lock().unwrap().read(addr),
+            // the APLIC domains are in the address range of the PLIC
+            plic::PLIC..=plic::PLIC_END => match &self.irqchip {
+                Irqchip::Plic(plic) => {
+                    let mut plic = plic.lock().unwrap();
+                    let v = plic.read(addr);
+                    self.update_plic_lines(&plic);
+                    v
+                }
+                Irqchip::Aia(aplic, imsic) => {
+                    let mut aplic = aplic.lock().unwrap();
+                    let v = aplic.read(addr);
+                    self.update_aplic_lines(&mut aplic, imsic);
+                    drop(aplic);
+                    // a read can deliver an MSI
+                    self.notify();
+                    v
+                }
+            },
+            imsic::IMSIC_M..=imsic::IMSIC_M_END | imsic::IMSIC_S..=imsic::IMSIC_S_END => {
+                match self.imsic() {
+                    Some(imsic) => imsic.read(addr),
+                    None => panic!("invalid memory mapped address: 0x{:016X}", addr),
+                }
+            }
+            virtio::VIRTIO..=virtio::VIRTIO_END => self.virtio.lock().unwrap().read(addr),
             _ => panic!("invalid memory mapped address: 0x{:016X}", addr),
         }
     }
 
-    pub fn s_mm(&mut self, addr: u64, data: u64) {
+    pub fn s_mm(&self, addr: u64, size: u64, data: u64) {
         match addr {
-            uart::UART..=uart::UART_END => self.uart.write(addr, data),
-            plic::PLIC..=plic::PLIC_END => self.plic.write(addr, data),
-            virtio::VIRTIO..=virtio::VIRTIO_END => self.virtio.write(addr, data),
+            clint::CLINT..=clint::CLINT_END => self.clint.write(addr, size, data),
+            uart::UART..=uart::UART_END => self.uart.lock().unwrap().write(addr, data),
+            plic::PLIC..=plic::PLIC_END => match &self.irqchip {
+                Irqchip::Plic(plic) => {
+                    let mut plic = plic.lock().unwrap();
+                    plic.write(addr, data);
+                    self.update_plic_lines(&plic);
+                }
+                Irqchip::Aia(aplic, imsic) => {
+                    let mut aplic = aplic.lock().unwrap();
+                    aplic.write(addr, data);
+                    self.update_aplic_lines(&mut aplic, imsic);
+                }
+            },
+            imsic::IMSIC_M..=imsic::IMSIC_M_END | imsic::IMSIC_S..=imsic::IMSIC_S_END => {
+                match self.imsic() {
+                    Some(imsic) => imsic.write(addr, data),
+                    None => panic!("invalid memory mapped address: 0x{:016X}", addr),
+                }
+            }
+            virtio::VIRTIO..=virtio::VIRTIO_END => self.virtio.lock().unwrap().write(addr, data),
             _ => panic!("invalid memory mapped address: 0x{:016X}", addr),
         }
+        self.notify();
+    }
+
+    /// Read before a hart checks for a pending interrupt, and passed to `wait_event`
+    /// when it finds none, so an event in between is not missed.
+    pub fn events(&self) -> u64 {
+        *self.events.lock().unwrap()
+    }
+
+    /// Wake the harts blocked in `wait_event`.
+    pub fn notify(&self) {
+        *self.events.lock().unwrap() += 1;
+        self.event_cond.notify_all();
+    }
+
+    /// Block until an event after the count `events` read earlier, or until the timeout.
+    pub fn wait_event(&self, events: u64, timeout: Option<Duration>) {
+        let guard = self.events.lock().unwrap();
+        match timeout {
+            Some(t) => drop(
+                self.event_cond
+                    .wait_timeout_while(guard, t, |e| *e == events),
+            ),
+            None => drop(self.event_cond.wait_while(guard, |e| *e == events)),
+        }
+    }
+
+    /// Called with the PLIC locked after every access, since a claim changes the lines too.
+    fn update_plic_lines(&self, plic: &Plic) {
+        for (ctx, line) in self.ext_lines.iter().enumerate() {
+            line.store(plic.pending_int(ctx), Ordering::Relaxed);
+        }
+    }
+
+    /// Called with the APLIC locked after every access.
+    /// Domains in MSI delivery mode write their interrupts to the IMSIC instead of driving the lines.
+    fn update_aplic_lines(&self, aplic: &mut Aplic, imsic: &Imsic) {
+        for (file, id) in aplic.take_msis() {
+            imsic.send(file, id);
+        }
+        for (ctx, line) in self.ext_lines.iter().enumerate() {
+            line.store(aplic.pending_int(ctx), Ordering::Relaxed);
+        }
+    }
+
+    pub fn clint(&self) -> &Clint {
+        &self.clint
+    }
+
+    /// None without the AIA.
+    pub fn imsic(&self) -> Option<&Imsic> {
+        match &self.irqchip {
+            Irqchip::Aia(_, imsic) => Some(imsic),
+            Irqchip::Plic(_) => None,
+        }
+    }
+
+    /// Whether the external interrupt of the hart is raised at M-level or S-level.
+    pub fn ext_int(&self, hart: usize, s_level: bool) -> bool {
+        let (ctx, file) = if s_level {
+            (plic::s_context(hart), imsic::s_file(hart))
+        } else {
+            (plic::m_context(hart), imsic::m_file(hart))
+        };
+        let msi = match self.imsic() {
+            Some(imsic) => imsic.line(file),
+            None => false,
+        };
+        self.ext_lines[ctx].load(Ordering::Relaxed) || msi
     }
 }
@@ -0,0 +1,153 @@
+// https://github.com/riscv/riscv-aclint/blob/main/riscv-aclint.adoc
+// The SiFive CLINT layout: an ACLINT MSWI device followed by an MTIMER device.
+
+use crate::conf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+pub const CLINT: u64 = 0x200_0000;
+pub const MSIP: u64 = CLINT; // 4 bytes per hart. 0x0000 - 0x3FFF
+pub const MSIP_END: u64 = CLINT + 0x3FFF;
+pub const MTIMECMP: u64 = CLINT + 0x4000; // 8 bytes per hart. 0x4000 - 0xBFF7
+pub const MTIMECMP_END: u64 = CLINT + 0xBFF7;
+pub const MTIME: u64 = CLINT + 0xBFF8;
+pub const MTIME_END: u64 = CLINT + 0xBFFF;
+pub const CLINT_END: u64 = CLINT + 0xFFFF;
+
+/// The registers are atomics because harts on host threads access them without a lock.
+/// mtime counts at the timebase frequency. By default it advances with the instructions
+/// the harts execute, as if each hart ran at `conf::HART_FREQ`. The fraction of a tick left over
+/// is carried to the next advance of the same hart, so mtime does not drift with a small quantum.
+/// With the wall clock it follows the host time instead.
+#[derive(Debug)]
+pub struct Clint {
+    msip: Vec<AtomicBool>,       // per hart
+    mtimecmp: Vec<AtomicU64>,    // per hart
+    mtime: AtomicU64,            // the offset from the host time with the wall clock
+    frac: Vec<AtomicU64>,        // per hart. instructions * timebase not yet counted as a tick
+    timebase: u64,               // Hz
+    wall_clock: Option<Instant>, // when mtime was 0
+}
+
+impl Clint {
+    pub fn new(harts: usize, timebase: u64, wall_clock: bool) -> Clint {
+        Clint {
+            msip: (0..harts).map(|_| AtomicBool::new(false)).collect(),
+            mtimecmp: (0..harts).map(|_| AtomicU64::new(0)).collect(),
+            mtime: AtomicU64::new(0),
+            frac: (0..harts).map(|_| AtomicU64::new(0)).collect(),
+            timebase,
+            wall_clock: if wall_clock {
+                Some(Instant::now())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Registers are 4 bytes wide. mtimecmp and mtime can also be accessed as 4-byte halves.
+    /// Registers of harts that do not exist read as 0 and ignore writes.
+    pub fn read(&self, addr: u64, size: u64) -> u64 {
+        let (reg, off) = match addr {
+            MSIP..=MSIP_END => match self.msip.get(((addr - MSIP) / 4) as usize) {
+                Some(msip) => (msip.load(Ordering::Relaxed) as u64, 0),
+                None => (0, 0),
+            },
+            MTIMECMP..=MTIMECMP_END => match self.mtimecmp.get(((addr - MTIMECMP) / 8) as usize) {
+                Some(mtimecmp) => (mtimecmp.load(Ordering::Relaxed), addr % 8),
+                None => (0, 0),
+            },
+            MTIME..=MTIME_END => (self.mtime(), addr % 8),
+            _ => (0, 0),
+        };
+        let v = reg >> (off * 8);
+        match size {
+            8 => v,
+            _ => v & 0xFFFF_FFFF,
+        }
+    }
+
+    pub fn write(&self, addr: u64, size: u64, data: u64) {
+        match addr {
+            MSIP..=MSIP_END => {
+                if let Some(msip) = self.msip.get(((addr - MSIP) / 4) as usize) {
+                    msip.store(data & 1 != 0, Ordering::Relaxed);
+                }
+            }
+            MTIMECMP..=MTIMECMP_END => {
+                if let Some(mtimecmp) = self.mtimecmp.get(((addr - MTIMECMP) / 8) as usize) {
+                    let v = merge(mtimecmp.load(Ordering::Relaxed), addr % 8, size, data);
+                    mtimecmp.store(v, Ordering::Relaxed);
+                }
+            }
+            MTIME..=MTIME_END => self.set_mtime(merge(self.mtime(), addr % 8, size, data)),
+            _ => (),
+        }
+    }
+
+    pub fn msip(&self, hart: usize) -> bool {
+        self.msip[hart].load(Ordering::Relaxed)
+    }
+
+    pub fn mtimecmp(&self, hart: usize) -> u64 {
+        self.mtimecmp[hart].load(Ordering::Relaxed)
+    }
+
+    pub fn mtime(&self) -> u64 {
+        let mtime = self.mtime.load(Ordering::Relaxed);
+        match self.wall_clock {
+            Some(start) => mtime.wrapping_add(self.host_ticks(start)),
+            None => mtime,
+        }
+    }
+
+    pub fn set_mtime(&self, mtime: u64) {
+        let mtime = match self.wall_clock {
+            Some(start) => mtime.wrapping_sub(self.host_ticks(start)),
+            None => mtime,
+        };
+        self.mtime.store(mtime, Ordering::Relaxed);
+    }
+
+    /// Ticks of the timebase since the start on the host clock.
+    fn host_ticks(&self, start: Instant) -> u64 {
+        (start.elapsed().as_nanos() * self.timebase as u128 / 1_000_000_000) as u64
+    }
+
+    /// mtime ticks for `n` more instructions of the hart, with the fraction carried from the last call.
+    /// Only the thread running the hart calls it.
+    pub fn ticks(&self, hart: usize, n: u64) -> u64 {
+        let freq = conf::HART_FREQ as u128;
+        let total =
+            n as u128 * self.timebase as u128 + self.frac[hart].load(Ordering::Relaxed) as u128;
+        self.frac[hart].store((total % freq) as u64, Ordering::Relaxed);
+        (total / freq) as u64
+    }
+
+    /// Host time until mtime reaches `mtime`, or None when mtime does not follow the host clock.
+    pub fn wall_time_until(&self, mtime: u64) -> Option<Duration> {
+        self.wall_clock?;
+        let ticks = mtime.saturating_sub(self.mtime()) as u128;
+        let nanos = (ticks * 1_000_000_000).div_ceil(self.timebase as u128);
+        Some(Duration::from_nanos(nanos.min(u64::MAX as u128) as u64))
+    }
+
+    /// Move mtime forward to `mtime`. It never goes back, whichever hart advances it.
+    /// Returns false when mtime follows the host clock and cannot be moved.
+    pub fn advance_mtime(&self, mtime: u64) -> bool {
+        if self.wall_clock.is_some() {
+            return false;
+        }
+        self.mtime.fetch_max(mtime, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Write the lower `size` bytes of the data at byte `off` of the register.
+fn merge(reg: u64, off: u64, size: u64, data: u64) -> u64 {
+    if size == 8 {
+        return data;
+    }
+    let mask = 0xFFFF_FFFF << (off * 8);
+    reg & !mask | (data << (off * 8)) & mask
+}
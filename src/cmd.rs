@@ -10,6 +10,16 @@ pub struct Command {
     pub elf: Option<String>,
     pub drive: Option<String>,
     pub dbg: Debug,
+    pub busy_wfi: bool,
+    pub trap_misaligned: bool,
+    pub cache_block: u64,
+    pub vlen: u64,
+    pub harts: usize,
+    pub quantum: u64,
+    pub threads: bool,
+    pub timebase: u64,
+    pub wall_clock: bool,
+    pub aia: bool,
 }
 
 impl Command {
@@ -20,6 +30,16 @@ impl Command {
             elf: None,
             drive: None,
             dbg: Debug::new(false, 0),
+            busy_wfi: false,
+            trap_misaligned: false,
+            cache_block: conf::CACHE_BLOCK,
+            vlen: conf::VLEN,
+            harts: conf::HARTS,
+            quantum: conf::QUANTUM,
+            threads: false,
+            timebase: conf::TIMEBASE_FREQ,
+            wall_clock: false,
+            aia: false,
         }
     }
 
@@ -28,9 +48,13 @@ impl Command {
     }
 
     pub fn get() -> Command {
-        let mut args: Vec<String> = env::args().collect();
-        args.reverse();
-        args.pop(); // remove executable path
+        let args: Vec<String> = env::args().skip(1).collect(); // skip executable path
+        Command::parse(&args)
+    }
+
+    /// Options in the order of the command line, without the executable path.
+    pub fn parse(args: &[String]) -> Command {
+        let mut args: Vec<String> = args.iter().rev().cloned().collect();
 
         let mut cmd = Command::init();
         loop {
@@ -44,6 +68,16 @@ impl Command {
                 "--elf" => cmd.elf = Command::get_arg_string(&mut args),
                 "--drive" => cmd.drive = Command::get_arg_string(&mut args),
                 "--debug" => cmd.dbg = Command::get_arg_debug(&mut args),
+                "--busy-wfi" => cmd.busy_wfi = true,
+                "--trap-misaligned" => cmd.trap_misaligned = true,
+                "--cache-block" => cmd.cache_block = Command::get_arg_cache_block(&mut args),
+                "--vlen" => cmd.vlen = Command::get_arg_vlen(&mut args),
+                "--harts" => cmd.harts = Command::get_arg_positive(&mut args) as usize,
+                "--quantum" => cmd.quantum = Command::get_arg_positive(&mut args),
+                "--threads" => cmd.threads = true,
+                "--timebase" => cmd.timebase = Command::get_arg_positive(&mut args),
+                "--wall-clock" => cmd.wall_clock = true,
+                "--aia" => cmd.aia = true,
                 _ => (),
             }
         }
@@ -64,6 +98,32 @@ impl Command {
         }
     }
 
+    /// VLEN is a power of 2 from 64 to 65536 bits.
+    fn get_arg_vlen(args: &mut Vec<String>) -> u64 {
+        let vlen = args.pop().unwrap().parse::<u64>().unwrap();
+        if !vlen.is_power_of_two() || !(64..=65536).contains(&vlen) {
+            panic!("invalid VLEN: {}", vlen);
+        }
+        vlen
+    }
+
+    /// A cache block is a power of 2 from 16 to 4096 bytes, so it never crosses a page.
+    fn get_arg_cache_block(args: &mut Vec<String>) -> u64 {
+        let size = args.pop().unwrap().parse::<u64>().unwrap();
+        if !size.is_power_of_two() || !(16..=4096).contains(&size) {
+            panic!("invalid cache block size: {}", size);
+        }
+        size
+    }
+
+    fn get_arg_positive(args: &mut Vec<String>) -> u64 {
+        let v = args.pop().unwrap().parse::<u64>().unwrap();
+        if v == 0 {
+            panic!("invalid argument: 0");
+        }
+        v
+    }
+
     fn get_arg_debug(args: &mut Vec<String>) -> Debug {
         match args.pop() {
             Some(v) => {
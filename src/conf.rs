@@ -1,3 +1,9 @@
 pub const STACK_BOTTOM: u64 = 0x000F_4240;
 pub const MEMORY_SIZE: usize = 256_000_000;
 pub const MEM_OFF: usize = 0x8000_0000;
+pub const VLEN: u64 = 128; // bits of a vector register
+pub const CACHE_BLOCK: u64 = 64; // bytes of a cache block for the cache-block operations
+pub const HARTS: usize = 1;
+pub const TIMEBASE_FREQ: u64 = 10_000_000; // Hz of mtime
+pub const HART_FREQ: u64 = 100_000_000; // instructions a hart executes per second of mtime, unless on the wall clock
+pub const QUANTUM: u64 = 1000; // instructions a hart runs before the next hart takes its turn
@@ -0,0 +1,719 @@
+// IEEE 754 binary32 and binary64 arithmetic for the F and D extensions.
+// The host floating-point unit can not be used because it always rounds to
+// nearest even, and it does not report the exception flags.
+//
+// A finite value is handled as `sig * 2^exp`, where `sig` is an integer.
+// Every operation computes an exact result, or a result whose lowest bit is
+// the OR of all the discarded bits ("jamming"), and `round_pack` rounds it
+// to the destination precision.
+
+// fflags
+pub const NX: u8 = 0b0_0001; // Inexact
+pub const UF: u8 = 0b0_0010; // Underflow
+pub const OF: u8 = 0b0_0100; // Overflow
+pub const DZ: u8 = 0b0_1000; // Divide by Zero
+pub const NV: u8 = 0b1_0000; // Invalid Operation
+
+// rounding mode
+pub const RNE: u8 = 0b000; // Round to Nearest, ties to Even
+pub const RTZ: u8 = 0b001; // Round towards Zero
+pub const RDN: u8 = 0b010; // Round Down (towards -inf)
+pub const RUP: u8 = 0b011; // Round Up (towards +inf)
+pub const RMM: u8 = 0b100; // Round to Nearest, ties to Max Magnitude
+pub const ROD: u8 = 0b101; // Round to Odd. Reserved in frm, used by vfncvt.rod.f.f.w.
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Precision {
+    Single,
+    Double,
+}
+
+impl Precision {
+    fn width(self) -> u32 {
+        match self {
+            Precision::Single => 32,
+            Precision::Double => 64,
+        }
+    }
+
+    fn frac_bits(self) -> u32 {
+        match self {
+            Precision::Single => 23,
+            Precision::Double => 52,
+        }
+    }
+
+    fn bias(self) -> i32 {
+        match self {
+            Precision::Single => 127,
+            Precision::Double => 1023,
+        }
+    }
+
+    fn exp_mask(self) -> u64 {
+        match self {
+            Precision::Single => 0xFF,
+            Precision::Double => 0x7FF,
+        }
+    }
+
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits()) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.width() - 1)
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        match self {
+            Precision::Single => 0x7FC0_0000,
+            Precision::Double => 0x7FF8_0000_0000_0000,
+        }
+    }
+}
+
+fn sign(p: Precision, a: u64) -> bool {
+    a & p.sign_bit() != 0
+}
+
+fn exp_field(p: Precision, a: u64) -> u64 {
+    (a >> p.frac_bits()) & p.exp_mask()
+}
+
+fn is_nan(p: Precision, a: u64) -> bool {
+    exp_field(p, a) == p.exp_mask() && a & p.frac_mask() != 0
+}
+
+fn is_snan(p: Precision, a: u64) -> bool {
+    is_nan(p, a) && a & (1 << (p.frac_bits() - 1)) == 0
+}
+
+fn is_inf(p: Precision, a: u64) -> bool {
+    exp_field(p, a) == p.exp_mask() && a & p.frac_mask() == 0
+}
+
+fn is_zero(p: Precision, a: u64) -> bool {
+    a & !p.sign_bit() == 0
+}
+
+fn zero(p: Precision, sign: bool) -> u64 {
+    if sign {
+        p.sign_bit()
+    } else {
+        0
+    }
+}
+
+fn inf(p: Precision, sign: bool) -> u64 {
+    zero(p, sign) | p.exp_mask() << p.frac_bits()
+}
+
+/// Returns (sign, exp, sig) of a finite value. value = sig * 2^exp
+fn unpack(p: Precision, a: u64) -> (bool, i32, u128) {
+    let e = exp_field(p, a) as i32;
+    let frac = (a & p.frac_mask()) as u128;
+    let exp_min = 1 - p.bias() - p.frac_bits() as i32;
+    if e == 0 {
+        (sign(p, a), exp_min, frac)
+    } else {
+        (sign(p, a), exp_min + e - 1, frac | 1 << p.frac_bits())
+    }
+}
+
+/// Index of the most significant set bit.
+fn msb(sig: u128) -> i32 {
+    127 - sig.leading_zeros() as i32
+}
+
+/// Shift right, and OR the discarded bits into the lowest bit.
+fn shift_right_jam(sig: u128, n: i32) -> u128 {
+    if n <= 0 {
+        sig
+    } else if n >= 128 {
+        (sig != 0) as u128
+    } else {
+        (sig >> n) | (sig & ((1 << n) - 1) != 0) as u128
+    }
+}
+
+/// Round `sig / 2^shift` to an integer.
+/// Returns the rounded value and whether it is inexact.
+fn round_shift(sig: u128, shift: i32, sign: bool, rm: u8) -> (u128, bool) {
+    if shift <= 0 {
+        return (sig << -shift, false);
+    }
+    let (sig, shift) = if shift > 126 {
+        (shift_right_jam(sig, shift - 126), 126)
+    } else {
+        (sig, shift)
+    };
+
+    let m = sig >> shift;
+    let rem = sig & ((1 << shift) - 1);
+    let half = 1 << (shift - 1);
+    let inexact = rem != 0;
+    let up = match rm {
+        RNE => rem > half || (rem == half && m & 1 == 1),
+        RTZ => false,
+        RDN => inexact && sign,
+        RUP => inexact && !sign,
+        RMM => rem >= half,
+        ROD => inexact && m & 1 == 0,
+        _ => panic!("invalid rounding mode: {}", rm),
+    };
+    (m + up as u128, inexact)
+}
+
+/// Round `(-1)^sign * sig * 2^exp` to the precision `p`.
+/// Tininess is detected after rounding.
+fn round_pack(p: Precision, sign: bool, exp: i32, sig: u128, rm: u8, flags: &mut u8) -> u64 {
+    if sig == 0 {
+        return zero(p, sign);
+    }
+    let prec = p.frac_bits() as i32 + 1;
+    let emin = 1 - p.bias();
+    let emax = p.bias();
+    let e = msb(sig) + exp;
+
+    // the result rounded as if the exponent range were unbounded
+    let (m, _) = round_shift(sig, msb(sig) - (prec - 1), sign, rm);
+    let tiny = if m >> prec != 0 {
+        e + 1 < emin
+    } else {
+        e < emin
+    };
+
+    let mut q = e.max(emin) - (prec - 1); // exponent of the result's lowest bit
+    let (mut m, inexact) = round_shift(sig, q - exp, sign, rm);
+    if m >> prec != 0 {
+        m >>= 1;
+        q += 1;
+    }
+
+    let normal = m >> (prec - 1) != 0;
+    if normal && q + prec - 1 > emax {
+        return overflow(p, sign, rm, flags);
+    }
+    if inexact {
+        *flags |= NX;
+        if tiny {
+            *flags |= UF;
+        }
+    }
+
+    if normal {
+        let biased = (q + prec - 1 + p.bias()) as u64;
+        zero(p, sign) | biased << p.frac_bits() | (m as u64 & p.frac_mask())
+    } else {
+        zero(p, sign) | m as u64
+    }
+}
+
+/// The largest finite value or infinity, whichever the rounding mode rounds an overflow to.
+fn overflow(p: Precision, sign: bool, rm: u8, flags: &mut u8) -> u64 {
+    *flags |= OF | NX;
+    let max_finite = inf(p, sign) - 1;
+    match rm {
+        RTZ | ROD => max_finite,
+        RDN if !sign => max_finite,
+        RUP if sign => max_finite,
+        _ => inf(p, sign),
+    }
+}
+
+/// Propagate NaN operands. Returns the canonical NaN if any operand is NaN.
+fn nan_operands(p: Precision, ops: &[u64], flags: &mut u8) -> Option<u64> {
+    if ops.iter().any(|a| is_snan(p, *a)) {
+        *flags |= NV;
+    }
+    if ops.iter().any(|a| is_nan(p, *a)) {
+        return Some(p.canonical_nan());
+    }
+    None
+}
+
+/// Sum of two unpacked finite values.
+fn add_unpacked(
+    p: Precision,
+    a: (bool, i32, u128),
+    b: (bool, i32, u128),
+    rm: u8,
+    flags: &mut u8,
+) -> u64 {
+    let (sa, ea, ma) = a;
+    let (sb, eb, mb) = b;
+    if ma == 0 && mb == 0 {
+        return zero(p, if sa == sb { sa } else { rm == RDN });
+    }
+    if ma == 0 {
+        return round_pack(p, sb, eb, mb, rm, flags);
+    }
+    if mb == 0 {
+        return round_pack(p, sa, ea, ma, rm, flags);
+    }
+
+    // place the most significant bit at bit 124 and align to the larger exponent
+    let (ea, ma) = (ea - (124 - msb(ma)), ma << (124 - msb(ma)));
+    let (eb, mb) = (eb - (124 - msb(mb)), mb << (124 - msb(mb)));
+    let ((sa, ea, ma), (sb, eb, mb)) = if ea >= eb {
+        ((sa, ea, ma), (sb, eb, mb))
+    } else {
+        ((sb, eb, mb), (sa, ea, ma))
+    };
+    let mb = shift_right_jam(mb, ea - eb);
+
+    let (sign, sig) = if sa == sb {
+        (sa, ma + mb)
+    } else if ma >= mb {
+        (sa, ma - mb)
+    } else {
+        (sb, mb - ma)
+    };
+    if sig == 0 {
+        return zero(p, rm == RDN);
+    }
+    round_pack(p, sign, ea, sig, rm, flags)
+}
+
+/// a + b, or a - b when `sub` is true.
+pub fn add(p: Precision, a: u64, b: u64, sub: bool, rm: u8, flags: &mut u8) -> u64 {
+    let b = if sub { b ^ p.sign_bit() } else { b };
+    if let Some(nan) = nan_operands(p, &[a, b], flags) {
+        return nan;
+    }
+    if is_inf(p, a) && is_inf(p, b) {
+        if sign(p, a) != sign(p, b) {
+            *flags |= NV;
+            return p.canonical_nan();
+        }
+        return a;
+    }
+    if is_inf(p, a) {
+        return a;
+    }
+    if is_inf(p, b) {
+        return b;
+    }
+    add_unpacked(p, unpack(p, a), unpack(p, b), rm, flags)
+}
+
+/// a * b
+pub fn mul(p: Precision, a: u64, b: u64, rm: u8, flags: &mut u8) -> u64 {
+    if let Some(nan) = nan_operands(p, &[a, b], flags) {
+        return nan;
+    }
+    let s = sign(p, a) != sign(p, b);
+    if is_inf(p, a) || is_inf(p, b) {
+        if is_zero(p, a) || is_zero(p, b) {
+            *flags |= NV;
+            return p.canonical_nan();
+        }
+        return inf(p, s);
+    }
+    let (_, ea, ma) = unpack(p, a);
+    let (_, eb, mb) = unpack(p, b);
+    round_pack(p, s, ea + eb, ma * mb, rm, flags)
+}
+
+/// a / b
+pub fn div(p: Precision, a: u64, b: u64, rm: u8, flags: &mut u8) -> u64 {
+    if let Some(nan) = nan_operands(p, &[a, b], flags) {
+        return nan;
+    }
+    let s = sign(p, a) != sign(p, b);
+    if (is_inf(p, a) && is_inf(p, b)) || (is_zero(p, a) && is_zero(p, b)) {
+        *flags |= NV;
+        return p.canonical_nan();
+    }
+    if is_inf(p, a) {
+        return inf(p, s);
+    }
+    if is_inf(p, b) {
+        return zero(p, s);
+    }
+    if is_zero(p, b) {
+        *flags |= DZ;
+        return inf(p, s);
+    }
+    if is_zero(p, a) {
+        return zero(p, s);
+    }
+
+    let (_, ea, ma) = unpack(p, a);
+    let (_, eb, mb) = unpack(p, b);
+    let shift = 125 - msb(ma);
+    let ma = ma << shift;
+    let q = ma / mb;
+    let q = q | !ma.is_multiple_of(mb) as u128;
+    round_pack(p, s, ea - shift - eb, q, rm, flags)
+}
+
+/// Integer square root. Returns (root, exact)
+fn isqrt(n: u128) -> (u128, bool) {
+    let mut rem = n;
+    let mut root: u128 = 0;
+    let mut bit: u128 = 1 << 126;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    (root, rem == 0)
+}
+
+/// sqrt(a)
+pub fn sqrt(p: Precision, a: u64, rm: u8, flags: &mut u8) -> u64 {
+    if let Some(nan) = nan_operands(p, &[a], flags) {
+        return nan;
+    }
+    if is_zero(p, a) {
+        return a;
+    }
+    if sign(p, a) {
+        *flags |= NV;
+        return p.canonical_nan();
+    }
+    if is_inf(p, a) {
+        return a;
+    }
+
+    let (_, e, m) = unpack(p, a);
+    // make the exponent even and the significand as large as possible
+    let mut shift = 124 - msb(m);
+    if (e - shift) % 2 != 0 {
+        shift += 1;
+    }
+    let (root, exact) = isqrt(m << shift);
+    let root = root | (!exact) as u128;
+    round_pack(p, false, (e - shift) / 2, root, rm, flags)
+}
+
+/// (a * b) + c with a single rounding.
+/// `neg_prod` negates a * b and `neg_c` negates c.
+#[allow(clippy::too_many_arguments)]
+pub fn fma(
+    p: Precision,
+    a: u64,
+    b: u64,
+    c: u64,
+    neg_prod: bool,
+    neg_c: bool,
+    rm: u8,
+    flags: &mut u8,
+) -> u64 {
+    let c = if neg_c { c ^ p.sign_bit() } else { c };
+    // inf * 0 is invalid even if c is a quiet NaN
+    if (is_inf(p, a) && is_zero(p, b)) || (is_zero(p, a) && is_inf(p, b)) {
+        *flags |= NV;
+        return p.canonical_nan();
+    }
+    if let Some(nan) = nan_operands(p, &[a, b, c], flags) {
+        return nan;
+    }
+
+    let sp = (sign(p, a) != sign(p, b)) != neg_prod;
+    if is_inf(p, a) || is_inf(p, b) {
+        if is_inf(p, c) && sign(p, c) != sp {
+            *flags |= NV;
+            return p.canonical_nan();
+        }
+        return inf(p, sp);
+    }
+    if is_inf(p, c) {
+        return c;
+    }
+
+    let (_, ea, ma) = unpack(p, a);
+    let (_, eb, mb) = unpack(p, b);
+    add_unpacked(p, (sp, ea + eb, ma * mb), unpack(p, c), rm, flags)
+}
+
+/// Convert between single and double precision.
+pub fn convert(from: Precision, to: Precision, a: u64, rm: u8, flags: &mut u8) -> u64 {
+    if nan_operands(from, &[a], flags).is_some() {
+        return to.canonical_nan();
+    }
+    if is_inf(from, a) {
+        return inf(to, sign(from, a));
+    }
+    let (s, e, m) = unpack(from, a);
+    round_pack(to, s, e, m, rm, flags)
+}
+
+/// Convert to a `width` bit signed or unsigned integer.
+/// The result is sign-extended to 64 bits.
+pub fn to_int(p: Precision, a: u64, signed: bool, width: u32, rm: u8, flags: &mut u8) -> u64 {
+    let max: u128 = if signed {
+        (1 << (width - 1)) - 1
+    } else {
+        (1 << width) - 1
+    };
+    let min_mag: u128 = if signed { 1 << (width - 1) } else { 0 };
+    let saturate = |neg: bool| -> u64 {
+        let v = if neg {
+            (min_mag as u64).wrapping_neg()
+        } else {
+            max as u64
+        };
+        if width == 32 {
+            v as i32 as i64 as u64
+        } else {
+            v
+        }
+    };
+
+    if is_nan(p, a) {
+        *flags |= NV;
+        return saturate(false);
+    }
+    let s = sign(p, a);
+    if is_inf(p, a) {
+        *flags |= NV;
+        return saturate(s);
+    }
+
+    let (_, e, m) = unpack(p, a);
+    if m != 0 && msb(m) + e >= 65 {
+        *flags |= NV;
+        return saturate(s);
+    }
+    let (v, inexact) = if e >= 0 {
+        (m << e, false)
+    } else {
+        round_shift(m, -e, s, rm)
+    };
+    let in_range = if s { v <= min_mag } else { v <= max };
+    if !in_range {
+        *flags |= NV;
+        return saturate(s);
+    }
+    if inexact {
+        *flags |= NX;
+    }
+    let v = if s {
+        (v as u64).wrapping_neg()
+    } else {
+        v as u64
+    };
+    if width == 32 {
+        v as i32 as i64 as u64
+    } else {
+        v
+    }
+}
+
+/// Convert a `width` bit signed or unsigned integer.
+pub fn from_int(p: Precision, v: u64, signed: bool, width: u32, rm: u8, flags: &mut u8) -> u64 {
+    let v = if width == 32 {
+        if signed {
+            v as i32 as i64 as u64
+        } else {
+            v as u32 as u64
+        }
+    } else {
+        v
+    };
+    let neg = signed && (v as i64) < 0;
+    let mag = if neg { v.wrapping_neg() } else { v };
+    round_pack(p, neg, 0, mag as u128, rm, flags)
+}
+
+/// minimumNumber or maximumNumber.
+pub fn min_max(p: Precision, a: u64, b: u64, max: bool, flags: &mut u8) -> u64 {
+    if is_snan(p, a) || is_snan(p, b) {
+        *flags |= NV;
+    }
+    match (is_nan(p, a), is_nan(p, b)) {
+        (true, true) => return p.canonical_nan(),
+        (true, false) => return b,
+        (false, true) => return a,
+        _ => (),
+    }
+    // -0.0 is less than +0.0
+    let a_lt_b = if is_zero(p, a) && is_zero(p, b) {
+        sign(p, a) && !sign(p, b)
+    } else {
+        lt(p, a, b)
+    };
+    if a_lt_b != max {
+        a
+    } else {
+        b
+    }
+}
+
+/// a < b for non-NaN values.
+fn lt(p: Precision, a: u64, b: u64) -> bool {
+    if is_zero(p, a) && is_zero(p, b) {
+        return false;
+    }
+    match (sign(p, a), sign(p, b)) {
+        (false, false) => a < b,
+        (true, true) => a > b,
+        (sa, _) => sa,
+    }
+}
+
+/// a == b. Only a signaling NaN raises the invalid operation exception.
+pub fn eq(p: Precision, a: u64, b: u64, flags: &mut u8) -> bool {
+    if is_snan(p, a) || is_snan(p, b) {
+        *flags |= NV;
+    }
+    if is_nan(p, a) || is_nan(p, b) {
+        return false;
+    }
+    a == b || (is_zero(p, a) && is_zero(p, b))
+}
+
+/// a < b, or a <= b when `or_eq` is true.
+/// Any NaN raises the invalid operation exception.
+pub fn less(p: Precision, a: u64, b: u64, or_eq: bool, flags: &mut u8) -> bool {
+    if is_nan(p, a) || is_nan(p, b) {
+        *flags |= NV;
+        return false;
+    }
+    lt(p, a, b) || (or_eq && (a == b || (is_zero(p, a) && is_zero(p, b))))
+}
+
+// 7-bit estimates of the significand of 1/x and 1/sqrt(x) for vfrec7 and vfrsqrt7.
+// REC7 is indexed by the upper 7 bits of the significand. RSQRT7 is indexed by the lowest bit
+// of the exponent and the upper 6 bits of the significand.
+#[rustfmt::skip]
+const REC7: [u64; 128] = [
+    127, 125, 123, 121, 119, 117, 116, 114, 112, 110, 109, 107, 105, 104, 102, 100,
+    99, 97, 96, 94, 93, 91, 90, 88, 87, 85, 84, 83, 81, 80, 79, 77,
+    76, 75, 74, 72, 71, 70, 69, 68, 66, 65, 64, 63, 62, 61, 60, 59,
+    58, 57, 56, 55, 54, 53, 52, 51, 50, 49, 48, 47, 46, 45, 44, 43,
+    42, 41, 40, 40, 39, 38, 37, 36, 35, 35, 34, 33, 32, 31, 31, 30,
+    29, 28, 28, 27, 26, 25, 25, 24, 23, 23, 22, 21, 21, 20, 19, 19,
+    18, 17, 17, 16, 15, 15, 14, 14, 13, 12, 12, 11, 11, 10, 9, 9,
+    8, 8, 7, 7, 6, 5, 5, 4, 4, 3, 3, 2, 2, 1, 1, 0,
+];
+#[rustfmt::skip]
+const RSQRT7: [u64; 128] = [
+    52, 51, 50, 48, 47, 46, 44, 43, 42, 41, 40, 39, 38, 36, 35, 34,
+    33, 32, 31, 30, 30, 29, 28, 27, 26, 25, 24, 23, 23, 22, 21, 20,
+    19, 19, 18, 17, 16, 16, 15, 14, 14, 13, 12, 12, 11, 10, 10, 9,
+    9, 8, 7, 7, 6, 6, 5, 4, 4, 3, 3, 2, 2, 1, 1, 0,
+    127, 125, 123, 121, 119, 118, 116, 114, 113, 111, 109, 108, 106, 105, 103, 102,
+    100, 99, 97, 96, 95, 93, 92, 91, 90, 88, 87, 86, 85, 84, 83, 82,
+    80, 79, 78, 77, 76, 75, 74, 73, 72, 71, 70, 70, 69, 68, 67, 66,
+    65, 64, 63, 63, 62, 61, 60, 59, 59, 58, 57, 56, 56, 55, 54, 53,
+];
+
+/// Returns (exponent field, fraction) of a nonzero finite value.
+/// A subnormal value is normalized, and its exponent is 0 or below.
+fn normalize(p: Precision, a: u64) -> (i32, u64) {
+    let e = exp_field(p, a) as i32;
+    let frac = a & p.frac_mask();
+    if e != 0 {
+        return (e, frac);
+    }
+    let lz = frac.leading_zeros() as i32 - (64 - p.frac_bits() as i32);
+    (-lz, (frac << (lz + 1)) & p.frac_mask())
+}
+
+/// Estimate of 1/a accurate to 7 bits (vfrec7). Only an overflow raises the inexact exception.
+pub fn rec7(p: Precision, a: u64, rm: u8, flags: &mut u8) -> u64 {
+    if nan_operands(p, &[a], flags).is_some() {
+        return p.canonical_nan();
+    }
+    let s = sign(p, a);
+    if is_inf(p, a) {
+        return zero(p, s);
+    }
+    if is_zero(p, a) {
+        *flags |= DZ;
+        return inf(p, s);
+    }
+    let (e, frac) = normalize(p, a);
+    // a subnormal value below 2^-(bias+1)
+    if e < -1 {
+        return overflow(p, s, rm, flags);
+    }
+    let f = p.frac_bits();
+    let sig = REC7[(frac >> (f - 7)) as usize] << (f - 7);
+    let out_e = 2 * p.bias() - 1 - e;
+    if out_e <= 0 {
+        // a subnormal result
+        return zero(p, s) | (1 << f | sig) >> (1 - out_e);
+    }
+    zero(p, s) | (out_e as u64) << f | sig
+}
+
+/// Estimate of 1/sqrt(a) accurate to 7 bits (vfrsqrt7).
+pub fn rsqrt7(p: Precision, a: u64, flags: &mut u8) -> u64 {
+    if nan_operands(p, &[a], flags).is_some() {
+        return p.canonical_nan();
+    }
+    let s = sign(p, a);
+    if is_zero(p, a) {
+        *flags |= DZ;
+        return inf(p, s);
+    }
+    if s {
+        *flags |= NV;
+        return p.canonical_nan();
+    }
+    if is_inf(p, a) {
+        return 0;
+    }
+    let (e, frac) = normalize(p, a);
+    let f = p.frac_bits();
+    let idx = ((e & 1) as u64) << 6 | frac >> (f - 6);
+    let out_e = (3 * p.bias() - 1 - e) / 2;
+    (out_e as u64) << f | RSQRT7[idx as usize] << (f - 7)
+}
+
+/// FCLASS result mask.
+///   0 -- negative infinity.
+///   1 -- negative normal number.
+///   2 -- negative subnormal number.
+///   3 -- negative zero.
+///   4 -- positive zero.
+///   5 -- positive subnormal number.
+///   6 -- positive normal number.
+///   7 -- positive infinity.
+///   8 -- signaling NaN.
+///   9 -- quiet NaN.
+pub fn classify(p: Precision, a: u64) -> u64 {
+    let s = sign(p, a);
+    let bit = if is_nan(p, a) {
+        if is_snan(p, a) {
+            8
+        } else {
+            9
+        }
+    } else if is_inf(p, a) {
+        if s {
+            0
+        } else {
+            7
+        }
+    } else if is_zero(p, a) {
+        if s {
+            3
+        } else {
+            4
+        }
+    } else if exp_field(p, a) == 0 {
+        if s {
+            2
+        } else {
+            5
+        }
+    } else if s {
+        1
+    } else {
+        6
+    };
+    1 << bit
+}
@@ -0,0 +1,393 @@
+// RISC-V "H" Extension for Hypervisor Support, version 1.0.
+// https://github.com/riscv/riscv-isa-manual/blob/main/src/hypervisor.adoc
+//
+// A guest runs in VS-mode or VU-mode, which are S-mode and U-mode with reg.virt set.
+// Its addresses are translated in two stages: VS-stage translates a guest virtual address
+// to a guest physical address with vsatp, and G-stage translates every guest physical
+// address, including those of the VS-stage page tables, to a physical address with hgatp.
+// Guest translations are not cached in the TLB, so hfence.vvma and hfence.gvma only
+// check the privilege.
+
+use super::instructions::Instruction;
+use super::int::Exception;
+use super::register::HPM_LOAD;
+use super::register::HPM_STORE;
+use super::{leaf_ppn, page_parts, pte_permitted, pte_reserved, AccessType, Cpu, Mode};
+use super::{BARE, SV39, SV48, SV57};
+use super::{HSTATUS_HU, HSTATUS_SPVP};
+use super::{MSTATUS_MXR, MSTATUS_SUM, MSTATUS_TVM};
+use super::{PAGE_OFF_SIZE, PAGE_SIZE, PTE_SIZE, SATP_PPN, VPN_MASK, VPN_SIZE};
+use super::{PTE_A, PTE_D, PTE_PPN, PTE_R, PTE_V, PTE_W, PTE_X};
+use crate::conf::MEM_OFF;
+
+// vsstatus
+const SSTATUS_SIE: u64 = 0b10;
+const SSTATUS_SPIE: u64 = 0b10_0000;
+const SSTATUS_SPP: u64 = 0b1_0000_0000;
+
+// The root page table of Sv39x4, Sv48x4 and Sv57x4 has 2048 entries.
+const ROOT_VPN_MASK: u64 = 0x7FF;
+
+impl Cpu {
+    /// Two-stage translation of the guest virtual address for an access in VS-mode or VU-mode.
+    ///   access -- the type of the exception raised on a fault.
+    ///   check  -- the permission required by the leaf PTEs. hlvx loads with execute permission.
+    pub(super) fn trans_guest(
+        &mut self,
+        va: u64,
+        access: AccessType,
+        check: AccessType,
+        mode: Mode,
+    ) -> Result<u64, Exception> {
+        let gpa = match self.reg.vsatp >> 60 {
+            BARE => va,
+            SV39 => self.vs_stage(va, access, check, mode, 3)?,
+            SV48 => self.vs_stage(va, access, check, mode, 4)?,
+            SV57 => self.vs_stage(va, access, check, mode, 5)?,
+            // write_csr ignores writes to vsatp with an unsupported mode, and the reset value is Bare
+            _ => unreachable!("unsupported vsatp mode"),
+        };
+        self.g_stage(va, gpa, access, check)
+    }
+
+    /// Walk the `levels` level VS-stage page table like page_walk, with vsatp, vsstatus.SUM,
+    /// vsstatus.MXR or mstatus.MXR, and henvcfg.PBMTE.
+    /// The page table entries are at guest physical addresses translated by G-stage.
+    /// A G-stage fault on them is a guest-page fault of the original access type.
+    fn vs_stage(
+        &mut self,
+        va: u64,
+        access: AccessType,
+        check: AccessType,
+        mode: Mode,
+        levels: u64,
+    ) -> Result<u64, Exception> {
+        let va_bits = PAGE_OFF_SIZE + VPN_SIZE * levels;
+        let upper = (va as i64) >> (va_bits - 1);
+        if upper != 0 && upper != -1 {
+            return Err(access.page_fault(va));
+        }
+
+        let mut a = (self.reg.vsatp & SATP_PPN) * PAGE_SIZE;
+        let mut i = levels - 1;
+        let (pte, pte_gpa) = loop {
+            let vpn = (va >> (PAGE_OFF_SIZE + VPN_SIZE * i)) & VPN_MASK;
+            let pte_gpa = a + vpn * PTE_SIZE;
+            let pte_addr = self.g_stage(va, pte_gpa, access, AccessType::Load)?;
+            let pte = self.load_pte(va, pte_addr, access)?;
+
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(access.page_fault(va));
+            }
+            if pte_reserved(pte, self.reg.vs_pbmte()) {
+                return Err(access.page_fault(va));
+            }
+            if pte & (PTE_R | PTE_X) != 0 {
+                break (pte, pte_gpa);
+            }
+            if i == 0 {
+                return Err(access.page_fault(va));
+            }
+            i -= 1;
+            a = ((pte & PTE_PPN) >> 10) * PAGE_SIZE;
+        };
+
+        let sum = self.reg.vsstatus & MSTATUS_SUM != 0;
+        let mxr = (self.reg.vsstatus | self.reg.mstatus) & MSTATUS_MXR != 0;
+        if !pte_permitted(pte, check, mode, sum, mxr) {
+            return Err(access.page_fault(va));
+        }
+
+        let ppn = match leaf_ppn(pte, va, i) {
+            Some(ppn) => ppn,
+            None => return Err(access.page_fault(va)),
+        };
+
+        let mut new_pte = pte | PTE_A;
+        if access == AccessType::Store {
+            new_pte |= PTE_D;
+        }
+        if new_pte != pte {
+            let pte_addr = self.g_stage(va, pte_gpa, access, AccessType::Store)?;
+            self.store_pte(va, pte_addr, new_pte, access)?;
+        }
+
+        Ok((ppn * PAGE_SIZE) | (va & (PAGE_SIZE - 1)))
+    }
+
+    /// Walk the G-stage page table of hgatp.
+    /// Sv39x4, Sv48x4 and Sv57x4 are Sv39, Sv48 and Sv57 with a 16 KiB root page table,
+    /// so a guest physical address has 2 more bits, and the upper bits must be zero.
+    /// Every access is checked as a U-mode access with mstatus.MXR, PBMT is enabled by menvcfg.PBMTE,
+    /// and a fault is a guest-page fault with the guest virtual and guest physical addresses.
+    fn g_stage(
+        &mut self,
+        va: u64,
+        gpa: u64,
+        access: AccessType,
+        check: AccessType,
+    ) -> Result<u64, Exception> {
+        let levels = match self.reg.hgatp >> 60 {
+            BARE => return Ok(gpa),
+            SV39 => 3,
+            SV48 => 4,
+            SV57 => 5,
+            // write_csr ignores writes to hgatp with an unsupported mode, and the reset value is Bare
+            _ => unreachable!("unsupported hgatp mode"),
+        };
+        let gpa_bits = PAGE_OFF_SIZE + VPN_SIZE * levels + 2;
+        if gpa >> gpa_bits != 0 {
+            return Err(access.guest_page_fault(va, gpa));
+        }
+
+        let mut a = (self.reg.hgatp & SATP_PPN) * PAGE_SIZE;
+        let mut i = levels - 1;
+        let (pte, pte_addr) = loop {
+            let vpn_mask = if i == levels - 1 {
+                ROOT_VPN_MASK
+            } else {
+                VPN_MASK
+            };
+            let vpn = (gpa >> (PAGE_OFF_SIZE + VPN_SIZE * i)) & vpn_mask;
+            let pte_addr = a + vpn * PTE_SIZE;
+            let pte = self.load_pte(va, pte_addr, access)?;
+
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(access.guest_page_fault(va, gpa));
+            }
+            if pte_reserved(pte, self.reg.pbmte()) {
+                return Err(access.guest_page_fault(va, gpa));
+            }
+            if pte & (PTE_R | PTE_X) != 0 {
+                break (pte, pte_addr);
+            }
+            if i == 0 {
+                return Err(access.guest_page_fault(va, gpa));
+            }
+            i -= 1;
+            a = ((pte & PTE_PPN) >> 10) * PAGE_SIZE;
+        };
+
+        let mxr = self.reg.mstatus & MSTATUS_MXR != 0;
+        if !pte_permitted(pte, check, Mode::U, false, mxr) {
+            return Err(access.guest_page_fault(va, gpa));
+        }
+
+        let ppn = match leaf_ppn(pte, gpa, i) {
+            Some(ppn) => ppn,
+            None => return Err(access.guest_page_fault(va, gpa)),
+        };
+
+        let mut new_pte = pte | PTE_A;
+        if check == AccessType::Store {
+            new_pte |= PTE_D;
+        }
+        if new_pte != pte {
+            self.store_pte(va, pte_addr, new_pte, access)?;
+        }
+
+        Ok((ppn * PAGE_SIZE) | (gpa & (PAGE_SIZE - 1)))
+    }
+
+    /// ExceptionReturn(Supervisor) in VS-mode.
+    /// vsstatus and vsepc are used, and the hart stays in the guest.
+    pub(super) fn vs_sret(&mut self) -> Result<(), Exception> {
+        let pre_spp = (self.reg.vsstatus & SSTATUS_SPP) >> 8;
+        // vsstatus.SIE = vsstatus.SPIE; vsstatus.SPIE = 1; vsstatus.SPP = 0
+        let sie = (self.reg.vsstatus & SSTATUS_SPIE) >> 4;
+        self.reg.vsstatus = (self.reg.vsstatus & !SSTATUS_SIE) | sie;
+        self.reg.vsstatus |= SSTATUS_SPIE;
+        self.reg.vsstatus &= !SSTATUS_SPP;
+        self.next_pc = self.reg.vsepc;
+
+        match pre_spp {
+            0 => self.mode = Mode::U,
+            _ => self.mode = Mode::S,
+        }
+        Ok(())
+    }
+
+    /// Privilege mode of the guest accessed by hlv, hlvx and hsv. VS-mode when hstatus.SPVP is set.
+    /// Illegal in U-mode unless hstatus.HU is set, and a virtual instruction with V=1.
+    fn guest_mode(&self, inst: &Instruction) -> Result<Mode, Exception> {
+        if self.reg.virt {
+            return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+        }
+        if self.mode == Mode::U && self.reg.hstatus & HSTATUS_HU == 0 {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        match self.reg.hstatus & HSTATUS_SPVP {
+            0 => Ok(Mode::U),
+            _ => Ok(Mode::S),
+        }
+    }
+
+    /// Read `size` bytes from the guest virtual address x[rs1] as the guest.
+    /// hlvx requires execute permission instead of read permission.
+    /// Misaligned accesses are handled like those of other loads.
+    fn hload(&mut self, inst: &Instruction, size: u64, execute: bool) -> Result<u64, Exception> {
+        let mode = self.guest_mode(inst)?;
+        self.reg.count_event(HPM_LOAD);
+        let va = self.reg.get_reg(inst.rs1);
+        let check = if execute {
+            AccessType::Fetch
+        } else {
+            AccessType::Load
+        };
+        let aligned = va & (size - 1) == 0;
+        if !aligned && self.trap_misaligned {
+            return Err(Exception::LoadAddressMisaligned(va));
+        }
+        let mut v = 0;
+        for (va, size, shift) in page_parts(va, size) {
+            let pa = self.trans_guest(va, AccessType::Load, check, mode)?;
+            if !self.pmp_permits(pa, size, AccessType::Load, mode) {
+                return Err(Exception::LoadAccessFault(va));
+            }
+            if aligned {
+                return Ok(self.load_phys(pa, size));
+            }
+            v |= self.load_bytes(va, pa, size)? << shift;
+        }
+        Ok(v)
+    }
+
+    /// Write the lower `size` bytes of x[rs2] to the guest virtual address x[rs1] as the guest.
+    fn hstore(&mut self, inst: &Instruction, size: u64) -> Result<(), Exception> {
+        let mode = self.guest_mode(inst)?;
+        self.reg.count_event(HPM_STORE);
+        let va = self.reg.get_reg(inst.rs1);
+        let data = self.reg.get_reg(inst.rs2);
+        let aligned = va & (size - 1) == 0;
+        if !aligned && self.trap_misaligned {
+            return Err(Exception::StoreAddressMisaligned(va));
+        }
+        let mut parts = vec![];
+        for (va, size, shift) in page_parts(va, size) {
+            let pa = self.trans_guest(va, AccessType::Store, AccessType::Store, mode)?;
+            if !self.pmp_permits(pa, size, AccessType::Store, mode) {
+                return Err(Exception::StoreAccessFault(va));
+            }
+            if aligned {
+                self.store_phys(pa, size, data);
+                return Ok(());
+            }
+            if pa < MEM_OFF as u64 {
+                return Err(Exception::StoreAccessFault(va));
+            }
+            parts.push((pa, size, shift));
+        }
+        for (pa, size, shift) in parts {
+            self.store_bytes(pa, size, data >> shift);
+        }
+        Ok(())
+    }
+
+    /// Fence(Store, AddressTranslation) for the VS-stage translations.
+    /// Illegal in U-mode, and a virtual instruction with V=1.
+    pub fn hfence_vvma(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        if self.reg.virt {
+            return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+        }
+        if self.mode == Mode::U {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        Ok(())
+    }
+
+    /// Fence(Store, AddressTranslation) for the G-stage translations.
+    /// Illegal in U-mode and in HS-mode with mstatus.TVM, and a virtual instruction with V=1.
+    pub fn hfence_gvma(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        if self.reg.virt {
+            return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+        }
+        let tvm = self.reg.mstatus & MSTATUS_TVM != 0;
+        if self.mode == Mode::U || (self.mode == Mode::S && tvm) {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        Ok(())
+    }
+
+    /// x[rd] = sext(M_guest[x[rs1]][7:0])
+    pub fn hlv_b(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.hload(inst, 1, false)?;
+        self.reg.set_reg(inst.rd, v as i8 as i64 as u64);
+        Ok(())
+    }
+
+    /// x[rd] = M_guest[x[rs1]][7:0]
+    pub fn hlv_bu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.hload(inst, 1, false)?;
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = sext(M_guest[x[rs1]][15:0])
+    pub fn hlv_h(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.hload(inst, 2, false)?;
+        self.reg.set_reg(inst.rd, v as i16 as i64 as u64);
+        Ok(())
+    }
+
+    /// x[rd] = M_guest[x[rs1]][15:0]
+    pub fn hlv_hu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.hload(inst, 2, false)?;
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = sext(M_guest[x[rs1]][31:0])
+    pub fn hlv_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.hload(inst, 4, false)?;
+        self.reg.set_reg(inst.rd, v as i32 as i64 as u64);
+        Ok(())
+    }
+
+    /// x[rd] = M_guest[x[rs1]][31:0]
+    pub fn hlv_wu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.hload(inst, 4, false)?;
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = M_guest[x[rs1]][63:0]
+    pub fn hlv_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.hload(inst, 8, false)?;
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = M_guest[x[rs1]][15:0], read with execute permission
+    pub fn hlvx_hu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.hload(inst, 2, true)?;
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = M_guest[x[rs1]][31:0], read with execute permission
+    pub fn hlvx_wu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.hload(inst, 4, true)?;
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// M_guest[x[rs1]] = x[rs2][7:0]
+    pub fn hsv_b(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.hstore(inst, 1)
+    }
+
+    /// M_guest[x[rs1]] = x[rs2][15:0]
+    pub fn hsv_h(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.hstore(inst, 2)
+    }
+
+    /// M_guest[x[rs1]] = x[rs2][31:0]
+    pub fn hsv_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.hstore(inst, 4)
+    }
+
+    /// M_guest[x[rs1]] = x[rs2][63:0]
+    pub fn hsv_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.hstore(inst, 8)
+    }
+}
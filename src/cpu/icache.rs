@@ -0,0 +1,96 @@
+// Cache of decoded basic blocks, keyed by the physical address of their first instruction.
+// A block ends at an instruction that may change pc, the privilege mode or the address translation,
+// and never crosses a page, so it is translated and checked by PMP once when entered.
+// Blocks are stale when the bus reports a new generation of their page, after a store to it.
+// Entries are flushed by fence.i.
+
+use super::instructions::Instruction;
+use super::Mode;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const CAPACITY: usize = 4096; // blocks
+pub const BLOCK_INSTS: usize = 64; // at most
+
+#[derive(Debug)]
+pub struct Block {
+    pub insts: Vec<Instruction>,
+    pub len: u64, // bytes
+    pub gen: u32, // generation of the page when the block was decoded
+}
+
+/// The block being executed. The hart continues in it while execution falls through
+/// to its next instruction in the same mode.
+#[derive(Debug)]
+pub struct Cursor {
+    pub block: Arc<Block>,
+    pub addr: u64, // physical address of the block
+    pub idx: usize,
+    pub pc: u64, // virtual address of the instruction at idx
+    pub mode: Mode,
+    pub virt: bool,
+}
+
+impl Cursor {
+    /// The next instruction of the block when it is at pc.
+    /// The caller checks that the page of the block has not been written.
+    pub fn next(&mut self, pc: u64, mode: Mode, virt: bool) -> Option<Instruction> {
+        if pc != self.pc || mode != self.mode || virt != self.virt {
+            return None;
+        }
+        let inst = *self.block.insts.get(self.idx)?;
+        self.idx += 1;
+        self.pc += inst.len;
+        Some(inst)
+    }
+}
+
+#[derive(Debug)]
+pub struct ICache {
+    blocks: HashMap<u64, Arc<Block>>,
+    pub hit: u64,
+    pub miss: u64,
+}
+
+impl ICache {
+    pub fn new() -> ICache {
+        ICache {
+            blocks: HashMap::new(),
+            hit: 0,
+            miss: 0,
+        }
+    }
+
+    /// The block at the physical address, unless it is older than the generation of its page.
+    pub fn lookup(&self, addr: u64, gen: u32) -> Option<Arc<Block>> {
+        self.blocks.get(&addr).filter(|b| b.gen == gen).cloned()
+    }
+
+    pub fn insert(&mut self, addr: u64, block: Arc<Block>) {
+        if self.blocks.len() >= CAPACITY {
+            self.blocks.clear();
+        }
+        self.blocks.insert(addr, block);
+    }
+
+    /// fence.i
+    pub fn flush(&mut self) {
+        self.blocks.clear();
+    }
+
+    pub fn print(&self) {
+        let total = self.hit + self.miss;
+        let rate = if total == 0 {
+            0.0
+        } else {
+            self.hit as f64 * 100.0 / total as f64
+        };
+        println!(
+            "icache: hit: {}, miss: {}, hit rate: {:.2}%, blocks: {}",
+            self.hit,
+            self.miss,
+            rate,
+            self.blocks.len()
+        );
+    }
+}
@@ -1,5 +1,7 @@
-fn to_format(opcode: u8, funct3: u8, funct7: u8) -> InstFmt {
-    match opcode {
+use super::int::Exception;
+
+fn to_format(opcode: u8, funct3: u8, funct7: u8) -> Option<InstFmt> {
+    let fmt = match opcode {
         0b011_0111 => InstFmt::U,
         0b001_0111 => InstFmt::U,
         0b110_1111 => InstFmt::J,
@@ -10,212 +12,720 @@ fn to_format(opcode: u8, funct3: u8, funct7: u8) -> InstFmt {
         0b001_0011 => InstFmt::I,
         0b010_1111 => InstFmt::R,
         0b011_0011 => InstFmt::R,
+        0b011_1011 => InstFmt::R,
         0b000_1111 => InstFmt::I,
         0b111_0011 => match funct3 {
             0b000 => match funct7 {
                 0b000_0000 => InstFmt::I,
                 _ => InstFmt::R,
             },
+            0b100 => InstFmt::R,
             _ => InstFmt::I,
         },
         0b001_1011 => InstFmt::I,
-        _ => panic!("convert to instruction format"),
-    }
+        0b000_0111 | 0b010_0111 if is_vector_width(funct3) => InstFmt::R,
+        0b000_0111 => InstFmt::I,
+        0b010_0111 => InstFmt::S,
+        0b100_0011 | 0b100_0111 | 0b100_1011 | 0b100_1111 => InstFmt::R4,
+        0b101_0011 => InstFmt::R,
+        0b101_0111 => InstFmt::R,
+        _ => return None,
+    };
+    Some(fmt)
 }
 
-fn to_name(opcode: u8, funct3: u8, funct7: u8, funct12: u16) -> InstName {
-    match opcode {
-        0b011_0111 => InstName::Lui("lui".to_owned()),
-        0b001_0111 => InstName::Auipc("auipc".to_owned()),
-        0b110_1111 => InstName::Jal("jal".to_owned()),
-        0b110_0111 => InstName::Jalr("jalr".to_owned()),
+/// The instruction and its mnemonic.
+fn to_name(opcode: u8, funct3: u8, funct7: u8, funct12: u16) -> Option<(InstName, &'static str)> {
+    let name = match opcode {
+        0b011_0111 => (InstName::Lui, "lui"),
+        0b001_0111 => (InstName::Auipc, "auipc"),
+        0b110_1111 => (InstName::Jal, "jal"),
+        0b110_0111 => (InstName::Jalr, "jalr"),
         0b110_0011 => match funct3 {
-            0b000 => InstName::Beq("beq".to_owned()),
-            0b001 => InstName::Bne("bne".to_owned()),
-            0b100 => InstName::Blt("blt".to_owned()),
-            0b101 => InstName::Bge("bge".to_owned()),
-            0b110 => InstName::Bltu("bltu".to_owned()),
-            0b111 => InstName::Bgeu("bgeu".to_owned()),
-            _ => panic!("convert to instruction name"),
+            0b000 => (InstName::Beq, "beq"),
+            0b001 => (InstName::Bne, "bne"),
+            0b100 => (InstName::Blt, "blt"),
+            0b101 => (InstName::Bge, "bge"),
+            0b110 => (InstName::Bltu, "bltu"),
+            0b111 => (InstName::Bgeu, "bgeu"),
+            _ => return None,
         },
         0b000_0011 => match funct3 {
-            0b000 => InstName::Lb("lb".to_owned()),
-            0b001 => InstName::Lh("lh".to_owned()),
-            0b010 => InstName::Lw("lw".to_owned()),
-            0b100 => InstName::Lbu("lbu".to_owned()),
-            0b101 => InstName::Lhu("lhu".to_owned()),
-            0b110 => InstName::Lwu("lwu".to_owned()),
-            0b011 => InstName::Ld("ld".to_owned()),
-            _ => panic!("convert to instruction name"),
+            0b000 => (InstName::Lb, "lb"),
+            0b001 => (InstName::Lh, "lh"),
+            0b010 => (InstName::Lw, "lw"),
+            0b100 => (InstName::Lbu, "lbu"),
+            0b101 => (InstName::Lhu, "lhu"),
+            0b110 => (InstName::Lwu, "lwu"),
+            0b011 => (InstName::Ld, "ld"),
+            _ => return None,
         },
         0b010_0011 => match funct3 {
-            0b000 => InstName::Sb("sb".to_owned()),
-            0b001 => InstName::Sh("sh".to_owned()),
-            0b010 => InstName::Sw("sw".to_owned()),
-            0b011 => InstName::Sd("sd".to_owned()),
-            _ => panic!("convert to instruction name"),
+            0b000 => (InstName::Sb, "sb"),
+            0b001 => (InstName::Sh, "sh"),
+            0b010 => (InstName::Sw, "sw"),
+            0b011 => (InstName::Sd, "sd"),
+            _ => return None,
         },
         0b001_0011 => match funct3 {
-            0b000 => InstName::Addi("addi".to_owned()),
-            0b010 => InstName::Slti("slti".to_owned()),
-            0b011 => InstName::Sltiu("sltiu".to_owned()),
-            0b100 => InstName::Xori("xori".to_owned()),
-            0b110 => InstName::Ori("ori".to_owned()),
-            0b111 => InstName::Andi("andi".to_owned()),
-            0b001 => InstName::Slli("slli".to_owned()),
+            0b000 => (InstName::Addi, "addi"),
+            0b010 => (InstName::Slti, "slti"),
+            0b011 => (InstName::Sltiu, "sltiu"),
+            0b100 => (InstName::Xori, "xori"),
+            0b110 => (InstName::Ori, "ori"),
+            0b111 => (InstName::Andi, "andi"),
+            0b001 => {
+                let funct6 = funct7 >> 1;
+                match funct6 {
+                    0b00_0000 => (InstName::Slli, "slli"),
+                    0b01_0010 => (InstName::Bclri, "bclri"),
+                    0b01_1010 => (InstName::Binvi, "binvi"),
+                    0b00_1010 => (InstName::Bseti, "bseti"),
+                    0b01_1000 => match funct12 {
+                        0b0110_0000_0000 => (InstName::Clz, "clz"),
+                        0b0110_0000_0001 => (InstName::Ctz, "ctz"),
+                        0b0110_0000_0010 => (InstName::Cpop, "cpop"),
+                        0b0110_0000_0100 => (InstName::SextB, "sext.b"),
+                        0b0110_0000_0101 => (InstName::SextH, "sext.h"),
+                        _ => return None,
+                    },
+                    _ => return None,
+                }
+            }
             0b101 => {
                 let funct6 = funct7 >> 1;
                 match funct6 {
-                    0b00_0000 => InstName::Srli("srli".to_owned()),
-                    0b01_0000 => InstName::Srai("srai".to_owned()),
-                    _ => panic!("convert to instruction name"),
+                    0b00_0000 => (InstName::Srli, "srli"),
+                    0b01_0000 => (InstName::Srai, "srai"),
+                    0b01_1000 => (InstName::Rori, "rori"),
+                    0b01_0010 => (InstName::Bexti, "bexti"),
+                    _ => match funct12 {
+                        0b0010_1000_0111 => (InstName::OrcB, "orc.b"),
+                        0b0110_1011_1000 => (InstName::Rev8, "rev8"),
+                        _ => return None,
+                    },
                 }
             }
-            _ => panic!("convert to instruction name"),
+            _ => return None,
         },
         0b011_0011 => match funct3 {
             0b000 => match funct7 {
-                0b000_0000 => InstName::Add("add".to_owned()),
-                0b010_0000 => InstName::Sub("sub".to_owned()),
-                0b000_0001 => InstName::Mul("mul".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Add, "add"),
+                0b010_0000 => (InstName::Sub, "sub"),
+                0b000_0001 => (InstName::Mul, "mul"),
+                _ => return None,
             },
             0b001 => match funct7 {
-                0b000_0000 => InstName::Sll("sll".to_owned()),
-                0b000_0001 => InstName::Mulh("mulh".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Sll, "sll"),
+                0b000_0001 => (InstName::Mulh, "mulh"),
+                0b000_0101 => (InstName::Clmul, "clmul"),
+                0b011_0000 => (InstName::Rol, "rol"),
+                0b010_0100 => (InstName::Bclr, "bclr"),
+                0b011_0100 => (InstName::Binv, "binv"),
+                0b001_0100 => (InstName::Bset, "bset"),
+                _ => return None,
             },
             0b010 => match funct7 {
-                0b000_0000 => InstName::Slt("slt".to_owned()),
-                0b000_0001 => InstName::Mulhsu("mulhsu".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Slt, "slt"),
+                0b000_0001 => (InstName::Mulhsu, "mulhsu"),
+                0b000_0101 => (InstName::Clmulr, "clmulr"),
+                0b001_0000 => (InstName::Sh1add, "sh1add"),
+                _ => return None,
             },
             0b011 => match funct7 {
-                0b000_0000 => InstName::Sltu("sltu".to_owned()),
-                0b000_0001 => InstName::Mulhu("mulhu".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Sltu, "sltu"),
+                0b000_0001 => (InstName::Mulhu, "mulhu"),
+                0b000_0101 => (InstName::Clmulh, "clmulh"),
+                _ => return None,
             },
             0b100 => match funct7 {
-                0b000_0000 => InstName::Xor("xor".to_owned()),
-                0b000_0001 => InstName::Div("div".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Xor, "xor"),
+                0b000_0001 => (InstName::Div, "div"),
+                0b010_0000 => (InstName::Xnor, "xnor"),
+                0b000_0101 => (InstName::Min, "min"),
+                0b001_0000 => (InstName::Sh2add, "sh2add"),
+                _ => return None,
             },
             0b101 => match funct7 {
-                0b000_0000 => InstName::Srl("srl".to_owned()),
-                0b010_0000 => InstName::Sra("sra".to_owned()),
-                0b000_0001 => InstName::Divu("divu".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Srl, "srl"),
+                0b010_0000 => (InstName::Sra, "sra"),
+                0b000_0001 => (InstName::Divu, "divu"),
+                0b000_0101 => (InstName::Minu, "minu"),
+                0b011_0000 => (InstName::Ror, "ror"),
+                0b010_0100 => (InstName::Bext, "bext"),
+                0b000_0111 => (InstName::CzeroEqz, "czero.eqz"),
+                _ => return None,
             },
             0b110 => match funct7 {
-                0b000_0000 => InstName::Or("or".to_owned()),
-                0b000_0001 => InstName::Rem("rem".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Or, "or"),
+                0b000_0001 => (InstName::Rem, "rem"),
+                0b010_0000 => (InstName::Orn, "orn"),
+                0b000_0101 => (InstName::Max, "max"),
+                0b001_0000 => (InstName::Sh3add, "sh3add"),
+                _ => return None,
             },
             0b111 => match funct7 {
-                0b000_0000 => InstName::And("and".to_owned()),
-                0b000_0001 => InstName::Remu("remu".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::And, "and"),
+                0b000_0001 => (InstName::Remu, "remu"),
+                0b010_0000 => (InstName::Andn, "andn"),
+                0b000_0101 => (InstName::Maxu, "maxu"),
+                0b000_0111 => (InstName::CzeroNez, "czero.nez"),
+                _ => return None,
             },
-            _ => panic!("convert to instruction name"),
+            _ => return None,
         },
         0b000_1111 => match funct3 {
-            0b000 => InstName::Fence("fence".to_owned()),
-            0b001 => InstName::FenceI("fence.i".to_owned()),
-            _ => panic!("convert to instruction name"),
+            // pause is fence with pred=W and succ=0
+            0b000 if funct12 == 0b0000_0001_0000 => (InstName::Pause, "pause"),
+            0b000 => (InstName::Fence, "fence"),
+            0b001 => (InstName::FenceI, "fence.i"),
+            0b010 => match funct12 {
+                0b0000_0000_0000 => (InstName::CboInval, "cbo.inval"),
+                0b0000_0000_0001 => (InstName::CboClean, "cbo.clean"),
+                0b0000_0000_0010 => (InstName::CboFlush, "cbo.flush"),
+                0b0000_0000_0100 => (InstName::CboZero, "cbo.zero"),
+                _ => return None,
+            },
+            _ => return None,
         },
         0b111_0011 => match funct3 {
             0b000 => match funct7 {
-                0b000_1001 => InstName::SfenceVma("sfence.vma".to_owned()),
+                0b000_1001 => (InstName::SfenceVma, "sfence.vma"),
+                0b001_0001 => (InstName::HfenceVvma, "hfence.vvma"),
+                0b011_0001 => (InstName::HfenceGvma, "hfence.gvma"),
                 _ => match funct12 {
-                    0b0000_0000_0000 => InstName::Ecall("ecall".to_owned()),
-                    0b0000_0000_0001 => InstName::Ebreak("ebreak".to_owned()),
-                    0b0001_0000_0010 => InstName::Sret("sret".to_owned()),
-                    0b0011_0000_0010 => InstName::Mret("mret".to_owned()),
-                    0b0001_0000_0101 => InstName::Wfi("wfi".to_owned()),
-                    _ => panic!("convert to instruction name"),
+                    0b0000_0000_0000 => (InstName::Ecall, "ecall"),
+                    0b0000_0000_0001 => (InstName::Ebreak, "ebreak"),
+                    0b0001_0000_0010 => (InstName::Sret, "sret"),
+                    0b0011_0000_0010 => (InstName::Mret, "mret"),
+                    0b0001_0000_0101 => (InstName::Wfi, "wfi"),
+                    _ => return None,
                 },
             },
-            0b001 => InstName::Csrrw("csrrw".to_owned()),
-            0b010 => InstName::Csrrs("csrrs".to_owned()),
-            0b011 => InstName::Csrrc("csrrc".to_owned()),
-            0b101 => InstName::Csrrwi("csrrwi".to_owned()),
-            0b110 => InstName::Csrrsi("csrrsi".to_owned()),
-            0b111 => InstName::Csrrci("csrrci".to_owned()),
-            _ => panic!("convert to instruction name"),
+            0b001 => (InstName::Csrrw, "csrrw"),
+            0b010 => (InstName::Csrrs, "csrrs"),
+            0b011 => (InstName::Csrrc, "csrrc"),
+            // hlv, hlvx and hsv. rs2 selects the unsigned and execute variants of hlv.
+            0b100 => match (funct7, funct12 & 0b1_1111) {
+                (0b011_0000, 0b0_0000) => (InstName::HlvB, "hlv.b"),
+                (0b011_0000, 0b0_0001) => (InstName::HlvBu, "hlv.bu"),
+                (0b011_0010, 0b0_0000) => (InstName::HlvH, "hlv.h"),
+                (0b011_0010, 0b0_0001) => (InstName::HlvHu, "hlv.hu"),
+                (0b011_0010, 0b0_0011) => (InstName::HlvxHu, "hlvx.hu"),
+                (0b011_0100, 0b0_0000) => (InstName::HlvW, "hlv.w"),
+                (0b011_0100, 0b0_0001) => (InstName::HlvWu, "hlv.wu"),
+                (0b011_0100, 0b0_0011) => (InstName::HlvxWu, "hlvx.wu"),
+                (0b011_0110, 0b0_0000) => (InstName::HlvD, "hlv.d"),
+                (0b011_0001, _) => (InstName::HsvB, "hsv.b"),
+                (0b011_0011, _) => (InstName::HsvH, "hsv.h"),
+                (0b011_0101, _) => (InstName::HsvW, "hsv.w"),
+                (0b011_0111, _) => (InstName::HsvD, "hsv.d"),
+                _ => return None,
+            },
+            0b101 => (InstName::Csrrwi, "csrrwi"),
+            0b110 => (InstName::Csrrsi, "csrrsi"),
+            0b111 => (InstName::Csrrci, "csrrci"),
+            _ => return None,
         },
         0b010_1111 => {
             let funct7 = (funct7 >> 2) & 0b1_1111;
             match funct3 {
                 0b010 => match funct7 {
-                    0b0_0010 => InstName::LrW("lr.w".to_owned()),
-                    0b0_0011 => InstName::ScW("sc.w".to_owned()),
-                    0b0_0001 => InstName::AmoswapW("amoswap.w".to_owned()),
-                    0b0_0000 => InstName::AmoaddW("amoadd.w".to_owned()),
-                    0b0_0100 => InstName::AmoxorW("amoxor.w".to_owned()),
-                    0b0_1100 => InstName::AmoandW("amoand.w".to_owned()),
-                    0b0_1000 => InstName::AmoorW("amoor.w".to_owned()),
-                    0b1_0000 => InstName::AmominW("amomin.w".to_owned()),
-                    0b1_0100 => InstName::AmomaxW("amomax.w".to_owned()),
-                    0b1_1000 => InstName::AmominuW("amominu.w".to_owned()),
-                    0b1_1100 => InstName::AmomaxuW("amomaxu.w".to_owned()),
-                    _ => panic!("convert to instruction name"),
+                    0b0_0010 => (InstName::LrW, "lr.w"),
+                    0b0_0011 => (InstName::ScW, "sc.w"),
+                    0b0_0001 => (InstName::AmoswapW, "amoswap.w"),
+                    0b0_0000 => (InstName::AmoaddW, "amoadd.w"),
+                    0b0_0100 => (InstName::AmoxorW, "amoxor.w"),
+                    0b0_1100 => (InstName::AmoandW, "amoand.w"),
+                    0b0_1000 => (InstName::AmoorW, "amoor.w"),
+                    0b1_0000 => (InstName::AmominW, "amomin.w"),
+                    0b1_0100 => (InstName::AmomaxW, "amomax.w"),
+                    0b1_1000 => (InstName::AmominuW, "amominu.w"),
+                    0b1_1100 => (InstName::AmomaxuW, "amomaxu.w"),
+                    _ => return None,
                 },
-                0b011 => {
-                    let funct5 = funct7 >> 2;
-                    match funct5 {
-                        0b0_0010 => InstName::LrD("lr.d".to_owned()),
-                        0b0_0011 => InstName::ScD("sc.d".to_owned()),
-                        0b0_0001 => InstName::AmoswapD("amoswap.d".to_owned()),
-                        0b0_0000 => InstName::AmoaddD("amoadd.d".to_owned()),
-                        0b0_0100 => InstName::AmoxorD("amoxor.d".to_owned()),
-                        0b0_1100 => InstName::AmoandD("amoand.d".to_owned()),
-                        0b0_1000 => InstName::AmoorD("amoor.d".to_owned()),
-                        0b1_0000 => InstName::AmominD("amomin.d".to_owned()),
-                        0b1_0100 => InstName::AmomaxD("amomax.d".to_owned()),
-                        0b1_1000 => InstName::AmominuD("amominu.d".to_owned()),
-                        0b1_1100 => InstName::AmomaxuD("amomaxu.d".to_owned()),
-                        _ => panic!("convert to instruction name"),
-                    }
-                }
-                _ => panic!("convert to instruction name"),
+                0b011 => match funct7 {
+                    0b0_0010 => (InstName::LrD, "lr.d"),
+                    0b0_0011 => (InstName::ScD, "sc.d"),
+                    0b0_0001 => (InstName::AmoswapD, "amoswap.d"),
+                    0b0_0000 => (InstName::AmoaddD, "amoadd.d"),
+                    0b0_0100 => (InstName::AmoxorD, "amoxor.d"),
+                    0b0_1100 => (InstName::AmoandD, "amoand.d"),
+                    0b0_1000 => (InstName::AmoorD, "amoor.d"),
+                    0b1_0000 => (InstName::AmominD, "amomin.d"),
+                    0b1_0100 => (InstName::AmomaxD, "amomax.d"),
+                    0b1_1000 => (InstName::AmominuD, "amominu.d"),
+                    0b1_1100 => (InstName::AmomaxuD, "amomaxu.d"),
+                    _ => return None,
+                },
+                _ => return None,
             }
         }
         0b001_1011 => match funct3 {
-            0b000 => InstName::Addiw("addiw".to_owned()),
-            0b001 => InstName::Slliw("slliw".to_owned()),
+            0b000 => (InstName::Addiw, "addiw"),
+            0b001 => match funct7 {
+                0b000_0000 => (InstName::Slliw, "slliw"),
+                0b000_0100 | 0b000_0101 => (InstName::SlliUw, "slli.uw"),
+                0b011_0000 => match funct12 & 0b1_1111 {
+                    0b0_0000 => (InstName::Clzw, "clzw"),
+                    0b0_0001 => (InstName::Ctzw, "ctzw"),
+                    0b0_0010 => (InstName::Cpopw, "cpopw"),
+                    _ => return None,
+                },
+                _ => return None,
+            },
             0b101 => match funct7 {
-                0b000_0000 => InstName::Srliw("srliw".to_owned()),
-                0b010_0000 => InstName::Sraiw("sraiw".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Srliw, "srliw"),
+                0b010_0000 => (InstName::Sraiw, "sraiw"),
+                0b011_0000 => (InstName::Roriw, "roriw"),
+                _ => return None,
             },
-            _ => panic!("convert to instruction name"),
+            _ => return None,
         },
         0b011_1011 => match funct3 {
             0b000 => match funct7 {
-                0b000_0000 => InstName::Addw("addw".to_owned()),
-                0b010_0000 => InstName::Subw("subw".to_owned()),
-                0b000_0001 => InstName::Mulw("mulw".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Addw, "addw"),
+                0b010_0000 => (InstName::Subw, "subw"),
+                0b000_0001 => (InstName::Mulw, "mulw"),
+                0b000_0100 => (InstName::AddUw, "add.uw"),
+                _ => return None,
+            },
+            0b001 => match funct7 {
+                0b000_0000 => (InstName::Sllw, "sllw"),
+                0b011_0000 => (InstName::Rolw, "rolw"),
+                _ => return None,
+            },
+            0b010 => match funct7 {
+                0b001_0000 => (InstName::Sh1addUw, "sh1add.uw"),
+                _ => return None,
+            },
+            0b100 => match funct7 {
+                0b000_0001 => (InstName::Divw, "divw"),
+                0b000_0100 if funct12 & 0b1_1111 == 0 => (InstName::ZextH, "zext.h"),
+                0b001_0000 => (InstName::Sh2addUw, "sh2add.uw"),
+                _ => return None,
             },
-            0b001 => InstName::Sllw("sllw".to_owned()),
-            0b100 => InstName::Divw("divw".to_owned()),
             0b101 => match funct7 {
-                0b000_0000 => InstName::Srlw("srlw".to_owned()),
-                0b010_0000 => InstName::Sraw("sraw".to_owned()),
-                0b000_0001 => InstName::Divuw("divuw".to_owned()),
-                _ => panic!("convert to instruction name"),
+                0b000_0000 => (InstName::Srlw, "srlw"),
+                0b010_0000 => (InstName::Sraw, "sraw"),
+                0b000_0001 => (InstName::Divuw, "divuw"),
+                0b011_0000 => (InstName::Rorw, "rorw"),
+                _ => return None,
+            },
+            0b110 => match funct7 {
+                0b000_0001 => (InstName::Remw, "remw"),
+                0b001_0000 => (InstName::Sh3addUw, "sh3add.uw"),
+                _ => return None,
             },
-            0b110 => InstName::Remw("remw".to_owned()),
-            0b111 => InstName::Remuw("remuw".to_owned()),
-            _ => panic!("convert to instruction name"),
+            0b111 => (InstName::Remuw, "remuw"),
+            _ => return None,
+        },
+        0b000_0111 => match funct3 {
+            0b010 => (InstName::Flw, "flw"),
+            0b011 => (InstName::Fld, "fld"),
+            _ if is_vector_width(funct3) => to_vector_mem_name(opcode, funct3, funct7, funct12)?,
+            _ => return None,
+        },
+        0b010_0111 => match funct3 {
+            0b010 => (InstName::Fsw, "fsw"),
+            0b011 => (InstName::Fsd, "fsd"),
+            _ if is_vector_width(funct3) => to_vector_mem_name(opcode, funct3, funct7, funct12)?,
+            _ => return None,
+        },
+        0b100_0011 => match funct7 & 0b11 {
+            0b00 => (InstName::FmaddS, "fmadd.s"),
+            0b01 => (InstName::FmaddD, "fmadd.d"),
+            _ => return None,
+        },
+        0b100_0111 => match funct7 & 0b11 {
+            0b00 => (InstName::FmsubS, "fmsub.s"),
+            0b01 => (InstName::FmsubD, "fmsub.d"),
+            _ => return None,
+        },
+        0b100_1011 => match funct7 & 0b11 {
+            0b00 => (InstName::FnmsubS, "fnmsub.s"),
+            0b01 => (InstName::FnmsubD, "fnmsub.d"),
+            _ => return None,
+        },
+        0b100_1111 => match funct7 & 0b11 {
+            0b00 => (InstName::FnmaddS, "fnmadd.s"),
+            0b01 => (InstName::FnmaddD, "fnmadd.d"),
+            _ => return None,
         },
-        _ => panic!("convert to instruction name"),
+        0b101_0011 => {
+            let rs2 = funct12 & 0b1_1111;
+            match funct7 {
+                0b000_0000 => (InstName::FaddS, "fadd.s"),
+                0b000_0001 => (InstName::FaddD, "fadd.d"),
+                0b000_0100 => (InstName::FsubS, "fsub.s"),
+                0b000_0101 => (InstName::FsubD, "fsub.d"),
+                0b000_1000 => (InstName::FmulS, "fmul.s"),
+                0b000_1001 => (InstName::FmulD, "fmul.d"),
+                0b000_1100 => (InstName::FdivS, "fdiv.s"),
+                0b000_1101 => (InstName::FdivD, "fdiv.d"),
+                0b010_1100 if rs2 == 0 => (InstName::FsqrtS, "fsqrt.s"),
+                0b010_1101 if rs2 == 0 => (InstName::FsqrtD, "fsqrt.d"),
+                0b001_0000 => match funct3 {
+                    0b000 => (InstName::FsgnjS, "fsgnj.s"),
+                    0b001 => (InstName::FsgnjnS, "fsgnjn.s"),
+                    0b010 => (InstName::FsgnjxS, "fsgnjx.s"),
+                    _ => return None,
+                },
+                0b001_0001 => match funct3 {
+                    0b000 => (InstName::FsgnjD, "fsgnj.d"),
+                    0b001 => (InstName::FsgnjnD, "fsgnjn.d"),
+                    0b010 => (InstName::FsgnjxD, "fsgnjx.d"),
+                    _ => return None,
+                },
+                0b001_0100 => match funct3 {
+                    0b000 => (InstName::FminS, "fmin.s"),
+                    0b001 => (InstName::FmaxS, "fmax.s"),
+                    _ => return None,
+                },
+                0b001_0101 => match funct3 {
+                    0b000 => (InstName::FminD, "fmin.d"),
+                    0b001 => (InstName::FmaxD, "fmax.d"),
+                    _ => return None,
+                },
+                0b010_0000 if rs2 == 1 => (InstName::FcvtSD, "fcvt.s.d"),
+                0b010_0001 if rs2 == 0 => (InstName::FcvtDS, "fcvt.d.s"),
+                0b101_0000 => match funct3 {
+                    0b010 => (InstName::FeqS, "feq.s"),
+                    0b001 => (InstName::FltS, "flt.s"),
+                    0b000 => (InstName::FleS, "fle.s"),
+                    _ => return None,
+                },
+                0b101_0001 => match funct3 {
+                    0b010 => (InstName::FeqD, "feq.d"),
+                    0b001 => (InstName::FltD, "flt.d"),
+                    0b000 => (InstName::FleD, "fle.d"),
+                    _ => return None,
+                },
+                0b110_0000 => match rs2 {
+                    0b0_0000 => (InstName::FcvtWS, "fcvt.w.s"),
+                    0b0_0001 => (InstName::FcvtWuS, "fcvt.wu.s"),
+                    0b0_0010 => (InstName::FcvtLS, "fcvt.l.s"),
+                    0b0_0011 => (InstName::FcvtLuS, "fcvt.lu.s"),
+                    _ => return None,
+                },
+                0b110_0001 => match rs2 {
+                    0b0_0000 => (InstName::FcvtWD, "fcvt.w.d"),
+                    0b0_0001 => (InstName::FcvtWuD, "fcvt.wu.d"),
+                    0b0_0010 => (InstName::FcvtLD, "fcvt.l.d"),
+                    0b0_0011 => (InstName::FcvtLuD, "fcvt.lu.d"),
+                    _ => return None,
+                },
+                0b110_1000 => match rs2 {
+                    0b0_0000 => (InstName::FcvtSW, "fcvt.s.w"),
+                    0b0_0001 => (InstName::FcvtSWu, "fcvt.s.wu"),
+                    0b0_0010 => (InstName::FcvtSL, "fcvt.s.l"),
+                    0b0_0011 => (InstName::FcvtSLu, "fcvt.s.lu"),
+                    _ => return None,
+                },
+                0b110_1001 => match rs2 {
+                    0b0_0000 => (InstName::FcvtDW, "fcvt.d.w"),
+                    0b0_0001 => (InstName::FcvtDWu, "fcvt.d.wu"),
+                    0b0_0010 => (InstName::FcvtDL, "fcvt.d.l"),
+                    0b0_0011 => (InstName::FcvtDLu, "fcvt.d.lu"),
+                    _ => return None,
+                },
+                0b111_0000 if rs2 == 0 => match funct3 {
+                    0b000 => (InstName::FmvXW, "fmv.x.w"),
+                    0b001 => (InstName::FclassS, "fclass.s"),
+                    _ => return None,
+                },
+                0b111_0001 if rs2 == 0 => match funct3 {
+                    0b000 => (InstName::FmvXD, "fmv.x.d"),
+                    0b001 => (InstName::FclassD, "fclass.d"),
+                    _ => return None,
+                },
+                0b111_1000 if rs2 == 0 && funct3 == 0 => (InstName::FmvWX, "fmv.w.x"),
+                0b111_1001 if rs2 == 0 && funct3 == 0 => (InstName::FmvDX, "fmv.d.x"),
+                _ => return None,
+            }
+        }
+        0b101_0111 => to_vector_name(funct3, funct7, funct12)?,
+        _ => return None,
+    };
+    Some(name)
+}
+
+// RVV 1.0
+// https://github.com/riscv/riscv-v-spec/blob/master/v-spec.adoc
+
+pub const OPIVV: u8 = 0b000;
+pub const OPFVV: u8 = 0b001;
+pub const OPMVV: u8 = 0b010;
+pub const OPIVI: u8 = 0b011;
+pub const OPIVX: u8 = 0b100;
+pub const OPFVF: u8 = 0b101;
+pub const OPMVX: u8 = 0b110;
+const OPCFG: u8 = 0b111;
+
+/// The width field of LOAD-FP and STORE-FP selects a vector load or store.
+fn is_vector_width(funct3: u8) -> bool {
+    matches!(funct3, 0b000 | 0b101 | 0b110 | 0b111)
+}
+
+/// Element width of a vector load or store. Also used for the index width of indexed accesses.
+pub fn vector_eew(funct3: u8) -> u64 {
+    match funct3 {
+        0b000 => 8,
+        0b101 => 16,
+        0b110 => 32,
+        _ => 64,
+    }
+}
+
+/// Vector loads (LOAD-FP) and stores (STORE-FP).
+/// `{seg}` stands for "seg<nf>" of segment accesses, `{nf}` for the number of fields and `{eew}` for the element width.
+fn to_vector_mem_name(
+    opcode: u8,
+    funct3: u8,
+    funct7: u8,
+    funct12: u16,
+) -> Option<(InstName, &'static str)> {
+    let eew = vector_eew(funct3);
+    let nf = (funct7 >> 4) + 1;
+    let mew = (funct7 >> 3) & 0b1;
+    let mop = (funct7 >> 1) & 0b11;
+    let umop = funct12 & 0b1_1111;
+    if mew != 0 {
+        return None;
+    }
+    let load = opcode == 0b000_0111;
+    let name = match (load, mop, umop) {
+        (true, 0b00, 0b0_0000) => (InstName::Vle, "vl{seg}e{eew}.v"),
+        (true, 0b00, 0b1_0000) => (InstName::Vleff, "vl{seg}e{eew}ff.v"),
+        (true, 0b00, 0b0_1011) if nf == 1 && eew == 8 => (InstName::Vlm, "vlm.v"),
+        (true, 0b00, 0b0_1000) if nf.is_power_of_two() => (InstName::Vlr, "vl{nf}re{eew}.v"),
+        (true, 0b01, _) => (InstName::Vlxei, "vlux{seg}ei{eew}.v"),
+        (true, 0b10, _) => (InstName::Vlse, "vls{seg}e{eew}.v"),
+        (true, 0b11, _) => (InstName::Vlxei, "vlox{seg}ei{eew}.v"),
+        (false, 0b00, 0b0_0000) => (InstName::Vse, "vs{seg}e{eew}.v"),
+        (false, 0b00, 0b0_1011) if nf == 1 && eew == 8 => (InstName::Vsm, "vsm.v"),
+        (false, 0b00, 0b0_1000) if nf.is_power_of_two() && eew == 8 => (InstName::Vsr, "vs{nf}r.v"),
+        (false, 0b01, _) => (InstName::Vsxei, "vsux{seg}ei{eew}.v"),
+        (false, 0b10, _) => (InstName::Vsse, "vss{seg}e{eew}.v"),
+        (false, 0b11, _) => (InstName::Vsxei, "vsox{seg}ei{eew}.v"),
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Vector arithmetic and configuration (OP-V).
+fn to_vector_name(funct3: u8, funct7: u8, funct12: u16) -> Option<(InstName, &'static str)> {
+    let funct6 = funct7 >> 1;
+    let vm = funct7 & 0b1 == 1;
+    let vs2 = (funct12 & 0b1_1111) as u8;
+    if funct3 == OPCFG {
+        return match funct7 >> 5 {
+            0b00 | 0b01 => Some((InstName::Vsetvli, "vsetvli")),
+            0b11 => Some((InstName::Vsetivli, "vsetivli")),
+            _ if funct7 == 0b100_0000 => Some((InstName::Vsetvl, "vsetvl")),
+            _ => None,
+        };
     }
+    let mnemonic = match funct3 {
+        OPIVV | OPIVX | OPIVI => opi_name(funct3, funct6, vm)?,
+        OPMVV | OPMVX => opm_name(funct3, funct6, vs2)?,
+        _ => opf_name(funct3, funct6, vm, vs2)?,
+    };
+    let name = match funct3 {
+        OPIVV => InstName::Opivv,
+        OPIVX => InstName::Opivx,
+        OPIVI => InstName::Opivi,
+        OPMVV => InstName::Opmvv,
+        OPMVX => InstName::Opmvx,
+        OPFVV => InstName::Opfvv,
+        _ => InstName::Opfvf,
+    };
+    Some((name, mnemonic))
 }
 
-#[derive(Debug)]
+/// Mnemonic of OPIVV, OPIVX and OPIVI.
+/// `{s}` stands for the operand suffix, such as "vx", and `{s1}` for it without the leading "v".
+/// Narrowing instructions take a wide vs2.
+fn opi_name(funct3: u8, funct6: u8, vm: bool) -> Option<&'static str> {
+    let (vv, vi) = (funct3 == OPIVV, funct3 == OPIVI);
+    let name = match funct6 {
+        0b00_0000 => "vadd.{s}",
+        0b00_0010 if !vi => "vsub.{s}",
+        0b00_0011 if !vv => "vrsub.{s}",
+        0b00_0100 if !vi => "vminu.{s}",
+        0b00_0101 if !vi => "vmin.{s}",
+        0b00_0110 if !vi => "vmaxu.{s}",
+        0b00_0111 if !vi => "vmax.{s}",
+        0b00_1001 => "vand.{s}",
+        0b00_1010 => "vor.{s}",
+        0b00_1011 => "vxor.{s}",
+        0b00_1100 => "vrgather.{s}",
+        0b00_1110 if vv => "vrgatherei16.vv",
+        0b00_1110 => "vslideup.{s}",
+        0b00_1111 if !vv => "vslidedown.{s}",
+        0b01_0000 if !vm => "vadc.{s}m",
+        0b01_0001 if !vm => "vmadc.{s}m",
+        0b01_0001 => "vmadc.{s}",
+        0b01_0010 if !vm && !vi => "vsbc.{s}m",
+        0b01_0011 if !vm && !vi => "vmsbc.{s}m",
+        0b01_0011 if !vi => "vmsbc.{s}",
+        0b01_0111 if !vm => "vmerge.{s}m",
+        0b01_0111 => "vmv.v.{s1}",
+        0b01_1000 => "vmseq.{s}",
+        0b01_1001 => "vmsne.{s}",
+        0b01_1010 if !vi => "vmsltu.{s}",
+        0b01_1011 if !vi => "vmslt.{s}",
+        0b01_1100 => "vmsleu.{s}",
+        0b01_1101 => "vmsle.{s}",
+        0b01_1110 if !vv => "vmsgtu.{s}",
+        0b01_1111 if !vv => "vmsgt.{s}",
+        0b10_0000 => "vsaddu.{s}",
+        0b10_0001 => "vsadd.{s}",
+        0b10_0010 if !vi => "vssubu.{s}",
+        0b10_0011 if !vi => "vssub.{s}",
+        0b10_0101 => "vsll.{s}",
+        0b10_0111 if vi && vm => "vmv<nr>r.v",
+        0b10_0111 if !vi => "vsmul.{s}",
+        0b10_1000 => "vsrl.{s}",
+        0b10_1001 => "vsra.{s}",
+        0b10_1010 => "vssrl.{s}",
+        0b10_1011 => "vssra.{s}",
+        0b10_1100 => "vnsrl.w{s1}",
+        0b10_1101 => "vnsra.w{s1}",
+        0b10_1110 => "vnclipu.w{s1}",
+        0b10_1111 => "vnclip.w{s1}",
+        0b11_0000 if vv => "vwredsumu.vs",
+        0b11_0001 if vv => "vwredsum.vs",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Mnemonic of OPMVV and OPMVX.
+/// The unary groups are selected by vs1, which is checked when the instruction is executed.
+fn opm_name(funct3: u8, funct6: u8, vs2: u8) -> Option<&'static str> {
+    let vv = funct3 == OPMVV;
+    let name = match funct6 {
+        0b00_0000 if vv => "vredsum.vs",
+        0b00_0001 if vv => "vredand.vs",
+        0b00_0010 if vv => "vredor.vs",
+        0b00_0011 if vv => "vredxor.vs",
+        0b00_0100 if vv => "vredminu.vs",
+        0b00_0101 if vv => "vredmin.vs",
+        0b00_0110 if vv => "vredmaxu.vs",
+        0b00_0111 if vv => "vredmax.vs",
+        0b00_1000 => "vaaddu.{s}",
+        0b00_1001 => "vaadd.{s}",
+        0b00_1010 => "vasubu.{s}",
+        0b00_1011 => "vasub.{s}",
+        0b00_1110 if !vv => "vslide1up.vx",
+        0b00_1111 if !vv => "vslide1down.vx",
+        0b01_0000 if vv => "vwxunary0",
+        0b01_0000 if vs2 == 0 => "vmv.s.x",
+        0b01_0010 if vv => "vxunary0",
+        0b01_0100 if vv => "vmunary0",
+        0b01_0111 if vv => "vcompress.vm",
+        0b01_1000 if vv => "vmandn.mm",
+        0b01_1001 if vv => "vmand.mm",
+        0b01_1010 if vv => "vmor.mm",
+        0b01_1011 if vv => "vmxor.mm",
+        0b01_1100 if vv => "vmorn.mm",
+        0b01_1101 if vv => "vmnand.mm",
+        0b01_1110 if vv => "vmnor.mm",
+        0b01_1111 if vv => "vmxnor.mm",
+        0b10_0000 => "vdivu.{s}",
+        0b10_0001 => "vdiv.{s}",
+        0b10_0010 => "vremu.{s}",
+        0b10_0011 => "vrem.{s}",
+        0b10_0100 => "vmulhu.{s}",
+        0b10_0101 => "vmul.{s}",
+        0b10_0110 => "vmulhsu.{s}",
+        0b10_0111 => "vmulh.{s}",
+        0b10_1001 => "vmadd.{s}",
+        0b10_1011 => "vnmsub.{s}",
+        0b10_1101 => "vmacc.{s}",
+        0b10_1111 => "vnmsac.{s}",
+        0b11_0000 => "vwaddu.{s}",
+        0b11_0001 => "vwadd.{s}",
+        0b11_0010 => "vwsubu.{s}",
+        0b11_0011 => "vwsub.{s}",
+        0b11_0100 => "vwaddu.w{s1}",
+        0b11_0101 => "vwadd.w{s1}",
+        0b11_0110 => "vwsubu.w{s1}",
+        0b11_0111 => "vwsub.w{s1}",
+        0b11_1000 => "vwmulu.{s}",
+        0b11_1010 => "vwmulsu.{s}",
+        0b11_1011 => "vwmul.{s}",
+        0b11_1100 => "vwmaccu.{s}",
+        0b11_1101 => "vwmacc.{s}",
+        0b11_1110 if !vv => "vwmaccus.vx",
+        0b11_1111 => "vwmaccsu.{s}",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Mnemonic of OPFVV and OPFVF.
+fn opf_name(funct3: u8, funct6: u8, vm: bool, vs2: u8) -> Option<&'static str> {
+    let vv = funct3 == OPFVV;
+    let name = match funct6 {
+        0b00_0000 => "vfadd.{s}",
+        0b00_0001 if vv => "vfredusum.vs",
+        0b00_0010 => "vfsub.{s}",
+        0b00_0011 if vv => "vfredosum.vs",
+        0b00_0100 => "vfmin.{s}",
+        0b00_0101 if vv => "vfredmin.vs",
+        0b00_0110 => "vfmax.{s}",
+        0b00_0111 if vv => "vfredmax.vs",
+        0b00_1000 => "vfsgnj.{s}",
+        0b00_1001 => "vfsgnjn.{s}",
+        0b00_1010 => "vfsgnjx.{s}",
+        0b00_1110 if !vv => "vfslide1up.vf",
+        0b00_1111 if !vv => "vfslide1down.vf",
+        0b01_0000 if vv => "vwfunary0",
+        0b01_0000 if vs2 == 0 => "vfmv.s.f",
+        0b01_0010 if vv => "vfunary0",
+        0b01_0011 if vv => "vfunary1",
+        0b01_0111 if !vv && !vm => "vfmerge.vfm",
+        0b01_0111 if !vv && vs2 == 0 => "vfmv.v.f",
+        0b01_1000 => "vmfeq.{s}",
+        0b01_1001 => "vmfle.{s}",
+        0b01_1011 => "vmflt.{s}",
+        0b01_1100 => "vmfne.{s}",
+        0b01_1101 if !vv => "vmfgt.vf",
+        0b01_1111 if !vv => "vmfge.vf",
+        0b10_0000 => "vfdiv.{s}",
+        0b10_0001 if !vv => "vfrdiv.vf",
+        0b10_0100 => "vfmul.{s}",
+        0b10_0111 if !vv => "vfrsub.vf",
+        0b10_1000 => "vfmadd.{s}",
+        0b10_1001 => "vfnmadd.{s}",
+        0b10_1010 => "vfmsub.{s}",
+        0b10_1011 => "vfnmsub.{s}",
+        0b10_1100 => "vfmacc.{s}",
+        0b10_1101 => "vfnmacc.{s}",
+        0b10_1110 => "vfmsac.{s}",
+        0b10_1111 => "vfnmsac.{s}",
+        0b11_0000 => "vfwadd.{s}",
+        0b11_0001 if vv => "vfwredusum.vs",
+        0b11_0010 => "vfwsub.{s}",
+        0b11_0011 if vv => "vfwredosum.vs",
+        0b11_0100 => "vfwadd.w{s1}",
+        0b11_0110 => "vfwsub.w{s1}",
+        0b11_1000 => "vfwmul.{s}",
+        0b11_1100 => "vfwmacc.{s}",
+        0b11_1101 => "vfwnmacc.{s}",
+        0b11_1110 => "vfwmsac.{s}",
+        0b11_1111 => "vfwnmsac.{s}",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum InstFmt {
     R,
+    R4,
     I,
     S,
     B,
@@ -223,117 +733,291 @@ pub enum InstFmt {
     J,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum InstName {
     // RV32I
-    Lui(String),
-    Auipc(String),
-    Jal(String),
-    Jalr(String),
-    Beq(String),
-    Bne(String),
-    Blt(String),
-    Bge(String),
-    Bltu(String),
-    Bgeu(String),
-    Lb(String),
-    Lh(String),
-    Lw(String),
-    Lbu(String),
-    Lhu(String),
-    Sb(String),
-    Sh(String),
-    Sw(String),
-    Addi(String),
-    Slti(String),
-    Sltiu(String),
-    Xori(String),
-    Ori(String),
-    Andi(String),
-    Slli(String),
-    Srli(String),
-    Srai(String),
-    Add(String),
-    Sub(String),
-    Sll(String),
-    Slt(String),
-    Sltu(String),
-    Xor(String),
-    Srl(String),
-    Sra(String),
-    Or(String),
-    And(String),
-    Fence(String),
-    FenceI(String),
-    Ecall(String),
-    Ebreak(String),
-    Csrrw(String),
-    Csrrs(String),
-    Csrrc(String),
-    Csrrwi(String),
-    Csrrsi(String),
-    Csrrci(String),
-    Sret(String),
-    Mret(String),
-    Wfi(String),
-    SfenceVma(String),
+    Lui,
+    Auipc,
+    Jal,
+    Jalr,
+    Beq,
+    Bne,
+    Blt,
+    Bge,
+    Bltu,
+    Bgeu,
+    Lb,
+    Lh,
+    Lw,
+    Lbu,
+    Lhu,
+    Sb,
+    Sh,
+    Sw,
+    Addi,
+    Slti,
+    Sltiu,
+    Xori,
+    Ori,
+    Andi,
+    Slli,
+    Srli,
+    Srai,
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Fence,
+    FenceI,
+    Ecall,
+    Ebreak,
+    Csrrw,
+    Csrrs,
+    Csrrc,
+    Csrrwi,
+    Csrrsi,
+    Csrrci,
+    Sret,
+    Mret,
+    Wfi,
+    SfenceVma,
 
     // RV32A
-    Mul(String),
-    Mulh(String),
-    Mulhsu(String),
-    Mulhu(String),
-    Div(String),
-    Divu(String),
-    Rem(String),
-    Remu(String),
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
 
     // RV32A
-    LrW(String),
-    ScW(String),
-    AmoswapW(String),
-    AmoaddW(String),
-    AmoxorW(String),
-    AmoandW(String),
-    AmoorW(String),
-    AmominW(String),
-    AmomaxW(String),
-    AmominuW(String),
-    AmomaxuW(String),
+    LrW,
+    ScW,
+    AmoswapW,
+    AmoaddW,
+    AmoxorW,
+    AmoandW,
+    AmoorW,
+    AmominW,
+    AmomaxW,
+    AmominuW,
+    AmomaxuW,
 
     // RV64I
-    Lwu(String),
-    Ld(String),
-    Sd(String),
-    Addiw(String),
-    Slliw(String),
-    Srliw(String),
-    Sraiw(String),
-    Addw(String),
-    Subw(String),
-    Sllw(String),
-    Srlw(String),
-    Sraw(String),
+    Lwu,
+    Ld,
+    Sd,
+    Addiw,
+    Slliw,
+    Srliw,
+    Sraiw,
+    Addw,
+    Subw,
+    Sllw,
+    Srlw,
+    Sraw,
 
     // RV64M
-    Mulw(String),
-    Divw(String),
-    Divuw(String),
-    Remw(String),
-    Remuw(String),
+    Mulw,
+    Divw,
+    Divuw,
+    Remw,
+    Remuw,
 
     // RV64A
-    LrD(String),
-    ScD(String),
-    AmoswapD(String),
-    AmoaddD(String),
-    AmoxorD(String),
-    AmoandD(String),
-    AmoorD(String),
-    AmominD(String),
-    AmomaxD(String),
-    AmominuD(String),
-    AmomaxuD(String),
+    LrD,
+    ScD,
+    AmoswapD,
+    AmoaddD,
+    AmoxorD,
+    AmoandD,
+    AmoorD,
+    AmominD,
+    AmomaxD,
+    AmominuD,
+    AmomaxuD,
+
+    // RV32F
+    Flw,
+    Fsw,
+    FmaddS,
+    FmsubS,
+    FnmsubS,
+    FnmaddS,
+    FaddS,
+    FsubS,
+    FmulS,
+    FdivS,
+    FsqrtS,
+    FsgnjS,
+    FsgnjnS,
+    FsgnjxS,
+    FminS,
+    FmaxS,
+    FcvtWS,
+    FcvtWuS,
+    FmvXW,
+    FeqS,
+    FltS,
+    FleS,
+    FclassS,
+    FcvtSW,
+    FcvtSWu,
+    FmvWX,
+
+    // RV32D
+    Fld,
+    Fsd,
+    FmaddD,
+    FmsubD,
+    FnmsubD,
+    FnmaddD,
+    FaddD,
+    FsubD,
+    FmulD,
+    FdivD,
+    FsqrtD,
+    FsgnjD,
+    FsgnjnD,
+    FsgnjxD,
+    FminD,
+    FmaxD,
+    FcvtSD,
+    FcvtDS,
+    FeqD,
+    FltD,
+    FleD,
+    FclassD,
+    FcvtWD,
+    FcvtWuD,
+    FcvtDW,
+    FcvtDWu,
+
+    // RV64F
+    FcvtLS,
+    FcvtLuS,
+    FcvtSL,
+    FcvtSLu,
+
+    // RV64D
+    FcvtLD,
+    FcvtLuD,
+    FmvXD,
+    FcvtDL,
+    FcvtDLu,
+    FmvDX,
+
+    // Zba
+    Sh1add,
+    Sh2add,
+    Sh3add,
+    AddUw,
+    Sh1addUw,
+    Sh2addUw,
+    Sh3addUw,
+    SlliUw,
+
+    // Zbb
+    Andn,
+    Orn,
+    Xnor,
+    Clz,
+    Clzw,
+    Ctz,
+    Ctzw,
+    Cpop,
+    Cpopw,
+    Max,
+    Maxu,
+    Min,
+    Minu,
+    SextB,
+    SextH,
+    ZextH,
+    Rol,
+    Rolw,
+    Ror,
+    Rori,
+    Roriw,
+    Rorw,
+    OrcB,
+    Rev8,
+
+    // Zbc
+    Clmul,
+    Clmulh,
+    Clmulr,
+
+    // Zbs
+    Bclr,
+    Bclri,
+    Bext,
+    Bexti,
+    Binv,
+    Binvi,
+    Bset,
+    Bseti,
+
+    // Zicond
+    CzeroEqz,
+    CzeroNez,
+
+    // Zicbom, Zicboz
+    CboClean,
+    CboFlush,
+    CboInval,
+    CboZero,
+
+    // Zihintpause
+    Pause,
+
+    // V
+    Vsetvli,
+    Vsetivli,
+    Vsetvl,
+    Vle,
+    Vleff,
+    Vlm,
+    Vlr,
+    Vlse,
+    Vlxei,
+    Vse,
+    Vsm,
+    Vsr,
+    Vsse,
+    Vsxei,
+    Opivv,
+    Opivx,
+    Opivi,
+    Opmvv,
+    Opmvx,
+    Opfvv,
+    Opfvf,
+
+    // H
+    HfenceVvma,
+    HfenceGvma,
+    HlvB,
+    HlvBu,
+    HlvH,
+    HlvHu,
+    HlvW,
+    HlvWu,
+    HlvD,
+    HlvxHu,
+    HlvxWu,
+    HsvB,
+    HsvH,
+    HsvW,
+    HsvD,
 }
 
 fn to_funct(inst: u32, fmt: &InstFmt) -> (u8, u8, u16) {
@@ -341,9 +1025,9 @@ fn to_funct(inst: u32, fmt: &InstFmt) -> (u8, u8, u16) {
     let mut funct7: u8 = 0;
     let mut funct12: u16 = 0;
     match fmt {
-        InstFmt::R | InstFmt::I | InstFmt::S | InstFmt::B => {
+        InstFmt::R | InstFmt::R4 | InstFmt::I | InstFmt::S | InstFmt::B => {
             funct3 = (inst >> 12 & 0b111) as u8;
-            if let InstFmt::I | InstFmt::R = fmt {
+            if let InstFmt::I | InstFmt::R | InstFmt::R4 = fmt {
                 funct7 = (inst >> 25 & 0b111_1111) as u8;
                 funct12 = (inst >> 20 & 0b1111_1111_1111) as u16;
             }
@@ -353,13 +1037,14 @@ fn to_funct(inst: u32, fmt: &InstFmt) -> (u8, u8, u16) {
     (funct3, funct7, funct12)
 }
 
-fn to_ri(inst: u32, fmt: &InstFmt) -> (u8, u8, u8, u32) {
+/// Registers and the sign-extended immediate. The U-type immediate is placed at bits 31..12.
+fn to_ri(inst: u32, fmt: &InstFmt) -> (u8, u8, u8, i64) {
     let mut rs1: u8 = 0;
     let mut rs2: u8 = 0;
     let mut rd: u8 = 0;
     let mut imm: u32 = 0;
     match fmt {
-        InstFmt::R => {
+        InstFmt::R | InstFmt::R4 => {
             rs1 = (inst >> 15 & 0b1_1111) as u8;
             rs2 = (inst >> 20 & 0b1_1111) as u8;
             rd = (inst >> 7 & 0b1_1111) as u8;
@@ -404,9 +1089,23 @@ fn to_ri(inst: u32, fmt: &InstFmt) -> (u8, u8, u8, u32) {
             imm <<= 1;
         }
     }
+    let imm = match fmt {
+        InstFmt::I | InstFmt::S => sext_imm(imm, 12),
+        InstFmt::B => sext_imm(imm, 13),
+        InstFmt::U => sext_imm(imm << 12, 32),
+        InstFmt::J => sext_imm(imm, 21),
+        _ => 0,
+    };
     (rs1, rs2, rd, imm)
 }
 
+/// Sign-extend the `len` bit immediate.
+fn sext_imm(imm: u32, len: u32) -> i64 {
+    ((imm << (32 - len)) as i32 >> (32 - len)) as i64
+}
+
+/// A decoded instruction. It holds no heap data, so decoding in the hot loop does not allocate.
+#[derive(Debug, Clone, Copy)]
 pub struct Instruction {
     pub opcode: u8, // 7bit
     pub name: InstName,
@@ -414,44 +1113,386 @@ pub struct Instruction {
     pub rs1: u8,  // 5bit
     pub rs2: u8,  // 5bit
     pub rd: u8,   // 5bit
-    pub imm: u32, // 19bit
+    pub rs3: u8,  // 5bit (R4)
+    pub rm: u8,   // 3bit rounding mode (F, D)
+    pub imm: i64, // sign-extended
     pub raw_inst: u32,
+    pub len: u64,           // 2 byte (compressed) or 4 byte
+    mnemonic: &'static str, // with the placeholders of vector instructions
 }
 
 impl Instruction {
-    pub fn decode(inst: u32) -> Instruction {
+    /// Decode a 32-bit instruction, or a 16-bit compressed instruction
+    /// held in the lower half of `inst`.
+    /// Encodings that are reserved or belong to unsupported extensions raise an illegal
+    /// instruction exception with the instruction bits, so the guest can probe for extensions.
+    pub fn decode(inst: u32) -> Result<Instruction, Exception> {
+        if inst & 0b11 != 0b11 {
+            let illegal = || Exception::IllegalInstruction((inst & 0xFFFF) as u64);
+            let expanded = expand_compressed(inst as u16).ok_or_else(illegal)?;
+            let mut res = Instruction::decode(expanded).map_err(|_| illegal())?;
+            res.raw_inst = inst & 0xFFFF;
+            res.len = 2;
+            return Ok(res);
+        }
+        let illegal = || Exception::IllegalInstruction(inst as u64);
+
         let opcode = (inst & 0b0111_1111) as u8;
-        // TODO refactoring
         let funct3 = (inst >> 12 & 0b111) as u8;
         let funct7 = (inst >> 25 & 0b111_1111) as u8;
-        let fmt = to_format(opcode, funct3, funct7);
+        let fmt = to_format(opcode, funct3, funct7).ok_or_else(illegal)?;
         let (funct3, funct7, funct12) = to_funct(inst, &fmt);
-        // println!("inst: 0b{:032b}", inst);
-        // println!("funct3: 0b{:016b}", funct3);
-        // println!("funct7: 0b{:016b}", funct7);
-        // println!("funct12: 0b{:016b}", funct12);
-        let name = to_name(opcode, funct3, funct7, funct12);
+        let (name, mnemonic) = to_name(opcode, funct3, funct7, funct12).ok_or_else(illegal)?;
         let (rs1, rs2, rd, imm) = to_ri(inst, &fmt);
-        Instruction {
+        let rs3 = (inst >> 27 & 0b1_1111) as u8;
+        let rm = funct3;
+        Ok(Instruction {
             opcode,
             name,
             fmt,
             rs1,
             rs2,
             rd,
+            rs3,
+            rm,
             imm,
             raw_inst: inst,
-        }
+            len: 4,
+            mnemonic,
+        })
+    }
+
+    /// Whether the instruction ends a basic block. Jumps and branches change pc, SYSTEM instructions
+    /// may also change the privilege mode or the address translation, and MISC-MEM has fence.i.
+    pub fn ends_block(&self) -> bool {
+        matches!(
+            self.opcode as u32,
+            OP_JAL | OP_JALR | OP_BRANCH | OP_SYSTEM | OP_MISC_MEM
+        )
+    }
+
+    /// The mnemonic with the operand fields of vector instructions filled in.
+    pub fn mnemonic(&self) -> String {
+        let funct3 = (self.raw_inst >> 12 & 0b111) as u8;
+        let nf = (self.raw_inst >> 29) + 1;
+        let suffix = match funct3 {
+            OPIVV | OPMVV | OPFVV => "vv",
+            OPIVX | OPMVX => "vx",
+            OPIVI => "vi",
+            _ => "vf",
+        };
+        let seg = if nf == 1 {
+            "".to_owned()
+        } else {
+            format!("seg{}", nf)
+        };
+        self.mnemonic
+            .replace("{s}", suffix)
+            .replace("{s1}", &suffix[1..])
+            .replace("{seg}", &seg)
+            .replace("{nf}", &nf.to_string())
+            .replace("{eew}", &vector_eew(funct3).to_string())
     }
 
     pub fn print(&self) {
+        if self.len == 2 {
+            println!(
+                "opcode: {:b}, name: {}, fmt: {:?}, raw_inst: {:04X} (compressed)",
+                self.opcode,
+                self.mnemonic(),
+                self.fmt,
+                self.raw_inst
+            );
+        } else {
+            println!(
+                "opcode: {:b}, name: {}, fmt: {:?}, raw_inst: {:08X}",
+                self.opcode,
+                self.mnemonic(),
+                self.fmt,
+                self.raw_inst
+            );
+        }
         println!(
-            "opcode: {:b}, name: {:?}, fmt: {:?}, raw_inst: {:08X}",
-            self.opcode, self.name, self.fmt, self.raw_inst
-        );
-        println!(
-            "rs1: {:05b}, rs2: {:05b}, rd: {:05b}, imm: {:032b}",
+            "rs1: {:05b}, rs2: {:05b}, rd: {:05b}, imm: {}",
             self.rs1, self.rs2, self.rd, self.imm
         );
     }
 }
+
+// RV64C
+// Each compressed instruction is expanded to the equivalent 32-bit instruction.
+// https://github.com/riscv/riscv-isa-manual/blob/main/src/c-st-ext.adoc
+
+/// Returns the `len` bits from the `lo` bit of the `inst`.
+fn bits(inst: u16, lo: u32, len: u32) -> u32 {
+    (inst as u32 >> lo) & ((1 << len) - 1)
+}
+
+/// Compressed register number rd', rs1' and rs2' (x8 - x15).
+fn c_reg(inst: u16, lo: u32) -> u32 {
+    bits(inst, lo, 3) + 8
+}
+
+fn enc_r(opcode: u32, rd: u32, funct3: u32, rs1: u32, rs2: u32, funct7: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn enc_i(opcode: u32, rd: u32, funct3: u32, rs1: u32, imm: u32) -> u32 {
+    (imm & 0xFFF) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn enc_s(opcode: u32, funct3: u32, rs1: u32, rs2: u32, imm: u32) -> u32 {
+    (imm >> 5 & 0b111_1111) << 25
+        | rs2 << 20
+        | rs1 << 15
+        | funct3 << 12
+        | (imm & 0b1_1111) << 7
+        | opcode
+}
+
+fn enc_b(opcode: u32, funct3: u32, rs1: u32, rs2: u32, imm: u32) -> u32 {
+    (imm >> 12 & 0b1) << 31
+        | (imm >> 5 & 0b11_1111) << 25
+        | rs2 << 20
+        | rs1 << 15
+        | funct3 << 12
+        | (imm >> 1 & 0b1111) << 8
+        | (imm >> 11 & 0b1) << 7
+        | opcode
+}
+
+fn enc_u(opcode: u32, rd: u32, imm: u32) -> u32 {
+    (imm & 0xF_FFFF) << 12 | rd << 7 | opcode
+}
+
+fn enc_j(opcode: u32, rd: u32, imm: u32) -> u32 {
+    (imm >> 20 & 0b1) << 31
+        | (imm >> 1 & 0b11_1111_1111) << 21
+        | (imm >> 11 & 0b1) << 20
+        | (imm >> 12 & 0b1111_1111) << 12
+        | rd << 7
+        | opcode
+}
+
+/// Sign-extend the `len` bit value.
+fn c_sext(imm: u32, len: u32) -> u32 {
+    ((imm << (32 - len)) as i32 >> (32 - len)) as u32
+}
+
+const OP_LUI: u32 = 0b011_0111;
+const OP_JAL: u32 = 0b110_1111;
+const OP_JALR: u32 = 0b110_0111;
+const OP_BRANCH: u32 = 0b110_0011;
+const OP_LOAD: u32 = 0b000_0011;
+const OP_STORE: u32 = 0b010_0011;
+const OP_LOAD_FP: u32 = 0b000_0111;
+const OP_STORE_FP: u32 = 0b010_0111;
+const OP_IMM: u32 = 0b001_0011;
+const OP_IMM_32: u32 = 0b001_1011;
+const OP: u32 = 0b011_0011;
+const OP_32: u32 = 0b011_1011;
+const OP_SYSTEM: u32 = 0b111_0011;
+const OP_MISC_MEM: u32 = 0b000_1111;
+
+fn expand_compressed(inst: u16) -> Option<u32> {
+    let op = bits(inst, 0, 2);
+    let funct3 = bits(inst, 13, 3);
+    let rd = bits(inst, 7, 5); // rd/rs1
+    let rs2 = bits(inst, 2, 5);
+    let rd_c = c_reg(inst, 2); // rd'/rs2'
+    let rs1_c = c_reg(inst, 7); // rs1'/rd'
+
+    let expanded = match (op, funct3) {
+        // C.ADDI4SPN: addi rd', x2, nzuimm[9:2]
+        (0b00, 0b000) => {
+            let imm = bits(inst, 11, 2) << 4
+                | bits(inst, 7, 4) << 6
+                | bits(inst, 6, 1) << 2
+                | bits(inst, 5, 1) << 3;
+            if imm == 0 {
+                return None;
+            }
+            enc_i(OP_IMM, rd_c, 0b000, 2, imm)
+        }
+        // C.FLD: fld rd', offset[7:3](rs1')
+        (0b00, 0b001) => {
+            let imm = bits(inst, 10, 3) << 3 | bits(inst, 5, 2) << 6;
+            enc_i(OP_LOAD_FP, rd_c, 0b011, rs1_c, imm)
+        }
+        // C.LW: lw rd', offset[6:2](rs1')
+        (0b00, 0b010) => {
+            let imm = bits(inst, 10, 3) << 3 | bits(inst, 6, 1) << 2 | bits(inst, 5, 1) << 6;
+            enc_i(OP_LOAD, rd_c, 0b010, rs1_c, imm)
+        }
+        // C.LD: ld rd', offset[7:3](rs1')
+        (0b00, 0b011) => {
+            let imm = bits(inst, 10, 3) << 3 | bits(inst, 5, 2) << 6;
+            enc_i(OP_LOAD, rd_c, 0b011, rs1_c, imm)
+        }
+        // C.FSD: fsd rs2', offset[7:3](rs1')
+        (0b00, 0b101) => {
+            let imm = bits(inst, 10, 3) << 3 | bits(inst, 5, 2) << 6;
+            enc_s(OP_STORE_FP, 0b011, rs1_c, rd_c, imm)
+        }
+        // C.SW: sw rs2', offset[6:2](rs1')
+        (0b00, 0b110) => {
+            let imm = bits(inst, 10, 3) << 3 | bits(inst, 6, 1) << 2 | bits(inst, 5, 1) << 6;
+            enc_s(OP_STORE, 0b010, rs1_c, rd_c, imm)
+        }
+        // C.SD: sd rs2', offset[7:3](rs1')
+        (0b00, 0b111) => {
+            let imm = bits(inst, 10, 3) << 3 | bits(inst, 5, 2) << 6;
+            enc_s(OP_STORE, 0b011, rs1_c, rd_c, imm)
+        }
+        // C.ADDI (C.NOP): addi rd, rd, nzimm[5:0]
+        (0b01, 0b000) => {
+            let imm = c_sext(bits(inst, 12, 1) << 5 | bits(inst, 2, 5), 6);
+            enc_i(OP_IMM, rd, 0b000, rd, imm)
+        }
+        // C.ADDIW: addiw rd, rd, imm[5:0]
+        (0b01, 0b001) => {
+            if rd == 0 {
+                return None;
+            }
+            let imm = c_sext(bits(inst, 12, 1) << 5 | bits(inst, 2, 5), 6);
+            enc_i(OP_IMM_32, rd, 0b000, rd, imm)
+        }
+        // C.LI: addi rd, x0, imm[5:0]
+        (0b01, 0b010) => {
+            let imm = c_sext(bits(inst, 12, 1) << 5 | bits(inst, 2, 5), 6);
+            enc_i(OP_IMM, rd, 0b000, 0, imm)
+        }
+        (0b01, 0b011) => {
+            if rd == 2 {
+                // C.ADDI16SP: addi x2, x2, nzimm[9:4]
+                let imm = bits(inst, 12, 1) << 9
+                    | bits(inst, 6, 1) << 4
+                    | bits(inst, 5, 1) << 6
+                    | bits(inst, 3, 2) << 7
+                    | bits(inst, 2, 1) << 5;
+                if imm == 0 {
+                    return None;
+                }
+                enc_i(OP_IMM, 2, 0b000, 2, c_sext(imm, 10))
+            } else {
+                // C.LUI: lui rd, nzimm[17:12]
+                let imm = bits(inst, 12, 1) << 5 | bits(inst, 2, 5);
+                if imm == 0 {
+                    return None;
+                }
+                enc_u(OP_LUI, rd, c_sext(imm, 6))
+            }
+        }
+        (0b01, 0b100) => {
+            let shamt = bits(inst, 12, 1) << 5 | bits(inst, 2, 5);
+            match bits(inst, 10, 2) {
+                // C.SRLI: srli rd', rd', shamt[5:0]
+                0b00 => enc_i(OP_IMM, rs1_c, 0b101, rs1_c, shamt),
+                // C.SRAI: srai rd', rd', shamt[5:0]
+                0b01 => enc_i(OP_IMM, rs1_c, 0b101, rs1_c, 0b0100_0000_0000 | shamt),
+                // C.ANDI: andi rd', rd', imm[5:0]
+                0b10 => enc_i(OP_IMM, rs1_c, 0b111, rs1_c, c_sext(shamt, 6)),
+                _ => match (bits(inst, 12, 1), bits(inst, 5, 2)) {
+                    // C.SUB
+                    (0, 0b00) => enc_r(OP, rs1_c, 0b000, rs1_c, rd_c, 0b010_0000),
+                    // C.XOR
+                    (0, 0b01) => enc_r(OP, rs1_c, 0b100, rs1_c, rd_c, 0b000_0000),
+                    // C.OR
+                    (0, 0b10) => enc_r(OP, rs1_c, 0b110, rs1_c, rd_c, 0b000_0000),
+                    // C.AND
+                    (0, 0b11) => enc_r(OP, rs1_c, 0b111, rs1_c, rd_c, 0b000_0000),
+                    // C.SUBW
+                    (1, 0b00) => enc_r(OP_32, rs1_c, 0b000, rs1_c, rd_c, 0b010_0000),
+                    // C.ADDW
+                    (1, 0b01) => enc_r(OP_32, rs1_c, 0b000, rs1_c, rd_c, 0b000_0000),
+                    _ => return None,
+                },
+            }
+        }
+        // C.J: jal x0, offset[11:1]
+        (0b01, 0b101) => enc_j(OP_JAL, 0, c_j_offset(inst)),
+        // C.BEQZ: beq rs1', x0, offset[8:1]
+        (0b01, 0b110) => enc_b(OP_BRANCH, 0b000, rs1_c, 0, c_b_offset(inst)),
+        // C.BNEZ: bne rs1', x0, offset[8:1]
+        (0b01, 0b111) => enc_b(OP_BRANCH, 0b001, rs1_c, 0, c_b_offset(inst)),
+        // C.SLLI: slli rd, rd, shamt[5:0]
+        (0b10, 0b000) => {
+            let shamt = bits(inst, 12, 1) << 5 | bits(inst, 2, 5);
+            enc_i(OP_IMM, rd, 0b001, rd, shamt)
+        }
+        // C.FLDSP: fld rd, offset[8:3](x2)
+        (0b10, 0b001) => {
+            let imm = bits(inst, 12, 1) << 5 | bits(inst, 5, 2) << 3 | bits(inst, 2, 3) << 6;
+            enc_i(OP_LOAD_FP, rd, 0b011, 2, imm)
+        }
+        // C.LWSP: lw rd, offset[7:2](x2)
+        (0b10, 0b010) => {
+            if rd == 0 {
+                return None;
+            }
+            let imm = bits(inst, 12, 1) << 5 | bits(inst, 4, 3) << 2 | bits(inst, 2, 2) << 6;
+            enc_i(OP_LOAD, rd, 0b010, 2, imm)
+        }
+        // C.LDSP: ld rd, offset[8:3](x2)
+        (0b10, 0b011) => {
+            if rd == 0 {
+                return None;
+            }
+            let imm = bits(inst, 12, 1) << 5 | bits(inst, 5, 2) << 3 | bits(inst, 2, 3) << 6;
+            enc_i(OP_LOAD, rd, 0b011, 2, imm)
+        }
+        (0b10, 0b100) => match (bits(inst, 12, 1), rd, rs2) {
+            (0, 0, 0) => return None,
+            // C.JR: jalr x0, 0(rs1)
+            (0, _, 0) => enc_i(OP_JALR, 0, 0b000, rd, 0),
+            // C.MV: add rd, x0, rs2
+            (0, _, _) => enc_r(OP, rd, 0b000, 0, rs2, 0b000_0000),
+            // C.EBREAK
+            (1, 0, 0) => enc_i(OP_SYSTEM, 0, 0b000, 0, 1),
+            // C.JALR: jalr x1, 0(rs1)
+            (1, _, 0) => enc_i(OP_JALR, 1, 0b000, rd, 0),
+            // C.ADD: add rd, rd, rs2
+            (_, _, _) => enc_r(OP, rd, 0b000, rd, rs2, 0b000_0000),
+        },
+        // C.FSDSP: fsd rs2, offset[8:3](x2)
+        (0b10, 0b101) => {
+            let imm = bits(inst, 10, 3) << 3 | bits(inst, 7, 3) << 6;
+            enc_s(OP_STORE_FP, 0b011, 2, rs2, imm)
+        }
+        // C.SWSP: sw rs2, offset[7:2](x2)
+        (0b10, 0b110) => {
+            let imm = bits(inst, 9, 4) << 2 | bits(inst, 7, 2) << 6;
+            enc_s(OP_STORE, 0b010, 2, rs2, imm)
+        }
+        // C.SDSP: sd rs2, offset[8:3](x2)
+        (0b10, 0b111) => {
+            let imm = bits(inst, 10, 3) << 3 | bits(inst, 7, 3) << 6;
+            enc_s(OP_STORE, 0b011, 2, rs2, imm)
+        }
+        _ => return None,
+    };
+    Some(expanded)
+}
+
+/// C.J offset[11|4|9:8|10|6|7|3:1|5]
+fn c_j_offset(inst: u16) -> u32 {
+    let imm = bits(inst, 12, 1) << 11
+        | bits(inst, 11, 1) << 4
+        | bits(inst, 9, 2) << 8
+        | bits(inst, 8, 1) << 10
+        | bits(inst, 7, 1) << 6
+        | bits(inst, 6, 1) << 7
+        | bits(inst, 3, 3) << 1
+        | bits(inst, 2, 1) << 5;
+    c_sext(imm, 12)
+}
+
+/// C.BEQZ and C.BNEZ offset[8|4:3] offset[7:6|2:1|5]
+fn c_b_offset(inst: u16) -> u32 {
+    let imm = bits(inst, 12, 1) << 8
+        | bits(inst, 10, 2) << 3
+        | bits(inst, 5, 2) << 6
+        | bits(inst, 3, 2) << 1
+        | bits(inst, 2, 1) << 5;
+    c_sext(imm, 9)
+}
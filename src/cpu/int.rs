@@ -2,208 +2,375 @@ use super::register::Register;
 use super::Mode;
 
 const MSTATUS_MIE: u64 = 0b1000;
+const MSTATUS_MPIE: u64 = 0b1000_0000;
+const MSTATUS_MPP: u64 = 0b1_1000_0000_0000;
 const SSTATUS_SIE: u64 = 0b0010;
+const SSTATUS_SPIE: u64 = 0b10_0000;
+const SSTATUS_SPP: u64 = 0b1_0000_0000;
+const MSTATUS_GVA: u64 = 1 << 38;
+const MSTATUS_MPV: u64 = 1 << 39;
+const HSTATUS_GVA: u64 = 0b0100_0000;
+const HSTATUS_SPV: u64 = 0b1000_0000;
+const HSTATUS_SPVP: u64 = 0b1_0000_0000;
 
-const MIP_SSIP: u64 = 0b0000_0000_0010; // Supervisor software interrupt
-const MIP_MSIP: u64 = 0b0000_0000_1000; // Machine software interrupt
-const MIP_STIP: u64 = 0b0000_0010_0000; // Supervisor timer interrupt
-const MIP_MTIP: u64 = 0b0000_1000_0000; // Machine timer interrupt
-const MIP_SEIP: u64 = 0b0010_0000_0000; // Supervisor external interrupt
-const MIP_MEIP: u64 = 0b1000_0000_0000; // Machine external interrupt
+const MIP_SSIP: u64 = 0b0_0000_0000_0010; // Supervisor software interrupt
+const MIP_VSSIP: u64 = 0b0_0000_0000_0100; // Virtual supervisor software interrupt
+const MIP_MSIP: u64 = 0b0_0000_0000_1000; // Machine software interrupt
+const MIP_STIP: u64 = 0b0_0000_0010_0000; // Supervisor timer interrupt
+const MIP_VSTIP: u64 = 0b0_0000_0100_0000; // Virtual supervisor timer interrupt
+const MIP_MTIP: u64 = 0b0_0000_1000_0000; // Machine timer interrupt
+const MIP_SEIP: u64 = 0b0_0010_0000_0000; // Supervisor external interrupt
+const MIP_VSEIP: u64 = 0b0_0100_0000_0000; // Virtual supervisor external interrupt
+const MIP_MEIP: u64 = 0b0_1000_0000_0000; // Machine external interrupt
+const MIP_SGEIP: u64 = 0b1_0000_0000_0000; // Supervisor guest external interrupt
 
-pub fn timer_int(reg: &mut Register, current_mode: &mut Mode, mtime: u64, mtimecmp: u64) {
-    if (*current_mode == Mode::M) && (reg.mstatus & MSTATUS_MIE == 0) {
-        return;
-    }
-    if (*current_mode == Mode::S) && (reg.sstatus & SSTATUS_SIE == 0) {
-        return;
-    }
+// VS-level interrupts and SGEI are always delegated to HS-mode.
+const MIDELEG_RO: u64 = MIP_VSSIP | MIP_VSTIP | MIP_VSEIP | MIP_SGEIP;
 
-    if mtime < mtimecmp {
-        return;
-    }
+const INTERRUPT: u64 = 0x8000_0000_0000_0000; // mcause/scause interrupt bit
 
-    match current_mode {
-        Mode::M => {
-            reg.mip |= MIP_MTIP;
-            reg.mcause = 0x8000_0000_0000_0007; // timer interrupt
-        }
-        Mode::S => {
-            reg.sip |= MIP_STIP;
-            reg.scause = 0x8000_0000_0000_0005; // timer interrupt
-        }
-        Mode::U => {
-            return;
-        }
-    }
-    int(reg, current_mode);
+/// Synchronous exceptions.
+/// The value held by a variant is written to mtval or stval.
+/// Guest-page faults also hold the guest physical address for mtval2 or htval.
+#[derive(Debug, PartialEq)]
+pub enum Exception {
+    InstructionAddressMisaligned(u64),
+    InstructionAccessFault(u64),
+    IllegalInstruction(u64),
+    Breakpoint(u64),
+    LoadAddressMisaligned(u64),
+    LoadAccessFault(u64),
+    StoreAddressMisaligned(u64),
+    StoreAccessFault(u64),
+    EnvironmentCallFromU,
+    EnvironmentCallFromS,
+    EnvironmentCallFromVS,
+    EnvironmentCallFromM,
+    InstructionPageFault(u64),
+    LoadPageFault(u64),
+    StorePageFault(u64),
+    InstructionGuestPageFault(u64, u64),
+    LoadGuestPageFault(u64, u64),
+    VirtualInstruction(u64),
+    StoreGuestPageFault(u64, u64),
 }
 
-pub fn int(reg: &mut Register, current_mode: &mut Mode) {
-    if (*current_mode == Mode::M) && (reg.mstatus & MSTATUS_MIE == 0) {
-        return;
-    }
-    if (*current_mode == Mode::S) && (reg.sstatus & SSTATUS_SIE == 0) {
-        return;
+impl Exception {
+    /// Exception code written to mcause or scause.
+    pub fn code(&self) -> u64 {
+        match self {
+            Exception::InstructionAddressMisaligned(_) => 0,
+            Exception::InstructionAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
+            Exception::Breakpoint(_) => 3,
+            Exception::LoadAddressMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreAddressMisaligned(_) => 6,
+            Exception::StoreAccessFault(_) => 7,
+            Exception::EnvironmentCallFromU => 8,
+            Exception::EnvironmentCallFromS => 9,
+            Exception::EnvironmentCallFromVS => 10,
+            Exception::EnvironmentCallFromM => 11,
+            Exception::InstructionPageFault(_) => 12,
+            Exception::LoadPageFault(_) => 13,
+            Exception::StorePageFault(_) => 15,
+            Exception::InstructionGuestPageFault(..) => 20,
+            Exception::LoadGuestPageFault(..) => 21,
+            Exception::VirtualInstruction(_) => 22,
+            Exception::StoreGuestPageFault(..) => 23,
+        }
     }
 
-    let pre_mode = match current_mode {
-        Mode::M => Mode::M,
-        Mode::S => Mode::S,
-        Mode::U => Mode::U,
-    };
-
-    *current_mode = Mode::M;
-
-    if (reg.mip | reg.sip) == 0 {
-        return;
+    /// Value written to mtval or stval.
+    pub fn tval(&self) -> u64 {
+        match self {
+            Exception::InstructionAddressMisaligned(v)
+            | Exception::InstructionAccessFault(v)
+            | Exception::IllegalInstruction(v)
+            | Exception::Breakpoint(v)
+            | Exception::LoadAddressMisaligned(v)
+            | Exception::LoadAccessFault(v)
+            | Exception::StoreAddressMisaligned(v)
+            | Exception::StoreAccessFault(v)
+            | Exception::InstructionPageFault(v)
+            | Exception::LoadPageFault(v)
+            | Exception::StorePageFault(v)
+            | Exception::InstructionGuestPageFault(v, _)
+            | Exception::LoadGuestPageFault(v, _)
+            | Exception::VirtualInstruction(v)
+            | Exception::StoreGuestPageFault(v, _) => *v,
+            Exception::EnvironmentCallFromU
+            | Exception::EnvironmentCallFromS
+            | Exception::EnvironmentCallFromVS
+            | Exception::EnvironmentCallFromM => 0,
+        }
     }
 
-    let (int_code, int_mode) = get_int_code(reg);
-    if is_disabled_int(reg, int_code, &int_mode) {
-        return;
+    /// Value written to mtval2 or htval. The guest physical address shifted right by 2.
+    pub fn tval2(&self) -> u64 {
+        match self {
+            Exception::InstructionGuestPageFault(_, gpa)
+            | Exception::LoadGuestPageFault(_, gpa)
+            | Exception::StoreGuestPageFault(_, gpa) => gpa >> 2,
+            _ => 0,
+        }
     }
 
-    match int_mode {
-        Mode::M => {
-            if reg.mideleg & int_code == 0 {
-                m_int(reg, pre_mode, current_mode, int_code);
-            } else {
-                s_int(reg, pre_mode, current_mode, int_code);
-            }
+    /// mtval or stval holds a guest virtual address. Guest-page faults always hold one,
+    /// and the other exceptions with an address do when raised with V=1.
+    /// Faults of hlv and hsv executed with V=0 leave GVA clear.
+    fn guest_va(&self, virt: bool) -> bool {
+        match self {
+            Exception::InstructionGuestPageFault(..)
+            | Exception::LoadGuestPageFault(..)
+            | Exception::StoreGuestPageFault(..) => true,
+            Exception::IllegalInstruction(_)
+            | Exception::VirtualInstruction(_)
+            | Exception::EnvironmentCallFromU
+            | Exception::EnvironmentCallFromS
+            | Exception::EnvironmentCallFromVS
+            | Exception::EnvironmentCallFromM => false,
+            _ => virt,
         }
-        Mode::S => s_int(reg, pre_mode, current_mode, int_code),
-        _ => (),
     }
+}
+
+/// Interrupts in decreasing priority order.
+const INT_PRIORITY: [u64; 10] = [
+    MIP_MEIP, MIP_MSIP, MIP_MTIP, MIP_SEIP, MIP_SSIP, MIP_STIP, MIP_SGEIP, MIP_VSEIP, MIP_VSSIP,
+    MIP_VSTIP,
+];
 
-    // free the interrupt pending bit
-    match int_mode {
-        Mode::M => reg.mip &= !int_code,
-        Mode::S => reg.sip &= !int_code,
-        _ => (),
+/// Timer interrupts are level-triggered.
+/// mip.MTIP stays pending while mtime >= mtimecmp, and is cleared by writing mtimecmp.
+/// With menvcfg.STCE, mip.STIP is raised the same way by stimecmp instead of M-mode software.
+/// mip.VSTIP is hvip.VSTIP, or raised by vstimecmp against the guest time with henvcfg.STCE.
+pub fn timer_int(reg: &mut Register, mtime: u64, mtimecmp: u64) {
+    set_pending(reg, MIP_MTIP, mtime >= mtimecmp);
+    if reg.stce() {
+        set_pending(reg, MIP_STIP, mtime >= reg.stimecmp);
     }
+    let vstime = mtime.wrapping_add(reg.htimedelta);
+    set_pending(
+        reg,
+        MIP_VSTIP,
+        reg.hvip_vstip || (reg.vstce() && vstime >= reg.vstimecmp),
+    );
 }
 
-fn get_int_code(reg: &Register) -> (u64, Mode) {
-    if reg.mip != 0 {
-        if reg.mip & MIP_SSIP != 0 {
-            return (MIP_SSIP, Mode::M);
-        }
-        if reg.mip & MIP_MSIP != 0 {
-            return (MIP_MSIP, Mode::M);
-        }
-        if reg.mip & MIP_STIP != 0 {
-            return (MIP_STIP, Mode::M);
-        }
-        if reg.mip & MIP_MTIP != 0 {
-            return (MIP_MTIP, Mode::M);
-        }
-        if reg.mip & MIP_SEIP != 0 {
-            return (MIP_SEIP, Mode::M);
-        }
-        if reg.mip & MIP_MEIP != 0 {
-            return (MIP_MEIP, Mode::M);
-        }
+fn set_pending(reg: &mut Register, bit: u64, pending: bool) {
+    if pending {
+        reg.mip |= bit;
+    } else {
+        reg.mip &= !bit;
     }
+}
 
-    if reg.sip & MIP_SSIP != 0 {
-        return (MIP_SSIP, Mode::S);
-    }
-    if reg.sip & MIP_MSIP != 0 {
-        return (MIP_MSIP, Mode::S);
-    }
-    if reg.sip & MIP_STIP != 0 {
-        return (MIP_STIP, Mode::S);
+/// mip.MSIP follows the msip register of the hart in the CLINT.
+pub fn soft_int(reg: &mut Register, msip: bool) {
+    set_pending(reg, MIP_MSIP, msip);
+}
+
+/// mip.MEIP follows the M-mode context of the hart in the PLIC. The S-mode context is the SEIP line,
+/// which is ORed with the SEIP bit written by M-mode software rather than overwriting it.
+pub fn ext_int(reg: &mut Register, meip: bool, seip: bool) {
+    set_pending(reg, MIP_MEIP, meip);
+    reg.seip_line = seip;
+}
+
+/// Take the highest priority interrupt that is pending and enabled. Returns true when one is taken.
+/// An interrupt is taken in S-mode when it is delegated by mideleg, otherwise in M-mode.
+/// A delegated interrupt is taken in VS-mode when it is also delegated by hideleg.
+/// Interrupts for a more privileged mode than the current one are always enabled,
+/// and interrupts for a less privileged mode are always disabled.
+/// The pending bit is not cleared here. The source clears it.
+pub fn int(reg: &mut Register, current_mode: &mut Mode) -> bool {
+    let pending = reg.read_mip() & reg.mie;
+    if pending == 0 {
+        return false;
     }
-    if reg.sip & MIP_MTIP != 0 {
-        return (MIP_MTIP, Mode::S);
+
+    let m_enabled = match current_mode {
+        Mode::M => reg.mstatus & MSTATUS_MIE != 0,
+        _ => true,
+    };
+    // HS-mode is more privileged than VS-mode and VU-mode
+    let s_enabled = match current_mode {
+        Mode::M => false,
+        Mode::S if !reg.virt => reg.mstatus & SSTATUS_SIE != 0,
+        _ => true,
+    };
+    let vs_enabled = match current_mode {
+        Mode::S if reg.virt => reg.vsstatus & SSTATUS_SIE != 0,
+        Mode::U => reg.virt,
+        _ => false,
+    };
+
+    // interrupts for M-mode are serviced before interrupts for HS-mode, and those before VS-mode
+    let mideleg = reg.mideleg | MIDELEG_RO;
+    let m_pending = pending & !mideleg;
+    let s_pending = pending & mideleg & !reg.hideleg;
+    let vs_pending = pending & mideleg & reg.hideleg;
+    if m_enabled && m_pending != 0 {
+        m_int(reg, current_mode, int_code(m_pending));
+        return true;
     }
-    if reg.sip & MIP_SEIP != 0 {
-        return (MIP_SEIP, Mode::S);
+    if s_enabled && s_pending != 0 {
+        s_int(reg, current_mode, int_code(s_pending));
+        return true;
     }
-    if reg.sip & MIP_MEIP != 0 {
-        return (MIP_MEIP, Mode::S);
+    if vs_enabled && vs_pending != 0 {
+        // VS-mode sees VSSI, VSTI and VSEI as SSI, STI and SEI
+        vs_int(reg, current_mode, int_code(vs_pending) - 1);
+        return true;
     }
+    false
+}
 
-    panic!(
-        "ivalid interrupt code. reg.mip: {}, reg.sip: {}",
-        reg.mip, reg.sip
-    );
+/// Exception code of the highest priority interrupt in `pending`.
+fn int_code(pending: u64) -> u64 {
+    let bit = INT_PRIORITY
+        .iter()
+        .find(|&&bit| pending & bit != 0)
+        .expect("no pending interrupt");
+    bit.trailing_zeros() as u64
 }
 
-fn is_disabled_int(reg: &Register, int_code: u64, int_mode: &Mode) -> bool {
-    match *int_mode {
-        Mode::M => {
-            if (int_code & reg.mie) == 0 {
-                return true;
-            }
-        }
-        Mode::S => {
-            if (int_code & reg.sie) == 0 {
-                return true;
-            }
-        }
-        _ => (),
+/// Address of the interrupt handler. Vectored mode jumps to BASE + 4 * cause.
+fn int_vector(tvec: u64, int_code: u64) -> u64 {
+    match tvec & 0b11 {
+        1 => (tvec & !0b11) + 4 * int_code,
+        _ => tvec & !0b11,
     }
-    false
 }
 
-fn m_int(reg: &mut Register, pre_mode: Mode, current_mode: &mut Mode, _int_code: u64) {
-    *current_mode = Mode::M;
+fn m_int(reg: &mut Register, current_mode: &mut Mode, int_code: u64) {
+    m_trap(reg, current_mode, INTERRUPT | int_code, 0, 0, false);
+    reg.pc = int_vector(reg.mtvec, int_code);
+}
 
-    let mstatus_mie = (reg.mstatus & MSTATUS_MIE) >> 3;
-    let mstatus_mpie = mstatus_mie << 7;
-    reg.mstatus |= mstatus_mpie;
-    reg.mstatus &= !MSTATUS_MIE;
-    match pre_mode {
-        Mode::M => reg.mstatus |= 0b1_1000_0000_0000,
-        Mode::S => reg.mstatus |= 0b0_1000_0000_0000,
-        Mode::U => reg.mstatus |= 0b0_0000_0000_0000,
-    }
+fn s_int(reg: &mut Register, current_mode: &mut Mode, int_code: u64) {
+    s_trap(reg, current_mode, INTERRUPT | int_code, 0, 0, false);
+    reg.pc = int_vector(reg.stvec, int_code);
+}
 
-    reg.mepc = reg.pc;
-    reg.pc = reg.mtvec;
+fn vs_int(reg: &mut Register, current_mode: &mut Mode, int_code: u64) {
+    vs_trap(reg, current_mode, INTERRUPT | int_code, 0);
+    reg.pc = int_vector(reg.vstvec, int_code);
 }
 
-fn s_int(reg: &mut Register, pre_mode: Mode, current_mode: &mut Mode, _int_code: u64) {
-    *current_mode = Mode::S;
+/// Raise a synchronous exception.
+/// The exception is taken in S-mode when it is delegated by medeleg
+/// and the hart is not running in M-mode, otherwise in M-mode.
+/// A delegated exception raised with V=1 is taken in VS-mode when it is also delegated by hedeleg.
+pub fn exception(reg: &mut Register, current_mode: &mut Mode, e: Exception) {
+    let e_code = e.code();
+    let tval = e.tval();
+    let gva = e.guest_va(reg.virt);
+    if *current_mode != Mode::M && (reg.medeleg >> e_code) & 1 == 1 {
+        if reg.virt && (reg.hedeleg >> e_code) & 1 == 1 {
+            vs_trap(reg, current_mode, e_code, tval);
+            reg.pc = reg.vstvec & !0b11;
+        } else {
+            s_trap(reg, current_mode, e_code, tval, e.tval2(), gva);
+            reg.pc = reg.stvec & !0b11;
+        }
+    } else {
+        m_trap(reg, current_mode, e_code, tval, e.tval2(), gva);
+        // synchronous exceptions always use the base address even in vectored mode.
+        reg.pc = reg.mtvec & !0b11;
+    }
+}
 
-    let sstatus_sie = (reg.sstatus & SSTATUS_SIE) >> 1;
-    let sstatus_spie = sstatus_sie << 5;
-    reg.sstatus |= sstatus_spie;
-    reg.sstatus &= !SSTATUS_SIE;
-    match pre_mode {
-        Mode::S => reg.sstatus |= 0b1_0000_0000,
-        Mode::U => reg.sstatus |= 0b0_0000_0000,
-        _ => (),
+/// Enter M-mode. mstatus.MPV and mstatus.GVA record a trap from a guest.
+fn m_trap(
+    reg: &mut Register,
+    current_mode: &mut Mode,
+    cause: u64,
+    tval: u64,
+    tval2: u64,
+    gva: bool,
+) {
+    // mstatus.MPIE = mstatus.MIE; mstatus.MIE = 0
+    let mpie = (reg.mstatus & MSTATUS_MIE) << 4;
+    reg.mstatus = (reg.mstatus & !(MSTATUS_MPIE | MSTATUS_MIE)) | mpie;
+    // mstatus.MPP = previous privilege mode
+    let mpp = match current_mode {
+        Mode::M => 0b11,
+        Mode::S => 0b01,
+        Mode::U => 0b00,
+    };
+    reg.mstatus = (reg.mstatus & !MSTATUS_MPP) | (mpp << 11);
+    // mstatus.MPV = V; mstatus.GVA = gva
+    reg.mstatus &= !(MSTATUS_MPV | MSTATUS_GVA);
+    if reg.virt {
+        reg.mstatus |= MSTATUS_MPV;
+    }
+    if gva {
+        reg.mstatus |= MSTATUS_GVA;
     }
 
-    reg.sepc = reg.pc;
-    reg.pc = reg.stvec;
+    reg.mcause = cause;
+    reg.mepc = reg.pc;
+    reg.mtval = tval;
+    reg.mtval2 = tval2;
+    reg.mtinst = 0;
+    reg.virt = false;
+    *current_mode = Mode::M;
 }
 
-#[allow(dead_code)]
-pub fn m_trap(reg: &mut Register, current_mode: &mut Mode) {
-    let e_code = reg.mcause & 0x7FFF_FFFF_FFF_FFF;
-    //trap(reg, current_mode, e_code);
-    if reg.medeleg & e_code != 0 {
-        s_exception(reg, current_mode, e_code);
-    } else {
-        m_exception(reg, current_mode, e_code);
+/// Enter HS-mode. hstatus.SPV, hstatus.SPVP and hstatus.GVA record a trap from a guest.
+fn s_trap(
+    reg: &mut Register,
+    current_mode: &mut Mode,
+    cause: u64,
+    tval: u64,
+    tval2: u64,
+    gva: bool,
+) {
+    // sstatus.SPIE = sstatus.SIE; sstatus.SIE = 0
+    let spie = (reg.mstatus & SSTATUS_SIE) << 4;
+    reg.mstatus = (reg.mstatus & !(SSTATUS_SPIE | SSTATUS_SIE)) | spie;
+    // sstatus.SPP = previous privilege mode
+    match current_mode {
+        Mode::U => reg.mstatus &= !SSTATUS_SPP,
+        _ => reg.mstatus |= SSTATUS_SPP,
+    }
+    // hstatus.SPV = V; hstatus.SPVP = previous privilege mode when V=1; hstatus.GVA = gva
+    reg.hstatus &= !(HSTATUS_SPV | HSTATUS_GVA);
+    if reg.virt {
+        reg.hstatus |= HSTATUS_SPV;
+        match current_mode {
+            Mode::U => reg.hstatus &= !HSTATUS_SPVP,
+            _ => reg.hstatus |= HSTATUS_SPVP,
+        }
+    }
+    if gva {
+        reg.hstatus |= HSTATUS_GVA;
     }
-}
 
-#[allow(dead_code)]
-pub fn s_trap(reg: &mut Register, _current_mode: &mut Mode) {
-    let _e_code = reg.scause & 0x7FFF_FFFF_FFF_FFF;
+    reg.scause = cause;
+    reg.sepc = reg.pc;
+    reg.stval = tval;
+    reg.htval = tval2;
+    reg.htinst = 0;
+    reg.virt = false;
+    *current_mode = Mode::S;
 }
 
-#[allow(dead_code)]
-fn m_exception(_reg: &mut Register, mode: &mut Mode, _e_code: u64) {
-    *mode = Mode::M;
-}
+/// Enter VS-mode. The guest sees vsstatus, vscause, vsepc and vstval as its sstatus, scause, sepc and stval.
+fn vs_trap(reg: &mut Register, current_mode: &mut Mode, cause: u64, tval: u64) {
+    // vsstatus.SPIE = vsstatus.SIE; vsstatus.SIE = 0
+    let spie = (reg.vsstatus & SSTATUS_SIE) << 4;
+    reg.vsstatus = (reg.vsstatus & !(SSTATUS_SPIE | SSTATUS_SIE)) | spie;
+    // vsstatus.SPP = previous privilege mode
+    match current_mode {
+        Mode::U => reg.vsstatus &= !SSTATUS_SPP,
+        _ => reg.vsstatus |= SSTATUS_SPP,
+    }
 
-#[allow(dead_code)]
-fn s_exception(_reg: &mut Register, mode: &mut Mode, _e_code: u64) {
-    *mode = Mode::S;
+    reg.vscause = cause;
+    reg.vsepc = reg.pc;
+    reg.vstval = tval;
+    *current_mode = Mode::S;
 }
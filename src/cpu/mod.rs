@@ -1,45 +1,195 @@
+mod fpu;
+mod hypervisor;
+mod icache;
 pub mod instructions;
 mod int;
 pub mod register;
+mod tlb;
+mod vector;
 use crate::bus::Bus;
 use crate::conf;
 use crate::conf::MEM_OFF;
 use crate::dbg::Debug;
+use crate::imsic::{self, Imsic};
 use crate::util;
+use fpu::Precision;
+use icache::{Block, Cursor, ICache, BLOCK_INSTS};
 use instructions::InstName;
 use instructions::Instruction;
+use int::Exception;
 use register::Register;
+use register::{ENVCFG_CBCFE, ENVCFG_CBIE, ENVCFG_CBZE};
+use register::{HPM_BRANCH, HPM_LOAD, HPM_STORE, HPM_TLB_MISS};
 use std::io::{stdout, Write};
+use std::sync::atomic::{self, Ordering};
+use std::sync::Arc;
+use tlb::{Tlb, TlbEntry};
+use vector::VectorRegister;
 
-const MTIME: u64 = 0x200_BFF8;
-const MTIMECMP: u64 = 0x200_4000;
+const MIE_STIE: u64 = 0b10_0000;
+const MIE_VSTIE: u64 = 0b100_0000;
+const MIE_MTIE: u64 = 0b1000_0000;
 
 // paging
 const BARE: u64 = 0x00;
 const SV39: u64 = 0x08;
 const SV48: u64 = 0x09;
-const SV57: u64 = 0x10;
-const SV64: u64 = 0x11;
+const SV57: u64 = 0x0A;
 const SATP_PPN: u64 = 0xFFF_FFFF_FFFF;
-const PAGE_OFF: u64 = 0xFFF;
+const SATP_ASID: u64 = 0x0FFF_F000_0000_0000; // 44..59
+const PAGE_SIZE: u64 = 4096;
+const PAGE_OFF_SIZE: u64 = 12; // bit
 const VPN_SIZE: u64 = 9; // bit
+const VPN_MASK: u64 = 0x1FF;
+const PTE_SIZE: u64 = 8;
+
+// pmpcfg
+const PMP_R: u8 = 0b0000_0001;
+const PMP_W: u8 = 0b0000_0010;
+const PMP_X: u8 = 0b0000_0100;
+const PMP_A: u8 = 0b0001_1000;
+const PMP_L: u8 = 0b1000_0000;
+const PMP_TOR: u8 = 1;
+const PMP_NA4: u8 = 2;
+const PMP_NAPOT: u8 = 3;
+
+// page table entry
+const PTE_V: u64 = 0b0000_0001; // Valid
+const PTE_R: u64 = 0b0000_0010; // Readable
+const PTE_W: u64 = 0b0000_0100; // Writable
+const PTE_X: u64 = 0b0000_1000; // Executable
+const PTE_U: u64 = 0b0001_0000; // User
+const PTE_G: u64 = 0b0010_0000; // Global
+const PTE_A: u64 = 0b0100_0000; // Accessed
+const PTE_D: u64 = 0b1000_0000; // Dirty
+const PTE_PPN: u64 = 0x3F_FFFF_FFFF_FC00; // 10..53
+const PTE_RESERVED: u64 = 0x1FC0_0000_0000_0000; // 54..60
+const PTE_PBMT: u64 = 0x6000_0000_0000_0000; // 61..62 page-based memory type (Svpbmt)
+const PTE_N: u64 = 1 << 63; // NAPOT translation (Svnapot)
+const PBMT_RESERVED: u64 = 0x6000_0000_0000_0000; // PMA: 0, NC: 1, IO: 2
+const NAPOT_64K: u64 = 0b1000; // ppn[3:0] of a 64 KiB NAPOT page
+const NAPOT_64K_MASK: u64 = 0b1111;
+
+// mstatus
+const MSTATUS_MPP: u64 = 0b1_1000_0000_0000;
+const MSTATUS_MPRV: u64 = 0b10_0000_0000_0000_0000;
+const MSTATUS_SUM: u64 = 0b100_0000_0000_0000_0000;
+const MSTATUS_MXR: u64 = 0b1000_0000_0000_0000_0000;
+const MSTATUS_TVM: u64 = 0b1_0000_0000_0000_0000_0000;
+const MSTATUS_TW: u64 = 0b10_0000_0000_0000_0000_0000;
+const MSTATUS_TSR: u64 = 0b100_0000_0000_0000_0000_0000;
+const MSTATUS_MPV: u64 = 1 << 39;
+
+// hstatus
+const HSTATUS_SPV: u64 = 0b1000_0000;
+const HSTATUS_SPVP: u64 = 0b1_0000_0000;
+const HSTATUS_HU: u64 = 0b10_0000_0000;
+const HSTATUS_VTVM: u64 = 1 << 20;
+const HSTATUS_VTW: u64 = 1 << 21;
+const HSTATUS_VTSR: u64 = 1 << 22;
+
+/// Split an access of `size` bytes at the page boundary it crosses.
+/// Returns the address, the size and the bit offset in the data of each part.
+fn page_parts(va: u64, size: u64) -> Vec<(u64, u64, u64)> {
+    let first = (PAGE_SIZE - (va & (PAGE_SIZE - 1))).min(size);
+    let mut parts = vec![(va, first, 0)];
+    if first < size {
+        parts.push((va.wrapping_add(first), size - first, first * 8));
+    }
+    parts
+}
 
-// Sv39
-const SV39_VPN: u64 = 0x7F_C000_0000;
-const SV39_PTE_PPN: u64 = 0x3F_FFFF_FFFF_FC00;
-
-#[derive(Debug, PartialEq)]
+/// Privilege mode. VS-mode and VU-mode are S-mode and U-mode with the virtualization mode
+/// `reg.virt` set, and S-mode with V=0 is HS-mode.
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Mode {
     M,
     S,
     U,
 }
 
-#[derive(Debug, PartialEq)]
-enum PMPPerm {
-    R,
-    W,
-    X,
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum AccessType {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl AccessType {
+    fn page_fault(&self, va: u64) -> Exception {
+        match self {
+            AccessType::Fetch => Exception::InstructionPageFault(va),
+            AccessType::Load => Exception::LoadPageFault(va),
+            AccessType::Store => Exception::StorePageFault(va),
+        }
+    }
+
+    fn access_fault(&self, addr: u64) -> Exception {
+        match self {
+            AccessType::Fetch => Exception::InstructionAccessFault(addr),
+            AccessType::Load => Exception::LoadAccessFault(addr),
+            AccessType::Store => Exception::StoreAccessFault(addr),
+        }
+    }
+
+    fn guest_page_fault(&self, va: u64, gpa: u64) -> Exception {
+        match self {
+            AccessType::Fetch => Exception::InstructionGuestPageFault(va, gpa),
+            AccessType::Load => Exception::LoadGuestPageFault(va, gpa),
+            AccessType::Store => Exception::StoreGuestPageFault(va, gpa),
+        }
+    }
+}
+
+/// Check R/W/X/U bits of a leaf PTE for the access in `mode`.
+///   sum -- S-mode may load and store U-mode pages.
+///   mxr -- executable pages are also readable.
+fn pte_permitted(pte: u64, access: AccessType, mode: Mode, sum: bool, mxr: bool) -> bool {
+    let readable = pte & PTE_R != 0 || (mxr && pte & PTE_X != 0);
+    let permitted = match access {
+        AccessType::Fetch => pte & PTE_X != 0,
+        AccessType::Load => readable,
+        AccessType::Store => pte & PTE_W != 0,
+    };
+    let user = pte & PTE_U != 0;
+    let privileged = match mode {
+        Mode::U => user,
+        Mode::S => !user || (sum && access != AccessType::Fetch),
+        Mode::M => true,
+    };
+    permitted && privileged
+}
+
+/// Whether the PTE sets bits or encodings reserved for future standard use.
+/// PBMT is reserved without Svpbmt enabled by `pbmte`, and in non-leaf PTEs with N.
+/// The memory is never cached, so the NC and IO memory types behave as the PMA of the address,
+/// which already makes the devices I/O.
+fn pte_reserved(pte: u64, pbmte: bool) -> bool {
+    let leaf = pte & (PTE_R | PTE_X) != 0;
+    let pbmt = pte & PTE_PBMT;
+    pte & PTE_RESERVED != 0
+        || pbmt == PBMT_RESERVED
+        || (pbmt != 0 && !pbmte)
+        || (!leaf && pte & (PTE_PBMT | PTE_N) != 0)
+}
+
+/// Physical page number of the 4 KiB page of the virtual address, mapped by the leaf PTE at level `i`.
+/// A superpage must be aligned to its size. A NAPOT PTE is a level-0 leaf with ppn[3:0] = 0b1000,
+/// mapping 64 KiB whose lower 4 bits of the page number come from the virtual address.
+/// Returns None for a misaligned superpage or a NAPOT PTE of another size.
+fn leaf_ppn(pte: u64, va: u64, i: u64) -> Option<u64> {
+    let ppn = (pte & PTE_PPN) >> 10;
+    if pte & PTE_N != 0 {
+        if i != 0 || ppn & NAPOT_64K_MASK != NAPOT_64K {
+            return None;
+        }
+        return Some(ppn & !NAPOT_64K_MASK | (va >> PAGE_OFF_SIZE) & NAPOT_64K_MASK);
+    }
+    let superpage_mask = (1 << (VPN_SIZE * i)) - 1;
+    if ppn & superpage_mask != 0 {
+        return None;
+    }
+    Some(ppn | (va >> PAGE_OFF_SIZE) & superpage_mask)
 }
 
 #[derive(Debug)]
@@ -47,74 +197,128 @@ pub struct Cpu {
     dbg: Debug,
     dbg_step: bool,
 
-    bus: Bus,
-    mem_reserved_w: Vec<u8>,
+    bus: Arc<Bus>,
     mode: Mode, // privilege mode
-
-    // memory mapped
-    mtime: u64,
-    mtimecmp: u64,
+    tlb: Tlb,
+    icache: ICache,
+    cursor: Option<Cursor>, // the decoded block being executed
+    next_pc: u64,           // pc of the next instruction. jumps and branches overwrite it.
+    wfi: bool,              // halted by WFI until an interrupt is pending
+    busy_wfi: bool,         // WFI is a no-op and the hart keeps running
+    trap_misaligned: bool,  // misaligned loads and stores raise address-misaligned exceptions
+    cache_block: u64,       // bytes of a cache block for the cache-block operations
 
     reg: Register,
+    vreg: VectorRegister,
 }
 
 impl Cpu {
-    pub fn new(bus: Bus, mem_size: usize, dbg: Debug) -> Cpu {
+    pub fn new(
+        bus: Arc<Bus>,
+        hartid: u64,
+        dbg: Debug,
+        busy_wfi: bool,
+        trap_misaligned: bool,
+        cache_block: u64,
+        vlen: u64,
+    ) -> Cpu {
         let dbg_step = if dbg.bp == 0 { true } else { false };
+        let mut reg = Register::new();
+        reg.mhartid = hartid;
+        reg.vlenb = vlen / 8;
 
         Cpu {
             dbg,
             dbg_step: dbg_step,
 
             bus,
-            mem_reserved_w: vec![0; mem_size / 32],
             mode: Mode::M,
-
-            mtime: 0,
-            mtimecmp: 0,
-
-            reg: Register::new(),
+            tlb: Tlb::new(),
+            icache: ICache::new(),
+            cursor: None,
+            next_pc: 0,
+            wfi: false,
+            busy_wfi,
+            trap_misaligned,
+            cache_block,
+
+            reg,
+            vreg: VectorRegister::new(vlen),
         }
     }
 
     pub fn print(&self) {
-        println!("mode:\t {:?}", self.mode);
+        println!("mode:\t {:?} (V={})", self.mode, self.reg.virt as u8);
         self.reg.print();
-        println!("mtime:\t\t0x{:016X}, 0b{:064b}", self.mtime, self.mtime);
-        println!(
-            "mtimecmp:\t0x{:016X}, 0b{:064b}",
-            self.mtimecmp, self.mtimecmp
-        );
+        let mtime = self.bus.clint().mtime();
+        let mtimecmp = self.bus.clint().mtimecmp(self.hartid());
+        println!("mtime:\t\t0x{:016X}, 0b{:064b}", mtime, mtime);
+        println!("mtimecmp:\t0x{:016X}, 0b{:064b}", mtimecmp, mtimecmp);
     }
 
-    pub fn pdram_range(&self, begin: usize, end: usize) {
-        self.bus.pdram_range(begin, end);
+    fn hartid(&self) -> usize {
+        self.reg.mhartid as usize
     }
 
-    fn l_mm(&self, addr: u64) -> u64 {
-        match addr {
-            MTIME => self.mtime,
-            MTIMECMP => self.mtimecmp,
-            _ => self.bus.l_mm(addr),
+    /// Privilege mode used for the memory access.
+    /// Loads and stores in M-mode use mstatus.MPP when mstatus.MPRV is set.
+    fn effective_mode(&self, access: AccessType) -> Mode {
+        if access != AccessType::Fetch
+            && self.mode == Mode::M
+            && self.reg.mstatus & MSTATUS_MPRV != 0
+        {
+            return match (self.reg.mstatus & MSTATUS_MPP) >> 11 {
+                0b00 => Mode::U,
+                0b01 => Mode::S,
+                _ => Mode::M,
+            };
         }
+        self.mode
     }
 
-    fn s_mm(&mut self, addr: u64, data: u64) {
-        match addr {
-            MTIME => self.mtime = data,
-            MTIMECMP => self.mtimecmp = data,
-            _ => self.bus.s_mm(addr, data),
+    /// Virtualization mode used for the memory access.
+    /// Loads and stores in M-mode use mstatus.MPV when mstatus.MPRV is set and mstatus.MPP is not M.
+    fn effective_virt(&self, access: AccessType) -> bool {
+        if access != AccessType::Fetch
+            && self.mode == Mode::M
+            && self.reg.mstatus & MSTATUS_MPRV != 0
+        {
+            return self.reg.mstatus & MSTATUS_MPV != 0 && self.effective_mode(access) != Mode::M;
         }
+        self.reg.virt
     }
 
-    fn trans_addr(&self, addr: u64) -> u64 {
+    /// Translate the virtual address to the physical address.
+    /// M-mode accesses are not translated, and guest accesses are translated in two stages.
+    fn trans_addr(&mut self, addr: u64, access: AccessType) -> Result<u64, Exception> {
+        if self.effective_virt(access) {
+            let mode = self.effective_mode(access);
+            return self.trans_guest(addr, access, access, mode);
+        }
+        if self.effective_mode(access) == Mode::M || self.reg.satp >> 60 == BARE {
+            return Ok(addr);
+        }
+
+        let vpn = addr >> PAGE_OFF_SIZE;
+        let asid = ((self.reg.satp & SATP_ASID) >> 44) as u16;
+        if let Some(e) = self.tlb.lookup(vpn, asid) {
+            // a store to a clean page walks the table again to set the D bit
+            let dirty = access != AccessType::Store || e.pte & PTE_D != 0;
+            if dirty && self.leaf_permitted(e.pte, access) {
+                self.tlb.hit += 1;
+                return Ok((e.ppn * PAGE_SIZE) | (addr & (PAGE_SIZE - 1)));
+            }
+        }
+        self.tlb.miss += 1;
+        self.reg.count_event(HPM_TLB_MISS);
+
         match self.reg.satp >> 60 {
-            BARE => addr,
-            SV39 => self.sv39(addr),
-            SV48 => self.sv48(addr),
-            SV57 => self.sv57(addr),
-            SV64 => self.sv64(addr),
-            _ => panic!("invalid satp mode"),
+            BARE => Ok(addr),
+            SV39 => self.sv39(addr, access),
+            SV48 => self.sv48(addr, access),
+            SV57 => self.sv57(addr, access),
+            // write_csr ignores writes to satp with an unsupported mode, and the reset value is Bare
+            _ => unreachable!("unsupported satp mode"),
         }
     }
 
@@ -123,70 +327,276 @@ impl Cpu {
     ///   21..29 -- 9 bits of level-1 index.
     ///   12..20 -- 9 bits of level-0 index.
     ///    0..11 -- 12 bits of byte offset within the page.
-    fn sv39(&self, va: u64) -> u64 {
-        let ppn = self.reg.satp & SATP_PPN;
-        let ppn = ppn << 12; // * 4096 (page size)
-        let vpn = (va & SV39_VPN) >> 30;
-        let vpn = vpn << 3; // * 8 (pte size)
-        let mut pte = self.bus.ld_dram(ppn + vpn - MEM_OFF as u64);
-
-        let mut vpn_shift = 21;
-        for i in 1..3 {
-            if pte & 0x1 == 0 {
-                panic!("page fault");
+    fn sv39(&mut self, va: u64, access: AccessType) -> Result<u64, Exception> {
+        self.page_walk(va, access, 3)
+    }
+
+    /// Sv48 virtual address
+    ///   39..47 -- 9 bits of level-3 index.
+    ///   30..38 -- 9 bits of level-2 index.
+    ///   21..29 -- 9 bits of level-1 index.
+    ///   12..20 -- 9 bits of level-0 index.
+    ///    0..11 -- 12 bits of byte offset within the page.
+    fn sv48(&mut self, va: u64, access: AccessType) -> Result<u64, Exception> {
+        self.page_walk(va, access, 4)
+    }
+
+    /// Sv57 virtual address
+    ///   48..56 -- 9 bits of level-4 index.
+    ///   39..47 -- 9 bits of level-3 index.
+    ///   30..38 -- 9 bits of level-2 index.
+    ///   21..29 -- 9 bits of level-1 index.
+    ///   12..20 -- 9 bits of level-0 index.
+    ///    0..11 -- 12 bits of byte offset within the page.
+    fn sv57(&mut self, va: u64, access: AccessType) -> Result<u64, Exception> {
+        self.page_walk(va, access, 5)
+    }
+
+    /// Walk the `levels` level page table.
+    /// Follows "Virtual Address Translation Process" of the privileged architecture.
+    ///   1. a = satp.ppn * PAGESIZE, i = levels - 1
+    ///   2. pte = M[a + va.vpn[i] * PTESIZE]
+    ///   3. pte.v = 0, pte.r = 0 and pte.w = 1, or reserved bits, including PBMT without menvcfg.PBMTE -- page fault
+    ///   4. pte.r = 0 and pte.x = 0 -- a = pte.ppn * PAGESIZE, i = i - 1, goto 2
+    ///   5. leaf PTE -- check R/W/X/U with mstatus.SUM and mstatus.MXR
+    ///   6. misaligned superpage or invalid NAPOT PTE -- page fault
+    ///   7. set pte.a, and pte.d on a store
+    ///   8. pa = pte.ppn[levels - 1:i] | va.vpn[i - 1:0] | va.pgoff
+    ///   9. NAPOT PTE -- ppn[3:0] = va.vpn[0][3:0]
+    fn page_walk(&mut self, va: u64, access: AccessType, levels: u64) -> Result<u64, Exception> {
+        // the upper bits must be copies of the highest bit of the virtual address
+        let va_bits = PAGE_OFF_SIZE + VPN_SIZE * levels;
+        let upper = (va as i64) >> (va_bits - 1);
+        if upper != 0 && upper != -1 {
+            return Err(access.page_fault(va));
+        }
+
+        let mut a = (self.reg.satp & SATP_PPN) * PAGE_SIZE;
+        let mut i = levels - 1;
+        let mut global = false;
+        let (pte, pte_addr) = loop {
+            let vpn = (va >> (PAGE_OFF_SIZE + VPN_SIZE * i)) & VPN_MASK;
+            let pte_addr = a + vpn * PTE_SIZE;
+            let pte = self.load_pte(va, pte_addr, access)?;
+
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(access.page_fault(va));
+            }
+            if pte_reserved(pte, self.reg.pbmte()) {
+                return Err(access.page_fault(va));
+            }
+            // a global pointer makes all mappings below it global
+            global |= pte & PTE_G != 0;
+            if pte & (PTE_R | PTE_X) != 0 {
+                break (pte, pte_addr);
+            }
+            // pointer to the next level of the page table
+            if i == 0 {
+                return Err(access.page_fault(va));
             }
-            let ppn = pte & SV39_PTE_PPN;
-            let ppn = ppn << 2; // * 4096 (page size)
-            let vpn = (va & SV39_VPN >> (VPN_SIZE * i)) >> vpn_shift;
-            let vpn = vpn << 3; // * 8 (pte size)
-            pte = self.bus.ld_dram(ppn + vpn - MEM_OFF as u64);
-            vpn_shift -= VPN_SIZE;
+            i -= 1;
+            a = ((pte & PTE_PPN) >> 10) * PAGE_SIZE;
+        };
+
+        if !self.leaf_permitted(pte, access) {
+            return Err(access.page_fault(va));
+        }
+
+        let ppn = match leaf_ppn(pte, va, i) {
+            Some(ppn) => ppn,
+            None => return Err(access.page_fault(va)),
+        };
+
+        let mut new_pte = pte | PTE_A;
+        if access == AccessType::Store {
+            new_pte |= PTE_D;
         }
+        if new_pte != pte {
+            self.store_pte(va, pte_addr, new_pte, access)?;
+        }
+
+        let pa = (ppn * PAGE_SIZE) | (va & (PAGE_SIZE - 1));
 
-        let ppn = pte & SV39_PTE_PPN;
-        let ppn = ppn << 2; // * 4096 (page size)
-        let pa = ppn + (va & PAGE_OFF);
-        pa
+        let entry = TlbEntry {
+            ppn: pa >> PAGE_OFF_SIZE,
+            pte: new_pte,
+            level: i,
+        };
+        let asid = ((self.reg.satp & SATP_ASID) >> 44) as u16;
+        self.tlb.insert(va >> PAGE_OFF_SIZE, asid, global, entry);
+        Ok(pa)
     }
 
-    fn sv48(&self, _va: u64) -> u64 {
-        0
+    /// Check R/W/X/U bits of the leaf PTE with mstatus.SUM and mstatus.MXR.
+    fn leaf_permitted(&self, pte: u64, access: AccessType) -> bool {
+        let sum = self.reg.mstatus & MSTATUS_SUM != 0;
+        let mxr = self.reg.mstatus & MSTATUS_MXR != 0;
+        pte_permitted(pte, access, self.effective_mode(access), sum, mxr)
     }
 
-    fn sv57(&self, _va: u64) -> u64 {
-        0
+    /// Whether the `size` bytes at the physical address are in DRAM.
+    fn in_dram(&self, addr: u64, size: u64) -> bool {
+        addr >= MEM_OFF as u64 && addr - MEM_OFF as u64 + size <= self.bus.dram_size()
     }
 
-    fn sv64(&self, _va: u64) -> u64 {
-        0
+    /// Read a page table entry. The walk raises an access fault of the original access type.
+    /// PMP checks the page table accesses as S-mode accesses.
+    /// The page table must be in DRAM, wherever satp or a non-leaf PTE points.
+    fn load_pte(&self, va: u64, addr: u64, access: AccessType) -> Result<u64, Exception> {
+        if !self.in_dram(addr, 8) || !self.pmp_permits(addr, 8, AccessType::Load, Mode::S) {
+            return Err(access.access_fault(va));
+        }
+        Ok(self.bus.ld_dram(addr - MEM_OFF as u64))
     }
 
-    pub fn init(&mut self, entry_point: usize) {
-        self.reg.sp = conf::STACK_BOTTOM;
-        self.reg.pc = entry_point as u64;
+    fn store_pte(
+        &mut self,
+        va: u64,
+        addr: u64,
+        pte: u64,
+        access: AccessType,
+    ) -> Result<(), Exception> {
+        if !self.in_dram(addr, 8) || !self.pmp_permits(addr, 8, AccessType::Store, Mode::S) {
+            return Err(access.access_fault(va));
+        }
+        self.bus.sd_dram(addr - MEM_OFF as u64, pte);
+        Ok(())
     }
 
-    pub fn run(&mut self) {
-        loop {
-            let data = self.fetch();
-            let inst = Instruction::decode(data);
+    /// Read `size` bytes from the virtual address.
+    /// A misaligned load raises an exception with `trap_misaligned`, for the firmware to emulate it.
+    /// Otherwise it is split at the page boundary, and each part is translated and checked on its own.
+    fn load(&mut self, va: u64, size: u64) -> Result<u64, Exception> {
+        self.reg.count_event(HPM_LOAD);
+        if va & (size - 1) == 0 {
+            let addr = self.trans_addr(va, AccessType::Load)?;
+            self.check_pmp(va, addr, size, AccessType::Load)?;
+            return Ok(self.load_phys(addr, size));
+        }
+        if self.trap_misaligned {
+            return Err(Exception::LoadAddressMisaligned(va));
+        }
+        let mut v = 0;
+        for (va, size, shift) in page_parts(va, size) {
+            let addr = self.trans_addr(va, AccessType::Load)?;
+            self.check_pmp(va, addr, size, AccessType::Load)?;
+            v |= self.load_bytes(va, addr, size)? << shift;
+        }
+        Ok(v)
+    }
+
+    /// Read `size` bytes from the physical address one at a time, for a misaligned load.
+    /// Devices do not support misaligned accesses, so they raise an access fault.
+    fn load_bytes(&self, va: u64, addr: u64, size: u64) -> Result<u64, Exception> {
+        if addr < MEM_OFF as u64 {
+            return Err(Exception::LoadAccessFault(va));
+        }
+        let addr = addr - MEM_OFF as u64;
+        Ok((0..size).fold(0, |v, i| v | (self.bus.lb_dram(addr + i) as u64) << (i * 8)))
+    }
+
+    /// Read `size` bytes from the physical address.
+    fn load_phys(&self, addr: u64, size: u64) -> u64 {
+        let v = if addr < MEM_OFF as u64 {
+            self.bus.l_mm(addr, size)
+        } else {
+            let addr = addr - MEM_OFF as u64;
+            match size {
+                1 => self.bus.lb_dram(addr) as u64,
+                2 => self.bus.lh_dram(addr) as u64,
+                4 => self.bus.lw_dram(addr) as u64,
+                _ => self.bus.ld_dram(addr),
+            }
+        };
+        match size {
+            1 => v as u8 as u64,
+            2 => v as u16 as u64,
+            4 => v as u32 as u64,
+            _ => v,
+        }
+    }
 
-            if self.dbg.enable && self.dbg_step {
-                println!("instruction: ");
-                inst.print();
-                println!("pc: 0x{:016X}", self.reg.pc);
+    /// Write the lower `size` bytes of the data to the virtual address.
+    /// Misaligned stores follow the policy of loads. Both parts are translated and checked
+    /// before either is written, so a fault leaves memory unchanged.
+    fn store(&mut self, va: u64, size: u64, data: u64) -> Result<(), Exception> {
+        self.reg.count_event(HPM_STORE);
+        if va & (size - 1) == 0 {
+            let addr = self.trans_addr(va, AccessType::Store)?;
+            self.check_pmp(va, addr, size, AccessType::Store)?;
+            self.store_phys(addr, size, data);
+            return Ok(());
+        }
+        if self.trap_misaligned {
+            return Err(Exception::StoreAddressMisaligned(va));
+        }
+        let mut parts = vec![];
+        for (va, size, shift) in page_parts(va, size) {
+            let addr = self.trans_addr(va, AccessType::Store)?;
+            self.check_pmp(va, addr, size, AccessType::Store)?;
+            if addr < MEM_OFF as u64 {
+                return Err(Exception::StoreAccessFault(va));
             }
+            parts.push((addr, size, shift));
+        }
+        for (addr, size, shift) in parts {
+            self.store_bytes(addr, size, data >> shift);
+        }
+        Ok(())
+    }
+
+    /// Write the lower `size` bytes of the data to the physical address in DRAM one at a time.
+    fn store_bytes(&self, addr: u64, size: u64, data: u64) {
+        let addr = addr - MEM_OFF as u64;
+        for i in 0..size {
+            self.bus.sb_dram(addr + i, (data >> (i * 8)) as u8);
+        }
+    }
 
-            let pre_pc = self.reg.pc;
-            self.exec_instruction(&inst);
+    /// Write the lower `size` bytes of the data to the physical address.
+    fn store_phys(&mut self, addr: u64, size: u64, data: u64) {
+        if addr < MEM_OFF as u64 {
+            let data = match size {
+                1 => data as u8 as u64,
+                2 => data as u16 as u64,
+                4 => data as u32 as u64,
+                _ => data,
+            };
+            self.bus.s_mm(addr, size, data);
+            return;
+        }
+        let addr = addr - MEM_OFF as u64;
+        match size {
+            1 => self.bus.sb_dram(addr, data as u8),
+            2 => self.bus.sh_dram(addr, data as u16),
+            4 => self.bus.sw_dram(addr, data as u32),
+            _ => self.bus.sd_dram(addr, data),
+        }
+    }
 
-            self.mtime += 2500;
+    pub fn init(&mut self, entry_point: usize) {
+        self.reg.sp = conf::STACK_BOTTOM;
+        self.reg.pc = entry_point as u64;
+    }
 
-            int::timer_int(&mut self.reg, &mut self.mode, self.mtime, self.mtimecmp);
+    /// Execute up to `n` instructions. Returns early when the hart is halted by WFI.
+    pub fn run(&mut self, n: u64) {
+        for _ in 0..n {
+            self.poll_int();
+            // WFI resumes on a pending and enabled interrupt even when it is globally disabled.
+            if self.reg.read_mip() & self.reg.mie != 0 {
+                self.wfi = false;
+            }
             int::int(&mut self.reg, &mut self.mode);
+            if self.wfi {
+                return;
+            }
 
-            if pre_pc == self.reg.pc {
-                self.reg.pc += 4;
+            let res = self.step();
+            // an instruction raising an exception does not retire
+            self.reg.tick(res.is_ok());
+            if let Err(e) = res {
+                int::exception(&mut self.reg, &mut self.mode, e);
             }
 
             if self.dbg.enable {
@@ -200,6 +610,81 @@ impl Cpu {
         }
     }
 
+    pub fn instret(&self) -> u64 {
+        self.reg.minstret
+    }
+
+    pub fn halted(&self) -> bool {
+        self.wfi
+    }
+
+    /// The earliest mtime at which a timer interrupt wakes the hart halted by WFI.
+    /// None when the timer interrupts are disabled.
+    /// vstimecmp compares with the guest time, mtime + htimedelta.
+    pub fn wake_time(&self) -> Option<u64> {
+        let timers = [
+            (
+                self.reg.mie & MIE_MTIE != 0,
+                self.bus.clint().mtimecmp(self.hartid()),
+            ),
+            (
+                self.reg.mie & MIE_STIE != 0 && self.reg.stce(),
+                self.reg.stimecmp,
+            ),
+            (
+                self.reg.mie & MIE_VSTIE != 0 && self.reg.vstce(),
+                self.reg.vstimecmp.wrapping_sub(self.reg.htimedelta),
+            ),
+        ];
+        timers
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, cmp)| *cmp)
+            .min()
+    }
+
+    /// Reflect the interrupt lines of the CLINT and the PLIC or the AIA for this hart in mip.
+    fn poll_int(&mut self) {
+        let hart = self.hartid();
+        let clint = self.bus.clint();
+        int::timer_int(&mut self.reg, clint.mtime(), clint.mtimecmp(hart));
+        int::soft_int(&mut self.reg, clint.msip(hart));
+        int::ext_int(
+            &mut self.reg,
+            self.bus.ext_int(hart, false),
+            self.bus.ext_int(hart, true),
+        );
+    }
+
+    /// Fetch, decode and execute one instruction.
+    /// When an exception is raised, pc still points to the instruction that caused it.
+    fn step(&mut self) -> Result<(), Exception> {
+        let inst = match self.cached_inst()? {
+            Some(inst) => inst,
+            None => Instruction::decode(self.fetch()?)?,
+        };
+
+        if self.dbg.enable && self.dbg_step {
+            println!("instruction: ");
+            inst.print();
+            println!("pc: 0x{:016X}", self.reg.pc);
+        }
+
+        self.next_pc = self.reg.pc + inst.len;
+        self.exec_instruction(&inst)?;
+        if let InstName::Beq
+        | InstName::Bne
+        | InstName::Blt
+        | InstName::Bge
+        | InstName::Bltu
+        | InstName::Bgeu = inst.name
+        {
+            self.reg.count_event(HPM_BRANCH);
+        }
+        self.reg.pc = self.next_pc;
+        Ok(())
+    }
+
     fn debug(&mut self) {
         loop {
             print!(">> ");
@@ -223,6 +708,12 @@ impl Cpu {
             } else if b.trim() == "uart".to_string() {
                 // print UART registers
                 self.bus.puart();
+            } else if b.trim() == "tlb" {
+                // print TLB hit and miss counters
+                self.tlb.print();
+            } else if b.trim() == "icache" {
+                // print decoded block hit and miss counters
+                self.icache.print();
             } else if b.starts_with("b") {
                 // set break point
                 // example: b 0x8000157c
@@ -237,664 +728,1096 @@ impl Cpu {
         println!();
     }
 
-    fn fetch(&self) -> u32 {
-        let addr = self.trans_addr(self.reg.pc);
-        self.check_pmp(addr, PMPPerm::X);
-        self.bus.lw_dram(addr - MEM_OFF as u64)
-    }
-
-    fn check_pmp(&self, addr: u64, perm: PMPPerm) {
-        if let Mode::S | Mode::U = self.mode {
-            // do PMP
-            self.blocked_by_pmp(addr, perm);
-            return;
+    /// The instruction at pc from the decoded block at the physical pc, decoding the block on a miss.
+    /// The hart continues in the current block while it falls through, and otherwise translates pc
+    /// to look the block up.
+    /// Returns None when pc is not in DRAM, the block is empty or PMP does not cover all of it,
+    /// and the instruction is fetched and decoded on its own.
+    fn cached_inst(&mut self) -> Result<Option<Instruction>, Exception> {
+        if let Some(c) = &mut self.cursor {
+            if self.bus.code_gen(c.addr) == c.block.gen {
+                if let Some(inst) = c.next(self.reg.pc, self.mode, self.reg.virt) {
+                    return Ok(Some(inst));
+                }
+            }
+            self.cursor = None;
         }
 
-        let mprv = (self.reg.mstatus & 0b10_0000_0000_0000_0000) >> 17;
-        if let Mode::S | Mode::U = self.mode {
-            if mprv == 0 && perm != PMPPerm::X {
-                // do PMP
-                self.blocked_by_pmp(addr, perm);
-                return;
+        let addr = self.trans_addr(self.reg.pc, AccessType::Fetch)?;
+        if !self.in_dram(addr, 2) {
+            return Ok(None);
+        }
+        let addr = addr - MEM_OFF as u64;
+        let block = match self.icache.lookup(addr, self.bus.code_gen(addr)) {
+            Some(block) => {
+                self.icache.hit += 1;
+                block
             }
+            None => {
+                self.icache.miss += 1;
+                let block = Arc::new(self.decode_block(addr));
+                self.icache.insert(addr, block.clone());
+                block
+            }
+        };
+        let mode = self.effective_mode(AccessType::Fetch);
+        let pa = addr + MEM_OFF as u64;
+        if block.insts.is_empty() || !self.pmp_permits(pa, block.len, AccessType::Fetch, mode) {
+            return Ok(None);
         }
 
-        let mpp = (self.reg.mstatus & 0b1_1000_0000_0000) >> 11;
-        if mprv == 1 && (mpp == 0 || mpp == 1) {
-            // do PMP
-            self.blocked_by_pmp(addr, perm);
-            return;
-        }
+        let mut cursor = Cursor {
+            block,
+            addr,
+            idx: 0,
+            pc: self.reg.pc,
+            mode: self.mode,
+            virt: self.reg.virt,
+        };
+        let inst = cursor.next(self.reg.pc, self.mode, self.reg.virt);
+        self.cursor = Some(cursor);
+        Ok(inst)
     }
 
-    fn blocked_by_pmp(&self, addr: u64, perm: PMPPerm) {
-        let pmpcfg_base = 0x3A0;
-        let pmpaddr_base = 0x3B0;
+    /// Decode the block at the DRAM address up to the instruction ending it, the end of the page,
+    /// or an instruction that does not decode or crosses the page.
+    fn decode_block(&self, addr: u64) -> Block {
+        let gen = self.bus.mark_code(addr);
+        let end = ((addr | (PAGE_SIZE - 1)) + 1).min(self.bus.dram_size());
+        let mut insts = vec![];
+        let mut a = addr;
+        while insts.len() < BLOCK_INSTS && a + 2 <= end {
+            let mut data = self.bus.lh_dram(a) as u32;
+            if data & 0b11 == 0b11 {
+                if a + 4 > end {
+                    break;
+                }
+                data |= (self.bus.lh_dram(a + 2) as u32) << 16;
+            }
+            let inst = match Instruction::decode(data) {
+                Ok(inst) => inst,
+                Err(_) => break,
+            };
+            a += inst.len;
+            insts.push(inst);
+            if inst.ends_block() {
+                break;
+            }
+        }
+        Block {
+            insts,
+            len: a - addr,
+            gen,
+        }
+    }
 
-        for i in 0..64 {
-            let pmpcfg = self.reg.get_csr(pmpcfg_base + (i / 8 * 2));
-            let pmpcfg = pmpcfg >> ((i % 8) * 8);
+    /// Fetch a 16-bit compressed instruction or a 32-bit instruction.
+    /// A 32-bit instruction is fetched as two halves because it may be only
+    /// 2 byte aligned and cross a page boundary.
+    fn fetch(&mut self) -> Result<u32, Exception> {
+        let lo = self.fetch_hword(self.reg.pc)? as u32;
+        if lo & 0b11 != 0b11 {
+            return Ok(lo);
+        }
+        let hi = self.fetch_hword(self.reg.pc + 2)? as u32;
+        Ok(hi << 16 | lo)
+    }
 
-            let a = (pmpcfg & 0b1_1000) >> 3;
+    fn fetch_hword(&mut self, va: u64) -> Result<u16, Exception> {
+        let addr = self.trans_addr(va, AccessType::Fetch)?;
+        self.check_pmp(va, addr, 2, AccessType::Fetch)?;
+        if !self.in_dram(addr, 2) {
+            return Err(Exception::InstructionAccessFault(va));
+        }
+        Ok(self.bus.lh_dram(addr - MEM_OFF as u64))
+    }
 
-            // OFF
-            if a == 0 {
-                continue;
-            }
+    /// Raise an access fault when PMP denies the access of `size` bytes at the physical address.
+    fn check_pmp(&self, va: u64, pa: u64, size: u64, access: AccessType) -> Result<(), Exception> {
+        if self.pmp_permits(pa, size, access, self.effective_mode(access)) {
+            return Ok(());
+        }
+        Err(access.access_fault(va))
+    }
 
-            // TOR
-            if a == 1 {
-                let begin: u64;
-                if i == 0 {
-                    begin = 0;
-                } else {
-                    begin = self.reg.get_csr(pmpaddr_base + i - 1);
-                }
-                let end = self.reg.get_csr(pmpaddr_base + i);
+    /// Physical memory protection.
+    /// The lowest-numbered entry that matches any byte of the access decides whether
+    /// the access succeeds, and the entry must match all bytes of the access.
+    /// M-mode accesses are checked only by locked entries, and succeed when no entry matches.
+    /// S-mode and U-mode accesses fail when no entry matches.
+    ///   pmpcfg
+    ///     7    -- L: locked.
+    ///     3..4 -- A: address matching mode. OFF, TOR, NA4 or NAPOT.
+    ///     2    -- X: executable.
+    ///     1    -- W: writable.
+    ///     0    -- R: readable.
+    fn pmp_permits(&self, addr: u64, size: u64, access: AccessType, mode: Mode) -> bool {
+        let begin = addr as u128;
+        let end = begin + size as u128;
 
-                if addr < begin && end <= addr {
-                    continue;
+        for i in 0..64 {
+            let cfg = self.reg.pmpcfg(i);
+            let pmpaddr = self.reg.pmpaddr(i) as u128;
+            let (lo, hi) = match (cfg & PMP_A) >> 3 {
+                PMP_TOR => {
+                    let lo = if i == 0 {
+                        0
+                    } else {
+                        self.reg.pmpaddr(i - 1) as u128
+                    };
+                    (lo << 2, pmpaddr << 2)
                 }
-
-                match perm {
-                    PMPPerm::R => {
-                        if pmpcfg & 1 == 0b001 {
-                            return;
-                        }
-                    }
-                    PMPPerm::W => {
-                        if pmpcfg & 2 == 0b010 {
-                            return;
-                        }
-                    }
-                    PMPPerm::X => {
-                        if pmpcfg & 4 == 0b100 {
-                            return;
-                        }
-                    }
+                PMP_NA4 => (pmpaddr << 2, (pmpaddr << 2) + 4),
+                PMP_NAPOT => {
+                    // pmpaddr = base | (size / 8 - 1)
+                    let ones = (self.reg.pmpaddr(i)).trailing_ones();
+                    let base = pmpaddr & !((1 << ones) - 1);
+                    (base << 2, (base << 2) + (1 << (ones + 3)))
                 }
-                panic!("Fetch blocked by PMP");
+                _ => continue, // OFF
+            };
+            // a TOR entry with pmpaddr[i-1] >= pmpaddr[i] matches nothing
+            if lo >= hi || end <= lo || hi <= begin {
+                continue;
             }
-
-            // NA4
-            if a == 2 {
-                panic!("PMP NA4 is not implemented");
+            if begin < lo || hi < end {
+                return false;
             }
-
-            // NAPOT
-            if a == 3 {
-                panic!("PMP NAPOT is not implemented");
+            if mode == Mode::M && cfg & PMP_L == 0 {
+                return true;
             }
+            return match access {
+                AccessType::Load => cfg & PMP_R != 0,
+                AccessType::Store => cfg & PMP_W != 0,
+                AccessType::Fetch => cfg & PMP_X != 0,
+            };
         }
+        mode == Mode::M
     }
 
-    fn exec_instruction(&mut self, inst: &Instruction) {
+    fn exec_instruction(&mut self, inst: &Instruction) -> Result<(), Exception> {
         match inst.name {
             // RV32I
-            InstName::Lui(_) => self.lui(inst),
-            InstName::Auipc(_) => self.auipc(inst),
-            InstName::Jal(_) => self.jal(inst),
-            InstName::Jalr(_) => self.jalr(inst),
-            InstName::Beq(_) => self.beq(inst),
-            InstName::Bne(_) => self.bne(inst),
-            InstName::Blt(_) => self.blt(inst),
-            InstName::Bge(_) => self.bge(inst),
-            InstName::Bltu(_) => self.bltu(inst),
-            InstName::Bgeu(_) => self.bgeu(inst),
-            InstName::Lb(_) => self.lb(inst),
-            InstName::Lh(_) => self.lh(inst),
-            InstName::Lw(_) => self.lw(inst),
-            InstName::Lbu(_) => self.lbu(inst),
-            InstName::Lhu(_) => self.lhu(inst),
-            InstName::Sb(_) => self.sb(inst),
-            InstName::Sh(_) => self.sh(inst),
-            InstName::Sw(_) => self.sw(inst),
-            InstName::Addi(_) => self.addi(inst),
-            InstName::Slti(_) => self.slti(inst),
-            InstName::Sltiu(_) => self.sltiu(inst),
-            InstName::Xori(_) => self.xori(inst),
-            InstName::Ori(_) => self.ori(inst),
-            InstName::Andi(_) => self.andi(inst),
-            InstName::Slli(_) => self.slli(inst),
-            InstName::Srli(_) => self.srli(inst),
-            InstName::Srai(_) => self.srai(inst),
-            InstName::Add(_) => self.add(inst),
-            InstName::Sub(_) => self.sub(inst),
-            InstName::Sll(_) => self.sll(inst),
-            InstName::Slt(_) => self.slt(inst),
-            InstName::Sltu(_) => self.sltu(inst),
-            InstName::Xor(_) => self.xor(inst),
-            InstName::Srl(_) => self.srl(inst),
-            InstName::Sra(_) => self.sra(inst),
-            InstName::Or(_) => self.or(inst),
-            InstName::And(_) => self.and(inst),
-            InstName::Fence(_) => self.fence(inst),
-            InstName::FenceI(_) => self.fence_i(inst),
-            InstName::Ecall(_) => self.ecall(inst),
-            InstName::Ebreak(_) => self.ebreak(inst),
-            InstName::Csrrw(_) => self.csrrw(inst),
-            InstName::Csrrs(_) => self.csrrs(inst),
-            InstName::Csrrc(_) => self.csrrc(inst),
-            InstName::Csrrwi(_) => self.csrrwi(inst),
-            InstName::Csrrsi(_) => self.csrrsi(inst),
-            InstName::Csrrci(_) => self.csrrci(inst),
-            InstName::Sret(_) => self.sret(inst),
-            InstName::Mret(_) => self.mret(inst),
-            InstName::Wfi(_) => self.wfi(inst),
-            InstName::SfenceVma(_) => self.sfence_vma(inst),
+            InstName::Lui => self.lui(inst),
+            InstName::Auipc => self.auipc(inst),
+            InstName::Jal => self.jal(inst),
+            InstName::Jalr => self.jalr(inst),
+            InstName::Beq => self.beq(inst),
+            InstName::Bne => self.bne(inst),
+            InstName::Blt => self.blt(inst),
+            InstName::Bge => self.bge(inst),
+            InstName::Bltu => self.bltu(inst),
+            InstName::Bgeu => self.bgeu(inst),
+            InstName::Lb => self.lb(inst),
+            InstName::Lh => self.lh(inst),
+            InstName::Lw => self.lw(inst),
+            InstName::Lbu => self.lbu(inst),
+            InstName::Lhu => self.lhu(inst),
+            InstName::Sb => self.sb(inst),
+            InstName::Sh => self.sh(inst),
+            InstName::Sw => self.sw(inst),
+            InstName::Addi => self.addi(inst),
+            InstName::Slti => self.slti(inst),
+            InstName::Sltiu => self.sltiu(inst),
+            InstName::Xori => self.xori(inst),
+            InstName::Ori => self.ori(inst),
+            InstName::Andi => self.andi(inst),
+            InstName::Slli => self.slli(inst),
+            InstName::Srli => self.srli(inst),
+            InstName::Srai => self.srai(inst),
+            InstName::Add => self.add(inst),
+            InstName::Sub => self.sub(inst),
+            InstName::Sll => self.sll(inst),
+            InstName::Slt => self.slt(inst),
+            InstName::Sltu => self.sltu(inst),
+            InstName::Xor => self.xor(inst),
+            InstName::Srl => self.srl(inst),
+            InstName::Sra => self.sra(inst),
+            InstName::Or => self.or(inst),
+            InstName::And => self.and(inst),
+            InstName::Fence => self.fence(inst),
+            InstName::FenceI => self.fence_i(inst),
+            InstName::Ecall => self.ecall(inst),
+            InstName::Ebreak => self.ebreak(inst),
+            InstName::Csrrw => self.csrrw(inst),
+            InstName::Csrrs => self.csrrs(inst),
+            InstName::Csrrc => self.csrrc(inst),
+            InstName::Csrrwi => self.csrrwi(inst),
+            InstName::Csrrsi => self.csrrsi(inst),
+            InstName::Csrrci => self.csrrci(inst),
+            InstName::Sret => self.sret(inst),
+            InstName::Mret => self.mret(inst),
+            InstName::Wfi => self.wfi(inst),
+            InstName::SfenceVma => self.sfence_vma(inst),
 
             // RV32A
-            InstName::Mul(_) => self.mul(inst),
-            InstName::Mulh(_) => self.mulh(inst),
-            InstName::Mulhsu(_) => self.mulhsu(inst),
-            InstName::Mulhu(_) => self.mulhu(inst),
-            InstName::Div(_) => self.div(inst),
-            InstName::Divu(_) => self.divu(inst),
-            InstName::Rem(_) => self.rem(inst),
-            InstName::Remu(_) => self.remu(inst),
+            InstName::Mul => self.mul(inst),
+            InstName::Mulh => self.mulh(inst),
+            InstName::Mulhsu => self.mulhsu(inst),
+            InstName::Mulhu => self.mulhu(inst),
+            InstName::Div => self.div(inst),
+            InstName::Divu => self.divu(inst),
+            InstName::Rem => self.rem(inst),
+            InstName::Remu => self.remu(inst),
 
             // RV32A
-            InstName::LrW(_) => self.lr_w(inst),
-            InstName::ScW(_) => self.sc_w(inst),
-            InstName::AmoswapW(_) => self.amoswap_w(inst),
-            InstName::AmoaddW(_) => self.amoadd_w(inst),
-            InstName::AmoxorW(_) => self.amoxor_w(inst),
-            InstName::AmoandW(_) => self.amoand_w(inst),
-            InstName::AmoorW(_) => self.amoor_w(inst),
-            InstName::AmominW(_) => self.amomin_w(inst),
-            InstName::AmomaxW(_) => self.amomax_w(inst),
-            InstName::AmominuW(_) => self.amominu_w(inst),
-            InstName::AmomaxuW(_) => self.amomaxu_w(inst),
+            InstName::LrW => self.lr_w(inst),
+            InstName::ScW => self.sc_w(inst),
+            InstName::AmoswapW => self.amoswap_w(inst),
+            InstName::AmoaddW => self.amoadd_w(inst),
+            InstName::AmoxorW => self.amoxor_w(inst),
+            InstName::AmoandW => self.amoand_w(inst),
+            InstName::AmoorW => self.amoor_w(inst),
+            InstName::AmominW => self.amomin_w(inst),
+            InstName::AmomaxW => self.amomax_w(inst),
+            InstName::AmominuW => self.amominu_w(inst),
+            InstName::AmomaxuW => self.amomaxu_w(inst),
 
             // RV64I
-            InstName::Lwu(_) => self.lwu(inst),
-            InstName::Ld(_) => self.ld(inst),
-            InstName::Sd(_) => self.sd(inst),
-            InstName::Addiw(_) => self.addiw(inst),
-            InstName::Slliw(_) => self.slliw(inst),
-            InstName::Srliw(_) => self.srliw(inst),
-            InstName::Sraiw(_) => self.sraiw(inst),
-            InstName::Addw(_) => self.addw(inst),
-            InstName::Subw(_) => self.subw(inst),
-            InstName::Sllw(_) => self.sllw(inst),
-            InstName::Srlw(_) => self.srlw(inst),
-            InstName::Sraw(_) => self.sraw(inst),
+            InstName::Lwu => self.lwu(inst),
+            InstName::Ld => self.ld(inst),
+            InstName::Sd => self.sd(inst),
+            InstName::Addiw => self.addiw(inst),
+            InstName::Slliw => self.slliw(inst),
+            InstName::Srliw => self.srliw(inst),
+            InstName::Sraiw => self.sraiw(inst),
+            InstName::Addw => self.addw(inst),
+            InstName::Subw => self.subw(inst),
+            InstName::Sllw => self.sllw(inst),
+            InstName::Srlw => self.srlw(inst),
+            InstName::Sraw => self.sraw(inst),
 
             // RV64M
-            InstName::Mulw(_) => self.mulw(inst),
-            InstName::Divw(_) => self.divw(inst),
-            InstName::Divuw(_) => self.divuw(inst),
-            InstName::Remw(_) => self.remw(inst),
-            InstName::Remuw(_) => self.remuw(inst),
+            InstName::Mulw => self.mulw(inst),
+            InstName::Divw => self.divw(inst),
+            InstName::Divuw => self.divuw(inst),
+            InstName::Remw => self.remw(inst),
+            InstName::Remuw => self.remuw(inst),
 
             // RV64A
-            InstName::LrD(_) => self.lr_d(inst),
-            InstName::ScD(_) => self.sc_d(inst),
-            InstName::AmoswapD(_) => self.amoswap_d(inst),
-            InstName::AmoaddD(_) => self.amoadd_d(inst),
-            InstName::AmoxorD(_) => self.amoxor_d(inst),
-            InstName::AmoandD(_) => self.amoand_d(inst),
-            InstName::AmoorD(_) => self.amoor_d(inst),
-            InstName::AmominD(_) => self.amomin_d(inst),
-            InstName::AmomaxD(_) => self.amomax_d(inst),
-            InstName::AmominuD(_) => self.amominu_d(inst),
-            InstName::AmomaxuD(_) => self.amomaxu_d(inst),
+            InstName::LrD => self.lr_d(inst),
+            InstName::ScD => self.sc_d(inst),
+            InstName::AmoswapD => self.amoswap_d(inst),
+            InstName::AmoaddD => self.amoadd_d(inst),
+            InstName::AmoxorD => self.amoxor_d(inst),
+            InstName::AmoandD => self.amoand_d(inst),
+            InstName::AmoorD => self.amoor_d(inst),
+            InstName::AmominD => self.amomin_d(inst),
+            InstName::AmomaxD => self.amomax_d(inst),
+            InstName::AmominuD => self.amominu_d(inst),
+            InstName::AmomaxuD => self.amomaxu_d(inst),
+
+            // RV32F
+            InstName::Flw => self.flw(inst),
+            InstName::Fsw => self.fsw(inst),
+            InstName::FmaddS => self.fmadd(inst, Precision::Single),
+            InstName::FmsubS => self.fmsub(inst, Precision::Single),
+            InstName::FnmsubS => self.fnmsub(inst, Precision::Single),
+            InstName::FnmaddS => self.fnmadd(inst, Precision::Single),
+            InstName::FaddS => self.fadd(inst, Precision::Single),
+            InstName::FsubS => self.fsub(inst, Precision::Single),
+            InstName::FmulS => self.fmul(inst, Precision::Single),
+            InstName::FdivS => self.fdiv(inst, Precision::Single),
+            InstName::FsqrtS => self.fsqrt(inst, Precision::Single),
+            InstName::FsgnjS => self.fsgnj(inst, Precision::Single),
+            InstName::FsgnjnS => self.fsgnjn(inst, Precision::Single),
+            InstName::FsgnjxS => self.fsgnjx(inst, Precision::Single),
+            InstName::FminS => self.fmin(inst, Precision::Single),
+            InstName::FmaxS => self.fmax(inst, Precision::Single),
+            InstName::FcvtWS => self.fcvt_to_int(inst, Precision::Single, true, 32),
+            InstName::FcvtWuS => self.fcvt_to_int(inst, Precision::Single, false, 32),
+            InstName::FmvXW => self.fmv_x_w(inst),
+            InstName::FeqS => self.feq(inst, Precision::Single),
+            InstName::FltS => self.flt(inst, Precision::Single),
+            InstName::FleS => self.fle(inst, Precision::Single),
+            InstName::FclassS => self.fclass(inst, Precision::Single),
+            InstName::FcvtSW => self.fcvt_from_int(inst, Precision::Single, true, 32),
+            InstName::FcvtSWu => self.fcvt_from_int(inst, Precision::Single, false, 32),
+            InstName::FmvWX => self.fmv_w_x(inst),
+
+            // RV32D
+            InstName::Fld => self.fld(inst),
+            InstName::Fsd => self.fsd(inst),
+            InstName::FmaddD => self.fmadd(inst, Precision::Double),
+            InstName::FmsubD => self.fmsub(inst, Precision::Double),
+            InstName::FnmsubD => self.fnmsub(inst, Precision::Double),
+            InstName::FnmaddD => self.fnmadd(inst, Precision::Double),
+            InstName::FaddD => self.fadd(inst, Precision::Double),
+            InstName::FsubD => self.fsub(inst, Precision::Double),
+            InstName::FmulD => self.fmul(inst, Precision::Double),
+            InstName::FdivD => self.fdiv(inst, Precision::Double),
+            InstName::FsqrtD => self.fsqrt(inst, Precision::Double),
+            InstName::FsgnjD => self.fsgnj(inst, Precision::Double),
+            InstName::FsgnjnD => self.fsgnjn(inst, Precision::Double),
+            InstName::FsgnjxD => self.fsgnjx(inst, Precision::Double),
+            InstName::FminD => self.fmin(inst, Precision::Double),
+            InstName::FmaxD => self.fmax(inst, Precision::Double),
+            InstName::FcvtSD => self.fcvt_s_d(inst),
+            InstName::FcvtDS => self.fcvt_d_s(inst),
+            InstName::FeqD => self.feq(inst, Precision::Double),
+            InstName::FltD => self.flt(inst, Precision::Double),
+            InstName::FleD => self.fle(inst, Precision::Double),
+            InstName::FclassD => self.fclass(inst, Precision::Double),
+            InstName::FcvtWD => self.fcvt_to_int(inst, Precision::Double, true, 32),
+            InstName::FcvtWuD => self.fcvt_to_int(inst, Precision::Double, false, 32),
+            InstName::FcvtDW => self.fcvt_from_int(inst, Precision::Double, true, 32),
+            InstName::FcvtDWu => self.fcvt_from_int(inst, Precision::Double, false, 32),
+
+            // RV64F
+            InstName::FcvtLS => self.fcvt_to_int(inst, Precision::Single, true, 64),
+            InstName::FcvtLuS => self.fcvt_to_int(inst, Precision::Single, false, 64),
+            InstName::FcvtSL => self.fcvt_from_int(inst, Precision::Single, true, 64),
+            InstName::FcvtSLu => self.fcvt_from_int(inst, Precision::Single, false, 64),
+
+            // RV64D
+            InstName::FcvtLD => self.fcvt_to_int(inst, Precision::Double, true, 64),
+            InstName::FcvtLuD => self.fcvt_to_int(inst, Precision::Double, false, 64),
+            InstName::FmvXD => self.fmv_x_d(inst),
+            InstName::FcvtDL => self.fcvt_from_int(inst, Precision::Double, true, 64),
+            InstName::FcvtDLu => self.fcvt_from_int(inst, Precision::Double, false, 64),
+            InstName::FmvDX => self.fmv_d_x(inst),
+
+            // Zba
+            InstName::Sh1add => self.sh1add(inst),
+            InstName::Sh2add => self.sh2add(inst),
+            InstName::Sh3add => self.sh3add(inst),
+            InstName::AddUw => self.add_uw(inst),
+            InstName::Sh1addUw => self.sh1add_uw(inst),
+            InstName::Sh2addUw => self.sh2add_uw(inst),
+            InstName::Sh3addUw => self.sh3add_uw(inst),
+            InstName::SlliUw => self.slli_uw(inst),
+
+            // Zbb
+            InstName::Andn => self.andn(inst),
+            InstName::Orn => self.orn(inst),
+            InstName::Xnor => self.xnor(inst),
+            InstName::Clz => self.clz(inst),
+            InstName::Clzw => self.clzw(inst),
+            InstName::Ctz => self.ctz(inst),
+            InstName::Ctzw => self.ctzw(inst),
+            InstName::Cpop => self.cpop(inst),
+            InstName::Cpopw => self.cpopw(inst),
+            InstName::Max => self.max(inst),
+            InstName::Maxu => self.maxu(inst),
+            InstName::Min => self.min(inst),
+            InstName::Minu => self.minu(inst),
+            InstName::SextB => self.sext_b(inst),
+            InstName::SextH => self.sext_h(inst),
+            InstName::ZextH => self.zext_h(inst),
+            InstName::Rol => self.rol(inst),
+            InstName::Rolw => self.rolw(inst),
+            InstName::Ror => self.ror(inst),
+            InstName::Rori => self.rori(inst),
+            InstName::Roriw => self.roriw(inst),
+            InstName::Rorw => self.rorw(inst),
+            InstName::OrcB => self.orc_b(inst),
+            InstName::Rev8 => self.rev8(inst),
+
+            // Zbc
+            InstName::Clmul => self.clmul(inst),
+            InstName::Clmulh => self.clmulh(inst),
+            InstName::Clmulr => self.clmulr(inst),
+
+            // Zbs
+            InstName::Bclr => self.bclr(inst),
+            InstName::Bclri => self.bclri(inst),
+            InstName::Bext => self.bext(inst),
+            InstName::Bexti => self.bexti(inst),
+            InstName::Binv => self.binv(inst),
+            InstName::Binvi => self.binvi(inst),
+            InstName::Bset => self.bset(inst),
+            InstName::Bseti => self.bseti(inst),
+
+            // Zicond
+            InstName::CzeroEqz => self.czero_eqz(inst),
+            InstName::CzeroNez => self.czero_nez(inst),
+
+            // Zicbom, Zicboz
+            InstName::CboClean | InstName::CboFlush => self.cbo_clean(inst),
+            InstName::CboInval => self.cbo_inval(inst),
+            InstName::CboZero => self.cbo_zero(inst),
+
+            // Zihintpause
+            InstName::Pause => self.pause(inst),
+
+            // V
+            InstName::Vsetvli | InstName::Vsetivli | InstName::Vsetvl => self.vsetvl(inst),
+            InstName::Vle | InstName::Vleff | InstName::Vlse | InstName::Vlxei => {
+                self.vmem(inst, false)
+            }
+            InstName::Vse | InstName::Vsse | InstName::Vsxei => self.vmem(inst, true),
+            InstName::Vlm => self.vmem_mask(inst, false),
+            InstName::Vsm => self.vmem_mask(inst, true),
+            InstName::Vlr => self.vmem_reg(inst, false),
+            InstName::Vsr => self.vmem_reg(inst, true),
+            InstName::Opivv | InstName::Opivx | InstName::Opivi => self.opi(inst),
+            InstName::Opmvv | InstName::Opmvx => self.opm(inst),
+            InstName::Opfvv | InstName::Opfvf => self.opf(inst),
+
+            // H
+            InstName::HfenceVvma => self.hfence_vvma(inst),
+            InstName::HfenceGvma => self.hfence_gvma(inst),
+            InstName::HlvB => self.hlv_b(inst),
+            InstName::HlvBu => self.hlv_bu(inst),
+            InstName::HlvH => self.hlv_h(inst),
+            InstName::HlvHu => self.hlv_hu(inst),
+            InstName::HlvW => self.hlv_w(inst),
+            InstName::HlvWu => self.hlv_wu(inst),
+            InstName::HlvD => self.hlv_d(inst),
+            InstName::HlvxHu => self.hlvx_hu(inst),
+            InstName::HlvxWu => self.hlvx_wu(inst),
+            InstName::HsvB => self.hsv_b(inst),
+            InstName::HsvH => self.hsv_h(inst),
+            InstName::HsvW => self.hsv_w(inst),
+            InstName::HsvD => self.hsv_d(inst),
         }
     }
 
     /// x[rd] = sext(immediate[31:12] << 12)
-    fn lui(&mut self, inst: &Instruction) {
-        let v = (inst.imm << 12) as i32 as i64;
+    fn lui(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = inst.imm;
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = pc + sext(immediate[31:12] << 12)
-    fn auipc(&mut self, inst: &Instruction) {
-        let v = self.reg.pc as i64 + (inst.imm << 12) as i32 as i64;
+    fn auipc(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = (self.reg.pc as i64).wrapping_add(inst.imm);
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// pc = target of a jump or a taken branch.
+    /// Without C, a target not aligned to 4 bytes raises an instruction-address-misaligned
+    /// exception on the jump or branch, which does not write rd.
+    fn jump(&mut self, target: u64) -> Result<(), Exception> {
+        if target & 0b10 != 0 && self.reg.c_off() {
+            return Err(Exception::InstructionAddressMisaligned(target));
+        }
+        self.next_pc = target;
+        Ok(())
     }
 
     /// x[rd] = pc+4; pc += sext(offset)
-    fn jal(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x8_0000);
-        self.reg.set_reg(inst.rd, self.reg.pc + 4);
+    fn jal(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
         let v = self.reg.pc as i64 + imm;
-        self.reg.pc = v as u64;
+        self.jump(v as u64)?;
+        self.reg.set_reg(inst.rd, self.reg.pc + inst.len);
+        Ok(())
     }
 
     /// t =pc+4; pc=(x[rs1]+sext(offset))&∼1; x[rd]=t
-    fn jalr(&mut self, inst: &Instruction) {
-        let t = self.reg.pc + 4;
+    fn jalr(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let t = self.reg.pc + inst.len;
 
-        let imm = sext(inst.imm as u64, 0x800);
+        let imm = inst.imm;
         let v = (self.reg.get_reg(inst.rs1) as i64 + imm) as u64;
-        self.reg.pc = v & !1;
+        self.jump(v & !1)?;
 
         self.reg.set_reg(inst.rd, t);
+        Ok(())
     }
 
     /// if (rs1 == rs2) pc += sext(offset)
-    fn beq(&mut self, inst: &Instruction) {
+    fn beq(&mut self, inst: &Instruction) -> Result<(), Exception> {
         if self.reg.get_reg(inst.rs1) == self.reg.get_reg(inst.rs2) {
-            let imm = sext(inst.imm as u64, 0x800);
-            self.reg.pc = (self.reg.pc as i64 + imm) as u64;
+            let imm = inst.imm;
+            self.jump((self.reg.pc as i64 + imm) as u64)?;
         }
+        Ok(())
     }
 
     /// if (rs1 != rs2) pc += sext(offset)
-    fn bne(&mut self, inst: &Instruction) {
+    fn bne(&mut self, inst: &Instruction) -> Result<(), Exception> {
         if self.reg.get_reg(inst.rs1) != self.reg.get_reg(inst.rs2) {
-            let imm = sext(inst.imm as u64, 0x800);
-            self.reg.pc = (self.reg.pc as i64 + imm) as u64;
+            let imm = inst.imm;
+            self.jump((self.reg.pc as i64 + imm) as u64)?;
         }
+        Ok(())
     }
 
     /// if (rs1 <s rs2) pc += sext(offset)
-    fn blt(&mut self, inst: &Instruction) {
+    fn blt(&mut self, inst: &Instruction) -> Result<(), Exception> {
         if (self.reg.get_reg(inst.rs1) as i64) < (self.reg.get_reg(inst.rs2) as i64) {
-            let imm = sext(inst.imm as u64, 0x800);
-            self.reg.pc = (self.reg.pc as i64 + imm) as u64;
+            let imm = inst.imm;
+            self.jump((self.reg.pc as i64 + imm) as u64)?;
         }
+        Ok(())
     }
 
     /// if (rs1 >=s rs2) pc += sext(offset)
-    fn bge(&mut self, inst: &Instruction) {
+    fn bge(&mut self, inst: &Instruction) -> Result<(), Exception> {
         if (self.reg.get_reg(inst.rs1) as i64) >= (self.reg.get_reg(inst.rs2) as i64) {
-            let imm = sext(inst.imm as u64, 0x800);
-            self.reg.pc = (self.reg.pc as i64 + imm) as u64;
+            let imm = inst.imm;
+            self.jump((self.reg.pc as i64 + imm) as u64)?;
         }
+        Ok(())
     }
 
     /// if (rs1 >u rs2) pc += sext(offset)
-    fn bltu(&mut self, inst: &Instruction) {
+    fn bltu(&mut self, inst: &Instruction) -> Result<(), Exception> {
         if self.reg.get_reg(inst.rs1) < self.reg.get_reg(inst.rs2) {
-            let imm = sext(inst.imm as u64, 0x800);
-            self.reg.pc = (self.reg.pc as i64 + imm) as u64;
+            let imm = inst.imm;
+            self.jump((self.reg.pc as i64 + imm) as u64)?;
         }
+        Ok(())
     }
 
     /// if (rs1 >=u rs2) pc += sext(offset)
-    fn bgeu(&mut self, inst: &Instruction) {
+    fn bgeu(&mut self, inst: &Instruction) -> Result<(), Exception> {
         if self.reg.get_reg(inst.rs1) >= self.reg.get_reg(inst.rs2) {
-            let imm = sext(inst.imm as u64, 0x800);
-            self.reg.pc = (self.reg.pc as i64 + imm) as u64;
+            let imm = inst.imm;
+            self.jump((self.reg.pc as i64 + imm) as u64)?;
         }
+        Ok(())
     }
 
     /// x[rd] = sext(M[x[rs1] + sext(offset)][7:0])
-    fn lb(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1) as i64 + inst.imm as i64;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::R);
-
-        let v: i64;
-        if (addr as usize) < MEM_OFF {
-            v = self.l_mm(addr as u64) as u8 as i64;
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            v = self.bus.lb_dram(addr) as i64;
-        }
-        self.reg.set_reg(inst.rd, v as u64);
+    fn lb(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        let v = self.load(addr, 1)?;
+        self.reg.set_reg(inst.rd, v as i8 as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(M[x[rs1] + sext(offset)][15:0])
-    fn lh(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1) as i64 + inst.imm as i64;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::R);
-
-        let v: i64;
-        if (addr as usize) < MEM_OFF {
-            v = self.l_mm(addr as u64) as u16 as i64;
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            v = self.bus.lh_dram(addr) as i64;
-        }
-        self.reg.set_reg(inst.rd, v as u64);
+    fn lh(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        let v = self.load(addr, 2)?;
+        self.reg.set_reg(inst.rd, v as i16 as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(M[x[rs1] + sext(offset)][31:0])
-    fn lw(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1) as i64 + inst.imm as i64;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::R);
-
-        let v: i64;
-        if (addr as usize) < MEM_OFF {
-            v = self.l_mm(addr as u64) as u32 as i64;
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            v = self.bus.lw_dram(addr as u64) as i64;
-        }
-        self.reg.set_reg(inst.rd, v as u64);
+    fn lw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        let v = self.load(addr, 4)?;
+        self.reg.set_reg(inst.rd, v as i32 as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = M[x[rs1] + sext(offset)][7:0]
-    fn lbu(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1) as i64 + inst.imm as i64;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::R);
-
-        let v: u64;
-        if (addr as usize) < MEM_OFF {
-            v = self.l_mm(addr as u64) as u8 as u64;
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            v = self.bus.lb_dram(addr as u64) as u64;
-        }
+    fn lbu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        let v = self.load(addr, 1)?;
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// x[rd] = M[x[rs1] + sext(offset)][15:0]
-    fn lhu(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1) as i64 + inst.imm as i64;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::R);
-
-        let v: u64;
-        if (addr as usize) < MEM_OFF {
-            v = self.l_mm(addr as u64) as u16 as u64;
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            v = self.bus.lh_dram(addr as u64) as u64;
-        }
+    fn lhu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        let v = self.load(addr, 2)?;
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// M[x[rs1] + sext(offset)] = x[rs2][7:0]
-    fn sb(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1) as i64 + inst.imm as i64;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::W);
-
-        let v = self.reg.get_reg(inst.rs2) as u8;
-        if (addr as usize) < MEM_OFF {
-            self.s_mm(addr as u64, v as u64);
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            self.bus.sb_dram(addr as u64, v);
-        }
+    fn sb(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        self.store(addr, 1, self.reg.get_reg(inst.rs2))
     }
 
     /// M[x[rs1] + sext(offset)] = x[rs2][15:0]
-    fn sh(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1) as i64 + inst.imm as i64;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::W);
-
-        let v = self.reg.get_reg(inst.rs2) as u16;
-        if (addr as usize) < MEM_OFF {
-            self.s_mm(addr as u64, v as u64);
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            self.bus.sh_dram(addr as u64, v);
-        }
+    fn sh(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        self.store(addr, 2, self.reg.get_reg(inst.rs2))
     }
 
     /// M[x[rs1] + sext(offset)] = x[rs2][31:0]
-    fn sw(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1) as i64 + inst.imm as i64;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::W);
-
-        let v = self.reg.get_reg(inst.rs2) as u32;
-        if (addr as usize) < MEM_OFF {
-            self.s_mm(addr as u64, v as u64);
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            self.bus.sw_dram(addr as u64, v);
-        }
+    fn sw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        self.store(addr, 4, self.reg.get_reg(inst.rs2))
     }
 
     /// x[rd] = x[rs1] + sext(immediate)
-    fn addi(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
+    fn addi(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
         let v = self.reg.get_reg(inst.rs1) as i64 + imm;
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] <s sext(immediate)
-    fn slti(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
+    fn slti(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
         if (self.reg.get_reg(inst.rs1) as i64) < imm {
             self.reg.set_reg(inst.rd, 1);
         } else {
             self.reg.set_reg(inst.rd, 0);
         }
+        Ok(())
     }
 
     /// x[rd] = x[rs1] <u sext(immediate)
-    fn sltiu(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
+    fn sltiu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
         if self.reg.get_reg(inst.rs1) < imm as u64 {
             self.reg.set_reg(inst.rd, 1);
         } else {
             self.reg.set_reg(inst.rd, 0);
         }
+        Ok(())
     }
 
     /// x[rd] = x[rs1] ^ sext(immediate)
-    fn xori(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
+    fn xori(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
         let v = imm ^ (self.reg.get_reg(inst.rs1) as i64);
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] | sext(immediate)
-    fn ori(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
+    fn ori(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
         let v = imm | (self.reg.get_reg(inst.rs1) as i64);
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] & sext(immediate)
-    fn andi(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
+    fn andi(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
         let v = imm & (self.reg.get_reg(inst.rs1) as i64);
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] << shamt
-    fn slli(&mut self, inst: &Instruction) {
+    fn slli(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let shamt = (inst.imm & 0b11_1111) as u8;
         let v = self.reg.get_reg(inst.rs1) << shamt;
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] >>u shamt
-    fn srli(&mut self, inst: &Instruction) {
+    fn srli(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let shamt = (inst.imm & 0b11_1111) as u8;
         let v = self.reg.get_reg(inst.rs1) >> shamt;
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] >>s shamt
-    fn srai(&mut self, inst: &Instruction) {
+    fn srai(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let shamt = (inst.imm & 0b11_1111) as u8;
         let v = (self.reg.get_reg(inst.rs1) as i64) >> shamt;
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] + x[rs2]
-    fn add(&mut self, inst: &Instruction) {
+    fn add(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) + self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] - x[rs2]
-    fn sub(&mut self, inst: &Instruction) {
+    fn sub(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) - self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] << x[rs2]
-    fn sll(&mut self, inst: &Instruction) {
+    fn sll(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let shamt = self.reg.get_reg(inst.rs2) & 0b1_1111;
         let v = self.reg.get_reg(inst.rs1) << shamt;
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     // x[rd] = x[rs1] <s x[rs2]
-    fn slt(&mut self, inst: &Instruction) {
+    fn slt(&mut self, inst: &Instruction) -> Result<(), Exception> {
         if (self.reg.get_reg(inst.rs1) as i64) < (self.reg.get_reg(inst.rs2) as i64) {
             self.reg.set_reg(inst.rd, 1);
         } else {
             self.reg.set_reg(inst.rd, 0);
         }
+        Ok(())
     }
 
     /// x[rd] = x[rs1] <u x[rs2]
-    fn sltu(&mut self, inst: &Instruction) {
+    fn sltu(&mut self, inst: &Instruction) -> Result<(), Exception> {
         if self.reg.get_reg(inst.rs1) < self.reg.get_reg(inst.rs2) {
             self.reg.set_reg(inst.rd, 1);
         } else {
             self.reg.set_reg(inst.rd, 0);
         }
+        Ok(())
     }
 
     /// x[rd] = x[rs1] ^ x[rs2]
-    fn xor(&mut self, inst: &Instruction) {
+    fn xor(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) ^ self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] >>u x[rs2]
-    fn srl(&mut self, inst: &Instruction) {
+    fn srl(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let shamt = self.reg.get_reg(inst.rs2) & 0b1_1111;
         let v = self.reg.get_reg(inst.rs1) >> shamt;
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] >>s x[rs2]
-    fn sra(&mut self, inst: &Instruction) {
+    fn sra(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let shamt = self.reg.get_reg(inst.rs2) & 0b1_1111;
         let v = (self.reg.get_reg(inst.rs1) as i64) >> shamt;
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] | x[rs2]
-    fn or(&mut self, inst: &Instruction) {
+    fn or(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) | self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] & x[rs2]
-    fn and(&mut self, inst: &Instruction) {
+    fn and(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) & self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// Fence(pred, succ)
-    #[allow(unused_variables)]
-    fn fence(&mut self, inst: &Instruction) {
-        let pred = inst.imm >> 4 & 0b1111;
-        let succ = inst.imm & 0b1111;
-        // Implement when needed.
+    /// Loads and stores are relaxed on the host, so any FENCE orders them all.
+    fn fence(&mut self, _inst: &Instruction) -> Result<(), Exception> {
+        atomic::fence(Ordering::SeqCst);
+        Ok(())
     }
 
     /// Fence(Store, Fetch)
-    #[allow(unused_variables)]
-    fn fence_i(&mut self, inst: &Instruction) {
-        // Implement when needed.
+    /// Stores already make the decoded blocks of their page stale, so this only drops them all.
+    fn fence_i(&mut self, _inst: &Instruction) -> Result<(), Exception> {
+        self.icache.flush();
+        self.cursor = None;
+        atomic::fence(Ordering::SeqCst);
+        Ok(())
     }
 
     /// RaiseException(EnvironmentCall)
-    #[allow(unused_variables)]
-    fn ecall(&mut self, inst: &Instruction) {
-        // Implement when needed.
+    fn ecall(&mut self, _inst: &Instruction) -> Result<(), Exception> {
+        match self.mode {
+            Mode::M => Err(Exception::EnvironmentCallFromM),
+            Mode::S if self.reg.virt => Err(Exception::EnvironmentCallFromVS),
+            Mode::S => Err(Exception::EnvironmentCallFromS),
+            Mode::U => Err(Exception::EnvironmentCallFromU),
+        }
     }
 
     /// RaiseException(Breakpoint)
-    #[allow(unused_variables)]
-    fn ebreak(&mut self, inst: &Instruction) {
-        // Implement when needed.
+    fn ebreak(&mut self, _inst: &Instruction) -> Result<(), Exception> {
+        Err(Exception::Breakpoint(self.reg.pc))
+    }
+
+    /// Raise an illegal instruction exception when the CSR is not implemented, when the current
+    /// privilege mode can not access it, or when a read-only CSR is written.
+    /// VS-mode and VU-mode raise a virtual instruction exception instead
+    /// when HS-mode could access the CSR.
+    ///   csr[11:10] -- 0b11 is read-only.
+    ///   csr[9:8]   -- lowest privilege level that can access the CSR.
+    ///                 0b10 is the hypervisor and VS CSRs accessed from HS-mode.
+    fn check_csr(&self, inst: &Instruction, write: bool) -> Result<(), Exception> {
+        let csr = (inst.imm & 0xFFF) as u16;
+        if !register::csr_exists(csr) {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        let read_only = (csr >> 10) & 0b11 == 0b11;
+        let priv_level = (csr >> 8) & 0b11;
+        let mode_level = match self.mode {
+            Mode::M => 0b11,
+            Mode::S => 0b01,
+            Mode::U => 0b00,
+        };
+        if (write && read_only) || (priv_level == 0b11 && self.mode != Mode::M) {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        if self.reg.virt && (priv_level == 0b10 || mode_level < priv_level) {
+            return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+        }
+        if mode_level < priv_level.min(0b01) {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        // fflags, frm and fcsr
+        if (0x001..=0x003).contains(&csr) && self.reg.fs_off() {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        // vstart, vxsat, vxrm, vcsr, vl, vtype and vlenb
+        if matches!(csr, 0x008..=0x00A | 0x00F | 0xC20..=0xC22) && self.reg.vs_off() {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        // cycle, time, instret and hpmcounter3-31 are enabled by mcounteren in S-mode,
+        // and by both mcounteren and scounteren in U-mode.
+        // With V=1, hcounteren and then scounteren in VU-mode raise a virtual instruction exception.
+        if (0xC00..=0xC1F).contains(&csr) {
+            let bit = 1 << (csr - 0xC00);
+            let enabled = match self.mode {
+                Mode::M => true,
+                Mode::U if !self.reg.virt => self.reg.mcounteren & self.reg.scounteren & bit != 0,
+                _ => self.reg.mcounteren & bit != 0,
+            };
+            if !enabled {
+                return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+            }
+            let virt_enabled = match self.mode {
+                Mode::S => self.reg.hcounteren & bit != 0,
+                _ => self.reg.hcounteren & self.reg.scounteren & bit != 0,
+            };
+            if self.reg.virt && !virt_enabled {
+                return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+            }
+        }
+        // stimecmp and vstimecmp need menvcfg.STCE and mcounteren.TM below M-mode.
+        // stimecmp (vstimecmp) in VS-mode also needs henvcfg.STCE and hcounteren.TM.
+        if (csr == 0x14D || csr == 0x24D) && self.mode != Mode::M {
+            let tm = 0b10;
+            if !self.reg.stce() || self.reg.mcounteren & tm == 0 {
+                return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+            }
+            if self.reg.virt && (!self.reg.vstce() || self.reg.hcounteren & tm == 0) {
+                return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+            }
+        }
+        // miselect, mireg, mtopei, siselect, sireg and stopei exist with the IMSIC.
+        // There are no guest interrupt files, so VS-mode can not access them.
+        // mireg and sireg raise an illegal instruction exception for a reserved register number.
+        if matches!(csr, 0x150 | 0x151 | 0x15C | 0x350 | 0x351 | 0x35C) {
+            if self.bus.imsic().is_none() {
+                return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+            }
+            if self.reg.virt {
+                return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+            }
+            let reserved = match csr {
+                0x151 => !imsic::valid_iselect(self.reg.siselect),
+                0x351 => !imsic::valid_iselect(self.reg.miselect),
+                _ => false,
+            };
+            if reserved {
+                return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+            }
+        }
+        // satp and hgatp in HS-mode with mstatus.TVM, and satp (vsatp) in VS-mode with hstatus.VTVM
+        if csr == 0x180 && self.reg.virt && self.reg.hstatus & HSTATUS_VTVM != 0 {
+            return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+        }
+        let tvm = self.reg.mstatus & MSTATUS_TVM != 0;
+        if (csr == 0x180 || csr == 0x680) && self.mode == Mode::S && !self.reg.virt && tvm {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        Ok(())
+    }
+
+    /// CSRs[csr]
+    /// time is a read-only shadow of mtime. Guests see mtime + htimedelta.
+    /// mireg, mtopei, sireg and stopei access the interrupt files of the hart in the IMSIC.
+    fn read_csr(&self, csr: u16) -> u64 {
+        match csr {
+            0xC01 if self.reg.virt => self.bus.clint().mtime().wrapping_add(self.reg.htimedelta),
+            0xC01 => self.bus.clint().mtime(),
+            0x151 | 0x351 => self
+                .imsic()
+                .read_ireg(self.imsic_file(csr), self.iselect(csr)),
+            0x15C | 0x35C => self.imsic().topei(self.imsic_file(csr)),
+            _ => self.reg.get_csr(csr),
+        }
+    }
+
+    /// The value csrrs and csrrc set or clear bits of, which is `t` read from the CSR except for mip.
+    /// Only the software-writable SEIP bit takes part in it, not the interrupt line.
+    fn rmw_base(&self, csr: u16, t: u64) -> u64 {
+        match csr {
+            0x344 => self.reg.mip,
+            _ => t,
+        }
+    }
+
+    /// CSRs[csr] = value
+    /// Writing satp flushes the TLB. Writing mtopei or stopei claims the interrupt it reports.
+    fn write_csr(&mut self, csr: u16, value: u64) {
+        match csr {
+            0x151 | 0x351 => {
+                let file = self.imsic_file(csr);
+                self.imsic().write_ireg(file, self.iselect(csr), value)
+            }
+            0x15C | 0x35C => self.imsic().claim(self.imsic_file(csr)),
+            _ => self.reg.set_csr(csr, value),
+        }
+        if csr == 0x180 {
+            self.tlb.flush(None, None);
+        }
+    }
+
+    /// check_csr makes sure the IMSIC exists before its CSRs are accessed.
+    fn imsic(&self) -> &Imsic {
+        self.bus.imsic().expect("no IMSIC")
+    }
+
+    /// The interrupt file of the hart at the level of the CSR.
+    fn imsic_file(&self, csr: u16) -> usize {
+        match csr >> 8 {
+            0b11 => imsic::m_file(self.hartid()),
+            _ => imsic::s_file(self.hartid()),
+        }
+    }
+
+    /// miselect for mireg, siselect for sireg.
+    fn iselect(&self, csr: u16) -> u64 {
+        match csr >> 8 {
+            0b11 => self.reg.miselect,
+            _ => self.reg.siselect,
+        }
     }
 
     /// t = CSRs[csr]; CSRs[csr] = x[rs1]; x[rd] = t
-    fn csrrw(&mut self, inst: &Instruction) {
-        let csr = inst.imm as u16;
-        let t = self.reg.get_csr(csr);
-        self.reg.set_csr(csr, self.reg.get_reg(inst.rs1));
+    fn csrrw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let csr = (inst.imm & 0xFFF) as u16;
+        self.check_csr(inst, true)?;
+        let t = self.read_csr(csr);
+        self.write_csr(csr, self.reg.get_reg(inst.rs1));
         self.reg.set_reg(inst.rd, t);
+        Ok(())
     }
 
     /// t = CSRs[csr]; CSRs[csr] = t | x[rs1]; x[rd] = t
-    fn csrrs(&mut self, inst: &Instruction) {
-        let csr = inst.imm as u16;
-        let t = self.reg.get_csr(csr);
-        self.reg.set_csr(csr, t | self.reg.get_reg(inst.rs1));
+    fn csrrs(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let csr = (inst.imm & 0xFFF) as u16;
+        self.check_csr(inst, inst.rs1 != 0)?;
+        let t = self.read_csr(csr);
+        // rs1=x0 reads the csr without writing it
+        if inst.rs1 != 0 {
+            self.write_csr(csr, self.rmw_base(csr, t) | self.reg.get_reg(inst.rs1));
+        }
         self.reg.set_reg(inst.rd, t);
+        Ok(())
     }
 
     /// t = CSRs[csr]; CSRs[csr] = t &∼x[rs1]; x[rd] = t
-    fn csrrc(&mut self, inst: &Instruction) {
-        let csr = inst.imm as u16;
-        let t = self.reg.get_csr(csr);
-        self.reg.set_csr(csr, t & !self.reg.get_reg(inst.rs1));
+    fn csrrc(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let csr = (inst.imm & 0xFFF) as u16;
+        self.check_csr(inst, inst.rs1 != 0)?;
+        let t = self.read_csr(csr);
+        if inst.rs1 != 0 {
+            self.write_csr(csr, self.rmw_base(csr, t) & !self.reg.get_reg(inst.rs1));
+        }
         self.reg.set_reg(inst.rd, t);
+        Ok(())
     }
 
     /// x[rd] = CSRs[csr]; CSRs[csr] = zimm
-    fn csrrwi(&mut self, inst: &Instruction) {
-        let csr = inst.imm as u16;
-        self.reg.set_reg(inst.rd, self.reg.get_csr(csr));
+    fn csrrwi(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let csr = (inst.imm & 0xFFF) as u16;
+        self.check_csr(inst, true)?;
+        self.reg.set_reg(inst.rd, self.read_csr(csr));
         let zimm = inst.rs1;
-        self.reg.set_csr(csr, zimm as u64);
+        self.write_csr(csr, zimm as u64);
+        Ok(())
     }
 
     /// t = CSRs[csr]; CSRs[csr] = t | zimm; x[rd] = t
-    fn csrrsi(&mut self, inst: &Instruction) {
-        let csr = inst.imm as u16;
-        let t = self.reg.get_csr(csr);
+    fn csrrsi(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let csr = (inst.imm & 0xFFF) as u16;
+        self.check_csr(inst, inst.rs1 != 0)?;
+        let t = self.read_csr(csr);
         let zimm = inst.rs1;
-        self.reg.set_csr(csr, t | zimm as u64);
+        if inst.rs1 != 0 {
+            self.write_csr(csr, self.rmw_base(csr, t) | zimm as u64);
+        }
         self.reg.set_reg(inst.rd, t);
+        Ok(())
     }
 
     /// t = CSRs[csr]; CSRs[csr] = t &∼zimm; x[rd] = t
-    fn csrrci(&mut self, inst: &Instruction) {
-        let csr = inst.imm as u16;
-        let t = self.reg.get_csr(csr);
+    fn csrrci(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let csr = (inst.imm & 0xFFF) as u16;
+        self.check_csr(inst, inst.rs1 != 0)?;
+        let t = self.read_csr(csr);
         let zimm = inst.rs1;
-        self.reg.set_csr(csr, t & !(zimm as u64));
+        if inst.rs1 != 0 {
+            self.write_csr(csr, self.rmw_base(csr, t) & !(zimm as u64));
+        }
         self.reg.set_reg(inst.rd, t);
+        Ok(())
     }
 
-    /// ExceptionReturn(User)
-    fn sret(&mut self, _inst: &Instruction) {
-        let pre_spp = (self.reg.sstatus & 0b1_1000_0000_0000) >> 11;
-        let spie = self.reg.sstatus & 0b100_0000;
-        let sie = spie >> 3;
-        self.reg.sstatus |= sie;
-        let spie: u64 = 0b100_0000;
-        self.reg.sstatus |= spie;
-        let spp: u64 = 0b1_1000_0000_0000;
-        self.reg.sstatus &= !spp; // mpp = 0; U-MODE
-        let sprv: u64 = 0b10_0000_0000_0000_0000;
-        self.reg.sstatus &= !sprv; // mprv = 0;
-        self.reg.pc = self.reg.sepc;
+    /// ExceptionReturn(Supervisor)
+    /// HS-mode returns to the mode in sstatus.SPP with V = hstatus.SPV,
+    /// and VS-mode returns with vsstatus and vsepc.
+    fn sret(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let tsr = self.reg.mstatus & MSTATUS_TSR != 0;
+        let vtsr = self.reg.hstatus & HSTATUS_VTSR != 0;
+        if (self.mode == Mode::U && !self.reg.virt)
+            || (self.mode == Mode::S && !self.reg.virt && tsr)
+        {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        if self.reg.virt && (self.mode == Mode::U || vtsr) {
+            return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+        }
+        if self.reg.virt {
+            return self.vs_sret();
+        }
+
+        // sstatus is a view of mstatus
+        let pre_spp = (self.reg.mstatus & 0b1_0000_0000) >> 8;
+        // sstatus.SIE = sstatus.SPIE; sstatus.SPIE = 1
+        let sie = (self.reg.mstatus & 0b10_0000) >> 4;
+        self.reg.mstatus = (self.reg.mstatus & !0b10) | sie;
+        self.reg.mstatus |= 0b10_0000;
+        // sstatus.SPP = 0; U-MODE
+        self.reg.mstatus &= !0b1_0000_0000;
+        // mstatus.MPRV = 0
+        self.reg.mstatus &= !0b10_0000_0000_0000_0000;
+        // V = hstatus.SPV; hstatus.SPV = 0
+        self.reg.virt = self.reg.hstatus & HSTATUS_SPV != 0;
+        self.reg.hstatus &= !HSTATUS_SPV;
+        self.next_pc = self.reg.sepc;
 
         match pre_spp {
             0 => self.mode = Mode::U,
-            1 => self.mode = Mode::S,
-            3 => self.mode = Mode::M,
-            _ => (),
+            _ => self.mode = Mode::S,
         }
+        Ok(())
     }
 
     /// ExceptionReturn(Machine)
-    fn mret(&mut self, _inst: &Instruction) {
+    fn mret(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        if self.mode != Mode::M {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+
         let pre_mpp = (self.reg.mstatus & 0b1_1000_0000_0000) >> 11;
-        let mpie = self.reg.mstatus & 0b100_0000;
-        let mie = mpie >> 3;
-        self.reg.mstatus |= mie;
-        let mpie: u64 = 0b100_0000;
-        self.reg.mstatus |= mpie;
-        let mpp: u64 = 0b1_1000_0000_0000;
-        self.reg.mstatus &= !mpp; // mpp = 0; U-MODE
-        let mprv: u64 = 0b10_0000_0000_0000_0000;
-        self.reg.mstatus &= !mprv; // mprv = 0;
-        self.reg.pc = self.reg.mepc;
+        // mstatus.MIE = mstatus.MPIE; mstatus.MPIE = 1
+        let mie = (self.reg.mstatus & 0b1000_0000) >> 4;
+        self.reg.mstatus = (self.reg.mstatus & !0b1000) | mie;
+        self.reg.mstatus |= 0b1000_0000;
+        // mstatus.MPP = 0; U-MODE
+        self.reg.mstatus &= !0b1_1000_0000_0000;
+        if pre_mpp != 3 {
+            // mstatus.MPRV = 0
+            self.reg.mstatus &= !0b10_0000_0000_0000_0000;
+        }
+        // V = mstatus.MPV unless returning to M-mode; mstatus.MPV = 0
+        self.reg.virt = pre_mpp != 3 && self.reg.mstatus & MSTATUS_MPV != 0;
+        self.reg.mstatus &= !MSTATUS_MPV;
+        self.next_pc = self.reg.mepc;
 
         match pre_mpp {
             0 => self.mode = Mode::U,
@@ -902,848 +1825,2820 @@ impl Cpu {
             3 => self.mode = Mode::M,
             _ => (),
         }
+        Ok(())
     }
 
     /// while (noInterruptsPending) idle
-    #[allow(unused_variables)]
-    fn wfi(&mut self, inst: &Instruction) {
-        // Implement when needed.
+    /// Illegal in U-mode, and in S-mode when mstatus.TW is set.
+    /// A virtual instruction in VU-mode, and in VS-mode when hstatus.VTW is set.
+    fn wfi(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let tw = self.reg.mstatus & MSTATUS_TW != 0;
+        let vtw = self.reg.hstatus & HSTATUS_VTW != 0;
+        if (self.mode == Mode::U && !self.reg.virt) || (self.mode == Mode::S && tw) {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        if self.reg.virt && (self.mode == Mode::U || vtw) {
+            return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+        }
+        if !self.busy_wfi {
+            self.wfi = true;
+        }
+        Ok(())
     }
 
     /// Fence(Store, AddressTranslation)
-    #[allow(unused_variables)]
-    fn sfence_vma(&mut self, inst: &Instruction) {
-        // Implement when needed.
+    /// Flush the TLB.
+    ///   rs1 -- x0 flushes every virtual address, otherwise the page of x[rs1].
+    ///   rs2 -- x0 flushes every address space, otherwise the ASID x[rs2] except global mappings.
+    /// In VS-mode it orders the guest translations, which are not cached.
+    fn sfence_vma(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let tvm = self.reg.mstatus & MSTATUS_TVM != 0;
+        let vtvm = self.reg.hstatus & HSTATUS_VTVM != 0;
+        if self.reg.virt {
+            if self.mode == Mode::U || vtvm {
+                return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+            }
+            return Ok(());
+        }
+        if self.mode == Mode::U || (self.mode == Mode::S && tvm) {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+
+        let vpn = match inst.rs1 {
+            0 => None,
+            rs1 => Some(self.reg.get_reg(rs1) >> PAGE_OFF_SIZE),
+        };
+        let asid = match inst.rs2 {
+            0 => None,
+            rs2 => Some(self.reg.get_reg(rs2) as u16),
+        };
+        self.tlb.flush(vpn, asid);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] × x[rs2]
-    fn mul(&mut self, inst: &Instruction) {
+    fn mul(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) * self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = (x[rs1] s×s x[rs2]) >>s XLEN
-    fn mulh(&mut self, inst: &Instruction) {
+    fn mulh(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = (self.reg.get_reg(inst.rs1) as i64) * (self.reg.get_reg(inst.rs2) as i64);
         self.reg.set_reg(inst.rd, (v >> 32) as u64);
+        Ok(())
     }
 
     /// x[rd] = (x[rs1] s × x[rs2]) >>s XLEN
-    fn mulhsu(&mut self, inst: &Instruction) {
+    fn mulhsu(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = ((self.reg.get_reg(inst.rs1) as i64) as u64) * self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, (v >> 32) as u64);
+        Ok(())
     }
 
     /// x[rd] = (x[rs1] u × x[rs2]) >>u XLEN
-    fn mulhu(&mut self, inst: &Instruction) {
+    fn mulhu(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) * self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, (v >> 32) as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] /s x[rs2]
-    fn div(&mut self, inst: &Instruction) {
+    fn div(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = (self.reg.get_reg(inst.rs1) as i64) / (self.reg.get_reg(inst.rs2) as i64);
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] /u x[rs2]
-    fn divu(&mut self, inst: &Instruction) {
+    fn divu(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) / self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] %s x[rs2]
-    fn rem(&mut self, inst: &Instruction) {
+    fn rem(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = (self.reg.get_reg(inst.rs1) as i64) % (self.reg.get_reg(inst.rs2) as i64);
         self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
     }
 
     /// x[rd] = x[rs1] %u x[rs2]
-    fn remu(&mut self, inst: &Instruction) {
+    fn remu(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) % self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// x[rd] = LoadReserved32(M[x[rs1]])
-    fn lr_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::R);
+    fn lr_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let data = self.load_reserved(inst, 4)?;
+        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+        Ok(())
+    }
 
-        let data = self.bus.lw_dram(addr) as i64;
-        self.reg.set_reg(inst.rd, data as u64);
-        self.reserve_mem(addr, false);
+    /// x[rd] = StoreConditional32(M[x[rs1]], x[rs2])
+    fn sc_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.store_conditional(inst, 4)
     }
 
-    fn reserve_mem(&mut self, addr: u64, d: bool) {
-        if addr % 4 != 0 {
-            panic!("invalid alignment");
+    /// Load `size` bytes from x[rs1] and reserve the address for the hart.
+    /// LR is only supported on DRAM.
+    fn load_reserved(&mut self, inst: &Instruction, size: u64) -> Result<u64, Exception> {
+        let va = self.reg.get_reg(inst.rs1);
+        if va & (size - 1) != 0 {
+            return Err(Exception::LoadAddressMisaligned(va));
         }
-
-        let idx = addr / 32;
-        match self.mem_reserved_w.get(idx as usize) {
-            Some(rsv) => {
-                let mut bit = 0x80;
-                if d {
-                    bit = 0xC0;
-                }
-                let rsv = (*rsv as usize) | (bit >> ((addr - idx * 32) / 4));
-                self.mem_reserved_w[idx as usize] = rsv as u8;
-            }
-            None => panic!("invalid memory reserved word index"),
+        self.reg.count_event(HPM_LOAD);
+        let addr = self.trans_addr(va, AccessType::Load)?;
+        self.check_pmp(va, addr, size, AccessType::Load)?;
+        if addr < MEM_OFF as u64 {
+            return Err(Exception::LoadAccessFault(va));
         }
-    }
-
-    fn sc_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
 
-        self.invalidate_mem_reservation(addr, false);
-        let data = self.reg.get_reg(inst.rs2) as u32;
+        let addr = addr - MEM_OFF as u64;
+        Ok(self.bus.lr_dram(self.hartid(), addr, size, amo_order(inst)))
+    }
 
-        if self.check_mem_reservation(addr, false) {
-            panic!("memory is not reserved");
+    /// Store the lower `size` bytes of x[rs2] to x[rs1] when the hart still holds the reservation.
+    /// x[rd] is 0 on success and 1 on failure. Either way the reservation is released.
+    fn store_conditional(&mut self, inst: &Instruction, size: u64) -> Result<(), Exception> {
+        let va = self.reg.get_reg(inst.rs1);
+        if va & (size - 1) != 0 {
+            return Err(Exception::StoreAddressMisaligned(va));
+        }
+        let addr = self.trans_addr(va, AccessType::Store)?;
+        self.check_pmp(va, addr, size, AccessType::Store)?;
+        if addr < MEM_OFF as u64 {
+            return Err(Exception::StoreAccessFault(va));
         }
 
-        self.bus.sw_dram(addr, data);
+        let addr = addr - MEM_OFF as u64;
+        let data = self.reg.get_reg(inst.rs2);
+        if !self
+            .bus
+            .sc_dram(self.hartid(), addr, size, data, amo_order(inst))
+        {
+            self.reg.set_reg(inst.rd, 1);
+            return Ok(());
+        }
+        self.reg.count_event(HPM_STORE);
         self.reg.set_reg(inst.rd, 0);
-    }
-
-    fn check_mem_reservation(&self, addr: u64, d: bool) -> bool {
-        if addr % 4 != 0 {
-            panic!("invalid alinement");
-        }
-
-        let idx = addr / 32;
-        let rsv = self.mem_reserved_w.get(idx as usize).unwrap();
-        let mut bit = 0x80;
-        if d {
-            bit = 0xC0;
-        }
-        let rsv = (*rsv as usize) & (bit >> ((addr - idx * 32) / 4));
-        if rsv == 0 {
-            return false;
-        }
-        true
-    }
-
-    fn invalidate_mem_reservation(&mut self, addr: u64, d: bool) {
-        if addr & 4 != 0 {
-            panic!("invalid alinement");
-        }
-
-        let idx = addr / 32;
-        let rsv = self.mem_reserved_w.get(idx as usize).unwrap();
-        let mut bit = 0x80;
-        if d {
-            bit = 0xC0;
-        }
-        let rsv = (*rsv as usize) & !(bit >> ((addr - idx * 32) / 4));
-        self.mem_reserved_w[idx as usize] = rsv as u8;
+        Ok(())
     }
 
     /// x[rd] = AMO32(M[x[rs1]] SWAP x[rs2])
-    fn amoswap_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
-
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u32;
-        if mm {
-            data = self.l_mm(addr as u64) as u32;
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.lw_dram(addr as u64) as u32;
-        }
-
-        if mm {
-            self.bus
-                .s_mm(addr, self.reg.get_reg(inst.rs2) as u32 as u64);
-        } else {
-            self.bus.sw_dram(addr, self.reg.get_reg(inst.rs2) as u32);
-        }
-        self.reg.set_reg(inst.rs2, data as i32 as i64 as u64);
-        self.reg.set_reg(inst.rd, data as u64);
+    fn amoswap_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 4, |_, b| b)
     }
 
     /// x[rd] = AMO32(M[x[rs1]] + x[rs2])
-    fn amoadd_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
-
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u32;
-        if mm {
-            data = self.l_mm(addr as u64) as u32;
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.lw_dram(addr as u64) as u32;
-        }
-
-        let data = data as i32 + self.reg.get_reg(inst.rs2) as i32;
-        self.bus.sw_dram(addr, data as u32);
-        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+    fn amoadd_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 4, |a, b| a.wrapping_add(b))
     }
 
     /// x[rd] = AMO32(M[x[rs1]] ^ x[rs2])
-    fn amoxor_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
-
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u32;
-        if mm {
-            data = self.l_mm(addr as u64) as u32;
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.lw_dram(addr as u64) as u32;
-        }
-
-        let data = data ^ self.reg.get_reg(inst.rs2) as u32;
-        self.bus.sw_dram(addr, data);
-        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+    fn amoxor_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 4, |a, b| a ^ b)
     }
 
     /// x[rd] = AMO32(M[x[rs1]] & x[rs2])
-    fn amoand_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
-
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u32;
-        if mm {
-            data = self.l_mm(addr as u64) as u32;
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.lw_dram(addr as u64) as u32;
-        }
-
-        let data = data & self.reg.get_reg(inst.rs2) as u32;
-        self.bus.sw_dram(addr, data);
-        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+    fn amoand_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 4, |a, b| a & b)
     }
 
     /// x[rd] = AMO32(M[x[rs1]] | x[rs2])
-    fn amoor_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
-
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u32;
-        if mm {
-            data = self.l_mm(addr as u64) as u32;
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.lw_dram(addr as u64) as u32;
-        }
-
-        let data = data | self.reg.get_reg(inst.rs2) as u32;
-        self.bus.sw_dram(addr, data);
-        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+    fn amoor_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 4, |a, b| a | b)
     }
 
     /// x[rd] = AMO32(M[x[rs1]] MIN x[rs2])
-    fn amomin_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
-
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u32;
-        if mm {
-            data = self.l_mm(addr as u64) as u32;
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.lw_dram(addr as u64) as u32;
-        }
-
-        let rs2_v = self.reg.get_reg(inst.rs2) as i64 as i32;
-        if (data as i32) < rs2_v {
-            self.bus.sw_dram(addr, data);
-        } else {
-            self.bus.sw_dram(addr, self.reg.get_reg(inst.rs2) as u32);
-        }
-        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+    fn amomin_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 4, |a, b| (a as i32).min(b as i32) as u64)
     }
 
     /// x[rd] = AMO32(M[x[rs1]] MAX x[rs2])
-    fn amomax_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
-
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u32;
-        if mm {
-            data = self.l_mm(addr as u64) as u32;
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.lw_dram(addr as u64) as u32;
-        }
-
-        let rs2_v = self.reg.get_reg(inst.rs2) as i64 as i32;
-        if (data as i32) < rs2_v {
-            self.bus.sw_dram(addr, self.reg.get_reg(inst.rs2) as u32);
-        } else {
-            self.bus.sw_dram(addr, data);
-        }
-        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+    fn amomax_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 4, |a, b| (a as i32).max(b as i32) as u64)
     }
 
     /// x[rd] = AMO32(M[x[rs1]] MINU x[rs2])
-    fn amominu_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
-
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u32;
-        if mm {
-            data = self.l_mm(addr as u64) as u32;
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.lw_dram(addr as u64) as u32;
-        }
-
-        let rs2_v = self.reg.get_reg(inst.rs2) as i64 as i32;
-        if (data as i32) < rs2_v {
-            self.bus.sw_dram(addr, data);
-        } else {
-            self.bus.sw_dram(addr, self.reg.get_reg(inst.rs2) as u32);
-        }
-        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+    fn amominu_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 4, |a, b| (a as u32).min(b as u32) as u64)
     }
 
     /// x[rd] = AMO32(M[x[rs1]] MAXU x[rs2])
-    fn amomaxu_w(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    fn amomaxu_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 4, |a, b| (a as u32).max(b as u32) as u64)
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u32;
-        if mm {
-            data = self.l_mm(addr as u64) as u32;
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.lw_dram(addr as u64) as u32;
+    /// x[rd] = AMO(M[x[rs1]] op x[rs2])
+    /// Atomically replace the naturally aligned `size` bytes at x[rs1] with op(old, x[rs2]),
+    /// and write the old value to x[rd]. A 32-bit AMO sign-extends it.
+    /// An AMO on a device is not atomic with respect to other harts.
+    fn amo<F>(&mut self, inst: &Instruction, size: u64, op: F) -> Result<(), Exception>
+    where
+        F: Fn(u64, u64) -> u64,
+    {
+        let va = self.reg.get_reg(inst.rs1);
+        if va & (size - 1) != 0 {
+            return Err(Exception::StoreAddressMisaligned(va));
         }
-
-        let rs2_v = self.reg.get_reg(inst.rs2) as i64 as i32;
-        if (data as i32) < rs2_v {
-            self.bus.sw_dram(addr, self.reg.get_reg(inst.rs2) as u32);
+        let addr = self.trans_addr(va, AccessType::Store)?;
+        self.check_pmp(va, addr, size, AccessType::Store)?;
+
+        let src = self.reg.get_reg(inst.rs2);
+        let old = if addr < MEM_OFF as u64 {
+            let old = self.load_phys(addr, size);
+            self.store_phys(addr, size, op(old, src));
+            old
         } else {
-            self.bus.sw_dram(addr, data);
-        }
-        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+            let addr = addr - MEM_OFF as u64;
+            self.bus
+                .amo_dram(addr, size, amo_order(inst), |old| op(old, src))
+        };
+        let old = match size {
+            4 => old as i32 as i64 as u64,
+            _ => old,
+        };
+        self.reg.set_reg(inst.rd, old);
+        Ok(())
     }
 
     /// x[rd] = M[x[rs1] + sext(offset)][31:0]
-    fn lwu(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
-        let addr = self.reg.get_reg(inst.rs1) as i64 + imm;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::R);
-
-        let v: u64;
-        if (addr as usize) < MEM_OFF {
-            v = self.l_mm(addr as u64) as u32 as u64;
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            v = self.bus.lw_dram(addr as u64) as u64;
-        }
+    fn lwu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        let v = self.load(addr, 4)?;
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// x[rd] = M[x[rs1] + sext(offset)][63:0]
-    fn ld(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
-        let addr = self.reg.get_reg(inst.rs1) as i64 + imm;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::R);
-
-        let v: u64;
-        if (addr as usize) < MEM_OFF {
-            v = self.l_mm(addr as u64);
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            v = self.bus.ld_dram(addr as u64);
-        }
+    fn ld(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        let v = self.load(addr, 8)?;
         self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
     /// M[x[rs1] + sext(offset)] = x[rs2][63:0]
-    fn sd(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
-        let addr = self.reg.get_reg(inst.rs1) as i64 + imm;
-        let addr = self.trans_addr(addr as u64);
-        self.check_pmp(addr as u64, PMPPerm::W);
-
-        let v = self.reg.get_reg(inst.rs2);
-        if (addr as usize) < MEM_OFF {
-            self.s_mm(addr as u64, v);
-        } else {
-            let addr = (addr as i64 - MEM_OFF as i64) as u64;
-            self.bus.sd_dram(addr as u64, v);
-        }
+    fn sd(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        self.store(addr, 8, self.reg.get_reg(inst.rs2))
     }
 
     /// x[rd] = sext((x[rs1] + sext(immediate))[31:0])
-    fn addiw(&mut self, inst: &Instruction) {
-        let imm = sext(inst.imm as u64, 0x800);
+    fn addiw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let imm = inst.imm;
         let v = self.reg.get_reg(inst.rs1) as i64 + imm;
         self.reg.set_reg(inst.rd, v as i32 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext((x[rs1] << shamt)[31:0])
-    fn slliw(&mut self, inst: &Instruction) {
-        let shamt = (inst.imm & 0b11_1111) as u8;
-        if shamt & 0b10_0000 == 1 {
-            panic!("reserved encoding of slliw");
-        }
+    fn slliw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = (inst.imm & 0b1_1111) as u8;
         let v = self.reg.get_reg(inst.rs1) << shamt;
         self.reg.set_reg(inst.rd, v as i32 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(x[rs1][31:0] >>u shamt)
-    fn srliw(&mut self, inst: &Instruction) {
-        let shamt = (inst.imm & 0b11_1111) as u8;
-        if shamt & 0b10_0000 == 1 {
-            panic!("reserved encoding of srliw");
-        }
+    fn srliw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = (inst.imm & 0b1_1111) as u8;
         let v = (self.reg.get_reg(inst.rs1) as u32) >> shamt;
         self.reg.set_reg(inst.rd, v as i32 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(x[rs1][31:0] >>s shamt)
-    fn sraiw(&mut self, inst: &Instruction) {
-        let shamt = (inst.imm & 0b11_1111) as u8;
-        if shamt & 0b10_0000 == 1 {
-            panic!("reserved encoding of sraiw");
-        }
+    fn sraiw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = (inst.imm & 0b1_1111) as u8;
         let rs1 = self.reg.get_reg(inst.rs1) as i32;
         let v = rs1 >> shamt;
         self.reg.set_reg(inst.rd, v as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext((x[rs1] + x[rs2])[31:0])
-    fn addw(&mut self, inst: &Instruction) {
+    fn addw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) + self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v as i32 as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext((x[rs1] - x[rs2])[31:0])
-    fn subw(&mut self, inst: &Instruction) {
+    fn subw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) - self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v as i32 as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext((x[rs1] << x[rs2][4:0])[31:0])
-    fn sllw(&mut self, inst: &Instruction) {
+    fn sllw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let shamt = self.reg.get_reg(inst.rs2) & 0b1_1111;
         let v = self.reg.get_reg(inst.rs1) << shamt;
         self.reg.set_reg(inst.rd, v as u32 as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(x[rs1][31:0] >>u x[rs2][4:0])
-    fn srlw(&mut self, inst: &Instruction) {
+    fn srlw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let shamt = self.reg.get_reg(inst.rs2) & 0b1_1111;
         let v = (self.reg.get_reg(inst.rs1) as u32) >> shamt;
         self.reg.set_reg(inst.rd, v as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(x[rs1][31:0] >>s x[rs2][4:0])
-    fn sraw(&mut self, inst: &Instruction) {
+    fn sraw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let shamt = self.reg.get_reg(inst.rs2) & 0b1_1111;
         let v = (self.reg.get_reg(inst.rs1) as u32 as i32) >> shamt;
         self.reg.set_reg(inst.rd, v as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext((x[rs1] × x[rs2])[31:0])
-    fn mulw(&mut self, inst: &Instruction) {
+    fn mulw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = self.reg.get_reg(inst.rs1) * self.reg.get_reg(inst.rs2);
         self.reg.set_reg(inst.rd, v as u32 as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(x[rs1][31:0] /s x[rs2][31:0]
-    fn divw(&mut self, inst: &Instruction) {
+    fn divw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v =
             (self.reg.get_reg(inst.rs1) as u32 as i32) / (self.reg.get_reg(inst.rs2) as u32 as i32);
         self.reg.set_reg(inst.rd, v as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(x[rs1][31:0] /u x[rs2][31:0])
-    fn divuw(&mut self, inst: &Instruction) {
+    fn divuw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = (self.reg.get_reg(inst.rs1) as u32) / (self.reg.get_reg(inst.rs2) as u32);
         self.reg.set_reg(inst.rd, v as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(x[rs1][31:0] %s x[rs2][31:0])
-    fn remw(&mut self, inst: &Instruction) {
+    fn remw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v =
             (self.reg.get_reg(inst.rs1) as u32 as i32) % (self.reg.get_reg(inst.rs2) as u32 as i32);
         self.reg.set_reg(inst.rd, v as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = sext(x[rs1][31:0] %u x[rs2][31:0])
-    fn remuw(&mut self, inst: &Instruction) {
+    fn remuw(&mut self, inst: &Instruction) -> Result<(), Exception> {
         let v = (self.reg.get_reg(inst.rs1) as u32) % (self.reg.get_reg(inst.rs2) as u32);
         self.reg.set_reg(inst.rd, v as i64 as u64);
+        Ok(())
     }
 
     /// x[rd] = LoadReserved64(M[x[rs1]])
-    fn lr_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::R);
-
-        let data = self.bus.ld_dram(addr);
+    fn lr_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let data = self.load_reserved(inst, 8)?;
         self.reg.set_reg(inst.rd, data);
-        self.reserve_mem(addr, true);
+        Ok(())
     }
 
     /// x[rd] = StoreConditional64(M[x[rs1]], x[rs2])
-    fn sc_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
-        self.invalidate_mem_reservation(addr, true);
+    fn sc_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.store_conditional(inst, 8)
+    }
 
-        let data = self.reg.get_reg(inst.rs2);
+    /// x[rd] = AMO64(M[x[rs1]] SWAP x[rs2])
+    fn amoswap_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 8, |_, b| b)
+    }
 
-        if self.check_mem_reservation(addr, true) {
-            panic!("memory is not reserved");
-        }
+    /// x[rd] = AMO64(M[x[rs1]] + x[rs2])
+    fn amoadd_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 8, |a, b| a.wrapping_add(b))
+    }
 
-        self.bus.sd_dram(addr, data);
-        self.reg.set_reg(inst.rd, 0);
+    /// x[rd] = AMO64(M[x[rs1]] ^ x[rs2])
+    fn amoxor_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 8, |a, b| a ^ b)
     }
 
-    /// x[rd] = AMO64(M[x[rs1]] SWAP x[rs2])
-    fn amoswap_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    /// x[rd] = AMO64(M[x[rs1]] & x[rs2])
+    fn amoand_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 8, |a, b| a & b)
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u64;
-        if mm {
-            data = self.l_mm(addr as u64);
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.ld_dram(addr);
+    /// x[rd] = AMO64(M[x[rs1]] | x[rs2])
+    fn amoor_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 8, |a, b| a | b)
+    }
+
+    /// x[rd] = AMO64(M[x[rs1]] MIN x[rs2])
+    fn amomin_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 8, |a, b| (a as i64).min(b as i64) as u64)
+    }
+
+    /// x[rd] = AMO64(M[x[rs1]] MAX x[rs2])
+    fn amomax_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 8, |a, b| (a as i64).max(b as i64) as u64)
+    }
+
+    /// x[rd] = AMO64(M[x[rs1]] MINU x[rs2])
+    fn amominu_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 8, |a, b| a.min(b))
+    }
+
+    /// x[rd] = AMO64(M[x[rs1]] MAXU x[rs2])
+    fn amomaxu_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.amo(inst, 8, |a, b| a.max(b))
+    }
+    /// Raise an illegal instruction exception when mstatus.FS is Off.
+    fn check_fs(&self, inst: &Instruction) -> Result<(), Exception> {
+        if self.reg.fs_off() {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
         }
+        Ok(())
+    }
 
-        if mm {
-            self.bus.s_mm(addr, self.reg.get_reg(inst.rs2));
+    /// Rounding mode of the instruction. 0b111 selects the dynamic rounding mode in frm.
+    /// The reserved modes 0b101 and 0b110 are illegal.
+    fn rounding_mode(&self, inst: &Instruction) -> Result<u8, Exception> {
+        let rm = if inst.rm == 0b111 {
+            self.reg.get_csr(0x002) as u8
         } else {
-            self.bus.sd_dram(addr, self.reg.get_reg(inst.rs2));
+            inst.rm
+        };
+        if rm > fpu::RMM {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
         }
-        self.reg.set_reg(inst.rs2, data);
-        self.reg.set_reg(inst.rd, data);
+        Ok(rm)
     }
 
-    /// x[rd] = AMO64(M[x[rs1]] + x[rs2])
-    fn amoadd_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    /// Read f[reg]. A single-precision value must be NaN-boxed (upper 32 bits are all 1),
+    /// otherwise it is read as the canonical NaN.
+    fn get_fp(&self, p: Precision, reg: u8) -> u64 {
+        let v = self.reg.get_freg(reg);
+        match p {
+            Precision::Single if v >> 32 != 0xFFFF_FFFF => p.canonical_nan(),
+            Precision::Single => v & 0xFFFF_FFFF,
+            Precision::Double => v,
+        }
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
+    /// Write f[reg]. A single-precision value is NaN-boxed.
+    fn set_fp(&mut self, p: Precision, reg: u8, v: u64) {
+        let v = match p {
+            Precision::Single => 0xFFFF_FFFF_0000_0000 | v,
+            Precision::Double => v,
         };
-        let data: u64;
-        if mm {
-            data = self.l_mm(addr as u64);
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.ld_dram(addr);
+        self.reg.set_freg(reg, v);
+        self.reg.set_fs_dirty();
+    }
+
+    /// fcsr.fflags |= flags
+    fn accrue_fflags(&mut self, flags: u8) {
+        if flags != 0 {
+            self.reg.fcsr |= flags as u64;
+            self.reg.set_fs_dirty();
         }
+    }
 
-        let data = data as i64 + self.reg.get_reg(inst.rs2) as i64;
-        self.bus.sd_dram(addr, data as u64);
-        self.reg.set_reg(inst.rd, data as u64);
+    /// f[rd] = M[x[rs1] + sext(offset)][31:0]
+    fn flw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        let v = self.load(addr, 4)?;
+        self.set_fp(Precision::Single, inst.rd, v);
+        Ok(())
     }
 
-    /// x[rd] = AMO64(M[x[rs1]] ^ x[rs2])
-    fn amoxor_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    /// M[x[rs1] + sext(offset)] = f[rs2][31:0]
+    fn fsw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        self.store(addr, 4, self.reg.get_freg(inst.rs2))
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u64;
-        if mm {
-            data = self.l_mm(addr as u64);
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.ld_dram(addr as u64);
-        }
+    /// f[rd] = M[x[rs1] + sext(offset)][63:0]
+    fn fld(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        let v = self.load(addr, 8)?;
+        self.set_fp(Precision::Double, inst.rd, v);
+        Ok(())
+    }
 
-        let data = data ^ self.reg.get_reg(inst.rs2);
-        self.bus.sd_dram(addr, data);
-        self.reg.set_reg(inst.rd, data);
+    /// M[x[rs1] + sext(offset)] = f[rs2][63:0]
+    fn fsd(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let imm = inst.imm;
+        let addr = self.reg.get_reg(inst.rs1).wrapping_add(imm as u64);
+        self.store(addr, 8, self.reg.get_freg(inst.rs2))
     }
 
-    /// x[rd] = AMO64(M[x[rs1]] & x[rs2])
-    fn amoand_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    /// (a * b) + c. `neg_prod` negates a * b and `neg_c` negates c.
+    fn fused_mul_add(
+        &mut self,
+        inst: &Instruction,
+        p: Precision,
+        neg_prod: bool,
+        neg_c: bool,
+    ) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let c = self.get_fp(p, inst.rs3);
+        let mut flags = 0;
+        let v = fpu::fma(p, a, b, c, neg_prod, neg_c, rm, &mut flags);
+        self.set_fp(p, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u64;
-        if mm {
-            data = self.l_mm(addr as u64);
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.ld_dram(addr as u64);
-        }
+    /// f[rd] = f[rs1] * f[rs2] + f[rs3]
+    fn fmadd(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.fused_mul_add(inst, p, false, false)
+    }
 
-        let data = data & self.reg.get_reg(inst.rs2);
-        self.bus.sd_dram(addr, data);
-        self.reg.set_reg(inst.rd, data);
+    /// f[rd] = f[rs1] * f[rs2] - f[rs3]
+    fn fmsub(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.fused_mul_add(inst, p, false, true)
     }
 
-    /// x[rd] = AMO64(M[x[rs1]] | x[rs2])
-    fn amoor_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    /// f[rd] = -f[rs1] * f[rs2] + f[rs3]
+    fn fnmsub(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.fused_mul_add(inst, p, true, false)
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u64;
-        if mm {
-            data = self.l_mm(addr as u64);
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.ld_dram(addr as u64);
-        }
+    /// f[rd] = -f[rs1] * f[rs2] - f[rs3]
+    fn fnmadd(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.fused_mul_add(inst, p, true, true)
+    }
 
-        let data = data | self.reg.get_reg(inst.rs2);
-        self.bus.sd_dram(addr, data);
-        self.reg.set_reg(inst.rd, data as i32 as i64 as u64);
+    /// f[rd] = f[rs1] + f[rs2]
+    fn fadd(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let mut flags = 0;
+        let v = fpu::add(p, a, b, false, rm, &mut flags);
+        self.set_fp(p, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
     }
 
-    /// x[rd] = AMO64(M[x[rs1]] MIN x[rs2])
-    fn amomin_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    /// f[rd] = f[rs1] - f[rs2]
+    fn fsub(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let mut flags = 0;
+        let v = fpu::add(p, a, b, true, rm, &mut flags);
+        self.set_fp(p, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u64;
-        if mm {
-            data = self.l_mm(addr as u64);
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.ld_dram(addr as u64);
-        }
+    /// f[rd] = f[rs1] * f[rs2]
+    fn fmul(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let mut flags = 0;
+        let v = fpu::mul(p, a, b, rm, &mut flags);
+        self.set_fp(p, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
 
-        let rs2_v = self.reg.get_reg(inst.rs2) as i64 as i32;
-        if (data as i32) < rs2_v {
-            self.bus.sd_dram(addr, data);
-        } else {
-            self.bus.sd_dram(addr, self.reg.get_reg(inst.rs2));
-        }
-        self.reg.set_reg(inst.rd, data);
+    /// f[rd] = f[rs1] / f[rs2]
+    fn fdiv(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let mut flags = 0;
+        let v = fpu::div(p, a, b, rm, &mut flags);
+        self.set_fp(p, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
     }
 
-    /// x[rd] = AMO64(M[x[rs1]] MAX x[rs2])
-    fn amomax_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    /// f[rd] = sqrt(f[rs1])
+    fn fsqrt(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let mut flags = 0;
+        let v = fpu::sqrt(p, a, rm, &mut flags);
+        self.set_fp(p, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u64;
-        if mm {
-            data = self.l_mm(addr as u64);
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.ld_dram(addr as u64);
+    /// Sign bit of the precision `p`.
+    fn fp_sign_bit(p: Precision) -> u64 {
+        match p {
+            Precision::Single => 0x8000_0000,
+            Precision::Double => 0x8000_0000_0000_0000,
         }
+    }
 
-        let rs2_v = self.reg.get_reg(inst.rs2) as i64;
-        if (data as i64) < rs2_v {
-            self.bus.sd_dram(addr, self.reg.get_reg(inst.rs2));
-        } else {
-            self.bus.sd_dram(addr, data);
-        }
-        self.reg.set_reg(inst.rd, data);
+    /// f[rd] = {f[rs2][sign], f[rs1][others]}
+    fn fsgnj(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let sign = Cpu::fp_sign_bit(p);
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        self.set_fp(p, inst.rd, (a & !sign) | (b & sign));
+        Ok(())
     }
 
-    /// x[rd] = AMO64(M[x[rs1]] MINU x[rs2])
-    fn amominu_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    /// f[rd] = {~f[rs2][sign], f[rs1][others]}
+    fn fsgnjn(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let sign = Cpu::fp_sign_bit(p);
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        self.set_fp(p, inst.rd, (a & !sign) | (!b & sign));
+        Ok(())
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u64;
-        if mm {
-            data = self.l_mm(addr as u64);
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.ld_dram(addr as u64);
-        }
+    /// f[rd] = {f[rs1][sign] ^ f[rs2][sign], f[rs1][others]}
+    fn fsgnjx(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let sign = Cpu::fp_sign_bit(p);
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        self.set_fp(p, inst.rd, a ^ (b & sign));
+        Ok(())
+    }
 
-        let rs2_v = self.reg.get_reg(inst.rs2) as i64;
-        if (data as i64) < rs2_v {
-            self.bus.sd_dram(addr, data);
-        } else {
-            self.bus.sd_dram(addr, self.reg.get_reg(inst.rs2));
-        }
-        self.reg.set_reg(inst.rd, data);
+    /// f[rd] = min(f[rs1], f[rs2])
+    fn fmin(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let mut flags = 0;
+        let v = fpu::min_max(p, a, b, false, &mut flags);
+        self.set_fp(p, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
     }
 
-    /// x[rd] = AMO64(M[x[rs1]] MAXU x[rs2])
-    fn amomaxu_d(&mut self, inst: &Instruction) {
-        let addr = self.reg.get_reg(inst.rs1);
-        let mut addr = self.trans_addr(addr);
-        self.check_pmp(addr, PMPPerm::W);
+    /// f[rd] = max(f[rs1], f[rs2])
+    fn fmax(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let mut flags = 0;
+        let v = fpu::min_max(p, a, b, true, &mut flags);
+        self.set_fp(p, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
 
-        let mm = if (addr as usize) < MEM_OFF {
-            true
-        } else {
-            false
-        };
-        let data: u64;
-        if mm {
-            data = self.l_mm(addr as u64);
-        } else {
-            addr = (addr as i64 - MEM_OFF as i64) as u64;
-            data = self.bus.ld_dram(addr as u64);
-        }
+    /// f[rd] = f32(f[rs1])
+    fn fcvt_s_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let a = self.get_fp(Precision::Double, inst.rs1);
+        let mut flags = 0;
+        let v = fpu::convert(Precision::Double, Precision::Single, a, rm, &mut flags);
+        self.set_fp(Precision::Single, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
 
-        let rs2_v = self.reg.get_reg(inst.rs2) as i64;
-        if (data as i64) < rs2_v {
-            self.bus.sd_dram(addr, self.reg.get_reg(inst.rs2));
-        } else {
-            self.bus.sd_dram(addr, data);
-        }
-        self.reg.set_reg(inst.rd, data);
+    /// f[rd] = f64(f[rs1])
+    fn fcvt_d_s(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let a = self.get_fp(Precision::Single, inst.rs1);
+        let mut flags = 0;
+        let v = fpu::convert(Precision::Single, Precision::Double, a, rm, &mut flags);
+        self.set_fp(Precision::Double, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
+
+    /// x[rd] = f[rs1] == f[rs2]
+    fn feq(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let mut flags = 0;
+        let v = fpu::eq(p, a, b, &mut flags);
+        self.reg.set_reg(inst.rd, v as u64);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
+
+    /// x[rd] = f[rs1] < f[rs2]
+    fn flt(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let mut flags = 0;
+        let v = fpu::less(p, a, b, false, &mut flags);
+        self.reg.set_reg(inst.rd, v as u64);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
+
+    /// x[rd] = f[rs1] <= f[rs2]
+    fn fle(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let b = self.get_fp(p, inst.rs2);
+        let mut flags = 0;
+        let v = fpu::less(p, a, b, true, &mut flags);
+        self.reg.set_reg(inst.rd, v as u64);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
+
+    /// x[rd] = classify(f[rs1])
+    fn fclass(&mut self, inst: &Instruction, p: Precision) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        self.reg.set_reg(inst.rd, fpu::classify(p, a));
+        Ok(())
+    }
+
+    /// fcvt.w, fcvt.wu, fcvt.l and fcvt.lu
+    /// x[rd] = sext(int(f[rs1]))
+    /// An out of range value or NaN is saturated and raises the invalid operation flag.
+    fn fcvt_to_int(
+        &mut self,
+        inst: &Instruction,
+        p: Precision,
+        signed: bool,
+        width: u32,
+    ) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let a = self.get_fp(p, inst.rs1);
+        let mut flags = 0;
+        let v = fpu::to_int(p, a, signed, width, rm, &mut flags);
+        self.reg.set_reg(inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
+
+    /// fcvt.{s,d}.w, fcvt.{s,d}.wu, fcvt.{s,d}.l and fcvt.{s,d}.lu
+    /// f[rd] = float(x[rs1])
+    fn fcvt_from_int(
+        &mut self,
+        inst: &Instruction,
+        p: Precision,
+        signed: bool,
+        width: u32,
+    ) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let rm = self.rounding_mode(inst)?;
+        let v = self.reg.get_reg(inst.rs1);
+        let mut flags = 0;
+        let v = fpu::from_int(p, v, signed, width, rm, &mut flags);
+        self.set_fp(p, inst.rd, v);
+        self.accrue_fflags(flags);
+        Ok(())
+    }
+
+    /// x[rd] = sext(f[rs1][31:0])
+    fn fmv_x_w(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let v = self.reg.get_freg(inst.rs1) as i32 as i64;
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// f[rd] = x[rs1][31:0]
+    fn fmv_w_x(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let v = self.reg.get_reg(inst.rs1) as u32;
+        self.set_fp(Precision::Single, inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = f[rs1][63:0]
+    fn fmv_x_d(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        self.reg.set_reg(inst.rd, self.reg.get_freg(inst.rs1));
+        Ok(())
     }
-}
 
-/// Sign-extended when imm is negative.
-/// # Examples
-/// ```ignore
-/// let imm = 0xAD; // 0b1010_1101
-/// let sign_bit = 0x80; // 0b1000_0000
-/// assert_eq!(0xFFFF_FFFF_FFFF_FFAD, cpu::sext(imm, sign_bit));
-/// ```
-fn sext(imm: u64, sign_bit: u64) -> i64 {
-    if sign_bit & imm == 0 {
-        return imm as i64;
+    /// f[rd] = x[rs1][63:0]
+    fn fmv_d_x(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_fs(inst)?;
+        let v = self.reg.get_reg(inst.rs1);
+        self.set_fp(Precision::Double, inst.rd, v);
+        Ok(())
     }
 
-    let mut sign = 1;
-    let mut shift_cnt = sign_bit;
-    loop {
-        shift_cnt >>= 1;
-        if shift_cnt == 0 {
-            break;
+    /// x[rd] = x[rs2] + (x[rs1] << shamt). Zero-extends x[rs1][31:0] first when `uw` is set.
+    fn shift_add(&mut self, inst: &Instruction, shamt: u32, uw: bool) -> Result<(), Exception> {
+        let mut rs1 = self.reg.get_reg(inst.rs1);
+        if uw {
+            rs1 = rs1 as u32 as u64;
         }
-        sign <<= 1;
-        sign += 1;
+        let v = self.reg.get_reg(inst.rs2).wrapping_add(rs1 << shamt);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
     }
 
-    (imm | !sign) as i64
-}
+    /// x[rd] = x[rs2] + (x[rs1] << 1)
+    fn sh1add(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.shift_add(inst, 1, false)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::dram::Dram;
-    use crate::uart::Uart;
-    use crate::plic::Plic;
-    use crate::virtio::Virtio;
+    /// x[rd] = x[rs2] + (x[rs1] << 2)
+    fn sh2add(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.shift_add(inst, 2, false)
+    }
 
-    #[test]
-    fn lui_test() {
-        let dram = Dram::new(0);
-        let uart = Uart::new();
-        let plic = Plic::new();
-        let virtio = Virtio::new();
-        let bus = Bus::new(dram, uart, plic, virtio);
-        let dbg = Debug::new(false, 0);
-        let mut cpu = Cpu::new(bus, 0, dbg);
-        // lui	a0,0x1
-        let inst = Instruction::decode(0x0000_1537);
-        cpu.lui(&inst);
-        assert_eq!(cpu.reg.a0, 0x1000);
+    /// x[rd] = x[rs2] + (x[rs1] << 3)
+    fn sh3add(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.shift_add(inst, 3, false)
+    }
+
+    /// x[rd] = x[rs2] + zext(x[rs1][31:0])
+    fn add_uw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.shift_add(inst, 0, true)
+    }
+
+    /// x[rd] = x[rs2] + (zext(x[rs1][31:0]) << 1)
+    fn sh1add_uw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.shift_add(inst, 1, true)
+    }
+
+    /// x[rd] = x[rs2] + (zext(x[rs1][31:0]) << 2)
+    fn sh2add_uw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.shift_add(inst, 2, true)
+    }
+
+    /// x[rd] = x[rs2] + (zext(x[rs1][31:0]) << 3)
+    fn sh3add_uw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.shift_add(inst, 3, true)
+    }
+
+    /// x[rd] = zext(x[rs1][31:0]) << shamt
+    fn slli_uw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = inst.imm & 0b11_1111;
+        let v = (self.reg.get_reg(inst.rs1) as u32 as u64) << shamt;
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] & ~x[rs2]
+    fn andn(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1) & !self.reg.get_reg(inst.rs2);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] | ~x[rs2]
+    fn orn(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1) | !self.reg.get_reg(inst.rs2);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = ~(x[rs1] ^ x[rs2])
+    fn xnor(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = !(self.reg.get_reg(inst.rs1) ^ self.reg.get_reg(inst.rs2));
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = number of leading zero bits of x[rs1]
+    fn clz(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1).leading_zeros();
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = number of leading zero bits of x[rs1][31:0]
+    fn clzw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = (self.reg.get_reg(inst.rs1) as u32).leading_zeros();
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = number of trailing zero bits of x[rs1]
+    fn ctz(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1).trailing_zeros();
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = number of trailing zero bits of x[rs1][31:0]
+    fn ctzw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = (self.reg.get_reg(inst.rs1) as u32).trailing_zeros();
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = number of set bits of x[rs1]
+    fn cpop(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1).count_ones();
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = number of set bits of x[rs1][31:0]
+    fn cpopw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = (self.reg.get_reg(inst.rs1) as u32).count_ones();
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] >s x[rs2] ? x[rs1] : x[rs2]
+    fn max(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = (self.reg.get_reg(inst.rs1) as i64).max(self.reg.get_reg(inst.rs2) as i64);
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] >u x[rs2] ? x[rs1] : x[rs2]
+    fn maxu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1).max(self.reg.get_reg(inst.rs2));
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] <s x[rs2] ? x[rs1] : x[rs2]
+    fn min(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = (self.reg.get_reg(inst.rs1) as i64).min(self.reg.get_reg(inst.rs2) as i64);
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] <u x[rs2] ? x[rs1] : x[rs2]
+    fn minu(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1).min(self.reg.get_reg(inst.rs2));
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = sext(x[rs1][7:0])
+    fn sext_b(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1) as i8 as i64;
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = sext(x[rs1][15:0])
+    fn sext_h(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1) as i16 as i64;
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = zext(x[rs1][15:0])
+    fn zext_h(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1) as u16;
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = (x[rs1] << x[rs2][5:0]) | (x[rs1] >>u (64 - x[rs2][5:0]))
+    fn rol(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = self.reg.get_reg(inst.rs2) & 0b11_1111;
+        let v = self.reg.get_reg(inst.rs1).rotate_left(shamt as u32);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = sext((x[rs1][31:0] << x[rs2][4:0]) | (x[rs1][31:0] >>u (32 - x[rs2][4:0])))
+    fn rolw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = self.reg.get_reg(inst.rs2) & 0b1_1111;
+        let v = (self.reg.get_reg(inst.rs1) as u32).rotate_left(shamt as u32);
+        self.reg.set_reg(inst.rd, v as i32 as i64 as u64);
+        Ok(())
+    }
+
+    /// x[rd] = (x[rs1] >>u x[rs2][5:0]) | (x[rs1] << (64 - x[rs2][5:0]))
+    fn ror(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = self.reg.get_reg(inst.rs2) & 0b11_1111;
+        let v = self.reg.get_reg(inst.rs1).rotate_right(shamt as u32);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = (x[rs1] >>u shamt) | (x[rs1] << (64 - shamt))
+    fn rori(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = (inst.imm & 0b11_1111) as u32;
+        let v = self.reg.get_reg(inst.rs1).rotate_right(shamt);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = sext((x[rs1][31:0] >>u shamt) | (x[rs1][31:0] << (32 - shamt)))
+    fn roriw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = (inst.imm & 0b1_1111) as u32;
+        let v = (self.reg.get_reg(inst.rs1) as u32).rotate_right(shamt);
+        self.reg.set_reg(inst.rd, v as i32 as i64 as u64);
+        Ok(())
+    }
+
+    /// x[rd] = sext((x[rs1][31:0] >>u x[rs2][4:0]) | (x[rs1][31:0] << (32 - x[rs2][4:0])))
+    fn rorw(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let shamt = self.reg.get_reg(inst.rs2) & 0b1_1111;
+        let v = (self.reg.get_reg(inst.rs1) as u32).rotate_right(shamt as u32);
+        self.reg.set_reg(inst.rd, v as i32 as i64 as u64);
+        Ok(())
+    }
+
+    /// Each byte of x[rd] is 0xFF when the byte of x[rs1] is non-zero, otherwise 0x00.
+    fn orc_b(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let rs1 = self.reg.get_reg(inst.rs1);
+        let mut v = 0;
+        for i in 0..8 {
+            if (rs1 >> (i * 8)) & 0xFF != 0 {
+                v |= 0xFF << (i * 8);
+            }
+        }
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] with the byte order reversed
+    fn rev8(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.reg.get_reg(inst.rs1).swap_bytes();
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// 128-bit carry-less product of x[rs1] and x[rs2].
+    fn clmul_product(&self, inst: &Instruction) -> u128 {
+        let rs1 = self.reg.get_reg(inst.rs1) as u128;
+        let rs2 = self.reg.get_reg(inst.rs2);
+        let mut v = 0;
+        for i in 0..64 {
+            if (rs2 >> i) & 1 == 1 {
+                v ^= rs1 << i;
+            }
+        }
+        v
+    }
+
+    /// x[rd] = clmul(x[rs1], x[rs2])[63:0]
+    fn clmul(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.clmul_product(inst);
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = clmul(x[rs1], x[rs2])[127:64]
+    fn clmulh(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.clmul_product(inst) >> 64;
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = clmul(x[rs1], x[rs2])[126:63]
+    fn clmulr(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = self.clmul_product(inst) >> 63;
+        self.reg.set_reg(inst.rd, v as u64);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] & ~(1 << x[rs2][5:0])
+    fn bclr(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let index = self.reg.get_reg(inst.rs2) & 0b11_1111;
+        let v = self.reg.get_reg(inst.rs1) & !(1 << index);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] & ~(1 << shamt)
+    fn bclri(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let index = inst.imm & 0b11_1111;
+        let v = self.reg.get_reg(inst.rs1) & !(1 << index);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = (x[rs1] >> x[rs2][5:0]) & 1
+    fn bext(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let index = self.reg.get_reg(inst.rs2) & 0b11_1111;
+        let v = (self.reg.get_reg(inst.rs1) >> index) & 1;
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = (x[rs1] >> shamt) & 1
+    fn bexti(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let index = inst.imm & 0b11_1111;
+        let v = (self.reg.get_reg(inst.rs1) >> index) & 1;
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] ^ (1 << x[rs2][5:0])
+    fn binv(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let index = self.reg.get_reg(inst.rs2) & 0b11_1111;
+        let v = self.reg.get_reg(inst.rs1) ^ (1 << index);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] ^ (1 << shamt)
+    fn binvi(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let index = inst.imm & 0b11_1111;
+        let v = self.reg.get_reg(inst.rs1) ^ (1 << index);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] | (1 << x[rs2][5:0])
+    fn bset(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let index = self.reg.get_reg(inst.rs2) & 0b11_1111;
+        let v = self.reg.get_reg(inst.rs1) | (1 << index);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs1] | (1 << shamt)
+    fn bseti(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let index = inst.imm & 0b11_1111;
+        let v = self.reg.get_reg(inst.rs1) | (1 << index);
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs2] == 0 ? 0 : x[rs1]
+    fn czero_eqz(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = if self.reg.get_reg(inst.rs2) == 0 {
+            0
+        } else {
+            self.reg.get_reg(inst.rs1)
+        };
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// x[rd] = x[rs2] != 0 ? 0 : x[rs1]
+    fn czero_nez(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let v = if self.reg.get_reg(inst.rs2) != 0 {
+            0
+        } else {
+            self.reg.get_reg(inst.rs1)
+        };
+        self.reg.set_reg(inst.rd, v);
+        Ok(())
+    }
+
+    /// Whether the envcfg field enables a cache-block operation in the current mode.
+    /// Below M-mode it needs the field of menvcfg, and in U-mode also that of senvcfg.
+    /// With V=1 it also needs the field of henvcfg. Disabled by henvcfg, or by senvcfg
+    /// in VU-mode, the operation raises a virtual instruction exception.
+    fn check_cbo(&self, inst: &Instruction, field: u64) -> Result<(), Exception> {
+        if self.mode == Mode::M {
+            return Ok(());
+        }
+        if self.reg.menvcfg & field == 0 {
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        if self.reg.virt && self.reg.henvcfg & field == 0 {
+            return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+        }
+        if self.mode == Mode::U && self.reg.senvcfg & field == 0 {
+            if self.reg.virt {
+                return Err(Exception::VirtualInstruction(inst.raw_inst as u64));
+            }
+            return Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        }
+        Ok(())
+    }
+
+    /// The cache block containing x[rs1], which must be readable or writable.
+    /// Faults are reported as store faults.
+    /// The address is translated once, since a cache block never crosses a page.
+    fn cbo_addr(&mut self, inst: &Instruction, access: AccessType) -> Result<u64, Exception> {
+        let va = self.reg.get_reg(inst.rs1) & !(self.cache_block - 1);
+        let store_fault = |e: Exception| match e {
+            Exception::LoadPageFault(va) => Exception::StorePageFault(va),
+            Exception::LoadAccessFault(va) => Exception::StoreAccessFault(va),
+            Exception::LoadGuestPageFault(va, gpa) => Exception::StoreGuestPageFault(va, gpa),
+            e => e,
+        };
+        let addr = self.trans_addr(va, access).map_err(store_fault)?;
+        self.check_pmp(va, addr, self.cache_block, access)
+            .map_err(store_fault)?;
+        Ok(addr)
+    }
+
+    /// Write back the cache block containing x[rs1].
+    /// Memory is never cached, so cbo.clean and cbo.flush only check the access.
+    fn cbo_clean(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_cbo(inst, ENVCFG_CBCFE)?;
+        self.cbo_addr(inst, AccessType::Load)?;
+        Ok(())
+    }
+
+    /// Invalidate the cache block containing x[rs1].
+    /// cbo.inval is enabled by any non-zero CBIE, and either flushes or invalidates the block.
+    /// Both only check the access, since memory is never cached.
+    fn cbo_inval(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_cbo(inst, ENVCFG_CBIE)?;
+        self.cbo_addr(inst, AccessType::Load)?;
+        Ok(())
+    }
+
+    /// M[x[rs1] aligned down to the cache block][0..block size] = 0
+    fn cbo_zero(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_cbo(inst, ENVCFG_CBZE)?;
+        let addr = self.cbo_addr(inst, AccessType::Store)?;
+        if addr < MEM_OFF as u64 {
+            return Err(Exception::StoreAccessFault(self.reg.get_reg(inst.rs1)));
+        }
+        for off in (0..self.cache_block).step_by(8) {
+            self.store_phys(addr + off, 8, 0);
+        }
+        Ok(())
+    }
+
+    /// A hint that the hart is in a spin-wait loop.
+    fn pause(&mut self, _inst: &Instruction) -> Result<(), Exception> {
+        std::hint::spin_loop();
+        Ok(())
+    }
+}
+
+/// Memory ordering of an AMO, LR or SC from its aq and rl bits.
+fn amo_order(inst: &Instruction) -> Ordering {
+    match (inst.raw_inst >> 26 & 1, inst.raw_inst >> 25 & 1) {
+        (1, 1) => Ordering::SeqCst,
+        (1, 0) => Ordering::Acquire,
+        (0, 1) => Ordering::Release,
+        _ => Ordering::Relaxed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aplic;
+    use crate::bus::Irqchip;
+    use crate::clint::{self, Clint};
+    use crate::cpu::instructions::InstFmt;
+    use crate::dram::Dram;
+    use crate::plic;
+    use crate::uart::Uart;
+    use crate::virtio::Virtio;
+
+    fn new_cpu(mem_size: usize) -> Cpu {
+        new_harts(mem_size, 1).pop().unwrap()
+    }
+
+    /// Harts sharing one bus with the PLIC.
+    fn new_harts(mem_size: usize, harts: usize) -> Vec<Cpu> {
+        new_machine(mem_size, harts, Irqchip::plic(harts))
+    }
+
+    fn new_machine(mem_size: usize, harts: usize, irqchip: Irqchip) -> Vec<Cpu> {
+        let dram = Dram::new(mem_size);
+        let uart = Uart::new();
+        let virtio = Virtio::new();
+        let clint = Clint::new(harts, conf::TIMEBASE_FREQ, false);
+        let bus = Arc::new(Bus::new(dram, clint, uart, irqchip, virtio, harts));
+        (0..harts)
+            .map(|h| {
+                let dbg = Debug::new(false, 0);
+                let block = conf::CACHE_BLOCK;
+                Cpu::new(bus.clone(), h as u64, dbg, false, false, block, conf::VLEN)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn load_seg_test() {
+        // the memory is kept in 8-byte words, but its size is in bytes
+        let mut dram = Dram::new(0x1_0000);
+        let data: Vec<u8> = (0..0x8000).map(|i| i as u8).collect();
+        dram.load_seg(&data, 0x10, MEM_OFF + 0x8000, 0x7FF0);
+        assert_eq!(dram.load_byte(0x8000), 0x10);
+        assert_eq!(dram.load_byte(0xFFEF), 0xFF);
+        assert_eq!(dram.load_byte(0x7FFF), 0);
+    }
+
+    #[test]
+    fn branch_offset_test() {
+        // B and J immediates are sign-extended from bit 12 and bit 20
+        let mut cpu = new_cpu(0);
+        cpu.reg.pc = 0x8010_0000;
+        for (raw, target) in [
+            (0x0000_00E3, 0x8010_0800), // beq zero,zero,2048
+            (0x8000_0063, 0x800F_F000), // beq zero,zero,-4096
+            (0x0008_006F, 0x8018_0000), // jal zero,0x80000
+            (0x8000_006F, 0x8000_0000), // jal zero,-0x100000
+        ] {
+            let inst = Instruction::decode(raw).unwrap();
+            match inst.name {
+                InstName::Beq => cpu.beq(&inst).unwrap(),
+                _ => cpu.jal(&inst).unwrap(),
+            }
+            assert_eq!(cpu.next_pc, target);
+        }
+    }
+
+    #[test]
+    fn jump_misaligned_test() {
+        let mut cpu = new_cpu(0);
+        cpu.reg.pc = 0x8010_0000;
+        // jal ra,6
+        let inst = Instruction::decode(0x0060_00EF).unwrap();
+        cpu.jal(&inst).unwrap();
+        assert_eq!(cpu.next_pc, 0x8010_0006);
+        assert_eq!(cpu.reg.ra, 0x8010_0004);
+
+        // without C, the target has to be aligned to 4 bytes and rd is not written
+        cpu.reg.ra = 0;
+        cpu.reg.set_csr(0x301, cpu.reg.misa & !0b100);
+        assert_eq!(
+            cpu.jal(&inst),
+            Err(Exception::InstructionAddressMisaligned(0x8010_0006))
+        );
+        assert_eq!(cpu.reg.ra, 0);
+    }
+
+    #[test]
+    fn lui_test() {
+        let mut cpu = new_cpu(0);
+        // lui	a0,0x1
+        let inst = Instruction::decode(0x0000_1537).unwrap();
+        cpu.lui(&inst).unwrap();
+        assert_eq!(cpu.reg.a0, 0x1000);
+    }
+
+    #[test]
+    fn ecall_test() {
+        let mut cpu = new_cpu(0);
+        cpu.reg.pc = 0x8000_1000;
+        cpu.reg.mtvec = 0x8000_2000;
+        cpu.reg.stvec = 0x8000_3000;
+        cpu.reg.medeleg = 1 << 8; // delegate ecall from U-mode
+        cpu.reg.mstatus |= 0b10; // SIE
+
+        // ecall from U-mode is delegated to S-mode
+        cpu.mode = Mode::U;
+        let inst = Instruction::decode(0x0000_0073).unwrap();
+        let e = cpu.ecall(&inst).unwrap_err();
+        int::exception(&mut cpu.reg, &mut cpu.mode, e);
+        assert_eq!(cpu.mode, Mode::S);
+        assert_eq!(cpu.reg.scause, 8);
+        assert_eq!(cpu.reg.sepc, 0x8000_1000);
+        assert_eq!(cpu.reg.pc, 0x8000_3000);
+        assert_eq!(cpu.reg.get_csr(0x100) & 0b1_0010_0010, 0b10_0000); // SPP=U, SPIE=1, SIE=0
+
+        // ecall from S-mode is taken in M-mode
+        let e = cpu.ecall(&inst).unwrap_err();
+        int::exception(&mut cpu.reg, &mut cpu.mode, e);
+        assert_eq!(cpu.mode, Mode::M);
+        assert_eq!(cpu.reg.mcause, 9);
+        assert_eq!(cpu.reg.mepc, 0x8000_3000);
+        assert_eq!(cpu.reg.pc, 0x8000_2000);
+        assert_eq!((cpu.reg.mstatus >> 11) & 0b11, 0b01); // MPP=S
+    }
+
+    #[test]
+    fn compressed_test() {
+        let mut cpu = new_cpu(0);
+        // c.li	a0,1
+        let inst = Instruction::decode(0x4505).unwrap();
+        assert_eq!(inst.len, 2);
+        cpu.exec_instruction(&inst).unwrap();
+        assert_eq!(cpu.reg.a0, 1);
+        // c.addi	sp,-16
+        cpu.reg.sp = 0x100;
+        let inst = Instruction::decode(0x1141).unwrap();
+        cpu.exec_instruction(&inst).unwrap();
+        assert_eq!(cpu.reg.sp, 0xF0);
+        // c.jalr	a0
+        cpu.reg.pc = 0x8000_0000;
+        cpu.reg.a0 = 0x8000_1000;
+        let inst = Instruction::decode(0x9502).unwrap();
+        cpu.exec_instruction(&inst).unwrap();
+        assert_eq!(cpu.reg.ra, 0x8000_0002);
+        assert_eq!(cpu.next_pc, 0x8000_1000);
+        // c.sdsp	ra,8(sp) and c.ldsp	ra,8(sp)
+        let inst = Instruction::decode(0xE406).unwrap();
+        assert_eq!((inst.rs1, inst.rs2, inst.imm), (2, 1, 8));
+        let inst = Instruction::decode(0x60A2).unwrap();
+        assert_eq!((inst.rs1, inst.rd, inst.imm), (2, 1, 8));
+    }
+
+    #[test]
+    fn float_test() {
+        let mut cpu = new_cpu(0);
+        // fdiv.s	fa0,fa0,fa1
+        let inst = Instruction::decode(0x18B5_7553).unwrap();
+        // mstatus.FS is Off
+        let e = cpu.exec_instruction(&inst).unwrap_err();
+        assert_eq!(e, Exception::IllegalInstruction(0x18B5_7553));
+
+        cpu.reg.mstatus = 0b010_0000_0000_0000; // FS=Initial
+        cpu.reg.fa0 = 0xFFFF_FFFF_3F80_0000; // 1.0
+        cpu.reg.fa1 = 0xFFFF_FFFF_4040_0000; // 3.0
+        cpu.exec_instruction(&inst).unwrap();
+        assert_eq!(cpu.reg.fa0, 0xFFFF_FFFF_3EAA_AAAB); // rounded to nearest
+        assert_eq!(cpu.reg.get_csr(0x001), 0b0_0001); // NX
+        assert_eq!(cpu.reg.mstatus >> 13 & 0b11, 0b11); // FS=Dirty
+
+        // frm=RTZ
+        cpu.reg.set_csr(0x002, 0b001);
+        cpu.reg.fa0 = 0xFFFF_FFFF_3F80_0000;
+        cpu.exec_instruction(&inst).unwrap();
+        assert_eq!(cpu.reg.fa0, 0xFFFF_FFFF_3EAA_AAAA);
+
+        // fcvt.w.s	a0,fa1,rtz
+        cpu.reg.fa1 = 0xFFFF_FFFF_C020_0000; // -2.5
+        let inst = Instruction::decode(0xC005_9553).unwrap();
+        cpu.exec_instruction(&inst).unwrap();
+        assert_eq!(cpu.reg.a0 as i64, -2);
+    }
+
+    #[test]
+    fn sv39_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.pmpaddr0 = 0x3F_FFFF_FFFF_FFFF;
+        cpu.reg.pmpcfg0 = 0x1F; // NAPOT, RWX. S-mode can access all memory
+        let pte = |pa: u64, flags: u64| (pa >> 12) << 10 | flags;
+        // va 0x1000 -> pa 0x8000_5000 (4 KiB page)
+        cpu.bus.sd_dram(0x1000, pte(0x8000_2000, PTE_V));
+        cpu.bus.sd_dram(0x2000, pte(0x8000_3000, PTE_V));
+        cpu.bus
+            .sd_dram(0x3008, pte(0x8000_5000, PTE_V | PTE_R | PTE_W | PTE_U));
+        // va 0x8000_0000 -> pa 0x8000_0000 (1 GiB megapage)
+        cpu.bus
+            .sd_dram(0x1010, pte(0x8000_0000, PTE_V | PTE_R | PTE_X));
+        cpu.bus.sd_dram(0x5010, 0x1234);
+        cpu.reg.satp = SV39 << 60 | 0x8000_1000 >> 12;
+        cpu.mode = Mode::S;
+
+        // S-mode can not access a U-mode page without mstatus.SUM
+        assert_eq!(cpu.load(0x1010, 8), Err(Exception::LoadPageFault(0x1010)));
+        cpu.reg.mstatus |= MSTATUS_SUM;
+        assert_eq!(cpu.load(0x1010, 8), Ok(0x1234));
+        assert_eq!(cpu.bus.ld_dram(0x3008) & (PTE_A | PTE_D), PTE_A);
+        cpu.store(0x1010, 8, 0x5678).unwrap();
+        assert_eq!(cpu.bus.ld_dram(0x3008) & (PTE_A | PTE_D), PTE_A | PTE_D);
+
+        // megapage
+        assert_eq!(cpu.load(0x8000_5010, 8), Ok(0x5678));
+        let e = cpu.store(0x8000_5010, 8, 0);
+        assert_eq!(e, Err(Exception::StorePageFault(0x8000_5010)));
+        // not executable, not mapped, and not canonical
+        let e = cpu.trans_addr(0x1000, AccessType::Fetch);
+        assert_eq!(e, Err(Exception::InstructionPageFault(0x1000)));
+        let e = cpu.trans_addr(0x4000_0000, AccessType::Load);
+        assert_eq!(e, Err(Exception::LoadPageFault(0x4000_0000)));
+        let e = cpu.trans_addr(0x40_0000_0000, AccessType::Load);
+        assert_eq!(e, Err(Exception::LoadPageFault(0x40_0000_0000)));
+
+        // M-mode is not translated
+        cpu.mode = Mode::M;
+        assert_eq!(
+            cpu.trans_addr(0x8000_5010, AccessType::Store),
+            Ok(0x8000_5010)
+        );
+    }
+
+    #[test]
+    fn sv57_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.pmpaddr0 = 0x3F_FFFF_FFFF_FFFF;
+        cpu.reg.pmpcfg0 = 0x1F; // NAPOT, RWX. S-mode can access all memory
+                                // writes with an unsupported mode (Sv64) are ignored
+        cpu.reg.set_csr(0x180, 11 << 60 | 1);
+        assert_eq!(cpu.reg.satp, 0);
+
+        let pte = |pa: u64, flags: u64| (pa >> 12) << 10 | flags;
+        // va 0x1000 -> pa 0x8000_7000
+        cpu.bus.sd_dram(0x1000, pte(0x8000_2000, PTE_V));
+        cpu.bus.sd_dram(0x2000, pte(0x8000_3000, PTE_V));
+        cpu.bus.sd_dram(0x3000, pte(0x8000_4000, PTE_V));
+        cpu.bus.sd_dram(0x4000, pte(0x8000_5000, PTE_V));
+        cpu.bus.sd_dram(0x5008, pte(0x8000_7000, PTE_V | PTE_R));
+        cpu.reg.set_csr(0x180, SV57 << 60 | 0x8000_1000 >> 12);
+        cpu.mode = Mode::S;
+        assert_eq!(cpu.trans_addr(0x1008, AccessType::Load), Ok(0x8000_7008));
+        // bit 56 is the highest bit of a Sv57 virtual address
+        let va = 0x0100_0000_0000_1008;
+        let e = cpu.trans_addr(va, AccessType::Load);
+        assert_eq!(e, Err(Exception::LoadPageFault(va)));
+
+        // the same table is a level-3 table in Sv48
+        cpu.reg.set_csr(0x180, SV48 << 60 | 0x8000_2000 >> 12);
+        assert_eq!(cpu.trans_addr(0x1008, AccessType::Load), Ok(0x8000_7008));
+    }
+
+    #[test]
+    fn pmp_test() {
+        let mut cpu = new_cpu(0);
+        // no entry matches
+        assert!(cpu.pmp_permits(0x8000_0000, 8, AccessType::Store, Mode::M));
+        assert!(!cpu.pmp_permits(0x8000_0000, 8, AccessType::Load, Mode::S));
+
+        // 0: NA4 0x8000_0008, no permission
+        // 1: TOR 0x8000_0008 - 0x8000_1000, RW
+        // 2: NAPOT 0x8000_0000 - 0x8000_1000, RWX
+        cpu.reg.set_csr(0x3B0, 0x8000_0008 >> 2);
+        cpu.reg.set_csr(0x3B1, 0x8000_1000 >> 2);
+        cpu.reg.set_csr(0x3B2, 0x8000_0000 >> 2 | 0x1FF);
+        cpu.reg.set_csr(0x3A0, 0x1F_0B_10);
+
+        cpu.mode = Mode::S;
+        let e = cpu.check_pmp(0x1008, 0x8000_0008, 4, AccessType::Load);
+        assert_eq!(e, Err(Exception::LoadAccessFault(0x1008)));
+        // partially matches the entry 0
+        assert!(!cpu.pmp_permits(0x8000_0004, 8, AccessType::Load, Mode::S));
+        assert!(cpu.pmp_permits(0x8000_0010, 8, AccessType::Store, Mode::S));
+        // the entry 1 takes priority over the entry 2
+        assert!(!cpu.pmp_permits(0x8000_0010, 2, AccessType::Fetch, Mode::S));
+        assert!(cpu.pmp_permits(0x8000_0000, 2, AccessType::Fetch, Mode::S));
+        assert!(!cpu.pmp_permits(0x8000_1000, 2, AccessType::Fetch, Mode::S));
+
+        // a locked entry applies to M-mode, and ignores writes
+        cpu.mode = Mode::M;
+        assert!(cpu.pmp_permits(0x8000_0008, 4, AccessType::Load, Mode::M));
+        cpu.reg.set_csr(0x3A0, 0x1F_0B_90);
+        assert!(!cpu.pmp_permits(0x8000_0008, 4, AccessType::Load, Mode::M));
+        cpu.reg.set_csr(0x3A0, 0);
+        cpu.reg.set_csr(0x3B0, 0);
+        assert_eq!(cpu.reg.pmpcfg0, 0x90);
+        assert_eq!(cpu.reg.pmpaddr0, 0x8000_0008 >> 2);
+
+        // 0: OFF 0x8000_1000
+        // 1: TOR 0x8000_1000 - 0x8000_1000, no permission. Matches nothing.
+        // 2: NAPOT 0x8000_0000 - 0x8000_2000, RWX
+        let mut cpu = new_cpu(0);
+        cpu.reg.set_csr(0x3B0, 0x8000_1000 >> 2);
+        cpu.reg.set_csr(0x3B1, 0x8000_1000 >> 2);
+        cpu.reg.set_csr(0x3B2, 0x8000_0000 >> 2 | 0x3FF);
+        cpu.reg.set_csr(0x3A0, 0x1F_08_00);
+        assert!(cpu.pmp_permits(0x8000_0FFC, 8, AccessType::Load, Mode::S));
+        cpu.reg.set_csr(0x3B0, 0x8000_1800 >> 2);
+        assert!(cpu.pmp_permits(0x8000_0FFC, 8, AccessType::Load, Mode::S));
+        assert!(cpu.pmp_permits(0x8000_17FC, 8, AccessType::Load, Mode::S));
+    }
+
+    #[test]
+    fn tlb_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.pmpaddr0 = 0x3F_FFFF_FFFF_FFFF;
+        cpu.reg.pmpcfg0 = 0x1F; // NAPOT, RWX. S-mode can access all memory
+        let pte = |pa: u64, flags: u64| (pa >> 12) << 10 | flags;
+        // va 0x1000 -> pa 0x8000_5000
+        cpu.bus.sd_dram(0x1000, pte(0x8000_2000, PTE_V));
+        cpu.bus.sd_dram(0x2000, pte(0x8000_3000, PTE_V));
+        cpu.bus.sd_dram(0x3008, pte(0x8000_5000, PTE_V | PTE_R));
+        cpu.reg.satp = SV39 << 60 | 1 << 44 | 0x8000_1000 >> 12; // ASID=1
+        cpu.mode = Mode::S;
+
+        assert_eq!(cpu.trans_addr(0x1008, AccessType::Load), Ok(0x8000_5008));
+        assert_eq!((cpu.tlb.hit, cpu.tlb.miss), (0, 1));
+        assert_eq!(cpu.trans_addr(0x1010, AccessType::Load), Ok(0x8000_5010));
+        assert_eq!((cpu.tlb.hit, cpu.tlb.miss), (1, 1));
+
+        // the stale translation is used until sfence.vma
+        cpu.bus.sd_dram(0x3008, pte(0x8000_6000, PTE_V | PTE_R));
+        assert_eq!(cpu.trans_addr(0x1008, AccessType::Load), Ok(0x8000_5008));
+        // sfence.vma	a0,zero
+        cpu.reg.a0 = 0x1000;
+        cpu.exec_instruction(&Instruction::decode(0x1205_0073).unwrap())
+            .unwrap();
+        assert_eq!(cpu.trans_addr(0x1008, AccessType::Load), Ok(0x8000_6008));
+
+        // writing satp flushes the TLB
+        cpu.bus.sd_dram(0x3008, pte(0x8000_7000, PTE_V | PTE_R));
+        cpu.write_csr(0x180, SV39 << 60 | 2 << 44 | 0x8000_1000 >> 12);
+        assert_eq!(cpu.trans_addr(0x1008, AccessType::Load), Ok(0x8000_7008));
+    }
+
+    #[test]
+    fn sstatus_view_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        // sstatus writes only reach the supervisor fields of mstatus
+        cpu.reg.set_csr(0x100, u64::MAX);
+        assert_eq!(cpu.reg.mstatus & 0b1000_1000, 0); // MIE, MPIE
+        assert_eq!(cpu.reg.mstatus & 0b1_0010_0010, 0b1_0010_0010); // SPP, SPIE, SIE
+        assert_eq!(cpu.reg.get_csr(0x100) >> 63, 1); // SD (FS=Dirty)
+        assert_eq!(cpu.reg.get_csr(0x100) >> 32 & 0b11, 2); // UXL=64
+                                                            // mstatus writes are visible in sstatus, the machine fields are not
+        cpu.reg.set_csr(0x300, 0b1000);
+        assert_eq!(cpu.reg.get_csr(0x100), 2 << 32);
+        // MPP=2 is reserved
+        cpu.reg.set_csr(0x300, 0b1_1000_0000_0000);
+        cpu.reg.set_csr(0x300, 0b1_0000_0000_0000);
+        assert_eq!(cpu.reg.mstatus & 0b1_1000_0000_0000, 0b1_1000_0000_0000);
+
+        // sie and sip only expose the delegated interrupts
+        cpu.reg.set_csr(0x303, u64::MAX); // mideleg
+        assert_eq!(cpu.reg.mideleg, 0x222);
+        cpu.reg.set_csr(0x104, u64::MAX); // sie
+        assert_eq!(cpu.reg.mie, 0x222);
+        cpu.reg.set_csr(0x304, u64::MAX); // mie
+        assert_eq!(cpu.reg.get_csr(0x104), 0x222);
+        cpu.reg.mip = 0xAAA;
+        assert_eq!(cpu.reg.get_csr(0x144), 0x222);
+        // only SSIP can be cleared through sip
+        cpu.reg.set_csr(0x144, 0);
+        assert_eq!(cpu.reg.mip, 0xAA8);
+    }
+
+    #[test]
+    fn int_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.pc = 0x8000_1000;
+        cpu.reg.mtvec = 0x8000_2001; // vectored
+        cpu.reg.stvec = 0x8000_3000;
+        cpu.reg.mie = 0xAAA;
+        cpu.reg.mideleg = 0x222;
+        cpu.bus.s_mm(clint::MTIMECMP, 8, 100);
+
+        // M-mode with mstatus.MIE=0 is not interrupted
+        cpu.bus.clint().set_mtime(100);
+        cpu.poll_int();
+        cpu.reg.mip |= 0b10; // SSIP
+        assert!(!int::int(&mut cpu.reg, &mut cpu.mode));
+
+        // S-mode is always interruptible by M-mode interrupts
+        cpu.mode = Mode::S;
+        assert!(int::int(&mut cpu.reg, &mut cpu.mode));
+        assert_eq!(cpu.mode, Mode::M);
+        assert_eq!(cpu.reg.mcause, 0x8000_0000_0000_0007);
+        assert_eq!(cpu.reg.mepc, 0x8000_1000);
+        assert_eq!(cpu.reg.pc, 0x8000_2000 + 4 * 7);
+        assert_eq!(cpu.reg.mstatus & 0b1_1000_0000_0000, 0b0_1000_0000_0000); // MPP=S
+                                                                              // level-triggered: MTIP stays pending until mtimecmp is written
+        assert_ne!(cpu.reg.mip & 0b1000_0000, 0);
+        cpu.bus.s_mm(clint::MTIMECMP, 8, 200);
+        cpu.poll_int();
+        assert_eq!(cpu.reg.mip & 0b1000_0000, 0);
+
+        // delegated interrupts are not taken in S-mode with sstatus.SIE=0
+        cpu.mode = Mode::S;
+        assert!(!int::int(&mut cpu.reg, &mut cpu.mode));
+        // but U-mode is always interruptible
+        cpu.mode = Mode::U;
+        cpu.reg.mip |= 0b10_0000; // STIP
+        assert!(int::int(&mut cpu.reg, &mut cpu.mode));
+        assert_eq!(cpu.mode, Mode::S);
+        assert_eq!(cpu.reg.scause, 0x8000_0000_0000_0001); // SSI before STI
+        assert_eq!(cpu.reg.pc, 0x8000_3000);
+
+        // mip.SEIP written by M-mode is not lost while the PLIC line is low
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.mie = 0x200;
+        cpu.reg.set_csr(0x344, 0x200);
+        cpu.poll_int();
+        assert_eq!(cpu.reg.get_csr(0x344) & 0x200, 0x200);
+        cpu.mode = Mode::S;
+        assert!(int::int(&mut cpu.reg, &mut cpu.mode));
+        assert_eq!(cpu.reg.mcause, 0x8000_0000_0000_0009);
+
+        // SEIP reads as the line, but csrrs does not write the line back to the software bit
+        cpu.reg.set_csr(0x344, 0);
+        cpu.reg.seip_line = true;
+        cpu.mode = Mode::M;
+        cpu.reg.a0 = 0b10;
+        // csrrs a1,mip,a0
+        cpu.exec_instruction(&Instruction::decode(0x3445_25F3).unwrap())
+            .unwrap();
+        assert_eq!(cpu.reg.a1 & 0x202, 0x200);
+        cpu.reg.seip_line = false;
+        assert_eq!(cpu.reg.get_csr(0x344) & 0x202, 0b10);
+    }
+
+    #[test]
+    fn wfi_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.mie = 0b1000_0000; // MTIE
+        cpu.bus.clint().set_mtime(100);
+        cpu.bus.s_mm(clint::MTIMECMP, 8, 1_000_000);
+        // wfi
+        let wfi = Instruction::decode(0x1050_0073).unwrap();
+        cpu.exec_instruction(&wfi).unwrap();
+        assert!(cpu.halted());
+        // the timer deadline wakes the hart
+        assert_eq!(cpu.wake_time(), Some(1_000_000));
+        cpu.run(1);
+        assert!(cpu.halted());
+        cpu.bus.clint().set_mtime(1_000_000);
+        cpu.run(1);
+        assert!(!cpu.halted());
+
+        // illegal in U-mode
+        cpu.wfi = false;
+        cpu.mode = Mode::U;
+        assert_eq!(
+            cpu.exec_instruction(&wfi),
+            Err(Exception::IllegalInstruction(0x1050_0073))
+        );
+
+        // the busy behaviour keeps running
+        cpu.mode = Mode::M;
+        cpu.busy_wfi = true;
+        cpu.exec_instruction(&wfi).unwrap();
+        assert!(!cpu.wfi);
+    }
+
+    #[test]
+    fn counter_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.tick(true);
+        cpu.reg.tick(false);
+        assert_eq!((cpu.reg.mcycle, cpu.reg.minstret), (2, 1));
+        cpu.reg.set_csr(0x320, 0b101); // mcountinhibit: CY, IR
+        cpu.reg.tick(true);
+        assert_eq!((cpu.reg.mcycle, cpu.reg.minstret), (2, 1));
+
+        // mhpmcounter3 counts loads
+        cpu.reg.set_csr(0x323, HPM_LOAD);
+        cpu.reg.set_csr(0x324, 0xFFFF); // unsupported event
+        assert_eq!(cpu.reg.get_csr(0x324), 0);
+        // ld	a0,0(a1)
+        cpu.reg.a1 = 0x8000_0000;
+        cpu.exec_instruction(&Instruction::decode(0x0005_B503).unwrap())
+            .unwrap();
+        assert_eq!(cpu.reg.get_csr(0xB03), 1);
+
+        // csrr	a0,time
+        cpu.bus.clint().set_mtime(0x1234);
+        let rdtime = Instruction::decode(0xC010_2573).unwrap();
+        cpu.exec_instruction(&rdtime).unwrap();
+        assert_eq!(cpu.reg.a0, 0x1234);
+        // U-mode needs both mcounteren.TM and scounteren.TM
+        cpu.mode = Mode::U;
+        cpu.reg.set_csr(0x306, 0b010);
+        assert_eq!(
+            cpu.exec_instruction(&rdtime),
+            Err(Exception::IllegalInstruction(0xC010_2573))
+        );
+        cpu.reg.set_csr(0x106, 0b010);
+        cpu.exec_instruction(&rdtime).unwrap();
+    }
+
+    #[test]
+    fn bitmanip_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        let mut exec = |raw: u32, a1: u64, a2: u64| -> u64 {
+            cpu.reg.a1 = a1;
+            cpu.reg.a2 = a2;
+            cpu.exec_instruction(&Instruction::decode(raw).unwrap())
+                .unwrap();
+            cpu.reg.a0
+        };
+        // Zba
+        assert_eq!(exec(0x20C5_A533, 3, 10), 16); // sh1add	a0,a1,a2
+        assert_eq!(exec(0x08C5_853B, 0xFFFF_FFFF_0000_0001, 1), 2); // add.uw	a0,a1,a2
+                                                                    // Zbb
+        assert_eq!(exec(0x40C5_F533, 0b1100, 0b1010), 0b0100); // andn	a0,a1,a2
+        assert_eq!(exec(0x0AC5_C533, -5_i64 as u64, 3), -5_i64 as u64); // min	a0,a1,a2
+        assert_eq!(exec(0x6005_9513, 1 << 40, 0), 23); // clz	a0,a1
+        assert_eq!(exec(0x6025_951B, u64::MAX, 0), 32); // cpopw	a0,a1
+        assert_eq!(
+            exec(0x6B85_D513, 0x0102_0304_0506_0708, 0),
+            0x0807_0605_0403_0201
+        ); // rev8	a0,a1
+        assert_eq!(
+            exec(0x2875_D513, 0x0010_0000_8000_0001, 0),
+            0x00FF_0000_FF00_00FF
+        ); // orc.b	a0,a1
+        assert_eq!(exec(0x60C5_953B, 0x8000_0001, 1), 3); // rolw	a0,a1,a2
+        assert_eq!(exec(0x0805_C53B, 0x1234_5678, 0), 0x5678); // zext.h	a0,a1
+                                                               // Zbc
+        assert_eq!(exec(0x0AC5_B533, 1 << 63, 0b110), 0b11); // clmulh	a0,a1,a2
+                                                             // Zbs
+        assert_eq!(exec(0x28C5_9533, 0, 65), 0b10); // bset	a0,a1,a2
+        assert_eq!(exec(0x4BF5_D513, 1 << 63, 0), 1); // bexti	a0,a1,63
+    }
+
+    #[test]
+    fn vector_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        let base = MEM_OFF as u64;
+        let exec =
+            |cpu: &mut Cpu, raw: u32| cpu.exec_instruction(&Instruction::decode(raw).unwrap());
+        // vsetvli	a0,a1,e32,m1,tu,mu
+        // mstatus.VS is Off
+        assert_eq!(
+            exec(&mut cpu, 0x0105_F557),
+            Err(Exception::IllegalInstruction(0x0105_F557))
+        );
+        cpu.reg.mstatus |= 0b0010_0010_0000_0000; // FS=Initial, VS=Initial
+        cpu.reg.a1 = 6;
+        exec(&mut cpu, 0x0105_F557).unwrap();
+        assert_eq!(cpu.reg.a0, 4); // VLMAX = 128 / 32
+        assert_eq!(cpu.reg.vl, 4);
+
+        for (i, (a, b)) in [(1, 10), (2, 20), (3, 30), (4, 0xFFFF_FFFF)]
+            .iter()
+            .enumerate()
+        {
+            cpu.bus.sw_dram(0x100 + i as u64 * 4, *a);
+            cpu.bus.sw_dram(0x200 + i as u64 * 4, *b);
+        }
+        cpu.reg.a1 = base + 0x100;
+        cpu.reg.a2 = base + 0x200;
+        exec(&mut cpu, 0x0205_E087).unwrap(); // vle32.v	v1,(a1)
+        exec(&mut cpu, 0x0206_6107).unwrap(); // vle32.v	v2,(a2)
+        exec(&mut cpu, 0x0211_01D7).unwrap(); // vadd.vv	v3,v1,v2
+        exec(&mut cpu, 0x0206_61A7).unwrap(); // vse32.v	v3,(a2)
+        assert_eq!(cpu.bus.lw_dram(0x20C), 3);
+        exec(&mut cpu, 0x0230_2257).unwrap(); // vredsum.vs	v4,v3,v0
+        exec(&mut cpu, 0x4240_2557).unwrap(); // vmv.x.s	a0,v4
+        assert_eq!(cpu.reg.a0, 11 + 22 + 33 + 3);
+
+        cpu.reg.a1 = 3;
+        exec(&mut cpu, 0x6E15_C057).unwrap(); // vmslt.vx	v0,v1,a1
+        exec(&mut cpu, 0x001F_B1D7).unwrap(); // vadd.vi	v3,v1,-1,v0.t
+        let v3: Vec<u64> = (0..4).map(|i| cpu.vreg.get(3, i, 32)).collect();
+        assert_eq!(v3, vec![0, 1, 33, 3]); // inactive elements are undisturbed
+
+        cpu.vreg.set(1, 0, 32, 0x3FC0_0000); // 1.5
+        cpu.reg.fa0 = 0xFFFF_FFFF_3F80_0000; // 1.0
+        exec(&mut cpu, 0x0215_52D7).unwrap(); // vfadd.vf	v5,v1,fa0
+        exec(&mut cpu, 0x4250_1557).unwrap(); // vfmv.f.s	fa0,v5
+        assert_eq!(cpu.reg.fa0, 0xFFFF_FFFF_4020_0000); // 2.5
+
+        // vsetvli	a0,a1,e8,m1,tu,mu
+        cpu.reg.a1 = 16;
+        exec(&mut cpu, 0x0005_F557).unwrap();
+        cpu.vreg.set(1, 0, 8, 200);
+        cpu.vreg.set(2, 0, 8, 100);
+        exec(&mut cpu, 0x8211_01D7).unwrap(); // vsaddu.vv	v3,v1,v2
+        assert_eq!(cpu.vreg.get(3, 0, 8), 255);
+        assert_eq!(cpu.reg.get_csr(0x009), 1); // vxsat
+        assert_eq!(cpu.reg.mstatus >> 9 & 0b11, 0b11); // VS=Dirty
+    }
+
+    /// A hart with the vector and floating-point units on, and vtype and vl set.
+    fn vector_cpu(vtype: u64, vl: u64) -> Cpu {
+        let mut cpu = new_cpu(0);
+        cpu.reg.mstatus |= 0b0010_0010_0000_0000; // FS=Initial, VS=Initial
+        cpu.reg.vtype = vtype;
+        cpu.reg.vl = vl;
+        cpu
+    }
+
+    fn vexec(cpu: &mut Cpu, raw: u32) {
+        cpu.exec_instruction(&Instruction::decode(raw).unwrap())
+            .unwrap();
+    }
+
+    fn set_v(cpu: &mut Cpu, reg: u8, sew: u64, values: &[u64]) {
+        for (i, v) in values.iter().enumerate() {
+            cpu.vreg.set(reg, i as u64, sew, *v);
+        }
+    }
+
+    fn get_v(cpu: &Cpu, reg: u8, sew: u64, n: u64) -> Vec<u64> {
+        (0..n).map(|i| cpu.vreg.get(reg, i, sew)).collect()
+    }
+
+    #[test]
+    fn vector_widen_narrow_test() {
+        // e8, m1
+        let mut cpu = vector_cpu(0b000_000, 4);
+        set_v(&mut cpu, 2, 8, &[250, 1, 128, 255]);
+        set_v(&mut cpu, 1, 8, &[10, 2, 128, 255]);
+        vexec(&mut cpu, 0xC220_A257); // vwaddu.vv v4,v2,v1
+        assert_eq!(get_v(&cpu, 4, 16, 4), vec![260, 3, 256, 510]);
+        vexec(&mut cpu, 0xEA20_A257); // vwmulsu.vv v4,v2,v1
+        assert_eq!(get_v(&cpu, 4, 16, 2), vec![0xFFC4, 2]); // -6 * 10, 1 * 2
+        set_v(&mut cpu, 2, 16, &[0x1000, 5]);
+        set_v(&mut cpu, 1, 8, &[0xFF, 2]);
+        vexec(&mut cpu, 0xD620_A257); // vwadd.wv v4,v2,v1
+        assert_eq!(get_v(&cpu, 4, 16, 2), vec![0x0FFF, 7]);
+        set_v(&mut cpu, 4, 16, &[1000]);
+        set_v(&mut cpu, 2, 8, &[200]);
+        cpu.reg.a0 = 100;
+        vexec(&mut cpu, 0xF225_6257); // vwmaccu.vx v4,a0,v2
+        assert_eq!(cpu.vreg.get(4, 0, 16), 21000);
+
+        // e16: vwadd.vx v4,v2,a0 sign-extends both operands
+        cpu.reg.vtype = 0b001_000;
+        cpu.reg.vl = 2;
+        set_v(&mut cpu, 2, 16, &[0xFFFB, 3]);
+        cpu.reg.a0 = -7i64 as u64;
+        vexec(&mut cpu, 0xC625_6257);
+        assert_eq!(get_v(&cpu, 4, 32, 2), vec![0xFFFF_FFF4, 0xFFFF_FFFC]);
+        // vnsra.wx v6,v2,a0
+        set_v(&mut cpu, 2, 32, &[0x8000_0000, 0x0001_0000]);
+        cpu.reg.a0 = 16;
+        vexec(&mut cpu, 0xB625_4357);
+        assert_eq!(get_v(&cpu, 6, 16, 2), vec![0x8000, 0x0001]);
+
+        // e8: vnsrl.wi v6,v2,4, and masked by v0 = 0b01
+        cpu.reg.vtype = 0b000_000;
+        set_v(&mut cpu, 2, 16, &[0x1234, 0xABCD]);
+        vexec(&mut cpu, 0xB222_3357);
+        assert_eq!(get_v(&cpu, 6, 8, 2), vec![0x23, 0xBC]);
+        set_v(&mut cpu, 6, 8, &[0, 0]);
+        set_v(&mut cpu, 0, 8, &[0b01]);
+        vexec(&mut cpu, 0xB022_3357);
+        assert_eq!(get_v(&cpu, 6, 8, 2), vec![0x23, 0]);
+
+        // e32: vfwadd.vv v4,v2,v1 and vfwcvt.f.f.v v4,v2 are exact
+        cpu.reg.vtype = 0b010_000;
+        cpu.reg.vl = 1;
+        set_v(&mut cpu, 2, 32, &[0x3FC0_0000]); // 1.5
+        set_v(&mut cpu, 1, 32, &[0x4010_0000]); // 2.25
+        vexec(&mut cpu, 0xC220_9257);
+        assert_eq!(cpu.vreg.get(4, 0, 64), 0x400E_0000_0000_0000); // 3.75
+        set_v(&mut cpu, 2, 32, &[0x3DCC_CCCD]); // 0.1
+        vexec(&mut cpu, 0x4A26_1257);
+        assert_eq!(cpu.vreg.get(4, 0, 64), 0x3FB9_9999_A000_0000);
+
+        // vfncvt.f.f.w v6,v2 rounds to nearest even, vfncvt.rod.f.f.w v6,v2 rounds to odd.
+        // 1 + 3*2^-24 is halfway between 1 + 2^-23 and 1 + 2^-22.
+        cpu.reg.vl = 3;
+        set_v(
+            &mut cpu,
+            2,
+            64,
+            &[
+                0x3FF0_0000_3000_0000,
+                0x3FF0_0000_0000_0000,
+                0x7E37_E43C_8800_759C,
+            ],
+        );
+        vexec(&mut cpu, 0x4A2A_1357);
+        assert_eq!(
+            get_v(&cpu, 6, 32, 3),
+            vec![0x3F80_0002, 0x3F80_0000, 0x7F80_0000]
+        );
+        vexec(&mut cpu, 0x4A2A_9357);
+        // an overflow rounds to the largest finite value
+        assert_eq!(
+            get_v(&cpu, 6, 32, 3),
+            vec![0x3F80_0001, 0x3F80_0000, 0x7F7F_FFFF]
+        );
+    }
+
+    #[test]
+    fn vector_fixed_point_test() {
+        // e16 -> e8: vnclipu.wi v6,v2,4 and vnclip.wi v6,v2,1 in each vxrm
+        let mut cpu = vector_cpu(0b000_000, 3);
+        for (vxrm, expected) in [
+            (0, [0x13, 0x14]), // rnu
+            (1, [0x12, 0x14]), // rne
+            (2, [0x12, 0x13]), // rdn
+            (3, [0x13, 0x13]), // rod
+        ] {
+            cpu.reg.vxrm = vxrm;
+            cpu.reg.vxsat = 0;
+            set_v(&mut cpu, 2, 16, &[0x0128, 0x0138, 0x1238]);
+            vexec(&mut cpu, 0xBA22_3357);
+            assert_eq!(get_v(&cpu, 6, 8, 3), vec![expected[0], expected[1], 0xFF]);
+            assert_eq!(cpu.reg.vxsat, 1);
+        }
+        cpu.reg.vxrm = 0;
+        cpu.reg.vxsat = 0;
+        set_v(&mut cpu, 2, 16, &[0xFF00, 0x0100, 0xFFFF]);
+        vexec(&mut cpu, 0xBE20_B357);
+        assert_eq!(get_v(&cpu, 6, 8, 3), vec![0x80, 0x7F, 0x00]);
+        assert_eq!(cpu.reg.vxsat, 1);
+
+        // vaaddu.vv v3,v2,v1: (1 + 2) >> 1, vssrl.vi v3,v2,2: 10 >> 2,
+        // and vsmul.vv v3,v2,v1: (0x40 * 3) >> 7 and -1.0 * -1.0, which saturates
+        cpu.reg.vl = 2;
+        for (vxrm, aadd, ssrl, smul) in [(0, 2, 3, 2), (1, 2, 2, 2), (2, 1, 2, 1), (3, 1, 3, 1)] {
+            cpu.reg.vxrm = vxrm;
+            cpu.reg.vxsat = 0;
+            set_v(&mut cpu, 2, 8, &[1, 0]);
+            set_v(&mut cpu, 1, 8, &[2, 0]);
+            vexec(&mut cpu, 0x2220_A1D7);
+            assert_eq!(cpu.vreg.get(3, 0, 8), aadd);
+            set_v(&mut cpu, 2, 8, &[10]);
+            vexec(&mut cpu, 0xAA21_31D7);
+            assert_eq!(cpu.vreg.get(3, 0, 8), ssrl);
+            assert_eq!(cpu.reg.vxsat, 0);
+            set_v(&mut cpu, 2, 8, &[0x40, 0x80]);
+            set_v(&mut cpu, 1, 8, &[3, 0x80]);
+            vexec(&mut cpu, 0x9E20_81D7);
+            assert_eq!(get_v(&cpu, 3, 8, 2), vec![smul, 0x7F]);
+            assert_eq!(cpu.reg.vxsat, 1);
+        }
+    }
+
+    #[test]
+    fn vector_mask_tail_test() {
+        // e32, m1, ta, ma. Tail and inactive elements are left undisturbed, which agnostic allows.
+        let mut cpu = vector_cpu(0b1101_0000, 3);
+        set_v(&mut cpu, 0, 8, &[0b0101]);
+        set_v(&mut cpu, 1, 32, &[1, 2, 3, 4]);
+        set_v(&mut cpu, 2, 32, &[10, 20, 30, 40]);
+        set_v(&mut cpu, 3, 32, &[100, 101, 102, 103]);
+        vexec(&mut cpu, 0x0020_81D7); // vadd.vv v3,v2,v1,v0.t
+        assert_eq!(get_v(&cpu, 3, 32, 4), vec![11, 101, 33, 103]);
+
+        // the elements below vstart are not written, and vstart is reset
+        set_v(&mut cpu, 3, 32, &[100, 101, 102, 103]);
+        cpu.reg.vstart = 1;
+        vexec(&mut cpu, 0x0220_81D7); // vadd.vv v3,v2,v1
+        assert_eq!(get_v(&cpu, 3, 32, 4), vec![100, 22, 33, 103]);
+        assert_eq!(cpu.reg.vstart, 0);
+
+        // vmseq.vv v4,v2,v1,v0.t writes the mask bits of the active elements
+        set_v(&mut cpu, 1, 32, &[10, 20, 3, 40]);
+        set_v(&mut cpu, 4, 8, &[0b1010]);
+        vexec(&mut cpu, 0x6020_8257);
+        assert_eq!(cpu.vreg.get(4, 0, 8), 0b1011);
+
+        // vredsum.vs v5,v2,v1,v0.t sums the active elements into vd[0] only
+        set_v(&mut cpu, 1, 32, &[1000]);
+        set_v(&mut cpu, 5, 32, &[0, 7, 7, 7]);
+        vexec(&mut cpu, 0x0020_A2D7);
+        assert_eq!(get_v(&cpu, 5, 32, 4), vec![1040, 7, 7, 7]);
+    }
+
+    #[test]
+    fn vector_estimate_test() {
+        let mut cpu = vector_cpu(0b010_000, 4);
+        // vfrec7.v v3,v2: 2^127, 2^-127 (subnormal), 2^-149 and -0
+        set_v(
+            &mut cpu,
+            2,
+            32,
+            &[0x7F00_0000, 0x0040_0000, 0x0000_0001, 0x8000_0000],
+        );
+        vexec(&mut cpu, 0x4E22_91D7);
+        assert_eq!(
+            get_v(&cpu, 3, 32, 4),
+            vec![0x003F_C000, 0x7EFF_0000, 0x7F80_0000, 0xFF80_0000]
+        );
+        assert_eq!(cpu.reg.get_csr(0x001), (fpu::DZ | fpu::OF | fpu::NX) as u64);
+
+        // vfrsqrt7.v v3,v2: 4.0, 2^-149, -1.0 and +inf
+        cpu.reg.fcsr = 0;
+        set_v(
+            &mut cpu,
+            2,
+            32,
+            &[0x4080_0000, 0x0000_0001, 0xBF80_0000, 0x7F80_0000],
+        );
+        vexec(&mut cpu, 0x4E22_11D7);
+        assert_eq!(
+            get_v(&cpu, 3, 32, 4),
+            vec![0x3EFF_0000, 0x64B4_0000, 0x7FC0_0000, 0]
+        );
+        assert_eq!(cpu.reg.get_csr(0x001), fpu::NV as u64);
+    }
+
+    #[test]
+    fn hypervisor_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.pmpaddr0 = 0x3F_FFFF_FFFF_FFFF;
+        cpu.reg.pmpcfg0 = 0x1F; // NAPOT, RWX. S-mode can access all memory
+        let pte = |pa: u64, flags: u64| (pa >> 12) << 10 | flags;
+        // G-stage (Sv39x4): gpa 0 -> pa 0x8000_0000 (1 GiB megapage). The root table is 16 KiB.
+        cpu.bus
+            .sd_dram(0x4000, pte(0x8000_0000, PTE_V | PTE_R | PTE_W | PTE_U));
+        cpu.reg.set_csr(0x680, SV39 << 60 | 0x8000_4000 >> 12);
+        // VS-stage (Sv39): va 0x1000 -> gpa 0x9000, va 0x2000 -> gpa 0x4000_0000 (no G-stage mapping)
+        cpu.bus.sd_dram(0x1000, pte(0x2000, PTE_V));
+        cpu.bus.sd_dram(0x2000, pte(0x3000, PTE_V));
+        cpu.bus.sd_dram(0x3008, pte(0x9000, PTE_V | PTE_R | PTE_W));
+        cpu.bus.sd_dram(0x3010, pte(0x4000_0000, PTE_V | PTE_R));
+        cpu.bus.sd_dram(0x9008, 0x1234);
+        cpu.reg.vsatp = SV39 << 60 | 0x1000 >> 12;
+
+        // VS-mode
+        cpu.mode = Mode::S;
+        cpu.reg.virt = true;
+        assert_eq!(cpu.load(0x1008, 8), Ok(0x1234));
+        assert_eq!(cpu.bus.ld_dram(0x3008) & PTE_A, PTE_A);
+        assert_eq!(cpu.bus.ld_dram(0x4000) & PTE_A, PTE_A);
+        // satp is vsatp, and hypervisor CSRs are virtual instructions
+        assert_eq!(cpu.reg.get_csr(0x180), cpu.reg.vsatp);
+        // csrr a0, hstatus
+        let inst = Instruction::decode(0x6000_2573).unwrap();
+        assert_eq!(
+            cpu.csrrs(&inst),
+            Err(Exception::VirtualInstruction(0x6000_2573))
+        );
+
+        // a guest-page fault can not be delegated to VS-mode, and is taken in HS-mode
+        cpu.reg.medeleg = 1 << 21 | 1 << 13;
+        cpu.reg.set_csr(0x602, 1 << 21 | 1 << 13);
+        assert_eq!(cpu.reg.hedeleg, 1 << 13);
+        cpu.reg.pc = 0x8000_1000;
+        cpu.reg.stvec = 0x8000_3000;
+        cpu.reg.vstvec = 0x8000_4000;
+        let e = cpu.load(0x2000, 8).unwrap_err();
+        assert_eq!(e, Exception::LoadGuestPageFault(0x2000, 0x4000_0000));
+        int::exception(&mut cpu.reg, &mut cpu.mode, e);
+        assert_eq!((cpu.mode, cpu.reg.virt), (Mode::S, false));
+        assert_eq!(cpu.reg.scause, 21);
+        assert_eq!(cpu.reg.stval, 0x2000);
+        assert_eq!(cpu.reg.htval, 0x4000_0000 >> 2);
+        assert_eq!(cpu.reg.hstatus & 0x1C0, 0x1C0); // SPVP=1, SPV=1, GVA=1
+
+        // HS-mode reads the guest memory with hlv.d a0, (a1)
+        cpu.reg.a1 = 0x1008;
+        let inst = Instruction::decode(0x6C05_C573).unwrap();
+        cpu.hlv_d(&inst).unwrap();
+        assert_eq!(cpu.reg.a0, 0x1234);
+
+        // sret returns to VS-mode
+        cpu.sret(&inst).unwrap();
+        assert_eq!((cpu.mode, cpu.reg.virt), (Mode::S, true));
+        assert_eq!(cpu.reg.hstatus & 0x80, 0); // SPV=0
+
+        // a page fault delegated by hedeleg is taken in VS-mode
+        let e = cpu.load(0x3000, 8).unwrap_err();
+        assert_eq!(e, Exception::LoadPageFault(0x3000));
+        int::exception(&mut cpu.reg, &mut cpu.mode, e);
+        assert_eq!((cpu.mode, cpu.reg.virt), (Mode::S, true));
+        assert_eq!(cpu.reg.get_csr(0x142), 13); // scause is vscause
+        assert_eq!(cpu.reg.pc, 0x8000_4000);
+
+        // VS-level interrupts delegated by hideleg are taken in VS-mode as supervisor interrupts
+        cpu.reg.vsstatus |= 0b10; // SIE
+        cpu.reg.set_csr(0x603, 0x444);
+        cpu.reg.set_csr(0x604, 0x40); // VSTIE
+        cpu.reg.set_csr(0x645, 0x40); // VSTIP
+        assert_eq!(cpu.reg.get_csr(0x144), 0x20); // sip.STIP is vsip.STIP
+        assert!(int::int(&mut cpu.reg, &mut cpu.mode));
+        assert_eq!(cpu.reg.vscause, 0x8000_0000_0000_0005);
+
+        // hfence.gvma in VS-mode is a virtual instruction
+        let inst = Instruction::decode(0x6200_0073).unwrap();
+        assert_eq!(
+            cpu.hfence_gvma(&inst),
+            Err(Exception::VirtualInstruction(0x6200_0073))
+        );
+    }
+
+    #[test]
+    fn vs_sret_test() {
+        let mut cpu = new_cpu(0);
+        // sret
+        let inst = Instruction::decode(0x1020_0073).unwrap();
+        // mstatus.TSR traps SRET in HS-mode only. VS-mode follows hstatus.VTSR.
+        cpu.reg.mstatus |= MSTATUS_TSR;
+        cpu.mode = Mode::S;
+        cpu.reg.virt = true;
+        cpu.reg.vsepc = 0x8000_2000;
+        cpu.reg.vsstatus |= 1 << 8; // SPP=S
+        cpu.sret(&inst).unwrap();
+        assert_eq!((cpu.mode, cpu.reg.virt), (Mode::S, true));
+        assert_eq!(cpu.next_pc, 0x8000_2000);
+
+        cpu.reg.hstatus |= HSTATUS_VTSR;
+        assert_eq!(
+            cpu.sret(&inst),
+            Err(Exception::VirtualInstruction(0x1020_0073))
+        );
+        cpu.reg.virt = false;
+        assert_eq!(
+            cpu.sret(&inst),
+            Err(Exception::IllegalInstruction(0x1020_0073))
+        );
+    }
+
+    #[test]
+    fn pte_out_of_dram_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.pmpaddr0 = 0x3F_FFFF_FFFF_FFFF;
+        cpu.reg.pmpcfg0 = 0x1F; // NAPOT, RWX
+        cpu.mode = Mode::S;
+        // the root page table is past the end of the 64 KiB DRAM
+        cpu.reg.satp = SV39 << 60 | 0x8010_0000 >> 12;
+        assert_eq!(cpu.load(0x1000, 8), Err(Exception::LoadAccessFault(0x1000)));
+        assert_eq!(
+            cpu.store(0x1000, 8, 0),
+            Err(Exception::StoreAccessFault(0x1000))
+        );
+        // a non-leaf PTE points past the end of DRAM
+        let pte = |pa: u64, flags: u64| (pa >> 12) << 10 | flags;
+        cpu.bus.sd_dram(0x1000, pte(0x8001_0000, PTE_V));
+        cpu.reg.satp = SV39 << 60 | 0x8000_1000 >> 12;
+        cpu.reg.pc = 0x2000;
+        assert_eq!(cpu.fetch(), Err(Exception::InstructionAccessFault(0x2000)));
+    }
+
+    #[test]
+    fn fetch_out_of_dram_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.mtvec = 0x8000_1000;
+        cpu.reg.pc = MEM_OFF as u64 + 0x1_0000;
+        cpu.run(1);
+        assert_eq!(cpu.reg.mcause, 1);
+        assert_eq!(cpu.reg.mtval, MEM_OFF as u64 + 0x1_0000);
+        assert_eq!(cpu.reg.pc, 0x8000_1000);
+    }
+
+    #[test]
+    fn smp_test() {
+        let mut harts = new_harts(0x1_0000, 2);
+        let bus = harts[0].bus.clone();
+        for cpu in harts.iter_mut() {
+            cpu.reg.a1 = 0x8000_1000;
+            cpu.reg.a2 = cpu.reg.mhartid + 1;
+        }
+        // lr.w	a0,(a1)
+        let lr_w = Instruction::decode(0x1005_A52F).unwrap();
+        // sc.w	a0,a2,(a1)
+        let sc_w = Instruction::decode(0x18C5_A52F).unwrap();
+        // sw	a2,4(a1)
+        let sw = Instruction::decode(0x00C5_A223).unwrap();
+
+        // a store of another hart to the reserved granule breaks the reservation
+        harts[0].exec_instruction(&lr_w).unwrap();
+        harts[1].exec_instruction(&sw).unwrap();
+        harts[0].exec_instruction(&sc_w).unwrap();
+        assert_eq!(harts[0].reg.a0, 1);
+        assert_eq!(bus.lw_dram(0x1000), 0);
+        // the first SC wins
+        harts[0].exec_instruction(&lr_w).unwrap();
+        harts[1].exec_instruction(&lr_w).unwrap();
+        harts[1].exec_instruction(&sc_w).unwrap();
+        harts[0].exec_instruction(&sc_w).unwrap();
+        assert_eq!((harts[0].reg.a0, harts[1].reg.a0), (1, 0));
+        assert_eq!(bus.lw_dram(0x1000), 2);
+
+        // hart 0 raises the software interrupt of hart 1 and moves its timer deadline
+        harts[0].reg.a1 = 0x200_0000; // msip
+        harts[0].reg.a2 = 1;
+        harts[0].exec_instruction(&sw).unwrap();
+        harts[0].reg.a1 = 0x200_3FFC; // mtimecmp of hart 1 is at 0x200_4008
+        harts[0]
+            .exec_instruction(&Instruction::decode(0x00C5_A623).unwrap()) // sw a2,12(a1)
+            .unwrap();
+        for cpu in harts.iter_mut() {
+            cpu.poll_int();
+        }
+        assert_eq!(harts[0].reg.mip & 0b1000_1000, 0b1000_0000); // MTIP
+        assert_eq!(harts[1].reg.mip & 0b1000_1000, 0b0000_1000); // MSIP
+
+        // source 10 goes to the S-mode context of hart 1
+        bus.s_mm(plic::PRIORITY + 4 * 9, 4, 1);
+        bus.s_mm(plic::ENABLE + 0x80 * 3, 4, 1 << 10);
+        bus.s_mm(plic::PENDING, 4, 1 << 10);
+        for cpu in harts.iter_mut() {
+            cpu.poll_int();
+        }
+        assert_eq!(harts[0].reg.read_mip() & 0b1010_0000_0000, 0);
+        assert_eq!(harts[1].reg.read_mip() & 0b1010_0000_0000, 0b0010_0000_0000); // SEIP
+        assert_eq!(bus.l_mm(plic::CONTEXT + 0x1000 * 3 + 4, 4), 10); // claim
+        harts[1].poll_int();
+        assert_eq!(harts[1].reg.read_mip() & 0b0010_0000_0000, 0);
+    }
+
+    #[test]
+    fn amo_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.a1 = 0x8000_1000;
+        cpu.bus.sd_dram(0x1000, 0xFFFF_FFFF);
+        // amominu.w	a0,a2,(a1)
+        cpu.reg.a2 = 1;
+        cpu.exec_instruction(&Instruction::decode(0xC0C5_A52F).unwrap())
+            .unwrap();
+        assert_eq!(cpu.reg.a0, u64::MAX); // the old value sign-extended
+        assert_eq!(cpu.bus.ld_dram(0x1000), 1);
+        // amomax.d.aqrl	a0,a2,(a1)
+        cpu.reg.a2 = -5i64 as u64;
+        cpu.exec_instruction(&Instruction::decode(0xA6C5_B52F).unwrap())
+            .unwrap();
+        assert_eq!((cpu.reg.a0, cpu.bus.ld_dram(0x1000)), (1, 1));
+        // misaligned
+        cpu.reg.a1 = 0x8000_1004;
+        assert_eq!(
+            cpu.exec_instruction(&Instruction::decode(0xA6C5_B52F).unwrap()),
+            Err(Exception::StoreAddressMisaligned(0x8000_1004))
+        );
+
+        // harts on host threads count with amoadd.w and with lr.d/sc.d loops
+        let harts = new_harts(0x1_0000, 4);
+        let bus = harts[0].bus.clone();
+        let threads: Vec<_> = harts
+            .into_iter()
+            .map(|mut cpu| {
+                std::thread::spawn(move || {
+                    // amoadd.w	a0,a2,(a1)
+                    let amoadd_w = Instruction::decode(0x00C5_A52F).unwrap();
+                    // lr.d	a0,(a1)
+                    let lr_d = Instruction::decode(0x1005_B52F).unwrap();
+                    // sc.d	a3,a2,(a1)
+                    let sc_d = Instruction::decode(0x18C5_B6AF).unwrap();
+                    for _ in 0..1000 {
+                        cpu.reg.a1 = 0x8000_1000;
+                        cpu.reg.a2 = 1;
+                        cpu.exec_instruction(&amoadd_w).unwrap();
+                        cpu.reg.a1 = 0x8000_1008;
+                        loop {
+                            cpu.exec_instruction(&lr_d).unwrap();
+                            cpu.reg.a2 = cpu.reg.a0 + 1;
+                            cpu.exec_instruction(&sc_d).unwrap();
+                            if cpu.reg.a3 == 0 {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(bus.lw_dram(0x1000), 4000);
+        assert_eq!(bus.ld_dram(0x1008), 4000);
+
+        // a hart halted by WFI on a host thread blocks until another hart raises its msip
+        let mut harts = new_harts(0x1_0000, 2);
+        let bus = harts[0].bus.clone();
+        bus.sw_dram(0, 0x0000_0013); // nop
+        let mut cpu = harts.pop().unwrap();
+        let t = std::thread::spawn(move || {
+            cpu.reg.mie = 0b1000; // MSIE
+            cpu.reg.pc = 0x8000_0000;
+            cpu.exec_instruction(&Instruction::decode(0x1050_0073).unwrap())
+                .unwrap();
+            loop {
+                let events = cpu.bus.events();
+                cpu.run(1);
+                if !cpu.halted() {
+                    break;
+                }
+                cpu.bus.wait_event(events, None);
+            }
+            cpu.reg.pc
+        });
+        bus.s_mm(clint::MSIP + 4, 4, 1);
+        assert_eq!(t.join().unwrap(), 0x8000_0004);
+        // or until the timeout
+        bus.wait_event(bus.events(), Some(std::time::Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn clint_test() {
+        let clint = Clint::new(2, 10_000_000, false);
+        // mtimecmp of hart 1 written as 4-byte halves
+        clint.write(clint::MTIMECMP + 8, 4, 0x89AB_CDEF);
+        clint.write(clint::MTIMECMP + 12, 4, 0x0123_4567);
+        assert_eq!(clint.mtimecmp(1), 0x0123_4567_89AB_CDEF);
+        assert_eq!(clint.read(clint::MTIMECMP + 12, 4), 0x0123_4567);
+        // msip of hart 1. Harts that do not exist read as 0.
+        clint.write(clint::MSIP + 4, 4, 0xFFFF_FFFF);
+        assert!(clint.msip(1) && !clint.msip(0));
+        assert_eq!(clint.read(clint::MSIP + 4, 4), 1);
+        clint.write(clint::MSIP + 8, 4, 1);
+        assert_eq!(clint.read(clint::MSIP + 8, 4), 0);
+        // mtime advances with the instructions at the timebase frequency
+        assert_eq!(clint.ticks(0, conf::HART_FREQ), 10_000_000);
+        // 10 instructions per tick. The fraction is carried to the next quantum of the same hart.
+        let quantum = |q: u64, n: u64| (0..n).map(|_| clint.ticks(1, q)).sum::<u64>();
+        assert_eq!(quantum(5, 10), 5);
+        assert_eq!(quantum(15, 2), 3);
+        assert_eq!(quantum(1, 9), 0);
+        assert_eq!(clint.ticks(0, 1), 0);
+        assert_eq!(clint.ticks(1, 1), 1);
+        assert!(clint.advance_mtime(100));
+        assert!(clint.advance_mtime(50));
+        assert_eq!(clint.read(clint::MTIME, 8), 100);
+
+        // or with the host time
+        let clint = Clint::new(1, 1_000_000_000, true);
+        clint.write(clint::MTIME, 8, 1 << 40);
+        assert!(!clint.advance_mtime(1 << 50));
+        let mtime = clint.mtime();
+        assert!(mtime >= 1 << 40 && mtime < 1 << 50);
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        assert!(clint.mtime() >= mtime + 1_000_000);
+        // a halted hart waits on the host clock until its timer deadline
+        let until = clint.wall_time_until(clint.mtime() + 1_000_000).unwrap();
+        assert!(until <= std::time::Duration::from_millis(1));
+        assert_eq!(clint.wall_time_until(0), Some(std::time::Duration::ZERO));
+        assert_eq!(Clint::new(1, 1_000_000, false).wall_time_until(1), None);
+    }
+
+    #[test]
+    fn sstc_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        let csrr = Instruction::decode(0x14D0_2573).unwrap(); // csrr a0, stimecmp
+        let csrw = Instruction::decode(0x14D5_1073).unwrap(); // csrw stimecmp, a0
+        cpu.mode = Mode::S;
+        // illegal without menvcfg.STCE and mcounteren.TM
+        assert_eq!(
+            cpu.exec_instruction(&csrr),
+            Err(Exception::IllegalInstruction(0x14D0_2573))
+        );
+        cpu.reg.menvcfg = 1 << 63;
+        assert!(cpu.exec_instruction(&csrr).is_err());
+        cpu.reg.mcounteren = 0b10;
+        cpu.reg.a0 = 500;
+        cpu.exec_instruction(&csrw).unwrap();
+        assert_eq!(cpu.reg.stimecmp, 500);
+
+        // STIP follows stimecmp and can not be written
+        cpu.bus.clint().set_mtime(100);
+        cpu.poll_int();
+        assert_eq!(cpu.reg.mip & 0b10_0000, 0);
+        cpu.bus.clint().set_mtime(500);
+        cpu.poll_int();
+        assert_eq!(cpu.reg.mip & 0b10_0000, 0b10_0000);
+        cpu.reg.set_csr(0x344, 0);
+        assert_eq!(cpu.reg.mip & 0b10_0000, 0b10_0000);
+        // stimecmp wakes the hart halted by WFI
+        cpu.reg.mie = 0b10_0000; // STIE
+        assert_eq!(cpu.wake_time(), Some(500));
+
+        // VS-mode accesses vstimecmp, compared with mtime + htimedelta
+        cpu.reg.virt = true;
+        assert_eq!(
+            cpu.exec_instruction(&csrr),
+            Err(Exception::VirtualInstruction(0x14D0_2573))
+        );
+        cpu.reg.henvcfg = 1 << 63;
+        cpu.reg.hcounteren = 0b10;
+        cpu.reg.htimedelta = 1000;
+        cpu.reg.a0 = 1600;
+        cpu.exec_instruction(&csrw).unwrap();
+        assert_eq!((cpu.reg.stimecmp, cpu.reg.vstimecmp), (500, 1600));
+        cpu.poll_int();
+        assert_eq!(cpu.reg.mip & 0b100_0000, 0);
+        cpu.bus.clint().set_mtime(600);
+        cpu.poll_int();
+        assert_eq!(cpu.reg.mip & 0b100_0000, 0b100_0000);
+
+        // hvip.VSTIP raises VSTIP on its own
+        cpu.reg.henvcfg = 0;
+        cpu.reg.set_csr(0x645, 0b100_0000);
+        cpu.poll_int();
+        assert_eq!(cpu.reg.mip & 0b100_0000, 0b100_0000);
+        // henvcfg.STCE is read-only zero without menvcfg.STCE
+        cpu.reg.henvcfg = 1 << 63;
+        cpu.reg.menvcfg = 0;
+        assert_eq!(cpu.reg.get_csr(0x60A), 0);
+    }
+
+    #[test]
+    fn aia_test() {
+        let mut harts = new_machine(0x1_0000, 2, Irqchip::aia(2));
+        let bus = harts[0].bus.clone();
+        let cpu = &mut harts[0];
+
+        // an MSI written to the M-level interrupt file of hart 0
+        cpu.reg.set_csr(0x350, 0x70); // miselect = eidelivery
+        cpu.write_csr(0x351, 1);
+        cpu.reg.set_csr(0x350, 0xC0); // miselect = eie0
+        cpu.write_csr(0x351, 1 << 5);
+        bus.s_mm(imsic::IMSIC_M, 4, 5); // seteipnum
+        cpu.poll_int();
+        assert_eq!(cpu.reg.mip & 0x800, 0x800); // MEIP
+        assert_eq!(cpu.read_csr(0x35C), (5 << 16) | 5); // mtopei
+        cpu.write_csr(0x35C, 0); // claim
+        assert_eq!(cpu.read_csr(0x35C), 0);
+        cpu.poll_int();
+        assert_eq!(cpu.reg.mip & 0x800, 0);
+        // eip1 does not exist on RV64
+        cpu.reg.set_csr(0x350, 0x81);
+        let csrr = Instruction::decode(0x3510_2573).unwrap(); // csrr a0, mireg
+        assert_eq!(
+            cpu.exec_instruction(&csrr),
+            Err(Exception::IllegalInstruction(0x3510_2573))
+        );
+
+        // direct delivery. Source 3 targets hart 1 with priority 2.
+        let m = aplic::APLIC_M;
+        bus.s_mm(m, 4, 0x100); // domaincfg.IE
+        bus.s_mm(m + 4 * 3, 4, 4); // sourcecfg[3]: rising edge
+        bus.s_mm(m + 0x3004 + 4 * 2, 4, (1 << 18) | 2); // target[3]
+        bus.s_mm(m + 0x1EDC, 4, 3); // setienum
+        bus.s_mm(m + 0x4000 + 32, 4, 1); // idelivery of hart 1
+        bus.s_mm(m + 0x1CDC, 4, 3); // setipnum
+        assert!(bus.ext_int(1, false) && !bus.ext_int(0, false));
+        assert_eq!(bus.l_mm(m + 0x4000 + 32 + 0x1C, 4), (3 << 16) | 2); // claimi
+        assert!(!bus.ext_int(1, false));
+
+        // MSI delivery. Source 7 is delegated to the S-level domain,
+        // which writes it to the S-level interrupt file of hart 0 as identity 9.
+        let s = aplic::APLIC_S;
+        bus.s_mm(m + 4 * 7, 4, 1 << 10); // sourcecfg[7].D
+        bus.s_mm(s + 4 * 7, 4, 4);
+        bus.s_mm(s, 4, 0x104); // domaincfg.IE and DM
+        bus.s_mm(s + 0x3004 + 4 * 6, 4, 9); // target[7]: hart 0, EIID 9
+        bus.s_mm(s + 0x1EDC, 4, 7);
+        cpu.reg.set_csr(0x150, 0x70); // siselect = eidelivery
+        cpu.write_csr(0x151, 1);
+        cpu.reg.set_csr(0x150, 0xC0); // siselect = eie0
+        cpu.write_csr(0x151, 1 << 9);
+        bus.s_mm(s + 0x1CDC, 4, 7);
+        assert_eq!(bus.l_mm(s + 0x1C00, 4), 0); // the MSI clears the pending bit
+        assert_eq!(cpu.read_csr(0x15C), (9 << 16) | 9); // stopei
+        assert!(bus.ext_int(0, true));
+        // a source the root domain does not delegate is inactive in the child domain
+        bus.s_mm(s + 4 * 8, 4, 4);
+        assert_eq!(bus.l_mm(s + 4 * 8, 4), 0);
+
+        // the AIA CSRs do not exist with the PLIC
+        let mut cpu = new_cpu(0x1_0000);
+        assert_eq!(
+            cpu.exec_instruction(&csrr),
+            Err(Exception::IllegalInstruction(0x3510_2573))
+        );
+    }
+
+    #[test]
+    fn misaligned_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.pmpaddr0 = 0x3F_FFFF_FFFF_FFFF;
+        cpu.reg.pmpcfg0 = 0x1F; // NAPOT, RWX
+        let pte = |pa: u64, flags: u64| (pa >> 12) << 10 | flags;
+        // va 0x1000 -> pa 0x8000_5000 and va 0x2000 -> pa 0x8000_7000. The next page is not mapped.
+        let rw = PTE_V | PTE_R | PTE_W | PTE_A | PTE_D;
+        cpu.bus.sd_dram(0x1000, pte(0x8000_2000, PTE_V));
+        cpu.bus.sd_dram(0x2000, pte(0x8000_3000, PTE_V));
+        cpu.bus.sd_dram(0x3008, pte(0x8000_5000, rw));
+        cpu.bus.sd_dram(0x3010, pte(0x8000_7000, rw));
+        cpu.reg.satp = SV39 << 60 | 0x8000_1000 >> 12;
+        cpu.mode = Mode::S;
+
+        // a store across the page boundary is translated twice
+        cpu.store(0x1FFD, 8, 0x8877_6655_4433_2211).unwrap();
+        assert_eq!(cpu.bus.ld_dram(0x5FF8) >> 40, 0x33_2211);
+        assert_eq!(cpu.bus.ld_dram(0x7000), 0x88_7766_5544);
+        assert_eq!(cpu.load(0x1FFD, 8), Ok(0x8877_6655_4433_2211));
+        assert_eq!(cpu.load(0x1FFF, 2), Ok(0x4433));
+        // a fault in the second page leaves the first page unchanged
+        assert_eq!(
+            cpu.store(0x2FFE, 4, 0),
+            Err(Exception::StorePageFault(0x3000))
+        );
+        assert_eq!(cpu.load(0x2FFE, 2), Ok(0));
+
+        // devices do not support misaligned accesses
+        cpu.mode = Mode::M;
+        assert_eq!(
+            cpu.load(clint::MTIME + 2, 4),
+            Err(Exception::LoadAccessFault(clint::MTIME + 2))
+        );
+
+        // the firmware emulates misaligned accesses
+        cpu.trap_misaligned = true;
+        assert_eq!(
+            cpu.load(0x8000_5FFD, 8),
+            Err(Exception::LoadAddressMisaligned(0x8000_5FFD))
+        );
+        assert_eq!(
+            cpu.store(0x8000_5001, 2, 0),
+            Err(Exception::StoreAddressMisaligned(0x8000_5001))
+        );
+        assert_eq!(cpu.load(0x8000_5FF8, 8), Ok(0x3322_1100_0000_0000));
+    }
+
+    #[test]
+    fn zicond_zicbo_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.pmpaddr0 = 0x3F_FFFF_FFFF_FFFF;
+        cpu.reg.pmpcfg0 = 0x1F; // NAPOT, RWX
+        let czero_eqz = Instruction::decode(0x0EC5_D533).unwrap(); // czero.eqz a0, a1, a2
+        let czero_nez = Instruction::decode(0x0EC5_F533).unwrap(); // czero.nez a0, a1, a2
+        cpu.reg.set_reg(11, 42);
+        cpu.exec_instruction(&czero_eqz).unwrap();
+        assert_eq!(cpu.reg.get_reg(10), 0);
+        cpu.exec_instruction(&czero_nez).unwrap();
+        assert_eq!(cpu.reg.get_reg(10), 42);
+        cpu.reg.set_reg(12, 1);
+        cpu.exec_instruction(&czero_eqz).unwrap();
+        assert_eq!(cpu.reg.get_reg(10), 42);
+        cpu.exec_instruction(&czero_nez).unwrap();
+        assert_eq!(cpu.reg.get_reg(10), 0);
+
+        let pause = Instruction::decode(0x0100_000F).unwrap();
+        assert!(matches!(pause.name, InstName::Pause));
+        cpu.exec_instruction(&pause).unwrap();
+
+        // cbo.zero clears the whole block around the address
+        let cbo_zero = Instruction::decode(0x0045_200F).unwrap(); // cbo.zero (a0)
+        let cbo_clean = Instruction::decode(0x0015_200F).unwrap(); // cbo.clean (a0)
+        let cbo_inval = Instruction::decode(0x0005_200F).unwrap(); // cbo.inval (a0)
+        for off in (0x1000..0x1100).step_by(8) {
+            cpu.bus.sd_dram(off, u64::MAX);
+        }
+        cpu.reg.set_reg(10, 0x8000_1047);
+        cpu.exec_instruction(&cbo_zero).unwrap();
+        assert_eq!(cpu.bus.ld_dram(0x1038), u64::MAX);
+        assert_eq!(cpu.bus.ld_dram(0x1040), 0);
+        assert_eq!(cpu.bus.ld_dram(0x1078), 0);
+        assert_eq!(cpu.bus.ld_dram(0x1080), u64::MAX);
+        cpu.cache_block = 128;
+        cpu.exec_instruction(&cbo_zero).unwrap();
+        assert_eq!(cpu.bus.ld_dram(0x1000), 0);
+        assert_eq!(cpu.bus.ld_dram(0x1080), u64::MAX);
+
+        // below M-mode the operations are enabled by menvcfg, and in U-mode also by senvcfg
+        cpu.mode = Mode::U;
+        let illegal = |inst: &Instruction| Err(Exception::IllegalInstruction(inst.raw_inst as u64));
+        assert_eq!(cpu.exec_instruction(&cbo_zero), illegal(&cbo_zero));
+        cpu.reg.menvcfg = ENVCFG_CBZE | ENVCFG_CBCFE;
+        assert_eq!(cpu.exec_instruction(&cbo_zero), illegal(&cbo_zero));
+        cpu.reg.senvcfg = ENVCFG_CBZE | ENVCFG_CBCFE | ENVCFG_CBIE;
+        cpu.exec_instruction(&cbo_zero).unwrap();
+        cpu.exec_instruction(&cbo_clean).unwrap();
+        assert_eq!(cpu.exec_instruction(&cbo_inval), illegal(&cbo_inval));
+        // CBIE=0b10 is reserved
+        cpu.reg.set_csr(0x30A, 0b10_0000);
+        assert_eq!(cpu.reg.menvcfg & ENVCFG_CBIE, 0);
+        cpu.reg.set_csr(0x30A, 0b01_0000);
+        cpu.exec_instruction(&cbo_inval).unwrap();
+
+        // faults are reported as store faults
+        cpu.mode = Mode::S;
+        cpu.reg.satp = SV39 << 60 | 0x8000_2000 >> 12;
+        assert_eq!(
+            cpu.exec_instruction(&cbo_inval),
+            Err(Exception::StorePageFault(0x8000_1000))
+        );
+        cpu.reg.set_reg(10, clint::MTIME);
+        cpu.reg.satp = 0;
+        cpu.reg.menvcfg = ENVCFG_CBZE | ENVCFG_CBCFE;
+        cpu.exec_instruction(&cbo_clean).unwrap();
+        assert_eq!(
+            cpu.exec_instruction(&cbo_zero),
+            Err(Exception::StoreAccessFault(clint::MTIME))
+        );
+    }
+
+    #[test]
+    fn svpbmt_svnapot_test() {
+        let mut cpu = new_cpu(0x4_0000);
+        cpu.reg.pmpaddr0 = 0x3F_FFFF_FFFF_FFFF;
+        cpu.reg.pmpcfg0 = 0x1F; // NAPOT, RWX
+        let pte = |pa: u64, flags: u64| (pa >> 12) << 10 | flags;
+        let rw = PTE_V | PTE_R | PTE_W | PTE_A | PTE_D;
+        // va 0x1_0000 - 0x1_FFFF -> pa 0x8002_0000 - 0x8002_FFFF by one NAPOT PTE
+        cpu.bus.sd_dram(0x1000, pte(0x8000_2000, PTE_V));
+        cpu.bus.sd_dram(0x2000, pte(0x8000_3000, PTE_V));
+        let napot = pte(0x8002_8000, rw | PTE_N);
+        cpu.bus.sd_dram(0x3000 + 0x17 * 8, napot);
+        // va 0x2_0000 is an IO page
+        let io = pte(0x8003_0000, rw) | 2 << 61;
+        cpu.bus.sd_dram(0x3000 + 0x20 * 8, io);
+        cpu.reg.satp = SV39 << 60 | 0x8000_1000 >> 12;
+        cpu.mode = Mode::S;
+
+        cpu.bus.sd_dram(0x2_7008, 0x1234);
+        assert_eq!(cpu.load(0x1_7008, 8), Ok(0x1234));
+        // the TLB entry of a NAPOT page is flushed by any address in it
+        cpu.tlb.flush(Some(0x1_0000 >> 12), None);
+        assert_eq!(cpu.tlb.lookup(0x1_7000 >> 12, 0), None);
+        // only 64 KiB NAPOT pages exist
+        cpu.bus
+            .sd_dram(0x3000 + 0x17 * 8, pte(0x8002_4000, rw | PTE_N));
+        assert_eq!(
+            cpu.load(0x1_7008, 8),
+            Err(Exception::LoadPageFault(0x1_7008))
+        );
+
+        // PBMT is reserved without menvcfg.PBMTE
+        assert_eq!(
+            cpu.load(0x2_0000, 8),
+            Err(Exception::LoadPageFault(0x2_0000))
+        );
+        cpu.reg.set_csr(0x30A, 1 << 62);
+        cpu.bus.sd_dram(0x3_0000, 0x5678);
+        assert_eq!(cpu.load(0x2_0000, 8), Ok(0x5678));
+        // PBMT = 3 and non-leaf PBMT are reserved
+        cpu.tlb.flush(None, None);
+        cpu.bus.sd_dram(0x3000 + 0x20 * 8, io | 3 << 61);
+        assert_eq!(
+            cpu.load(0x2_0000, 8),
+            Err(Exception::LoadPageFault(0x2_0000))
+        );
+        cpu.bus.sd_dram(0x3000 + 0x20 * 8, io);
+        cpu.bus.sd_dram(0x2000, pte(0x8000_3000, PTE_V) | 1 << 61);
+        assert_eq!(
+            cpu.load(0x2_0000, 8),
+            Err(Exception::LoadPageFault(0x2_0000))
+        );
+
+        // henvcfg.PBMTE is read-only zero without menvcfg.PBMTE
+        cpu.reg.set_csr(0x60A, 1 << 62);
+        assert!(cpu.reg.vs_pbmte());
+        cpu.reg.set_csr(0x30A, 0);
+        assert_eq!(cpu.reg.get_csr(0x60A), 0);
+    }
+
+    #[test]
+    fn illegal_instruction_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        cpu.reg.mtvec = 0x8000_1000;
+        // an unknown opcode, a reserved compressed encoding and an unsupported funct7
+        for (inst, len) in [(0xFFFF_FFFF, 4), (0x0000, 2), (0x7E00_0033, 4)] {
+            cpu.reg.pc = MEM_OFF as u64;
+            cpu.bus.sw_dram(0, inst);
+            cpu.run(1);
+            assert_eq!(cpu.reg.pc, 0x8000_1000);
+            assert_eq!(cpu.reg.mcause, 2);
+            assert_eq!(cpu.reg.mepc, MEM_OFF as u64);
+            let raw = if len == 2 { inst & 0xFFFF } else { inst };
+            assert_eq!(cpu.reg.mtval, raw as u64);
+        }
+        assert!(Instruction::decode(0x0000_0013).is_ok()); // nop
+
+        // csrr a0,0x7C0 reads a CSR that is not implemented
+        cpu.reg.a0 = 1;
+        cpu.reg.pc = MEM_OFF as u64;
+        cpu.bus.sw_dram(0, 0x7C00_2573);
+        cpu.run(1);
+        assert_eq!(cpu.reg.pc, 0x8000_1000);
+        assert_eq!(cpu.reg.mcause, 2);
+        assert_eq!(cpu.reg.mtval, 0x7C00_2573);
+        assert_eq!(cpu.reg.a0, 1);
+    }
+
+    #[test]
+    fn icache_test() {
+        let mut cpu = new_cpu(0x1_0000);
+        // loop: addi a0,a0,1; addi a0,a0,1; j loop
+        cpu.bus.sw_dram(0x0, 0x0015_0513);
+        cpu.bus.sw_dram(0x4, 0x0015_0513);
+        cpu.bus.sw_dram(0x8, 0xFF9F_F06F);
+        cpu.bus.sw_dram(0x100, 0x0000_100F); // fence.i
+        cpu.reg.pc = MEM_OFF as u64;
+        cpu.run(30);
+        assert_eq!(cpu.reg.a0, 20);
+        assert_eq!((cpu.icache.hit, cpu.icache.miss), (9, 1));
+
+        // a store to the code page makes the block stale: addi a0,a0,2
+        cpu.store(MEM_OFF as u64 + 4, 4, 0x0025_0513).unwrap();
+        cpu.run(30);
+        assert_eq!(cpu.reg.a0, 50);
+        assert_eq!((cpu.icache.hit, cpu.icache.miss), (18, 2));
+
+        // a store to the block being executed takes effect on its next instruction
+        cpu.run(1);
+        cpu.store(MEM_OFF as u64 + 4, 4, 0x0015_0513).unwrap();
+        cpu.run(2);
+        assert_eq!(cpu.reg.a0, 52);
+
+        cpu.reg.pc = MEM_OFF as u64 + 0x100;
+        cpu.run(1);
+        assert!(cpu.cursor.is_none());
+        cpu.reg.pc = MEM_OFF as u64;
+        cpu.run(3);
+        assert_eq!(cpu.reg.a0, 54);
+        assert_eq!(cpu.icache.miss, 5);
+    }
+
+    #[test]
+    fn decode_test() {
+        // immediates are sign-extended from the top bit of each format
+        // beq zero,zero,-4
+        let beq = Instruction::decode(0xFE00_0EE3).unwrap();
+        assert_eq!(
+            (beq.name, beq.fmt, beq.imm),
+            (InstName::Beq, InstFmt::B, -4)
+        );
+        // jal zero,-8
+        let jal = Instruction::decode(0xFF9F_F06F).unwrap();
+        assert_eq!(
+            (jal.name, jal.fmt, jal.imm),
+            (InstName::Jal, InstFmt::J, -8)
+        );
+        // lui a0,0xfffff
+        let lui = Instruction::decode(0xFFFF_F537).unwrap();
+        assert_eq!(lui.imm, -0x1000);
+        // c.addi a0,-1 expands to addi a0,a0,-1
+        let c_addi = Instruction::decode(0x157D).unwrap();
+        assert_eq!((c_addi.name, c_addi.imm), (InstName::Addi, -1));
+
+        let mut cpu = new_cpu(0);
+        cpu.reg.pc = MEM_OFF as u64 + 8;
+        cpu.beq(&beq).unwrap();
+        assert_eq!(cpu.next_pc, MEM_OFF as u64 + 4);
+        cpu.lui(&lui).unwrap();
+        assert_eq!(cpu.reg.a0, 0xFFFF_FFFF_FFFF_F000);
+
+        // mnemonics are expanded from the static table without changing the instruction
+        assert_eq!(beq.mnemonic(), "beq");
+        // vle32.v v1,(a0)
+        assert_eq!(
+            Instruction::decode(0x0205_6087).unwrap().mnemonic(),
+            "vle32.v"
+        );
+        // vadd.vv v1,v2,v3
+        assert_eq!(
+            Instruction::decode(0x0221_80D7).unwrap().mnemonic(),
+            "vadd.vv"
+        );
     }
 }
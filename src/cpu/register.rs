@@ -1,3 +1,80 @@
+// MXL=2 (XLEN=64), Extensions=ABCDFHIMSUV
+// B is Zba, Zbb and Zbs. Zbc is also implemented but has no misa bit.
+const MISA: u64 = 0x8000_0000_0034_11AF;
+const MISA_C: u64 = 0b100; // compressed instructions
+
+const MSTATUS_FS: u64 = 0b110_0000_0000_0000; // floating-point unit status
+const MSTATUS_SD: u64 = 0x8000_0000_0000_0000; // FS, VS or XS is Dirty
+const MSTATUS_VS: u64 = 0b110_0000_0000; // vector unit status
+const MSTATUS_XS: u64 = 0b1_1000_0000_0000_0000; // user extension status
+const MSTATUS_MPP: u64 = 0b1_1000_0000_0000;
+const MSTATUS_UXL: u64 = 0b10 << 32; // XLEN=64 in U-mode
+const MSTATUS_SXL: u64 = 0b10 << 34; // XLEN=64 in S-mode
+const HSTATUS_VSXL: u64 = 0b10 << 32; // XLEN=64 in VS-mode
+
+// WARL write masks
+// mstatus: SIE, MIE, SPIE, MPIE, SPP, VS, MPP, FS, MPRV, SUM, MXR, TVM, TW, TSR, GVA, MPV
+const MSTATUS_WMASK: u64 = 0xC0_007E_7FAA;
+// sstatus: SIE, SPIE, SPP, VS, FS, SUM, MXR
+const SSTATUS_WMASK: u64 = 0x0C_6722;
+// sstatus: the writable bits, UBE, VS, XS, UXL and SD
+const SSTATUS_RMASK: u64 =
+    SSTATUS_WMASK | 0x40 | MSTATUS_VS | MSTATUS_XS | (0b11 << 32) | MSTATUS_SD;
+
+const MIP_SSIP: u64 = 0b10;
+const MIP_VSSIP: u64 = 0b100;
+// VSSIP, VSTIP and VSEIP
+const MIP_VS: u64 = 0x444;
+const MIP_SGEIP: u64 = 0x1000;
+const MIP_STIP: u64 = 0x20;
+const MIP_SEIP: u64 = 0x200;
+const MIP_VSTIP: u64 = 0x40;
+// SSIP, VSSIP, STIP, SEIP. MSIP, MTIP and MEIP are set by the CLINT and PLIC,
+// VSTIP and VSEIP by hvip.
+const MIP_WMASK: u64 = 0x226;
+// SSIE, VSSIE, MSIE, STIE, VSTIE, MTIE, SEIE, VSEIE, MEIE, SGEIE
+const MIE_WMASK: u64 = 0x1EEE;
+// Only supervisor interrupts can be delegated.
+const MIDELEG_WMASK: u64 = 0x222;
+// VS-level interrupts and SGEI are always delegated to HS-mode.
+const MIDELEG_RO: u64 = MIP_VS | MIP_SGEIP;
+// Environment call from M-mode can not be delegated.
+const MEDELEG_WMASK: u64 = 0xF0_B7FF;
+// Environment calls from HS-mode, VS-mode and M-mode, guest-page faults
+// and virtual instruction exceptions can not be delegated to VS-mode.
+const HEDELEG_WMASK: u64 = 0xB1FF;
+// hstatus: GVA, SPV, SPVP, HU, VTVM, VTW, VTSR
+const HSTATUS_WMASK: u64 = 0x70_03C0;
+// hgatp: MODE, VMID (14 bits) and PPN. The root page table is 16 KiB aligned.
+const HGATP_WMASK: u64 = 0xF3FF_FFFF_FFFF_FFFC;
+
+// miselect and siselect select registers 0x000 - 0xFFF
+const ISELECT_MASK: u64 = 0xFFF;
+
+// menvcfg and henvcfg: stimecmp and vstimecmp are enabled (Sstc)
+const ENVCFG_STCE: u64 = 1 << 63;
+// menvcfg and henvcfg: PBMT of the page table entries is enabled (Svpbmt)
+const ENVCFG_PBMTE: u64 = 1 << 62;
+// menvcfg, henvcfg and senvcfg: cache-block operations are enabled (Zicbom, Zicboz)
+pub const ENVCFG_CBIE: u64 = 0b11_0000; // cbo.inval. 00: illegal, 01: flush, 11: invalidate
+pub const ENVCFG_CBCFE: u64 = 0b100_0000; // cbo.clean and cbo.flush
+pub const ENVCFG_CBZE: u64 = 0b1000_0000; // cbo.zero
+
+// mhpmevent
+pub const HPM_LOAD: u64 = 1; // load instructions
+pub const HPM_STORE: u64 = 2; // store instructions
+pub const HPM_BRANCH: u64 = 3; // conditional branch instructions
+pub const HPM_TLB_MISS: u64 = 4; // address translations missing the TLB
+const HPM_EVENT_MAX: u64 = HPM_TLB_MISS;
+const HPM_NUM: usize = 29; // mhpmcounter3 - mhpmcounter31
+
+// mcountinhibit
+const MCOUNTINHIBIT_CY: u64 = 0b001;
+const MCOUNTINHIBIT_IR: u64 = 0b100;
+
+const PMP_L: u8 = 0b1000_0000; // pmpcfg locked
+const PMPADDR_MASK: u64 = 0x3F_FFFF_FFFF_FFFF; // 54 bit
+
 #[derive(Debug)]
 pub struct Register {
     // registers
@@ -36,20 +113,87 @@ pub struct Register {
 
     pub pc: u64,
 
+    // floating-point registers
+    pub ft0: u64,
+    pub ft1: u64,
+    pub ft2: u64,
+    pub ft3: u64,
+    pub ft4: u64,
+    pub ft5: u64,
+    pub ft6: u64,
+    pub ft7: u64,
+    pub fs0: u64,
+    pub fs1: u64,
+    pub fa0: u64,
+    pub fa1: u64,
+    pub fa2: u64,
+    pub fa3: u64,
+    pub fa4: u64,
+    pub fa5: u64,
+    pub fa6: u64,
+    pub fa7: u64,
+    pub fs2: u64,
+    pub fs3: u64,
+    pub fs4: u64,
+    pub fs5: u64,
+    pub fs6: u64,
+    pub fs7: u64,
+    pub fs8: u64,
+    pub fs9: u64,
+    pub fs10: u64,
+    pub fs11: u64,
+    pub ft8: u64,
+    pub ft9: u64,
+    pub ft10: u64,
+    pub ft11: u64,
+
+    // floating-point csr
+    pub fcsr: u64, // 0x003 (fflags: 0x001, frm: 0x002)
+
+    // vector csr
+    pub vstart: u64, // 0x008
+    pub vxsat: u64,  // 0x009 (vcsr: 0x00F)
+    pub vxrm: u64,   // 0x00A
+    pub vl: u64,     // 0xC20
+    pub vtype: u64,  // 0xC21
+    pub vlenb: u64,  // 0xC22
+
     // supervisor-level csr
-    pub sstatus: u64,    // 0x100
-    pub sie: u64,        // 0x104
     pub stvec: u64,      // 0x105
     pub scounteren: u64, // 0x106
     pub senvcfg: u64,    // 0x10A
+    pub siselect: u64,   // 0x150
     pub sscratch: u64,   // 0x140
     pub sepc: u64,       // 0x141
     pub scause: u64,     // 0x142
     pub stval: u64,      // 0x143
-    pub sip: u64,        // 0x144
+    pub stimecmp: u64,   // 0x14D
     pub satp: u64,       // 0x180
     pub scontext: u64,   // 0x5A8
 
+    // hypervisor and virtual supervisor csr
+    // virt is the virtualization mode V. VS-mode and VU-mode are S-mode and U-mode with V=1.
+    // Supervisor csr accessed with V=1 are substituted by the virtual supervisor csr.
+    pub virt: bool,
+    pub hstatus: u64,     // 0x600
+    pub hedeleg: u64,     // 0x602
+    pub hideleg: u64,     // 0x603
+    pub hvip_vstip: bool, // 0x645. mip.VSTIP is hvip.VSTIP or the vstimecmp interrupt.
+    pub htimedelta: u64,  // 0x605
+    pub hcounteren: u64,  // 0x606
+    pub henvcfg: u64,     // 0x60A
+    pub htval: u64,       // 0x643
+    pub htinst: u64,      // 0x64A
+    pub hgatp: u64,       // 0x680
+    pub vsstatus: u64,    // 0x200
+    pub vstvec: u64,      // 0x205
+    pub vsscratch: u64,   // 0x240
+    pub vsepc: u64,       // 0x241
+    pub vscause: u64,     // 0x242
+    pub vstval: u64,      // 0x243
+    pub vstimecmp: u64,   // 0x24D
+    pub vsatp: u64,       // 0x280
+
     // machine-level csr
     pub mvendorid: u64,  // 0xF11
     pub marchid: u64,    // 0xF12
@@ -63,12 +207,14 @@ pub struct Register {
     pub mie: u64,        // 0x304
     pub mtvec: u64,      // 0x305
     pub mcounteren: u64, // 0x306
+    pub miselect: u64,   // 0x350
     pub mstatush: u64,   // 0x310
     pub mscratch: u64,   // 0x340
     pub mepc: u64,       // 0x341
     pub mcause: u64,     // 0x342
     pub mtval: u64,      // 0x343
-    pub mip: u64,        // 0x344
+    pub mip: u64,        // 0x344. SEIP is the software-writable bit.
+    pub seip_line: bool, // supervisor external interrupt from the PLIC or the APLIC
     pub mtinst: u64,     // 0x34A
     pub mtval2: u64,     // 0x34B
     pub menvcfg: u64,    // 0x30A
@@ -155,6 +301,13 @@ pub struct Register {
     pub pmpaddr61: u64,  // 0x3ED
     pub pmpaddr62: u64,  // 0x3EE
     pub pmpaddr63: u64,  // 0x3EF
+
+    // counters
+    pub mcountinhibit: u64,          // 0x320
+    pub mhpmevent: [u64; HPM_NUM],   // 0x323 - 0x33F
+    pub mcycle: u64,                 // 0xB00
+    pub minstret: u64,               // 0xB02
+    pub mhpmcounter: [u64; HPM_NUM], // 0xB03 - 0xB1F
 }
 
 impl Register {
@@ -194,125 +347,199 @@ impl Register {
             t6: 0,
             pc: 0,
 
+            // floating-point registers
+            ft0: 0,
+            ft1: 0,
+            ft2: 0,
+            ft3: 0,
+            ft4: 0,
+            ft5: 0,
+            ft6: 0,
+            ft7: 0,
+            fs0: 0,
+            fs1: 0,
+            fa0: 0,
+            fa1: 0,
+            fa2: 0,
+            fa3: 0,
+            fa4: 0,
+            fa5: 0,
+            fa6: 0,
+            fa7: 0,
+            fs2: 0,
+            fs3: 0,
+            fs4: 0,
+            fs5: 0,
+            fs6: 0,
+            fs7: 0,
+            fs8: 0,
+            fs9: 0,
+            fs10: 0,
+            fs11: 0,
+            ft8: 0,
+            ft9: 0,
+            ft10: 0,
+            ft11: 0,
+
+            // floating-point csr
+            fcsr: 0, // 0x003 (fflags: 0x001, frm: 0x002)
+
+            // vector csr
+            vstart: 0,      // 0x008
+            vxsat: 0,       // 0x009 (vcsr: 0x00F)
+            vxrm: 0,        // 0x00A
+            vl: 0,          // 0xC20
+            vtype: 1 << 63, // 0xC21 (vill)
+            vlenb: 0,       // 0xC22
+
             // supervisor-level csr
-            sstatus: 0,    // 0x100
-            sie: 0,        // 0x104
             stvec: 0,      // 0x105
             scounteren: 0, // 0x106
             senvcfg: 0,    // 0x10A
+            siselect: 0,   // 0x150
             sscratch: 0,   // 0x140
             sepc: 0,       // 0x141
             scause: 0,     // 0x142
             stval: 0,      // 0x143
-            sip: 0,        // 0x144
+            stimecmp: 0,   // 0x14D
             satp: 0,       // 0x180
             scontext: 0,   // 0x5A8
 
+            // hypervisor and virtual supervisor csr
+            virt: false,
+            hstatus: HSTATUS_VSXL, // 0x600
+            hedeleg: 0,            // 0x602
+            hideleg: 0,            // 0x603
+            hvip_vstip: false,     // 0x645
+            htimedelta: 0,         // 0x605
+            hcounteren: 0,         // 0x606
+            henvcfg: 0,            // 0x60A
+            htval: 0,              // 0x643
+            htinst: 0,             // 0x64A
+            hgatp: 0,              // 0x680
+            vsstatus: MSTATUS_UXL, // 0x200
+            vstvec: 0,             // 0x205
+            vsscratch: 0,          // 0x240
+            vsepc: 0,              // 0x241
+            vscause: 0,            // 0x242
+            vstval: 0,             // 0x243
+            vstimecmp: 0,          // 0x24D
+            vsatp: 0,              // 0x280
+
             // machine-level csr
-            mvendorid: 0,  // 0xF11
-            marchid: 0,    // 0xF12
-            mimpid: 0,     // 0xF13
-            mhartid: 0,    // 0xF14
-            mconfigptr: 0, // 0xF15
-            mstatus: 0,    // 0x300
-            misa: 0,       // 0x301
-            medeleg: 0,    // 0x302
-            mideleg: 0,    // 0x303
-            mie: 0,        // 0x304
-            mtvec: 0,      // 0x305
-            mcounteren: 0, // 0x306
-            mstatush: 0,   // 0x310
-            mscratch: 0,   // 0x340
-            mepc: 0,       // 0x341
-            mcause: 0,     // 0x342
-            mtval: 0,      // 0x343
-            mip: 0,        // 0x344
-            mtinst: 0,     // 0x34A
-            mtval2: 0,     // 0x34B
-            menvcfg: 0,    // 0x30A
-            menvcfgh: 0,   // 0x31A
-            mseccfg: 0,    // 0x747
-            mseccfgh: 0,   // 0x757
-            pmpcfg0: 0,    // 0x3A0
-            pmpcfg1: 0,    // 0x3A1
-            pmpcfg2: 0,    // 0x3A2
-            pmpcfg3: 0,    // 0x3A3
-            pmpcfg4: 0,    // 0x3A4
-            pmpcfg5: 0,    // 0x3A5
-            pmpcfg6: 0,    // 0x3A6
-            pmpcfg7: 0,    // 0x3A7
-            pmpcfg8: 0,    // 0x3A8
-            pmpcfg9: 0,    // 0x3A9
-            pmpcfg10: 0,   // 0x3AA
-            pmpcfg11: 0,   // 0x3AB
-            pmpcfg12: 0,   // 0x3AC
-            pmpcfg13: 0,   // 0x3AD
-            pmpcfg14: 0,   // 0x3AE
-            pmpcfg15: 0,   // 0x3AF
-            pmpaddr0: 0,   // 0x3B0
-            pmpaddr1: 0,   // 0x3B1
-            pmpaddr2: 0,   // 0x3B2
-            pmpaddr3: 0,   // 0x3B3
-            pmpaddr4: 0,   // 0x3B4
-            pmpaddr5: 0,   // 0x3B5
-            pmpaddr6: 0,   // 0x3B6
-            pmpaddr7: 0,   // 0x3B7
-            pmpaddr8: 0,   // 0x3B8
-            pmpaddr9: 0,   // 0x3B9
-            pmpaddr10: 0,  // 0x3BA
-            pmpaddr11: 0,  // 0x3BB
-            pmpaddr12: 0,  // 0x3BC
-            pmpaddr13: 0,  // 0x3BD
-            pmpaddr14: 0,  // 0x3BE
-            pmpaddr15: 0,  // 0x3BF
-            pmpaddr16: 0,  // 0x3C0
-            pmpaddr17: 0,  // 0x3C1
-            pmpaddr18: 0,  // 0x3C2
-            pmpaddr19: 0,  // 0x3C3
-            pmpaddr20: 0,  // 0x3C4
-            pmpaddr21: 0,  // 0x3C5
-            pmpaddr22: 0,  // 0x3C6
-            pmpaddr23: 0,  // 0x3C7
-            pmpaddr24: 0,  // 0x3C8
-            pmpaddr25: 0,  // 0x3C9
-            pmpaddr26: 0,  // 0x3CA
-            pmpaddr27: 0,  // 0x3CB
-            pmpaddr28: 0,  // 0x3CC
-            pmpaddr29: 0,  // 0x3CD
-            pmpaddr30: 0,  // 0x3CE
-            pmpaddr31: 0,  // 0x3CF
-            pmpaddr32: 0,  // 0x3D0
-            pmpaddr33: 0,  // 0x3D1
-            pmpaddr34: 0,  // 0x3D2
-            pmpaddr35: 0,  // 0x3D3
-            pmpaddr36: 0,  // 0x3D4
-            pmpaddr37: 0,  // 0x3D5
-            pmpaddr38: 0,  // 0x3D6
-            pmpaddr39: 0,  // 0x3D7
-            pmpaddr40: 0,  // 0x3D8
-            pmpaddr41: 0,  // 0x3D9
-            pmpaddr42: 0,  // 0x3DA
-            pmpaddr43: 0,  // 0x3DB
-            pmpaddr44: 0,  // 0x3DC
-            pmpaddr45: 0,  // 0x3DD
-            pmpaddr46: 0,  // 0x3DE
-            pmpaddr47: 0,  // 0x3DF
-            pmpaddr48: 0,  // 0x3E0
-            pmpaddr49: 0,  // 0x3E1
-            pmpaddr50: 0,  // 0x3E2
-            pmpaddr51: 0,  // 0x3E3
-            pmpaddr52: 0,  // 0x3E4
-            pmpaddr53: 0,  // 0x3E5
-            pmpaddr54: 0,  // 0x3E6
-            pmpaddr55: 0,  // 0x3E7
-            pmpaddr56: 0,  // 0x3E8
-            pmpaddr57: 0,  // 0x3E9
-            pmpaddr58: 0,  // 0x3EA
-            pmpaddr59: 0,  // 0x3EB
-            pmpaddr60: 0,  // 0x3EC
-            pmpaddr61: 0,  // 0x3ED
-            pmpaddr62: 0,  // 0x3EE
-            pmpaddr63: 0,  // 0x3EF
+            mvendorid: 0,                       // 0xF11
+            marchid: 0,                         // 0xF12
+            mimpid: 0,                          // 0xF13
+            mhartid: 0,                         // 0xF14
+            mconfigptr: 0,                      // 0xF15
+            mstatus: MSTATUS_UXL | MSTATUS_SXL, // 0x300
+            misa: MISA,                         // 0x301
+            medeleg: 0,                         // 0x302
+            mideleg: 0,                         // 0x303
+            mie: 0,                             // 0x304
+            mtvec: 0,                           // 0x305
+            mcounteren: 0,                      // 0x306
+            miselect: 0,                        // 0x350
+            mstatush: 0,                        // 0x310
+            mscratch: 0,                        // 0x340
+            mepc: 0,                            // 0x341
+            mcause: 0,                          // 0x342
+            mtval: 0,                           // 0x343
+            mip: 0,                             // 0x344
+            seip_line: false,                   // 0x344
+            mtinst: 0,                          // 0x34A
+            mtval2: 0,                          // 0x34B
+            menvcfg: 0,                         // 0x30A
+            menvcfgh: 0,                        // 0x31A
+            mseccfg: 0,                         // 0x747
+            mseccfgh: 0,                        // 0x757
+            pmpcfg0: 0,                         // 0x3A0
+            pmpcfg1: 0,                         // 0x3A1
+            pmpcfg2: 0,                         // 0x3A2
+            pmpcfg3: 0,                         // 0x3A3
+            pmpcfg4: 0,                         // 0x3A4
+            pmpcfg5: 0,                         // 0x3A5
+            pmpcfg6: 0,                         // 0x3A6
+            pmpcfg7: 0,                         // 0x3A7
+            pmpcfg8: 0,                         // 0x3A8
+            pmpcfg9: 0,                         // 0x3A9
+            pmpcfg10: 0,                        // 0x3AA
+            pmpcfg11: 0,                        // 0x3AB
+            pmpcfg12: 0,                        // 0x3AC
+            pmpcfg13: 0,                        // 0x3AD
+            pmpcfg14: 0,                        // 0x3AE
+            pmpcfg15: 0,                        // 0x3AF
+            pmpaddr0: 0,                        // 0x3B0
+            pmpaddr1: 0,                        // 0x3B1
+            pmpaddr2: 0,                        // 0x3B2
+            pmpaddr3: 0,                        // 0x3B3
+            pmpaddr4: 0,                        // 0x3B4
+            pmpaddr5: 0,                        // 0x3B5
+            pmpaddr6: 0,                        // 0x3B6
+            pmpaddr7: 0,                        // 0x3B7
+            pmpaddr8: 0,                        // 0x3B8
+            pmpaddr9: 0,                        // 0x3B9
+            pmpaddr10: 0,                       // 0x3BA
+            pmpaddr11: 0,                       // 0x3BB
+            pmpaddr12: 0,                       // 0x3BC
+            pmpaddr13: 0,                       // 0x3BD
+            pmpaddr14: 0,                       // 0x3BE
+            pmpaddr15: 0,                       // 0x3BF
+            pmpaddr16: 0,                       // 0x3C0
+            pmpaddr17: 0,                       // 0x3C1
+            pmpaddr18: 0,                       // 0x3C2
+            pmpaddr19: 0,                       // 0x3C3
+            pmpaddr20: 0,                       // 0x3C4
+            pmpaddr21: 0,                       // 0x3C5
+            pmpaddr22: 0,                       // 0x3C6
+            pmpaddr23: 0,                       // 0x3C7
+            pmpaddr24: 0,                       // 0x3C8
+            pmpaddr25: 0,                       // 0x3C9
+            pmpaddr26: 0,                       // 0x3CA
+            pmpaddr27: 0,                       // 0x3CB
+            pmpaddr28: 0,                       // 0x3CC
+            pmpaddr29: 0,                       // 0x3CD
+            pmpaddr30: 0,                       // 0x3CE
+            pmpaddr31: 0,                       // 0x3CF
+            pmpaddr32: 0,                       // 0x3D0
+            pmpaddr33: 0,                       // 0x3D1
+            pmpaddr34: 0,                       // 0x3D2
+            pmpaddr35: 0,                       // 0x3D3
+            pmpaddr36: 0,                       // 0x3D4
+            pmpaddr37: 0,                       // 0x3D5
+            pmpaddr38: 0,                       // 0x3D6
+            pmpaddr39: 0,                       // 0x3D7
+            pmpaddr40: 0,                       // 0x3D8
+            pmpaddr41: 0,                       // 0x3D9
+            pmpaddr42: 0,                       // 0x3DA
+            pmpaddr43: 0,                       // 0x3DB
+            pmpaddr44: 0,                       // 0x3DC
+            pmpaddr45: 0,                       // 0x3DD
+            pmpaddr46: 0,                       // 0x3DE
+            pmpaddr47: 0,                       // 0x3DF
+            pmpaddr48: 0,                       // 0x3E0
+            pmpaddr49: 0,                       // 0x3E1
+            pmpaddr50: 0,                       // 0x3E2
+            pmpaddr51: 0,                       // 0x3E3
+            pmpaddr52: 0,                       // 0x3E4
+            pmpaddr53: 0,                       // 0x3E5
+            pmpaddr54: 0,                       // 0x3E6
+            pmpaddr55: 0,                       // 0x3E7
+            pmpaddr56: 0,                       // 0x3E8
+            pmpaddr57: 0,                       // 0x3E9
+            pmpaddr58: 0,                       // 0x3EA
+            pmpaddr59: 0,                       // 0x3EB
+            pmpaddr60: 0,                       // 0x3EC
+            pmpaddr61: 0,                       // 0x3ED
+            pmpaddr62: 0,                       // 0x3EE
+            pmpaddr63: 0,                       // 0x3EF
+
+            // counters
+            mcountinhibit: 0,          // 0x320
+            mhpmevent: [0; HPM_NUM],   // 0x323 - 0x33F
+            mcycle: 0,                 // 0xB00
+            minstret: 0,               // 0xB02
+            mhpmcounter: [0; HPM_NUM], // 0xB03 - 0xB1F
         }
     }
 
@@ -352,24 +579,73 @@ impl Register {
         non_zero_print("t6", "0x01F", self.t6, &mut zero_ls);
         non_zero_print("pc", "0x020", self.pc, &mut zero_ls);
 
+        non_zero_print("ft0", "f0", self.ft0, &mut zero_ls);
+        non_zero_print("ft1", "f1", self.ft1, &mut zero_ls);
+        non_zero_print("ft2", "f2", self.ft2, &mut zero_ls);
+        non_zero_print("ft3", "f3", self.ft3, &mut zero_ls);
+        non_zero_print("ft4", "f4", self.ft4, &mut zero_ls);
+        non_zero_print("ft5", "f5", self.ft5, &mut zero_ls);
+        non_zero_print("ft6", "f6", self.ft6, &mut zero_ls);
+        non_zero_print("ft7", "f7", self.ft7, &mut zero_ls);
+        non_zero_print("fs0", "f8", self.fs0, &mut zero_ls);
+        non_zero_print("fs1", "f9", self.fs1, &mut zero_ls);
+        non_zero_print("fa0", "f10", self.fa0, &mut zero_ls);
+        non_zero_print("fa1", "f11", self.fa1, &mut zero_ls);
+        non_zero_print("fa2", "f12", self.fa2, &mut zero_ls);
+        non_zero_print("fa3", "f13", self.fa3, &mut zero_ls);
+        non_zero_print("fa4", "f14", self.fa4, &mut zero_ls);
+        non_zero_print("fa5", "f15", self.fa5, &mut zero_ls);
+        non_zero_print("fa6", "f16", self.fa6, &mut zero_ls);
+        non_zero_print("fa7", "f17", self.fa7, &mut zero_ls);
+        non_zero_print("fs2", "f18", self.fs2, &mut zero_ls);
+        non_zero_print("fs3", "f19", self.fs3, &mut zero_ls);
+        non_zero_print("fs4", "f20", self.fs4, &mut zero_ls);
+        non_zero_print("fs5", "f21", self.fs5, &mut zero_ls);
+        non_zero_print("fs6", "f22", self.fs6, &mut zero_ls);
+        non_zero_print("fs7", "f23", self.fs7, &mut zero_ls);
+        non_zero_print("fs8", "f24", self.fs8, &mut zero_ls);
+        non_zero_print("fs9", "f25", self.fs9, &mut zero_ls);
+        non_zero_print("fs10", "f26", self.fs10, &mut zero_ls);
+        non_zero_print("fs11", "f27", self.fs11, &mut zero_ls);
+        non_zero_print("ft8", "f28", self.ft8, &mut zero_ls);
+        non_zero_print("ft9", "f29", self.ft9, &mut zero_ls);
+        non_zero_print("ft10", "f30", self.ft10, &mut zero_ls);
+        non_zero_print("ft11", "f31", self.ft11, &mut zero_ls);
+        non_zero_print("fcsr", "0x003", self.fcsr, &mut zero_ls);
+        non_zero_print("vstart", "0x008", self.vstart, &mut zero_ls);
+        non_zero_print("vcsr", "0x00F", self.get_csr(0x00F), &mut zero_ls);
+        non_zero_print("vl", "0xC20", self.vl, &mut zero_ls);
+        non_zero_print("vtype", "0xC21", self.vtype, &mut zero_ls);
+
         non_zero_print("mstatus", "0x300", self.mstatus, &mut zero_ls);
         non_zero_print("medeleg", "0x302", self.medeleg, &mut zero_ls);
         non_zero_print("mideleg", "0x303", self.mideleg, &mut zero_ls);
         non_zero_print("mie", "0x304", self.mie, &mut zero_ls);
         non_zero_print("mscratch", "0x340", self.mscratch, &mut zero_ls);
         non_zero_print("mepc", "0x341", self.mepc, &mut zero_ls);
-        non_zero_print("mip", "0x344", self.mip, &mut zero_ls);
+        non_zero_print("mip", "0x344", self.read_mip(), &mut zero_ls);
         non_zero_print("mtvec", "0x3B0", self.mtvec, &mut zero_ls);
+        non_zero_print("mcycle", "0xB00", self.mcycle, &mut zero_ls);
+        non_zero_print("minstret", "0xB02", self.minstret, &mut zero_ls);
 
-        non_zero_print("sstatus", "0x100", self.sstatus, &mut zero_ls);
-        non_zero_print("sie", "0x104", self.sie, &mut zero_ls);
+        non_zero_print("sstatus", "0x100", self.get_csr(0x100), &mut zero_ls);
+        non_zero_print("sie", "0x104", self.get_csr(0x104), &mut zero_ls);
         non_zero_print("stvec", "0x105", self.stvec, &mut zero_ls);
         non_zero_print("sscratch", "0x140", self.sscratch, &mut zero_ls);
         non_zero_print("sepc", "0x141", self.sepc, &mut zero_ls);
         non_zero_print("scause", "0x142", self.scause, &mut zero_ls);
-        non_zero_print("sip", "0x144", self.sip, &mut zero_ls);
+        non_zero_print("sip", "0x144", self.get_csr(0x144), &mut zero_ls);
+        non_zero_print("stimecmp", "0x14D", self.stimecmp, &mut zero_ls);
         non_zero_print("satp", "0x180", self.satp, &mut zero_ls);
 
+        non_zero_print("hstatus", "0x600", self.hstatus, &mut zero_ls);
+        non_zero_print("hgatp", "0x680", self.hgatp, &mut zero_ls);
+        non_zero_print("vsstatus", "0x200", self.vsstatus, &mut zero_ls);
+        non_zero_print("vsepc", "0x241", self.vsepc, &mut zero_ls);
+        non_zero_print("vscause", "0x242", self.vscause, &mut zero_ls);
+        non_zero_print("vstimecmp", "0x24D", self.vstimecmp, &mut zero_ls);
+        non_zero_print("vsatp", "0x280", self.vsatp, &mut zero_ls);
+
         non_zero_print("pmpaddr0", "0x3B0", self.pmpaddr0, &mut zero_ls);
         non_zero_print("pmpcfg0", "0x3A0", self.pmpcfg0, &mut zero_ls);
 
@@ -414,22 +690,248 @@ impl Register {
         }
     }
 
-    pub fn get_csr(&self, reg: u16) -> u64 {
+    /// mstatus.FS is Off, or vsstatus.FS with V=1. Floating-point instructions and CSRs are illegal.
+    pub fn fs_off(&self) -> bool {
+        self.mstatus & MSTATUS_FS == 0 || (self.virt && self.vsstatus & MSTATUS_FS == 0)
+    }
+
+    /// mstatus.FS = Dirty, and vsstatus.FS = Dirty with V=1
+    pub fn set_fs_dirty(&mut self) {
+        self.mstatus |= MSTATUS_FS | MSTATUS_SD;
+        if self.virt {
+            self.vsstatus |= MSTATUS_FS | MSTATUS_SD;
+        }
+    }
+
+    /// Advance mcycle by a cycle, and minstret when an instruction retired.
+    /// mcountinhibit stops the counters.
+    pub fn tick(&mut self, retired: bool) {
+        if self.mcountinhibit & MCOUNTINHIBIT_CY == 0 {
+            self.mcycle = self.mcycle.wrapping_add(1);
+        }
+        if retired && self.mcountinhibit & MCOUNTINHIBIT_IR == 0 {
+            self.minstret = self.minstret.wrapping_add(1);
+        }
+    }
+
+    /// Increment the mhpmcounters selected by their mhpmevent for `event`.
+    pub fn count_event(&mut self, event: u64) {
+        for i in 0..HPM_NUM {
+            let inhibited = (self.mcountinhibit >> (i + 3)) & 1 == 1;
+            if self.mhpmevent[i] == event && !inhibited {
+                self.mhpmcounter[i] = self.mhpmcounter[i].wrapping_add(1);
+            }
+        }
+    }
+
+    /// Writes the bits of `mask` in mstatus. Shared by mstatus and sstatus.
+    /// MPP=2 is reserved, and the previous MPP is kept.
+    /// SD is read-only and summarizes FS, VS and XS.
+    fn set_mstatus(&mut self, mask: u64, value: u64) {
+        let mut mask = mask;
+        if value & MSTATUS_MPP == 0b10 << 11 {
+            mask &= !MSTATUS_MPP;
+        }
+        self.mstatus = summarize_dirty(masked_write(self.mstatus, mask, value));
+    }
+
+    /// mstatus.VS is Off, or vsstatus.VS with V=1. Vector instructions and CSRs are illegal.
+    pub fn vs_off(&self) -> bool {
+        self.mstatus & MSTATUS_VS == 0 || (self.virt && self.vsstatus & MSTATUS_VS == 0)
+    }
+
+    /// mstatus.VS = Dirty, and vsstatus.VS = Dirty with V=1
+    pub fn set_vs_dirty(&mut self) {
+        self.mstatus |= MSTATUS_VS | MSTATUS_SD;
+        if self.virt {
+            self.vsstatus |= MSTATUS_VS | MSTATUS_SD;
+        }
+    }
+
+    /// misa.C is clear. Jumps and branches need targets aligned to 4 bytes.
+    pub fn c_off(&self) -> bool {
+        self.misa & MISA_C == 0
+    }
+
+    /// mip as read by CSR instructions and seen by the interrupt selection.
+    /// SEIP is the OR of the software-writable bit and the interrupt line.
+    pub fn read_mip(&self) -> u64 {
+        if self.seip_line {
+            self.mip | MIP_SEIP
+        } else {
+            self.mip
+        }
+    }
+
+    /// menvcfg.STCE. stimecmp raises mip.STIP, which is then read-only.
+    pub fn stce(&self) -> bool {
+        self.menvcfg & ENVCFG_STCE != 0
+    }
+
+    /// henvcfg.STCE. vstimecmp raises mip.VSTIP. Read-only zero without menvcfg.STCE.
+    pub fn vstce(&self) -> bool {
+        self.stce() && self.henvcfg & ENVCFG_STCE != 0
+    }
+
+    /// menvcfg.PBMTE. Enables PBMT for the page tables of satp and hgatp.
+    pub fn pbmte(&self) -> bool {
+        self.menvcfg & ENVCFG_PBMTE != 0
+    }
+
+    /// henvcfg.PBMTE. Enables PBMT for the page tables of vsatp. Read-only zero without menvcfg.PBMTE.
+    pub fn vs_pbmte(&self) -> bool {
+        self.pbmte() && self.henvcfg & ENVCFG_PBMTE != 0
+    }
+
+    /// Supervisor csr accessed with V=1 are substituted by the virtual supervisor csr.
+    ///   sstatus, sie, stvec, sscratch, sepc, scause, stval, sip, stimecmp, satp -- 0x100 higher
+    fn virt_csr(&self, csr: u16) -> u16 {
+        match csr {
+            0x100 | 0x104 | 0x105 | 0x140..=0x144 | 0x14D | 0x180 if self.virt => csr + 0x100,
+            _ => csr,
+        }
+    }
+
+    /// pmpcfg of the PMP entry `i` (0 - 63).
+    /// RV64 has only the even numbered pmpcfg registers, each holds 8 entries.
+    pub fn pmpcfg(&self, i: usize) -> u8 {
+        let pmpcfg = self.get_csr(0x3A0 + (i / 8 * 2) as u16);
+        (pmpcfg >> ((i % 8) * 8)) as u8
+    }
+
+    /// pmpaddr of the PMP entry `i` (0 - 63). bits 2..55 of the address.
+    pub fn pmpaddr(&self, i: usize) -> u64 {
+        self.get_csr(0x3B0 + i as u16) & PMPADDR_MASK
+    }
+
+    /// pmpaddr `i` is locked by its own entry, or by the next TOR entry.
+    fn pmpaddr_locked(&self, i: usize) -> bool {
+        let locked = |cfg: u8| cfg & PMP_L != 0;
+        let tor = |cfg: u8| (cfg >> 3) & 0b11 == 1;
+        locked(self.pmpcfg(i)) || (i < 63 && locked(self.pmpcfg(i + 1)) && tor(self.pmpcfg(i + 1)))
+    }
+
+    /// Writes to a locked entry are ignored.
+    /// W without R is reserved, and W is cleared.
+    fn pmpcfg_warl(&self, reg: u16, value: u64) -> u64 {
+        let old = self.get_csr(reg);
+        let mut res = 0;
+        for j in 0..8 {
+            let old_cfg = (old >> (j * 8)) as u8;
+            let mut cfg = (value >> (j * 8)) as u8;
+            if old_cfg & PMP_L != 0 {
+                cfg = old_cfg;
+            } else if cfg & 0b11 == 0b10 {
+                cfg &= !0b10;
+            }
+            res |= (cfg as u64) << (j * 8);
+        }
+        res
+    }
+
+    pub fn get_freg(&self, reg: u8) -> u64 {
         match reg {
+            0b0_0000 => self.ft0,
+            0b0_0001 => self.ft1,
+            0b0_0010 => self.ft2,
+            0b0_0011 => self.ft3,
+            0b0_0100 => self.ft4,
+            0b0_0101 => self.ft5,
+            0b0_0110 => self.ft6,
+            0b0_0111 => self.ft7,
+            0b0_1000 => self.fs0,
+            0b0_1001 => self.fs1,
+            0b0_1010 => self.fa0,
+            0b0_1011 => self.fa1,
+            0b0_1100 => self.fa2,
+            0b0_1101 => self.fa3,
+            0b0_1110 => self.fa4,
+            0b0_1111 => self.fa5,
+            0b1_0000 => self.fa6,
+            0b1_0001 => self.fa7,
+            0b1_0010 => self.fs2,
+            0b1_0011 => self.fs3,
+            0b1_0100 => self.fs4,
+            0b1_0101 => self.fs5,
+            0b1_0110 => self.fs6,
+            0b1_0111 => self.fs7,
+            0b1_1000 => self.fs8,
+            0b1_1001 => self.fs9,
+            0b1_1010 => self.fs10,
+            0b1_1011 => self.fs11,
+            0b1_1100 => self.ft8,
+            0b1_1101 => self.ft9,
+            0b1_1110 => self.ft10,
+            0b1_1111 => self.ft11,
+            _ => panic!("invalid register"),
+        }
+    }
+
+    pub fn get_csr(&self, reg: u16) -> u64 {
+        match self.virt_csr(reg) {
+            // floating-point csr
+            0x001 => self.fcsr & 0x1F,
+            0x002 => (self.fcsr >> 5) & 0x7,
+            0x003 => self.fcsr & 0xFF,
+
+            // vector csr
+            0x008 => self.vstart,
+            0x009 => self.vxsat,
+            0x00A => self.vxrm,
+            0x00F => (self.vxrm << 1) | self.vxsat,
+            0xC20 => self.vl,
+            0xC21 => self.vtype,
+            0xC22 => self.vlenb,
+
             // supervisor-level csr
-            0x100 => self.sstatus,
-            0x104 => self.sie,
+            // sstatus, sie and sip are restricted views of mstatus, mie and mip
+            0x100 => self.mstatus & SSTATUS_RMASK,
+            0x104 => self.mie & self.mideleg & MIDELEG_WMASK,
             0x105 => self.stvec,
             0x106 => self.scounteren,
             0x10A => self.senvcfg,
+            0x150 => self.siselect,
             0x140 => self.sscratch,
             0x141 => self.sepc,
             0x142 => self.scause,
             0x143 => self.stval,
-            0x144 => self.sip,
+            0x144 => self.read_mip() & self.mideleg & MIDELEG_WMASK,
+            0x14D => self.stimecmp,
             0x180 => self.satp,
             0x5A8 => self.scontext,
 
+            // hypervisor csr
+            // hie, hip and hvip are views of the VS-level bits of mie and mip
+            0x600 => self.hstatus,
+            0x602 => self.hedeleg,
+            0x603 => self.hideleg,
+            0x604 => self.mie & MIDELEG_RO,
+            0x605 => self.htimedelta,
+            0x606 => self.hcounteren,
+            0x607 => 0, // hgeie. No guest external interrupts.
+            // STCE and PBMTE are read-only zero without those of menvcfg
+            0x60A => self.henvcfg & (self.menvcfg | !(ENVCFG_STCE | ENVCFG_PBMTE)),
+            0x643 => self.htval,
+            0x644 => self.mip & MIDELEG_RO,
+            0x645 if self.hvip_vstip => self.mip & MIP_VS | MIP_VSTIP,
+            0x645 => self.mip & MIP_VS & !MIP_VSTIP,
+            0x64A => self.htinst,
+            0x680 => self.hgatp,
+            0xE12 => 0, // hgeip
+
+            // virtual supervisor csr
+            // vsie and vsip show the VS-level bits delegated by hideleg at the positions of the S-level bits
+            0x200 => self.vsstatus,
+            0x204 => (self.mie & self.hideleg & MIP_VS) >> 1,
+            0x205 => self.vstvec,
+            0x240 => self.vsscratch,
+            0x241 => self.vsepc,
+            0x242 => self.vscause,
+            0x243 => self.vstval,
+            0x244 => (self.mip & self.hideleg & MIP_VS) >> 1,
+            0x24D => self.vstimecmp,
+            0x280 => self.vsatp,
+
             // machine-level csr
             0xF11 => self.mvendorid,
             0xF12 => self.marchid,
@@ -439,22 +941,33 @@ impl Register {
             0x300 => self.mstatus,
             0x301 => self.misa,
             0x302 => self.medeleg,
-            0x303 => self.mideleg,
+            0x303 => self.mideleg | MIDELEG_RO,
             0x304 => self.mie,
             0x305 => self.mtvec,
             0x306 => self.mcounteren,
+            0x350 => self.miselect,
             0x310 => self.mstatush,
             0x340 => self.mscratch,
             0x341 => self.mepc,
             0x342 => self.mcause,
             0x343 => self.mtval,
-            0x344 => self.mip,
+            0x344 => self.read_mip(),
             0x34A => self.mtinst,
             0x34B => self.mtval2,
             0x30A => self.menvcfg,
             0x31A => self.menvcfgh,
             0x747 => self.mseccfg,
             0x757 => self.mseccfgh,
+            0x320 => self.mcountinhibit,
+            0x323..=0x33F => self.mhpmevent[(reg - 0x323) as usize],
+            0xB00 => self.mcycle,
+            0xB02 => self.minstret,
+            0xB03..=0xB1F => self.mhpmcounter[(reg - 0xB03) as usize],
+
+            // unprivileged counters. time (0xC01) is memory mapped and read by the Cpu.
+            0xC00 => self.mcycle,
+            0xC02 => self.minstret,
+            0xC03..=0xC1F => self.mhpmcounter[(reg - 0xC03) as usize],
             0x3A0 => self.pmpcfg0,
             0x3A1 => self.pmpcfg1,
             0x3A2 => self.pmpcfg2,
@@ -536,7 +1049,8 @@ impl Register {
             0x3EE => self.pmpaddr62,
             0x3EF => self.pmpaddr63,
 
-            _ => panic!("invalid register"),
+            // check_csr raises an illegal instruction exception for the others
+            _ => unreachable!("unimplemented csr 0x{:03X}", reg),
         }
     }
 
@@ -578,47 +1092,189 @@ impl Register {
         }
     }
 
-    pub fn set_csr(&mut self, reg: u16, value: u64) {
+    pub fn set_freg(&mut self, reg: u8, value: u64) {
         match reg {
+            0b0_0000 => self.ft0 = value,
+            0b0_0001 => self.ft1 = value,
+            0b0_0010 => self.ft2 = value,
+            0b0_0011 => self.ft3 = value,
+            0b0_0100 => self.ft4 = value,
+            0b0_0101 => self.ft5 = value,
+            0b0_0110 => self.ft6 = value,
+            0b0_0111 => self.ft7 = value,
+            0b0_1000 => self.fs0 = value,
+            0b0_1001 => self.fs1 = value,
+            0b0_1010 => self.fa0 = value,
+            0b0_1011 => self.fa1 = value,
+            0b0_1100 => self.fa2 = value,
+            0b0_1101 => self.fa3 = value,
+            0b0_1110 => self.fa4 = value,
+            0b0_1111 => self.fa5 = value,
+            0b1_0000 => self.fa6 = value,
+            0b1_0001 => self.fa7 = value,
+            0b1_0010 => self.fs2 = value,
+            0b1_0011 => self.fs3 = value,
+            0b1_0100 => self.fs4 = value,
+            0b1_0101 => self.fs5 = value,
+            0b1_0110 => self.fs6 = value,
+            0b1_0111 => self.fs7 = value,
+            0b1_1000 => self.fs8 = value,
+            0b1_1001 => self.fs9 = value,
+            0b1_1010 => self.fs10 = value,
+            0b1_1011 => self.fs11 = value,
+            0b1_1100 => self.ft8 = value,
+            0b1_1101 => self.ft9 = value,
+            0b1_1110 => self.ft10 = value,
+            0b1_1111 => self.ft11 = value,
+            _ => panic!("invalid register"),
+        }
+    }
+
+    pub fn set_csr(&mut self, reg: u16, value: u64) {
+        let value = match reg {
+            0x3A0..=0x3AF => self.pmpcfg_warl(reg, value),
+            0x3B0..=0x3EF if self.pmpaddr_locked(reg as usize - 0x3B0) => return,
+            _ => value,
+        };
+        match self.virt_csr(reg) {
+            // floating-point csr
+            0x001 => {
+                self.fcsr = (self.fcsr & !0x1F) | (value & 0x1F);
+                self.set_fs_dirty();
+            }
+            0x002 => {
+                self.fcsr = (self.fcsr & !0xE0) | ((value & 0x7) << 5);
+                self.set_fs_dirty();
+            }
+            0x003 => {
+                self.fcsr = value & 0xFF;
+                self.set_fs_dirty();
+            }
+
+            // vector csr
+            0x008 => {
+                // enough bits to hold the largest element index (VLMAX = VLEN with SEW=8, LMUL=8)
+                self.vstart = value & (self.vlenb * 8 - 1);
+                self.set_vs_dirty();
+            }
+            0x009 => {
+                self.vxsat = value & 0b1;
+                self.set_vs_dirty();
+            }
+            0x00A => {
+                self.vxrm = value & 0b11;
+                self.set_vs_dirty();
+            }
+            0x00F => {
+                self.vxsat = value & 0b1;
+                self.vxrm = (value >> 1) & 0b11;
+                self.set_vs_dirty();
+            }
+
             // supervisor-level csr
-            0x100 => self.sstatus = value,
-            0x104 => self.sie = value,
+            0x100 => self.set_mstatus(SSTATUS_WMASK, value),
+            0x104 => self.mie = masked_write(self.mie, self.mideleg & MIE_WMASK, value),
             0x105 => self.stvec = value,
-            0x106 => self.scounteren = value,
-            0x10A => self.senvcfg = value,
+            0x106 => self.scounteren = value & 0xFFFF_FFFF,
+            0x10A => self.senvcfg = legalize_envcfg(value),
+            0x150 => self.siselect = value & ISELECT_MASK,
             0x140 => self.sscratch = value,
             0x141 => self.sepc = value,
             0x142 => self.scause = value,
             0x143 => self.stval = value,
-            0x144 => self.sip = value,
-            0x180 => self.satp = value,
+            // only SSIP is writable through sip
+            0x144 => self.mip = masked_write(self.mip, self.mideleg & MIP_SSIP, value),
+            0x14D => self.stimecmp = value,
+            0x180 => {
+                // Bare, Sv39, Sv48 and Sv57
+                if let 0 | 8 | 9 | 10 = value >> 60 {
+                    self.satp = value;
+                }
+            }
             0x5A8 => self.scontext = value,
 
+            // hypervisor csr
+            0x600 => self.hstatus = masked_write(self.hstatus, HSTATUS_WMASK, value),
+            0x602 => self.hedeleg = value & HEDELEG_WMASK,
+            0x603 => self.hideleg = value & MIP_VS,
+            0x604 => self.mie = masked_write(self.mie, MIDELEG_RO, value),
+            0x605 => self.htimedelta = value,
+            0x606 => self.hcounteren = value & 0xFFFF_FFFF,
+            0x607 => (),
+            0x60A => self.henvcfg = legalize_envcfg(value),
+            0x643 => self.htval = value,
+            // only VSSIP is writable through hip
+            0x644 => self.mip = masked_write(self.mip, MIP_VSSIP, value),
+            // vstimecmp may raise mip.VSTIP again on the next timer update
+            0x645 => {
+                self.mip = masked_write(self.mip, MIP_VS, value);
+                self.hvip_vstip = value & MIP_VSTIP != 0;
+            }
+            0x64A => self.htinst = value,
+            0x680 => {
+                // Bare, Sv39x4, Sv48x4 and Sv57x4
+                if let 0 | 8 | 9 | 10 = value >> 60 {
+                    self.hgatp = value & HGATP_WMASK;
+                }
+            }
+
+            // virtual supervisor csr
+            0x200 => {
+                self.vsstatus = summarize_dirty(masked_write(self.vsstatus, SSTATUS_WMASK, value))
+            }
+            0x204 => self.mie = masked_write(self.mie, self.hideleg & MIP_VS, value << 1),
+            0x205 => self.vstvec = value,
+            0x240 => self.vsscratch = value,
+            0x241 => self.vsepc = value,
+            0x242 => self.vscause = value,
+            0x243 => self.vstval = value,
+            // only SSIP (VSSIP) is writable through vsip
+            0x244 => self.mip = masked_write(self.mip, self.hideleg & MIP_VSSIP, value << 1),
+            0x24D => self.vstimecmp = value,
+            0x280 => {
+                if let 0 | 8 | 9 | 10 = value >> 60 {
+                    self.vsatp = value;
+                }
+            }
+
             // machine-level csr
             0xF11 => self.mvendorid = value,
             0xF12 => self.marchid = value,
             0xF13 => self.mimpid = value,
             0xF14 => self.mhartid = value,
             0xF15 => self.mconfigptr = value,
-            0x300 => self.mstatus = value,
+            0x300 => self.set_mstatus(MSTATUS_WMASK, value),
             0x301 => self.misa = value,
-            0x302 => self.medeleg = value,
-            0x303 => self.mideleg = value,
-            0x304 => self.mie = value,
+            0x302 => self.medeleg = value & MEDELEG_WMASK,
+            0x303 => self.mideleg = value & MIDELEG_WMASK,
+            0x304 => self.mie = masked_write(self.mie, MIE_WMASK, value),
             0x305 => self.mtvec = value,
-            0x306 => self.mcounteren = value,
+            0x306 => self.mcounteren = value & 0xFFFF_FFFF,
+            0x350 => self.miselect = value & ISELECT_MASK,
             0x310 => self.mstatush = value,
             0x340 => self.mscratch = value,
             0x341 => self.mepc = value,
             0x342 => self.mcause = value,
             0x343 => self.mtval = value,
-            0x344 => self.mip = value,
+            // STIP is read-only while stimecmp raises it
+            0x344 if self.stce() => self.mip = masked_write(self.mip, MIP_WMASK & !MIP_STIP, value),
+            0x344 => self.mip = masked_write(self.mip, MIP_WMASK, value),
             0x34A => self.mtinst = value,
             0x34B => self.mtval2 = value,
-            0x30A => self.menvcfg = value,
+            0x30A => self.menvcfg = legalize_envcfg(value),
             0x31A => self.menvcfgh = value,
             0x747 => self.mseccfg = value,
             0x757 => self.mseccfgh = value,
+            // bit 1 (time) is read-only zero
+            0x320 => self.mcountinhibit = value & 0xFFFF_FFFD,
+            // unsupported events are WARL and read as 0 (no event)
+            0x323..=0x33F if value <= HPM_EVENT_MAX => {
+                self.mhpmevent[(reg - 0x323) as usize] = value
+            }
+            0x323..=0x33F => self.mhpmevent[(reg - 0x323) as usize] = 0,
+            0xB00 => self.mcycle = value,
+            0xB02 => self.minstret = value,
+            0xB03..=0xB1F => self.mhpmcounter[(reg - 0xB03) as usize] = value,
             0x3A0 => self.pmpcfg0 = value,
             0x3A1 => self.pmpcfg1 = value,
             0x3A2 => self.pmpcfg2 = value,
@@ -700,7 +1356,8 @@ impl Register {
             0x3EE => self.pmpaddr62 = value,
             0x3EF => self.pmpaddr63 = value,
 
-            _ => panic!("invalid register"),
+            // check_csr raises an illegal instruction exception for the others
+            _ => unreachable!("unimplemented csr 0x{:03X}", reg),
         }
     }
 }
@@ -718,3 +1375,48 @@ fn non_zero_print(reg_name: &str, reg_code: &str, value: u64, zero_ls: &mut Vec<
     }
     println!("{}0x{:016X}, 0b{:064b}", reg_name, value, value);
 }
+
+/// The CSR is implemented. Accessing any other raises an illegal instruction exception,
+/// which is how software probes for optional extensions.
+pub fn csr_exists(csr: u16) -> bool {
+    matches!(
+        csr,
+        // floating-point and vector
+        0x001..=0x003 | 0x008..=0x00A | 0x00F | 0xC20..=0xC22
+        // supervisor, including sireg and stopei
+        | 0x100 | 0x104..=0x106 | 0x10A | 0x140..=0x144 | 0x14D | 0x150 | 0x151 | 0x15C | 0x180
+        | 0x5A8
+        // hypervisor and virtual supervisor
+        | 0x600 | 0x602..=0x607 | 0x60A | 0x643..=0x645 | 0x64A | 0x680 | 0xE12
+        | 0x200 | 0x204 | 0x205 | 0x240..=0x244 | 0x24D | 0x280
+        // machine, including mireg and mtopei
+        | 0xF11..=0xF15 | 0x300..=0x306 | 0x30A | 0x310 | 0x31A | 0x320 | 0x323..=0x33F
+        | 0x340..=0x344 | 0x34A | 0x34B | 0x350 | 0x351 | 0x35C | 0x3A0..=0x3EF | 0x747 | 0x757
+        | 0xB00 | 0xB02..=0xB1F
+        // cycle, time, instret and hpmcounters
+        | 0xC00..=0xC1F
+    )
+}
+
+/// Replaces the bits of `old` selected by `mask` with those of `value`.
+fn masked_write(old: u64, mask: u64, value: u64) -> u64 {
+    (old & !mask) | (value & mask)
+}
+
+/// CBIE is WARL, and the reserved value 0b10 is written as 0b00.
+fn legalize_envcfg(value: u64) -> u64 {
+    if value & ENVCFG_CBIE == 0b10_0000 {
+        return value & !ENVCFG_CBIE;
+    }
+    value
+}
+
+/// Set SD of mstatus or vsstatus when FS, VS or XS is Dirty, and clear it otherwise.
+fn summarize_dirty(status: u64) -> u64 {
+    let dirty = |field: u64| status & field == field;
+    if dirty(MSTATUS_FS) || dirty(MSTATUS_VS) || dirty(MSTATUS_XS) {
+        status | MSTATUS_SD
+    } else {
+        status & !MSTATUS_SD
+    }
+}
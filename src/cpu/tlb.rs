@@ -0,0 +1,93 @@
+// Translation lookaside buffer.
+// Caches leaf PTEs of the page table walk, keyed by the virtual page number and ASID.
+// Entries are flushed by sfence.vma and by writes to satp.
+
+use std::collections::HashMap;
+
+const CAPACITY: usize = 4096;
+const GLOBAL: u32 = 0x1_0000; // ASID of global mappings (PTE.G). matches every ASID.
+const VPN_SIZE: u64 = 9; // bit
+const PTE_N: u64 = 1 << 63; // NAPOT translation (Svnapot)
+const NAPOT_64K_SIZE: u64 = 4; // bit. a 64 KiB NAPOT page covers 16 pages
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TlbEntry {
+    pub ppn: u64,   // physical page number of the 4 KiB page
+    pub pte: u64,   // the leaf PTE
+    pub level: u64, // 0: 4 KiB page, 1: 2 MiB megapage, 2: 1 GiB gigapage, ...
+}
+
+#[derive(Debug)]
+pub struct Tlb {
+    entries: HashMap<(u64, u32), TlbEntry>,
+    pub hit: u64,
+    pub miss: u64,
+}
+
+impl Tlb {
+    pub fn new() -> Tlb {
+        Tlb {
+            entries: HashMap::new(),
+            hit: 0,
+            miss: 0,
+        }
+    }
+
+    pub fn lookup(&self, vpn: u64, asid: u16) -> Option<TlbEntry> {
+        self.entries
+            .get(&(vpn, asid as u32))
+            .or_else(|| self.entries.get(&(vpn, GLOBAL)))
+            .copied()
+    }
+
+    pub fn insert(&mut self, vpn: u64, asid: u16, global: bool, entry: TlbEntry) {
+        if self.entries.len() >= CAPACITY {
+            self.entries.clear();
+        }
+        let asid = if global { GLOBAL } else { asid as u32 };
+        self.entries.insert((vpn, asid), entry);
+    }
+
+    /// sfence.vma
+    ///   vpn -- None flushes every virtual address. Some flushes the page containing it.
+    ///   asid -- None flushes every address space. Some flushes the address space except global mappings.
+    pub fn flush(&mut self, vpn: Option<u64>, asid: Option<u16>) {
+        if vpn.is_none() && asid.is_none() {
+            self.entries.clear();
+            return;
+        }
+        self.entries.retain(|(key_vpn, key_asid), e| {
+            // a superpage covers 512^level pages
+            let shift = if e.pte & PTE_N != 0 {
+                NAPOT_64K_SIZE
+            } else {
+                VPN_SIZE * e.level
+            };
+            let vpn_match = match vpn {
+                Some(vpn) => vpn >> shift == key_vpn >> shift,
+                None => true,
+            };
+            let asid_match = match asid {
+                Some(asid) => *key_asid == asid as u32,
+                None => true,
+            };
+            !(vpn_match && asid_match)
+        });
+    }
+
+    pub fn print(&self) {
+        let total = self.hit + self.miss;
+        let rate = if total == 0 {
+            0.0
+        } else {
+            self.hit as f64 * 100.0 / total as f64
+        };
+        println!(
+            "tlb: hit: {}, miss: {}, hit rate: {:.2}%, entries: {}",
+            self.hit,
+            self.miss,
+            rate,
+            self.entries.len()
+        );
+    }
+}
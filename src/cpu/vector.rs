@@ -0,0 +1,1487 @@
+// RISC-V "V" Standard Extension for Vector Operations, version 1.0.
+// https://github.com/riscv/riscv-v-spec/blob/master/v-spec.adoc
+//
+// Element i of the register group starting at v[reg] is held at byte offset
+// reg * VLENB + i * SEW / 8 of the register file, so a register group and an element
+// wider than one register slot are just contiguous bytes.
+// Tail and inactive elements are always left undisturbed, which the agnostic policies allow.
+
+use super::fpu::{self, Precision};
+use super::instructions::{vector_eew, InstName, Instruction};
+use super::instructions::{OPFVF, OPFVV, OPIVI, OPIVV, OPIVX, OPMVV, OPMVX};
+use super::int::Exception;
+use super::Cpu;
+
+const ELEN: u64 = 64;
+
+// vxrm
+const RNU: u64 = 0b00; // round-to-nearest-up
+const RNE: u64 = 0b01; // round-to-nearest-even
+const RDN: u64 = 0b10; // round-down (truncate)
+
+const VILL: u64 = 1 << 63;
+
+#[derive(Debug)]
+pub struct VectorRegister {
+    vlenb: u64,
+    data: Vec<u8>,
+}
+
+impl VectorRegister {
+    pub fn new(vlen: u64) -> VectorRegister {
+        VectorRegister {
+            vlenb: vlen / 8,
+            data: vec![0; (vlen / 8 * 32) as usize],
+        }
+    }
+
+    /// Element `i` of `sew` bits in the register group starting at v[reg].
+    pub fn get(&self, reg: u8, i: u64, sew: u64) -> u64 {
+        let off = (reg as u64 * self.vlenb + i * sew / 8) as usize;
+        let mut v = 0;
+        for b in self.data[off..off + sew as usize / 8].iter().rev() {
+            v = v << 8 | *b as u64;
+        }
+        v
+    }
+
+    /// Write the lower `sew` bits of the value to element `i` of the register group.
+    pub fn set(&mut self, reg: u8, i: u64, sew: u64, v: u64) {
+        let off = (reg as u64 * self.vlenb + i * sew / 8) as usize;
+        for (n, b) in self.data[off..off + sew as usize / 8]
+            .iter_mut()
+            .enumerate()
+        {
+            *b = (v >> (n * 8)) as u8;
+        }
+    }
+
+    /// Mask bit `i` of v[reg].
+    pub fn mask(&self, reg: u8, i: u64) -> bool {
+        let off = (reg as u64 * self.vlenb + i / 8) as usize;
+        self.data[off] >> (i % 8) & 1 == 1
+    }
+
+    pub fn set_mask(&mut self, reg: u8, i: u64, bit: bool) {
+        let off = (reg as u64 * self.vlenb + i / 8) as usize;
+        if bit {
+            self.data[off] |= 1 << (i % 8);
+        } else {
+            self.data[off] &= !(1 << (i % 8));
+        }
+    }
+}
+
+/// vtype
+///   63   -- vill: the setting is not supported.
+///   7    -- vma: mask agnostic.
+///   6    -- vta: tail agnostic.
+///   3..5 -- vsew: SEW = 8 << vsew.
+///   0..2 -- vlmul: LMUL = 2^vlmul, sign-extended. 0b100 is reserved.
+#[derive(Debug, Clone, Copy)]
+struct VType {
+    sew: u64,
+    lmul: i64, // log2(LMUL), -3 to 3
+}
+
+impl VType {
+    /// None when the setting is not supported.
+    fn decode(vtype: u64) -> Option<VType> {
+        let vlmul = vtype & 0b111;
+        let vsew = (vtype >> 3) & 0b111;
+        if vtype >> 8 != 0 || vlmul == 0b100 || vsew > 0b011 {
+            return None;
+        }
+        let lmul = ((vlmul as i64) << 61) >> 61;
+        let sew = 8 << vsew;
+        // a fractional LMUL must hold at least one element of SEW
+        if lmul < 0 && sew > ELEN >> -lmul {
+            return None;
+        }
+        Some(VType { sew, lmul })
+    }
+
+    /// VLMAX = LMUL * VLEN / SEW
+    fn vlmax(&self, vlenb: u64) -> u64 {
+        let vlen = vlenb * 8;
+        if self.lmul < 0 {
+            (vlen >> -self.lmul) / self.sew
+        } else {
+            (vlen << self.lmul) / self.sew
+        }
+    }
+}
+
+fn log2(v: u64) -> i64 {
+    v.trailing_zeros() as i64
+}
+
+/// The lower `sew` bits.
+fn trunc(v: u64, sew: u64) -> u64 {
+    if sew >= 64 {
+        v
+    } else {
+        v & ((1 << sew) - 1)
+    }
+}
+
+/// Sign-extend the `sew` bit value.
+fn signed(v: u64, sew: u64) -> i64 {
+    ((v << (64 - sew)) as i64) >> (64 - sew)
+}
+
+/// Saturate to a `sew` bit unsigned integer. The flag is set when the value saturates.
+fn clamp_unsigned(v: i128, sew: u64) -> (u64, bool) {
+    let max = trunc(u64::MAX, sew) as i128;
+    if v < 0 {
+        (0, true)
+    } else if v > max {
+        (max as u64, true)
+    } else {
+        (v as u64, false)
+    }
+}
+
+/// Saturate to a `sew` bit signed integer. The flag is set when the value saturates.
+fn clamp_signed(v: i128, sew: u64) -> (u64, bool) {
+    let max = (1i128 << (sew - 1)) - 1;
+    let min = -(1i128 << (sew - 1));
+    if v < min {
+        (trunc(min as u64, sew), true)
+    } else if v > max {
+        (max as u64, true)
+    } else {
+        (trunc(v as u64, sew), false)
+    }
+}
+
+/// Shift right by `d` bits, rounding with the fixed-point rounding mode in vxrm.
+///   rnu -- v[d-1]
+///   rne -- v[d-1] & (v[d-2:0] != 0 | v[d])
+///   rdn -- 0
+///   rod -- !v[d] & v[d-1:0] != 0
+fn roundoff(v: i128, d: u64, vxrm: u64) -> i128 {
+    if d == 0 {
+        return v;
+    }
+    let bit = |n: u64| (v >> n) & 1;
+    let r = match vxrm {
+        RNU => bit(d - 1),
+        RNE => bit(d - 1) & ((v & ((1 << (d - 1)) - 1) != 0) as i128 | bit(d)),
+        RDN => 0,
+        _ => (bit(d) == 0 && v & ((1 << d) - 1) != 0) as i128,
+    };
+    (v >> d) + r
+}
+
+/// Widen a single-precision value to double precision. The conversion is exact.
+fn widen(a: u64, flags: &mut u8) -> u64 {
+    fpu::convert(Precision::Single, Precision::Double, a, fpu::RNE, flags)
+}
+
+fn funct6(inst: &Instruction) -> u32 {
+    inst.raw_inst >> 26
+}
+
+/// vm=1 is unmasked. Masked instructions (vm=0) operate on the elements where v0.mask[i] is set.
+fn unmasked(inst: &Instruction) -> bool {
+    inst.raw_inst >> 25 & 1 == 1
+}
+
+/// The vs1 field selects the operation of the unary instructions and is not an operand.
+fn is_unary(inst: &Instruction) -> bool {
+    let funct6 = funct6(inst);
+    match inst.rm {
+        OPMVV => matches!(funct6, 0b01_0000 | 0b01_0010 | 0b01_0100),
+        OPFVV => matches!(funct6, 0b01_0000 | 0b01_0010 | 0b01_0011),
+        _ => false,
+    }
+}
+
+fn illegal(inst: &Instruction) -> Exception {
+    Exception::IllegalInstruction(inst.raw_inst as u64)
+}
+
+/// Raise an illegal instruction exception unless the register group of EMUL = 2^emul
+/// registers starting at v[reg] is aligned to EMUL and within v0-v31.
+fn check_group(inst: &Instruction, reg: u8, emul: i64) -> Result<(), Exception> {
+    if !(-3..=3).contains(&emul) {
+        return Err(illegal(inst));
+    }
+    let n = 1 << emul.max(0);
+    if !reg.is_multiple_of(n) || reg + n > 32 {
+        return Err(illegal(inst));
+    }
+    Ok(())
+}
+
+impl Cpu {
+    /// Raise an illegal instruction exception when mstatus.VS is Off.
+    fn check_vs(&self, inst: &Instruction) -> Result<(), Exception> {
+        if self.reg.vs_off() {
+            return Err(illegal(inst));
+        }
+        Ok(())
+    }
+
+    /// The current vtype. Instructions depending on vtype are illegal when vill is set.
+    fn vconfig(&self, inst: &Instruction) -> Result<VType, Exception> {
+        match VType::decode(self.reg.vtype) {
+            Some(vt) => Ok(vt),
+            None => Err(illegal(inst)),
+        }
+    }
+
+    /// Floating-point precision of SEW. Only SEW=32 and SEW=64 are supported.
+    fn vprec(&self, inst: &Instruction, sew: u64) -> Result<Precision, Exception> {
+        match sew {
+            32 => Ok(Precision::Single),
+            64 => Ok(Precision::Double),
+            _ => Err(illegal(inst)),
+        }
+    }
+
+    /// Dynamic rounding mode in frm. The reserved modes are illegal.
+    fn vfrm(&self, inst: &Instruction) -> Result<u8, Exception> {
+        let rm = self.reg.get_csr(0x002) as u8;
+        if rm > fpu::RMM {
+            return Err(illegal(inst));
+        }
+        Ok(rm)
+    }
+
+    fn active(&self, inst: &Instruction, i: u64) -> bool {
+        unmasked(inst) || self.vreg.mask(0, i)
+    }
+
+    /// A vector instruction completed. vstart is reset and mstatus.VS becomes Dirty.
+    fn vdone(&mut self) {
+        self.reg.vstart = 0;
+        self.reg.set_vs_dirty();
+    }
+
+    /// The second operand of element i: vs1[i], x[rs1], f[rs1] or the 5 bit immediate.
+    /// The immediate is unsigned for shifts, slides and vrgather, and sign-extended otherwise.
+    fn operand(&self, inst: &Instruction, i: u64, sew: u64) -> u64 {
+        match inst.rm {
+            OPIVV | OPMVV | OPFVV if is_unary(inst) => 0,
+            OPIVV | OPMVV | OPFVV => self.vreg.get(inst.rs1, i, sew),
+            OPIVX | OPMVX => trunc(self.reg.get_reg(inst.rs1), sew),
+            OPFVF if sew == 32 => self.get_fp(Precision::Single, inst.rs1),
+            OPFVF => self.get_fp(Precision::Double, inst.rs1),
+            _ => match funct6(inst) {
+                0b00_1100 | 0b00_1110 | 0b00_1111 | 0b10_0101 | 0b10_1000..=0b10_1111 => {
+                    inst.rs1 as u64
+                }
+                _ => trunc(((inst.rs1 as i64) << 59 >> 59) as u64, sew),
+            },
+        }
+    }
+
+    /// Check the register groups of vd, vs2 and vs1 with their EMUL.
+    /// A masked instruction can not write v0 unless the destination is a mask.
+    fn check_operands(
+        &self,
+        inst: &Instruction,
+        vd_emul: i64,
+        vs2_emul: i64,
+        vs1_emul: i64,
+        mask_dest: bool,
+    ) -> Result<(), Exception> {
+        if !mask_dest {
+            check_group(inst, inst.rd, vd_emul)?;
+            if !unmasked(inst) && inst.rd == 0 {
+                return Err(illegal(inst));
+            }
+        }
+        check_group(inst, inst.rs2, vs2_emul)?;
+        if let OPIVV | OPMVV | OPFVV = inst.rm {
+            if !is_unary(inst) {
+                check_group(inst, inst.rs1, vs1_emul)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// vd[i] = op(vs2[i], operand, vd[i]) for the active elements from vstart to vl.
+    /// The second result of `op` sets vxsat.
+    fn varith<F>(&mut self, inst: &Instruction, vt: VType, mut op: F) -> Result<(), Exception>
+    where
+        F: FnMut(u64, u64, u64) -> (u64, bool),
+    {
+        self.check_operands(inst, vt.lmul, vt.lmul, vt.lmul, false)?;
+        let sew = vt.sew;
+        let mut sat = false;
+        for i in self.reg.vstart..self.reg.vl {
+            if !self.active(inst, i) {
+                continue;
+            }
+            let a = self.vreg.get(inst.rs2, i, sew);
+            let b = self.operand(inst, i, sew);
+            let d = self.vreg.get(inst.rd, i, sew);
+            let (v, s) = op(a, b, d);
+            sat |= s;
+            self.vreg.set(inst.rd, i, sew, v);
+        }
+        if sat {
+            self.reg.vxsat = 1;
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// Widening: vd[i] (2*SEW) = op(vs2[i], operand, vd[i]).
+    /// vs2 is 2*SEW when `wide` is set (the .w forms), otherwise SEW.
+    fn vwiden<F>(
+        &mut self,
+        inst: &Instruction,
+        vt: VType,
+        wide: bool,
+        mut op: F,
+    ) -> Result<(), Exception>
+    where
+        F: FnMut(u64, u64, u64) -> u64,
+    {
+        let sew = vt.sew;
+        if sew * 2 > ELEN {
+            return Err(illegal(inst));
+        }
+        let vs2_emul = if wide { vt.lmul + 1 } else { vt.lmul };
+        self.check_operands(inst, vt.lmul + 1, vs2_emul, vt.lmul, false)?;
+        let vs2_sew = if wide { sew * 2 } else { sew };
+        for i in self.reg.vstart..self.reg.vl {
+            if !self.active(inst, i) {
+                continue;
+            }
+            let a = self.vreg.get(inst.rs2, i, vs2_sew);
+            let b = self.operand(inst, i, sew);
+            let d = self.vreg.get(inst.rd, i, sew * 2);
+            let v = op(a, b, d);
+            self.vreg.set(inst.rd, i, sew * 2, v);
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// Narrowing: vd[i] = op(vs2[i] (2*SEW), operand). The second result of `op` sets vxsat.
+    fn vnarrow<F>(&mut self, inst: &Instruction, vt: VType, mut op: F) -> Result<(), Exception>
+    where
+        F: FnMut(u64, u64) -> (u64, bool),
+    {
+        let sew = vt.sew;
+        if sew * 2 > ELEN {
+            return Err(illegal(inst));
+        }
+        self.check_operands(inst, vt.lmul, vt.lmul + 1, vt.lmul, false)?;
+        let mut sat = false;
+        for i in self.reg.vstart..self.reg.vl {
+            if !self.active(inst, i) {
+                continue;
+            }
+            let a = self.vreg.get(inst.rs2, i, sew * 2);
+            let b = self.operand(inst, i, sew);
+            let (v, s) = op(a, b);
+            sat |= s;
+            self.vreg.set(inst.rd, i, sew, v);
+        }
+        if sat {
+            self.reg.vxsat = 1;
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vd.mask[i] = op(vs2[i], operand)
+    fn vcompare<F>(&mut self, inst: &Instruction, vt: VType, mut op: F) -> Result<(), Exception>
+    where
+        F: FnMut(u64, u64) -> bool,
+    {
+        self.check_operands(inst, 0, vt.lmul, vt.lmul, true)?;
+        for i in self.reg.vstart..self.reg.vl {
+            if !self.active(inst, i) {
+                continue;
+            }
+            let a = self.vreg.get(inst.rs2, i, vt.sew);
+            let b = self.operand(inst, i, vt.sew);
+            let v = op(a, b);
+            self.vreg.set_mask(inst.rd, i, v);
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vd[0] = op(...op(op(vs1[0], vs2[0]), vs2[1])..., vs2[vl-1]) over the active elements.
+    /// vs1[0] and vd[0] are 2*SEW for the widening reductions.
+    fn vreduce<F>(
+        &mut self,
+        inst: &Instruction,
+        vt: VType,
+        wide: bool,
+        mut op: F,
+    ) -> Result<(), Exception>
+    where
+        F: FnMut(u64, u64) -> u64,
+    {
+        let sew = vt.sew;
+        let acc_sew = if wide { sew * 2 } else { sew };
+        if acc_sew > ELEN || self.reg.vstart != 0 {
+            return Err(illegal(inst));
+        }
+        check_group(inst, inst.rs2, vt.lmul)?;
+        if self.reg.vl == 0 {
+            self.vdone();
+            return Ok(());
+        }
+        let mut acc = self.vreg.get(inst.rs1, 0, acc_sew);
+        for i in 0..self.reg.vl {
+            if self.active(inst, i) {
+                let a = self.vreg.get(inst.rs2, i, sew);
+                acc = trunc(op(acc, a), acc_sew);
+            }
+        }
+        self.vreg.set(inst.rd, 0, acc_sew, acc);
+        self.vdone();
+        Ok(())
+    }
+
+    /// vadc, vsbc -- vd[i] = vs2[i] +/- operand +/- v0.mask[i]
+    /// vmadc, vmsbc -- vd.mask[i] = carry-out or borrow-out. The carry-in is v0.mask[i] when vm=0.
+    fn vcarry(
+        &mut self,
+        inst: &Instruction,
+        vt: VType,
+        sub: bool,
+        mask_out: bool,
+    ) -> Result<(), Exception> {
+        let sew = vt.sew;
+        if mask_out {
+            self.check_operands(inst, 0, vt.lmul, vt.lmul, true)?;
+        } else {
+            // the carry-in is always v0, so vd can not be v0
+            check_group(inst, inst.rd, vt.lmul)?;
+            self.check_operands(inst, vt.lmul, vt.lmul, vt.lmul, true)?;
+            if inst.rd == 0 {
+                return Err(illegal(inst));
+            }
+        }
+        for i in self.reg.vstart..self.reg.vl {
+            let c = (!unmasked(inst) && self.vreg.mask(0, i)) as i128;
+            let a = self.vreg.get(inst.rs2, i, sew) as i128;
+            let b = self.operand(inst, i, sew) as i128;
+            let v = if sub { a - b - c } else { a + b + c };
+            if mask_out {
+                // the carry or the borrow is outside the SEW bits
+                self.vreg.set_mask(inst.rd, i, v >> sew != 0);
+            } else {
+                self.vreg.set(inst.rd, i, sew, v as u64);
+            }
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vmerge, vfmerge -- vd[i] = v0.mask[i] ? operand : vs2[i]
+    fn vmerge(&mut self, inst: &Instruction, vt: VType) -> Result<(), Exception> {
+        check_group(inst, inst.rd, vt.lmul)?;
+        self.check_operands(inst, vt.lmul, vt.lmul, vt.lmul, true)?;
+        if inst.rd == 0 {
+            return Err(illegal(inst));
+        }
+        for i in self.reg.vstart..self.reg.vl {
+            let v = if self.vreg.mask(0, i) {
+                self.operand(inst, i, vt.sew)
+            } else {
+                self.vreg.get(inst.rs2, i, vt.sew)
+            };
+            self.vreg.set(inst.rd, i, vt.sew, v);
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vmv.s.x, vfmv.s.f -- vd[0] = value
+    fn vmv_s(&mut self, inst: &Instruction, vt: VType, value: u64) -> Result<(), Exception> {
+        if self.reg.vstart < self.reg.vl {
+            self.vreg.set(inst.rd, 0, vt.sew, value);
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vslideup, vslide1up -- vd[i] = vs2[i - offset] for offset <= i < vl
+    /// vslide1up writes `first` to vd[0].
+    fn vslideup(
+        &mut self,
+        inst: &Instruction,
+        vt: VType,
+        offset: u64,
+        first: Option<u64>,
+    ) -> Result<(), Exception> {
+        self.check_operands(inst, vt.lmul, vt.lmul, vt.lmul, false)?;
+        // the destination can not overlap the source
+        if inst.rd == inst.rs2 {
+            return Err(illegal(inst));
+        }
+        for i in self.reg.vstart.max(offset)..self.reg.vl {
+            if self.active(inst, i) {
+                let v = self.vreg.get(inst.rs2, i - offset, vt.sew);
+                self.vreg.set(inst.rd, i, vt.sew, v);
+            }
+        }
+        if let Some(v) = first {
+            if self.reg.vstart == 0 && self.reg.vl > 0 && self.active(inst, 0) {
+                self.vreg.set(inst.rd, 0, vt.sew, v);
+            }
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vslidedown, vslide1down -- vd[i] = vs2[i + offset], or 0 past VLMAX
+    /// vslide1down writes `last` to vd[vl - 1].
+    fn vslidedown(
+        &mut self,
+        inst: &Instruction,
+        vt: VType,
+        offset: u64,
+        last: Option<u64>,
+    ) -> Result<(), Exception> {
+        self.check_operands(inst, vt.lmul, vt.lmul, vt.lmul, false)?;
+        let vlmax = vt.vlmax(self.reg.vlenb);
+        let vl = self.reg.vl;
+        for i in self.reg.vstart..vl {
+            if !self.active(inst, i) {
+                continue;
+            }
+            let v = match (last, i.checked_add(offset)) {
+                (Some(v), _) if i == vl - 1 => v,
+                (_, Some(src)) if src < vlmax => self.vreg.get(inst.rs2, src, vt.sew),
+                _ => 0,
+            };
+            self.vreg.set(inst.rd, i, vt.sew, v);
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vrgather -- vd[i] = (index >= VLMAX) ? 0 : vs2[index]
+    /// The index is vs1[i], x[rs1] or uimm. vrgatherei16 reads 16-bit indices from vs1.
+    fn vrgather(&mut self, inst: &Instruction, vt: VType, ei16: bool) -> Result<(), Exception> {
+        let (index_sew, index_emul) = if ei16 {
+            (16, vt.lmul + 4 - log2(vt.sew))
+        } else {
+            (vt.sew, vt.lmul)
+        };
+        self.check_operands(inst, vt.lmul, vt.lmul, index_emul, false)?;
+        if inst.rd == inst.rs2 || (inst.rm == OPIVV && inst.rd == inst.rs1) {
+            return Err(illegal(inst));
+        }
+        let vlmax = vt.vlmax(self.reg.vlenb);
+        for i in self.reg.vstart..self.reg.vl {
+            if !self.active(inst, i) {
+                continue;
+            }
+            let index = match inst.rm {
+                OPIVV => self.vreg.get(inst.rs1, i, index_sew),
+                OPIVX => self.reg.get_reg(inst.rs1),
+                _ => inst.rs1 as u64,
+            };
+            let v = if index < vlmax {
+                self.vreg.get(inst.rs2, index, vt.sew)
+            } else {
+                0
+            };
+            self.vreg.set(inst.rd, i, vt.sew, v);
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vcompress.vm -- pack the elements of vs2 where vs1.mask[i] is set into vd.
+    fn vcompress(&mut self, inst: &Instruction, vt: VType) -> Result<(), Exception> {
+        check_group(inst, inst.rd, vt.lmul)?;
+        check_group(inst, inst.rs2, vt.lmul)?;
+        if self.reg.vstart != 0 || inst.rd == inst.rs2 || inst.rd == inst.rs1 {
+            return Err(illegal(inst));
+        }
+        let mut j = 0;
+        for i in 0..self.reg.vl {
+            if self.vreg.mask(inst.rs1, i) {
+                let v = self.vreg.get(inst.rs2, i, vt.sew);
+                self.vreg.set(inst.rd, j, vt.sew, v);
+                j += 1;
+            }
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vmv<nr>r.v -- copy nr (simm5 + 1) whole registers. nr is 1, 2, 4 or 8.
+    fn vmv_nr(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        let nr = inst.rs1 as i64 + 1;
+        if !(nr as u64).is_power_of_two() || nr > 8 {
+            return Err(illegal(inst));
+        }
+        check_group(inst, inst.rd, log2(nr as u64))?;
+        check_group(inst, inst.rs2, log2(nr as u64))?;
+        // vstart counts the elements of SEW, while vtype does not matter otherwise
+        let sew = VType::decode(self.reg.vtype).map_or(8, |vt| vt.sew);
+        let evl = nr as u64 * self.reg.vlenb * 8 / sew;
+        for i in self.reg.vstart..evl {
+            let v = self.vreg.get(inst.rs2, i, sew);
+            self.vreg.set(inst.rd, i, sew, v);
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vd.mask[i] = op(vs2.mask[i], vs1.mask[i])
+    fn vmask_logical<F>(&mut self, inst: &Instruction, op: F) -> Result<(), Exception>
+    where
+        F: Fn(bool, bool) -> bool,
+    {
+        for i in self.reg.vstart..self.reg.vl {
+            let v = op(self.vreg.mask(inst.rs2, i), self.vreg.mask(inst.rs1, i));
+            self.vreg.set_mask(inst.rd, i, v);
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// VWXUNARY0
+    ///   vmv.x.s  -- x[rd] = sext(vs2[0])
+    ///   vcpop.m  -- x[rd] = the number of active elements where vs2.mask[i] is set
+    ///   vfirst.m -- x[rd] = the index of the first of them, or -1
+    fn vwxunary0(&mut self, inst: &Instruction, vt: VType) -> Result<(), Exception> {
+        let v = match inst.rs1 {
+            0b0_0000 => signed(self.vreg.get(inst.rs2, 0, vt.sew), vt.sew) as u64,
+            0b1_0000 | 0b1_0001 if self.reg.vstart != 0 => return Err(illegal(inst)),
+            0b1_0000 => (0..self.reg.vl)
+                .filter(|&i| self.active(inst, i) && self.vreg.mask(inst.rs2, i))
+                .count() as u64,
+            0b1_0001 => (0..self.reg.vl)
+                .find(|&i| self.active(inst, i) && self.vreg.mask(inst.rs2, i))
+                .unwrap_or(u64::MAX),
+            _ => return Err(illegal(inst)),
+        };
+        self.reg.set_reg(inst.rd, v);
+        self.vdone();
+        Ok(())
+    }
+
+    /// VXUNARY0 -- vzext.vf2/vf4/vf8 and vsext.vf2/vf4/vf8
+    /// vd[i] = extend(vs2[i]) where vs2 is SEW / 2, SEW / 4 or SEW / 8.
+    fn vext(&mut self, inst: &Instruction, vt: VType) -> Result<(), Exception> {
+        let factor = match inst.rs1 >> 1 {
+            0b01 => 3,
+            0b10 => 2,
+            0b11 => 1,
+            _ => return Err(illegal(inst)),
+        };
+        let sign = inst.rs1 & 1 == 1;
+        let src_sew = vt.sew >> factor;
+        if src_sew < 8 {
+            return Err(illegal(inst));
+        }
+        self.check_operands(inst, vt.lmul, vt.lmul - factor, vt.lmul, false)?;
+        for i in self.reg.vstart..self.reg.vl {
+            if !self.active(inst, i) {
+                continue;
+            }
+            let a = self.vreg.get(inst.rs2, i, src_sew);
+            let v = if sign { signed(a, src_sew) as u64 } else { a };
+            self.vreg.set(inst.rd, i, vt.sew, v);
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// VMUNARY0
+    ///   vmsbf.m -- set before the first set mask bit of vs2
+    ///   vmsif.m -- set including the first set mask bit
+    ///   vmsof.m -- set only the first set mask bit
+    ///   viota.m -- vd[i] = the number of set mask bits of vs2 in the active elements before i
+    ///   vid.v   -- vd[i] = i
+    fn vmunary0(&mut self, inst: &Instruction, vt: VType) -> Result<(), Exception> {
+        let kind = inst.rs1;
+        if kind == 0b1_0001 {
+            self.check_operands(inst, vt.lmul, 0, 0, false)?;
+            for i in self.reg.vstart..self.reg.vl {
+                if self.active(inst, i) {
+                    self.vreg.set(inst.rd, i, vt.sew, i);
+                }
+            }
+            self.vdone();
+            return Ok(());
+        }
+        if self.reg.vstart != 0 || inst.rd == inst.rs2 || (!unmasked(inst) && inst.rd == 0) {
+            return Err(illegal(inst));
+        }
+        if kind == 0b1_0000 {
+            check_group(inst, inst.rd, vt.lmul)?;
+            let mut count = 0;
+            for i in 0..self.reg.vl {
+                if self.active(inst, i) {
+                    self.vreg.set(inst.rd, i, vt.sew, count);
+                    count += self.vreg.mask(inst.rs2, i) as u64;
+                }
+            }
+            self.vdone();
+            return Ok(());
+        }
+        let mut found = false;
+        for i in 0..self.reg.vl {
+            if !self.active(inst, i) {
+                continue;
+            }
+            let bit = self.vreg.mask(inst.rs2, i);
+            let v = match kind {
+                0b0_0001 => !found && !bit,
+                0b0_0010 => !found && bit,
+                0b0_0011 => !found,
+                _ => return Err(illegal(inst)),
+            };
+            self.vreg.set_mask(inst.rd, i, v);
+            found |= bit;
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vsetvli rd, rs1, vtypei / vsetivli rd, uimm, vtypei / vsetvl rd, rs1, rs2
+    /// vl = min(AVL, VLMAX), x[rd] = vl, vtype = vtypei or x[rs2]
+    /// AVL is x[rs1] or uimm. rs1 = x0 requests VLMAX, and keeps vl when rd is also x0.
+    /// An unsupported vtype sets vill and vl = 0.
+    pub fn vsetvl(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_vs(inst)?;
+        let raw = inst.raw_inst as u64;
+        let (vtype, avl) = match inst.name {
+            InstName::Vsetivli => (raw >> 20 & 0x3FF, inst.rs1 as u64),
+            InstName::Vsetvli => (raw >> 20 & 0x7FF, self.avl(inst)),
+            _ => (self.reg.get_reg(inst.rs2), self.avl(inst)),
+        };
+        match VType::decode(vtype) {
+            Some(vt) => {
+                self.reg.vtype = vtype;
+                self.reg.vl = avl.min(vt.vlmax(self.reg.vlenb));
+            }
+            None => {
+                self.reg.vtype = VILL;
+                self.reg.vl = 0;
+            }
+        }
+        self.reg.set_reg(inst.rd, self.reg.vl);
+        self.vdone();
+        Ok(())
+    }
+
+    fn avl(&self, inst: &Instruction) -> u64 {
+        if inst.rs1 != 0 {
+            self.reg.get_reg(inst.rs1)
+        } else if inst.rd != 0 {
+            u64::MAX
+        } else {
+            self.reg.vl
+        }
+    }
+
+    /// Unit-stride, fault-only-first, strided and indexed loads and stores.
+    /// Field f of element i is at
+    ///   unit-stride -- x[rs1] + (i * nf + f) * EEW / 8
+    ///   strided     -- x[rs1] + i * x[rs2] + f * EEW / 8
+    ///   indexed     -- x[rs1] + vs2[i] + f * SEW / 8
+    /// and is held in the register group v[vd + f * EMUL].
+    /// Indexed accesses use the width field for the index EEW and SEW for the data.
+    /// On an exception vstart is the index of the faulting element, except that a
+    /// fault-only-first load trims vl instead when the element is not the first one.
+    pub fn vmem(&mut self, inst: &Instruction, store: bool) -> Result<(), Exception> {
+        self.check_vs(inst)?;
+        let vt = self.vconfig(inst)?;
+        let raw = inst.raw_inst;
+        let nf = (raw >> 29) as u64 + 1;
+        let mop = (raw >> 26) & 0b11;
+        let indexed = mop & 1 == 1;
+        let fault_first = !store && mop == 0 && inst.rs2 == 0b1_0000;
+        let width = vector_eew(inst.rm);
+        let eew = if indexed { vt.sew } else { width };
+        let emul = if indexed {
+            vt.lmul
+        } else {
+            log2(eew) - log2(vt.sew) + vt.lmul
+        };
+        check_group(inst, inst.rd, emul)?;
+        let regs = 1 << emul.max(0);
+        if nf * regs > 8 || inst.rd as u64 + nf * regs > 32 {
+            return Err(illegal(inst));
+        }
+        if indexed {
+            check_group(inst, inst.rs2, log2(width) - log2(vt.sew) + vt.lmul)?;
+        }
+        if !store && !unmasked(inst) && inst.rd == 0 {
+            return Err(illegal(inst));
+        }
+
+        let base = self.reg.get_reg(inst.rs1);
+        let stride = self.reg.get_reg(inst.rs2);
+        let size = eew / 8;
+        let mut i = self.reg.vstart;
+        'elements: while i < self.reg.vl {
+            if !self.active(inst, i) {
+                i += 1;
+                continue;
+            }
+            for f in 0..nf {
+                let offset = match mop {
+                    0b00 => (i * nf + f) * size,
+                    0b10 => i.wrapping_mul(stride).wrapping_add(f * size),
+                    _ => self.vreg.get(inst.rs2, i, width).wrapping_add(f * size),
+                };
+                let addr = base.wrapping_add(offset);
+                let reg = inst.rd + (f * regs) as u8;
+                let res = if store {
+                    let v = self.vreg.get(reg, i, eew);
+                    self.store(addr, size, v)
+                } else {
+                    self.load(addr, size).map(|v| self.vreg.set(reg, i, eew, v))
+                };
+                if let Err(e) = res {
+                    if fault_first && i > 0 {
+                        self.reg.vl = i;
+                        break 'elements;
+                    }
+                    self.reg.vstart = i;
+                    self.reg.set_vs_dirty();
+                    return Err(e);
+                }
+            }
+            i += 1;
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// vlm.v, vsm.v -- load or store ceil(vl / 8) bytes of the mask register vd.
+    pub fn vmem_mask(&mut self, inst: &Instruction, store: bool) -> Result<(), Exception> {
+        self.check_vs(inst)?;
+        self.vconfig(inst)?;
+        let evl = self.reg.vl.div_ceil(8);
+        self.vmem_whole(inst, store, evl, 8)
+    }
+
+    /// vl<nf>re<eew>.v, vs<nf>r.v -- load or store nf whole registers.
+    pub fn vmem_reg(&mut self, inst: &Instruction, store: bool) -> Result<(), Exception> {
+        self.check_vs(inst)?;
+        let nf = (inst.raw_inst >> 29) as u64 + 1;
+        check_group(inst, inst.rd, log2(nf))?;
+        let eew = if store { 8 } else { vector_eew(inst.rm) };
+        let evl = nf * self.reg.vlenb * 8 / eew;
+        self.vmem_whole(inst, store, evl, eew)
+    }
+
+    /// Unmasked unit-stride access of `evl` elements of `eew` bits.
+    fn vmem_whole(
+        &mut self,
+        inst: &Instruction,
+        store: bool,
+        evl: u64,
+        eew: u64,
+    ) -> Result<(), Exception> {
+        let base = self.reg.get_reg(inst.rs1);
+        let size = eew / 8;
+        for i in self.reg.vstart..evl {
+            let addr = base.wrapping_add(i * size);
+            let res = if store {
+                let v = self.vreg.get(inst.rd, i, eew);
+                self.store(addr, size, v)
+            } else {
+                self.load(addr, size)
+                    .map(|v| self.vreg.set(inst.rd, i, eew, v))
+            };
+            if let Err(e) = res {
+                self.reg.vstart = i;
+                self.reg.set_vs_dirty();
+                return Err(e);
+            }
+        }
+        self.vdone();
+        Ok(())
+    }
+
+    /// OPIVV, OPIVX and OPIVI
+    /// Integer arithmetic between vs2[i] and vs1[i], x[rs1] or a 5 bit immediate.
+    pub fn opi(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_vs(inst)?;
+        let funct6 = funct6(inst);
+        if funct6 == 0b10_0111 && inst.rm == OPIVI {
+            return self.vmv_nr(inst);
+        }
+        let vt = self.vconfig(inst)?;
+        let sew = vt.sew;
+        let s = |v: u64| signed(v, sew) as i128;
+        let xrm = self.reg.vxrm;
+        let shamt = |b: u64| b & (sew - 1);
+        let offset = || match inst.rm {
+            OPIVX => self.reg.get_reg(inst.rs1),
+            _ => inst.rs1 as u64,
+        };
+        match funct6 {
+            // vadd
+            0b00_0000 => self.varith(inst, vt, |a, b, _| (a.wrapping_add(b), false)),
+            // vsub
+            0b00_0010 => self.varith(inst, vt, |a, b, _| (a.wrapping_sub(b), false)),
+            // vrsub
+            0b00_0011 => self.varith(inst, vt, |a, b, _| (b.wrapping_sub(a), false)),
+            // vminu
+            0b00_0100 => self.varith(inst, vt, |a, b, _| (a.min(b), false)),
+            // vmin
+            0b00_0101 => self.varith(inst, vt, |a, b, _| (if s(a) < s(b) { a } else { b }, false)),
+            // vmaxu
+            0b00_0110 => self.varith(inst, vt, |a, b, _| (a.max(b), false)),
+            // vmax
+            0b00_0111 => self.varith(inst, vt, |a, b, _| (if s(a) > s(b) { a } else { b }, false)),
+            // vand
+            0b00_1001 => self.varith(inst, vt, |a, b, _| (a & b, false)),
+            // vor
+            0b00_1010 => self.varith(inst, vt, |a, b, _| (a | b, false)),
+            // vxor
+            0b00_1011 => self.varith(inst, vt, |a, b, _| (a ^ b, false)),
+            // vrgather
+            0b00_1100 => self.vrgather(inst, vt, false),
+            // vrgatherei16
+            0b00_1110 if inst.rm == OPIVV => self.vrgather(inst, vt, true),
+            // vslideup
+            0b00_1110 => {
+                let offset = offset();
+                self.vslideup(inst, vt, offset, None)
+            }
+            // vslidedown
+            0b00_1111 => {
+                let offset = offset();
+                self.vslidedown(inst, vt, offset, None)
+            }
+            // vadc
+            0b01_0000 => self.vcarry(inst, vt, false, false),
+            // vmadc
+            0b01_0001 => self.vcarry(inst, vt, false, true),
+            // vsbc
+            0b01_0010 => self.vcarry(inst, vt, true, false),
+            // vmsbc
+            0b01_0011 => self.vcarry(inst, vt, true, true),
+            // vmerge
+            0b01_0111 if !unmasked(inst) => self.vmerge(inst, vt),
+            // vmv.v
+            0b01_0111 => self.varith(inst, vt, |_, b, _| (b, false)),
+            // vmseq
+            0b01_1000 => self.vcompare(inst, vt, |a, b| a == b),
+            // vmsne
+            0b01_1001 => self.vcompare(inst, vt, |a, b| a != b),
+            // vmsltu
+            0b01_1010 => self.vcompare(inst, vt, |a, b| a < b),
+            // vmslt
+            0b01_1011 => self.vcompare(inst, vt, |a, b| s(a) < s(b)),
+            // vmsleu
+            0b01_1100 => self.vcompare(inst, vt, |a, b| a <= b),
+            // vmsle
+            0b01_1101 => self.vcompare(inst, vt, |a, b| s(a) <= s(b)),
+            // vmsgtu
+            0b01_1110 => self.vcompare(inst, vt, |a, b| a > b),
+            // vmsgt
+            0b01_1111 => self.vcompare(inst, vt, |a, b| s(a) > s(b)),
+            // vsaddu
+            0b10_0000 => self.varith(inst, vt, |a, b, _| {
+                clamp_unsigned(a as i128 + b as i128, sew)
+            }),
+            // vsadd
+            0b10_0001 => self.varith(inst, vt, |a, b, _| clamp_signed(s(a) + s(b), sew)),
+            // vssubu
+            0b10_0010 => self.varith(inst, vt, |a, b, _| {
+                clamp_unsigned(a as i128 - b as i128, sew)
+            }),
+            // vssub
+            0b10_0011 => self.varith(inst, vt, |a, b, _| clamp_signed(s(a) - s(b), sew)),
+            // vsll
+            0b10_0101 => self.varith(inst, vt, |a, b, _| (a << shamt(b), false)),
+            // vsmul: (vs2[i] * op) >> (SEW - 1), saturating -2^(SEW-1) * -2^(SEW-1)
+            0b10_0111 => self.varith(inst, vt, |a, b, _| {
+                clamp_signed(roundoff(s(a) * s(b), sew - 1, xrm), sew)
+            }),
+            // vsrl
+            0b10_1000 => self.varith(inst, vt, |a, b, _| (a >> shamt(b), false)),
+            // vsra
+            0b10_1001 => self.varith(inst, vt, |a, b, _| ((s(a) >> shamt(b)) as u64, false)),
+            // vssrl
+            0b10_1010 => self.varith(inst, vt, |a, b, _| {
+                (roundoff(a as i128, shamt(b), xrm) as u64, false)
+            }),
+            // vssra
+            0b10_1011 => self.varith(inst, vt, |a, b, _| {
+                (roundoff(s(a), shamt(b), xrm) as u64, false)
+            }),
+            // vnsrl
+            0b10_1100 => self.vnarrow(inst, vt, |a, b| (a >> (b & (sew * 2 - 1)), false)),
+            // vnsra
+            0b10_1101 => self.vnarrow(inst, vt, |a, b| {
+                ((signed(a, sew * 2) >> (b & (sew * 2 - 1))) as u64, false)
+            }),
+            // vnclipu
+            0b10_1110 => self.vnarrow(inst, vt, |a, b| {
+                clamp_unsigned(roundoff(a as i128, b & (sew * 2 - 1), xrm), sew)
+            }),
+            // vnclip
+            0b10_1111 => self.vnarrow(inst, vt, |a, b| {
+                let a = signed(a, sew * 2) as i128;
+                clamp_signed(roundoff(a, b & (sew * 2 - 1), xrm), sew)
+            }),
+            // vwredsumu
+            0b11_0000 => self.vreduce(inst, vt, true, |acc, a| acc.wrapping_add(a)),
+            // vwredsum
+            0b11_0001 => self.vreduce(inst, vt, true, |acc, a| acc.wrapping_add(s(a) as u64)),
+            _ => Err(illegal(inst)),
+        }
+    }
+
+    /// OPMVV and OPMVX
+    /// Integer arithmetic between vs2[i] and vs1[i] or x[rs1], reductions, mask operations
+    /// and the unary operations.
+    pub fn opm(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_vs(inst)?;
+        let funct6 = funct6(inst);
+        let vt = self.vconfig(inst)?;
+        let sew = vt.sew;
+        let s = |v: u64| signed(v, sew) as i128;
+        let xrm = self.reg.vxrm;
+        let x = trunc(self.reg.get_reg(inst.rs1), sew);
+        match funct6 {
+            // vredsum
+            0b00_0000 => self.vreduce(inst, vt, false, |acc, a| acc.wrapping_add(a)),
+            // vredand
+            0b00_0001 => self.vreduce(inst, vt, false, |acc, a| acc & a),
+            // vredor
+            0b00_0010 => self.vreduce(inst, vt, false, |acc, a| acc | a),
+            // vredxor
+            0b00_0011 => self.vreduce(inst, vt, false, |acc, a| acc ^ a),
+            // vredminu
+            0b00_0100 => self.vreduce(inst, vt, false, |acc, a| acc.min(a)),
+            // vredmin
+            0b00_0101 => self.vreduce(
+                inst,
+                vt,
+                false,
+                |acc, a| if s(a) < s(acc) { a } else { acc },
+            ),
+            // vredmaxu
+            0b00_0110 => self.vreduce(inst, vt, false, |acc, a| acc.max(a)),
+            // vredmax
+            0b00_0111 => self.vreduce(
+                inst,
+                vt,
+                false,
+                |acc, a| if s(a) > s(acc) { a } else { acc },
+            ),
+            // vaaddu
+            0b00_1000 => self.varith(inst, vt, |a, b, _| {
+                (roundoff(a as i128 + b as i128, 1, xrm) as u64, false)
+            }),
+            // vaadd
+            0b00_1001 => self.varith(inst, vt, |a, b, _| {
+                (roundoff(s(a) + s(b), 1, xrm) as u64, false)
+            }),
+            // vasubu
+            0b00_1010 => self.varith(inst, vt, |a, b, _| {
+                (roundoff(a as i128 - b as i128, 1, xrm) as u64, false)
+            }),
+            // vasub
+            0b00_1011 => self.varith(inst, vt, |a, b, _| {
+                (roundoff(s(a) - s(b), 1, xrm) as u64, false)
+            }),
+            // vslide1up
+            0b00_1110 => self.vslideup(inst, vt, 1, Some(x)),
+            // vslide1down
+            0b00_1111 => self.vslidedown(inst, vt, 1, Some(x)),
+            // VWXUNARY0
+            0b01_0000 if inst.rm == OPMVV => self.vwxunary0(inst, vt),
+            // vmv.s.x
+            0b01_0000 => self.vmv_s(inst, vt, x),
+            // VXUNARY0
+            0b01_0010 => self.vext(inst, vt),
+            // VMUNARY0
+            0b01_0100 => self.vmunary0(inst, vt),
+            // vcompress
+            0b01_0111 => self.vcompress(inst, vt),
+            // vmandn
+            0b01_1000 => self.vmask_logical(inst, |a, b| a & !b),
+            // vmand
+            0b01_1001 => self.vmask_logical(inst, |a, b| a & b),
+            // vmor
+            0b01_1010 => self.vmask_logical(inst, |a, b| a | b),
+            // vmxor
+            0b01_1011 => self.vmask_logical(inst, |a, b| a ^ b),
+            // vmorn
+            0b01_1100 => self.vmask_logical(inst, |a, b| a | !b),
+            // vmnand
+            0b01_1101 => self.vmask_logical(inst, |a, b| !(a & b)),
+            // vmnor
+            0b01_1110 => self.vmask_logical(inst, |a, b| !(a | b)),
+            // vmxnor
+            0b01_1111 => self.vmask_logical(inst, |a, b| !(a ^ b)),
+            // vdivu
+            0b10_0000 => self.varith(inst, vt, |a, b, _| {
+                (a.checked_div(b).unwrap_or(u64::MAX), false)
+            }),
+            // vdiv
+            0b10_0001 => self.varith(inst, vt, |a, b, _| {
+                let (a, b) = (signed(a, sew), signed(b, sew));
+                (
+                    if b == 0 {
+                        u64::MAX
+                    } else {
+                        a.wrapping_div(b) as u64
+                    },
+                    false,
+                )
+            }),
+            // vremu
+            0b10_0010 => self.varith(inst, vt, |a, b, _| (if b == 0 { a } else { a % b }, false)),
+            // vrem
+            0b10_0011 => self.varith(inst, vt, |a, b, _| {
+                let (a, b) = (signed(a, sew), signed(b, sew));
+                (
+                    if b == 0 {
+                        a as u64
+                    } else {
+                        a.wrapping_rem(b) as u64
+                    },
+                    false,
+                )
+            }),
+            // vmulhu
+            0b10_0100 => self.varith(inst, vt, |a, b, _| {
+                (((a as u128 * b as u128) >> sew) as u64, false)
+            }),
+            // vmul
+            0b10_0101 => self.varith(inst, vt, |a, b, _| (a.wrapping_mul(b), false)),
+            // vmulhsu
+            0b10_0110 => self.varith(inst, vt, |a, b, _| {
+                (((s(a) * b as i128) >> sew) as u64, false)
+            }),
+            // vmulh
+            0b10_0111 => self.varith(inst, vt, |a, b, _| (((s(a) * s(b)) >> sew) as u64, false)),
+            // vmadd: vd[i] = (op * vd[i]) + vs2[i]
+            0b10_1001 => self.varith(inst, vt, |a, b, d| {
+                (b.wrapping_mul(d).wrapping_add(a), false)
+            }),
+            // vnmsub: vd[i] = -(op * vd[i]) + vs2[i]
+            0b10_1011 => self.varith(inst, vt, |a, b, d| {
+                (a.wrapping_sub(b.wrapping_mul(d)), false)
+            }),
+            // vmacc: vd[i] = (op * vs2[i]) + vd[i]
+            0b10_1101 => self.varith(inst, vt, |a, b, d| {
+                (d.wrapping_add(b.wrapping_mul(a)), false)
+            }),
+            // vnmsac: vd[i] = -(op * vs2[i]) + vd[i]
+            0b10_1111 => self.varith(inst, vt, |a, b, d| {
+                (d.wrapping_sub(b.wrapping_mul(a)), false)
+            }),
+            // vwaddu
+            0b11_0000 => self.vwiden(inst, vt, false, |a, b, _| a.wrapping_add(b)),
+            // vwadd
+            0b11_0001 => self.vwiden(inst, vt, false, |a, b, _| (s(a) + s(b)) as u64),
+            // vwsubu
+            0b11_0010 => self.vwiden(inst, vt, false, |a, b, _| a.wrapping_sub(b)),
+            // vwsub
+            0b11_0011 => self.vwiden(inst, vt, false, |a, b, _| (s(a) - s(b)) as u64),
+            // vwaddu.w
+            0b11_0100 => self.vwiden(inst, vt, true, |a, b, _| a.wrapping_add(b)),
+            // vwadd.w
+            0b11_0101 => self.vwiden(inst, vt, true, |a, b, _| a.wrapping_add(s(b) as u64)),
+            // vwsubu.w
+            0b11_0110 => self.vwiden(inst, vt, true, |a, b, _| a.wrapping_sub(b)),
+            // vwsub.w
+            0b11_0111 => self.vwiden(inst, vt, true, |a, b, _| a.wrapping_sub(s(b) as u64)),
+            // vwmulu
+            0b11_1000 => self.vwiden(inst, vt, false, |a, b, _| a.wrapping_mul(b)),
+            // vwmulsu: signed vs2[i] * unsigned op
+            0b11_1010 => self.vwiden(inst, vt, false, |a, b, _| (s(a) * b as i128) as u64),
+            // vwmul
+            0b11_1011 => self.vwiden(inst, vt, false, |a, b, _| (s(a) * s(b)) as u64),
+            // vwmaccu
+            0b11_1100 => self.vwiden(inst, vt, false, |a, b, d| d.wrapping_add(a.wrapping_mul(b))),
+            // vwmacc
+            0b11_1101 => self.vwiden(inst, vt, false, |a, b, d| {
+                d.wrapping_add((s(a) * s(b)) as u64)
+            }),
+            // vwmaccus: unsigned x[rs1] * signed vs2[i]
+            0b11_1110 => self.vwiden(inst, vt, false, |a, b, d| {
+                d.wrapping_add((s(a) * b as i128) as u64)
+            }),
+            // vwmaccsu: signed op * unsigned vs2[i]
+            0b11_1111 => self.vwiden(inst, vt, false, |a, b, d| {
+                d.wrapping_add((a as i128 * s(b)) as u64)
+            }),
+            _ => Err(illegal(inst)),
+        }
+    }
+
+    /// OPFVV and OPFVF
+    /// Floating-point arithmetic between vs2[i] and vs1[i] or f[rs1] for SEW=32 and SEW=64.
+    /// The widening operations are supported from SEW=32 to 64.
+    pub fn opf(&mut self, inst: &Instruction) -> Result<(), Exception> {
+        self.check_vs(inst)?;
+        self.check_fs(inst)?;
+        let funct6 = funct6(inst);
+        let vt = self.vconfig(inst)?;
+        let rm = self.vfrm(inst)?;
+        if funct6 == 0b01_0010 {
+            return self.vfcvt(inst, vt, rm);
+        }
+        let p = self.vprec(inst, vt.sew)?;
+        let d = Precision::Double;
+        let sign = Cpu::fp_sign_bit(p);
+        let f = self.operand(inst, 0, vt.sew);
+        let mut flags = 0;
+        let fl = &mut flags;
+        let res = match funct6 {
+            // vfadd
+            0b00_0000 => self.varith(inst, vt, |a, b, _| {
+                (fpu::add(p, a, b, false, rm, fl), false)
+            }),
+            // vfredusum, vfredosum
+            0b00_0001 | 0b00_0011 => {
+                self.vreduce(inst, vt, false, |acc, a| fpu::add(p, acc, a, false, rm, fl))
+            }
+            // vfsub
+            0b00_0010 => self.varith(inst, vt, |a, b, _| (fpu::add(p, a, b, true, rm, fl), false)),
+            // vfmin
+            0b00_0100 => self.varith(inst, vt, |a, b, _| {
+                (fpu::min_max(p, a, b, false, fl), false)
+            }),
+            // vfredmin
+            0b00_0101 => self.vreduce(inst, vt, false, |acc, a| fpu::min_max(p, acc, a, false, fl)),
+            // vfmax
+            0b00_0110 => self.varith(inst, vt, |a, b, _| (fpu::min_max(p, a, b, true, fl), false)),
+            // vfredmax
+            0b00_0111 => self.vreduce(inst, vt, false, |acc, a| fpu::min_max(p, acc, a, true, fl)),
+            // vfsgnj
+            0b00_1000 => self.varith(inst, vt, |a, b, _| ((a & !sign) | (b & sign), false)),
+            // vfsgnjn
+            0b00_1001 => self.varith(inst, vt, |a, b, _| ((a & !sign) | (!b & sign), false)),
+            // vfsgnjx
+            0b00_1010 => self.varith(inst, vt, |a, b, _| (a ^ (b & sign), false)),
+            // vfslide1up
+            0b00_1110 => self.vslideup(inst, vt, 1, Some(f)),
+            // vfslide1down
+            0b00_1111 => self.vslidedown(inst, vt, 1, Some(f)),
+            // vfmv.f.s
+            0b01_0000 if inst.rm == OPFVV && inst.rs1 == 0 => {
+                let v = self.vreg.get(inst.rs2, 0, vt.sew);
+                self.set_fp(p, inst.rd, v);
+                self.vdone();
+                Ok(())
+            }
+            // vfmv.s.f
+            0b01_0000 if inst.rm == OPFVF => self.vmv_s(inst, vt, f),
+            // vfsqrt
+            0b01_0011 if inst.rs1 == 0b0_0000 => {
+                self.varith(inst, vt, |a, _, _| (fpu::sqrt(p, a, rm, fl), false))
+            }
+            // vfrsqrt7
+            0b01_0011 if inst.rs1 == 0b0_0100 => {
+                self.varith(inst, vt, |a, _, _| (fpu::rsqrt7(p, a, fl), false))
+            }
+            // vfrec7
+            0b01_0011 if inst.rs1 == 0b0_0101 => {
+                self.varith(inst, vt, |a, _, _| (fpu::rec7(p, a, rm, fl), false))
+            }
+            // vfclass
+            0b01_0011 if inst.rs1 == 0b1_0000 => {
+                self.varith(inst, vt, |a, _, _| (fpu::classify(p, a), false))
+            }
+            // vfmerge
+            0b01_0111 if !unmasked(inst) => self.vmerge(inst, vt),
+            // vfmv.v.f
+            0b01_0111 => self.varith(inst, vt, |_, b, _| (b, false)),
+            // vmfeq
+            0b01_1000 => self.vcompare(inst, vt, |a, b| fpu::eq(p, a, b, fl)),
+            // vmfle
+            0b01_1001 => self.vcompare(inst, vt, |a, b| fpu::less(p, a, b, true, fl)),
+            // vmflt
+            0b01_1011 => self.vcompare(inst, vt, |a, b| fpu::less(p, a, b, false, fl)),
+            // vmfne
+            0b01_1100 => self.vcompare(inst, vt, |a, b| !fpu::eq(p, a, b, fl)),
+            // vmfgt
+            0b01_1101 => self.vcompare(inst, vt, |a, b| fpu::less(p, b, a, false, fl)),
+            // vmfge
+            0b01_1111 => self.vcompare(inst, vt, |a, b| fpu::less(p, b, a, true, fl)),
+            // vfdiv
+            0b10_0000 => self.varith(inst, vt, |a, b, _| (fpu::div(p, a, b, rm, fl), false)),
+            // vfrdiv
+            0b10_0001 => self.varith(inst, vt, |a, b, _| (fpu::div(p, b, a, rm, fl), false)),
+            // vfmul
+            0b10_0100 => self.varith(inst, vt, |a, b, _| (fpu::mul(p, a, b, rm, fl), false)),
+            // vfrsub
+            0b10_0111 => self.varith(inst, vt, |a, b, _| (fpu::add(p, b, a, true, rm, fl), false)),
+            // vfmadd: vd[i] = +(op * vd[i]) + vs2[i]
+            0b10_1000 => self.varith(inst, vt, |a, b, d| {
+                (fpu::fma(p, b, d, a, false, false, rm, fl), false)
+            }),
+            // vfnmadd: vd[i] = -(op * vd[i]) - vs2[i]
+            0b10_1001 => self.varith(inst, vt, |a, b, d| {
+                (fpu::fma(p, b, d, a, true, true, rm, fl), false)
+            }),
+            // vfmsub: vd[i] = +(op * vd[i]) - vs2[i]
+            0b10_1010 => self.varith(inst, vt, |a, b, d| {
+                (fpu::fma(p, b, d, a, false, true, rm, fl), false)
+            }),
+            // vfnmsub: vd[i] = -(op * vd[i]) + vs2[i]
+            0b10_1011 => self.varith(inst, vt, |a, b, d| {
+                (fpu::fma(p, b, d, a, true, false, rm, fl), false)
+            }),
+            // vfmacc: vd[i] = +(op * vs2[i]) + vd[i]
+            0b10_1100 => self.varith(inst, vt, |a, b, d| {
+                (fpu::fma(p, b, a, d, false, false, rm, fl), false)
+            }),
+            // vfnmacc: vd[i] = -(op * vs2[i]) - vd[i]
+            0b10_1101 => self.varith(inst, vt, |a, b, d| {
+                (fpu::fma(p, b, a, d, true, true, rm, fl), false)
+            }),
+            // vfmsac: vd[i] = +(op * vs2[i]) - vd[i]
+            0b10_1110 => self.varith(inst, vt, |a, b, d| {
+                (fpu::fma(p, b, a, d, false, true, rm, fl), false)
+            }),
+            // vfnmsac: vd[i] = -(op * vs2[i]) + vd[i]
+            0b10_1111 => self.varith(inst, vt, |a, b, d| {
+                (fpu::fma(p, b, a, d, true, false, rm, fl), false)
+            }),
+            // the widening operations below compute in double precision
+            _ if p != Precision::Single => Err(illegal(inst)),
+            // vfwadd
+            0b11_0000 => self.vwiden(inst, vt, false, |a, b, _| {
+                let (a, b) = (widen(a, fl), widen(b, fl));
+                fpu::add(d, a, b, false, rm, fl)
+            }),
+            // vfwredusum, vfwredosum
+            0b11_0001 | 0b11_0011 => self.vreduce(inst, vt, true, |acc, a| {
+                let a = widen(a, fl);
+                fpu::add(d, acc, a, false, rm, fl)
+            }),
+            // vfwsub
+            0b11_0010 => self.vwiden(inst, vt, false, |a, b, _| {
+                let (a, b) = (widen(a, fl), widen(b, fl));
+                fpu::add(d, a, b, true, rm, fl)
+            }),
+            // vfwadd.w
+            0b11_0100 => self.vwiden(inst, vt, true, |a, b, _| {
+                let b = widen(b, fl);
+                fpu::add(d, a, b, false, rm, fl)
+            }),
+            // vfwsub.w
+            0b11_0110 => self.vwiden(inst, vt, true, |a, b, _| {
+                let b = widen(b, fl);
+                fpu::add(d, a, b, true, rm, fl)
+            }),
+            // vfwmul
+            0b11_1000 => self.vwiden(inst, vt, false, |a, b, _| {
+                let (a, b) = (widen(a, fl), widen(b, fl));
+                fpu::mul(d, a, b, rm, fl)
+            }),
+            // vfwmacc, vfwnmacc, vfwmsac, vfwnmsac
+            0b11_1100..=0b11_1111 => {
+                let neg_prod = funct6 & 0b01 != 0;
+                let neg_c = (funct6 & 0b11) == 0b01 || (funct6 & 0b11) == 0b10;
+                self.vwiden(inst, vt, false, |a, b, acc| {
+                    let (a, b) = (widen(a, fl), widen(b, fl));
+                    fpu::fma(d, b, a, acc, neg_prod, neg_c, rm, fl)
+                })
+            }
+            _ => Err(illegal(inst)),
+        };
+        self.accrue_fflags(flags);
+        res
+    }
+
+    /// VFUNARY0: conversions between floating-point and integer values.
+    ///   0..7   -- single-width, vd and vs2 are SEW
+    ///   8..15  -- widening, vd is 2*SEW
+    ///   16..23 -- narrowing, vs2 is 2*SEW
+    /// The rtz variants round towards zero regardless of frm.
+    fn vfcvt(&mut self, inst: &Instruction, vt: VType, rm: u8) -> Result<(), Exception> {
+        let sew = vt.sew;
+        let kind = inst.rs1;
+        let rm = if matches!(kind & 0b111, 0b110 | 0b111) {
+            fpu::RTZ
+        } else {
+            rm
+        };
+        let mut flags = 0;
+        let fl = &mut flags;
+        let res = match kind {
+            // vfcvt.xu.f.v, vfcvt.x.f.v, vfcvt.rtz.xu.f.v, vfcvt.rtz.x.f.v
+            0b0_0000 | 0b0_0001 | 0b0_0110 | 0b0_0111 => {
+                let p = self.vprec(inst, sew)?;
+                let sign = kind & 1 == 1;
+                self.varith(inst, vt, |a, _, _| {
+                    (fpu::to_int(p, a, sign, sew as u32, rm, fl), false)
+                })
+            }
+            // vfcvt.f.xu.v, vfcvt.f.x.v
+            0b0_0010 | 0b0_0011 => {
+                let p = self.vprec(inst, sew)?;
+                let sign = kind & 1 == 1;
+                self.varith(inst, vt, |a, _, _| {
+                    (fpu::from_int(p, a, sign, sew as u32, rm, fl), false)
+                })
+            }
+            // vfwcvt.xu.f.v, vfwcvt.x.f.v, vfwcvt.rtz.xu.f.v, vfwcvt.rtz.x.f.v
+            0b0_1000 | 0b0_1001 | 0b0_1110 | 0b0_1111 => {
+                let p = self.vprec(inst, sew)?;
+                let sign = kind & 1 == 1;
+                self.vwiden(inst, vt, false, |a, _, _| {
+                    fpu::to_int(p, a, sign, sew as u32 * 2, rm, fl)
+                })
+            }
+            // vfwcvt.f.xu.v, vfwcvt.f.x.v
+            0b0_1010 | 0b0_1011 => {
+                let p = self.vprec(inst, sew * 2)?;
+                let sign = kind & 1 == 1;
+                self.vwiden(inst, vt, false, |a, _, _| {
+                    let a = if sign { signed(a, sew) as u64 } else { a };
+                    fpu::from_int(p, a, sign, 64, rm, fl)
+                })
+            }
+            // vfwcvt.f.f.v
+            0b0_1100 if sew == 32 => self.vwiden(inst, vt, false, |a, _, _| widen(a, fl)),
+            // vfncvt.xu.f.w, vfncvt.x.f.w, vfncvt.rtz.xu.f.w, vfncvt.rtz.x.f.w
+            0b1_0000 | 0b1_0001 | 0b1_0110 | 0b1_0111 => {
+                let p = self.vprec(inst, sew * 2)?;
+                let sign = kind & 1 == 1;
+                self.vnarrow(inst, vt, |a, _| {
+                    (
+                        trunc(fpu::to_int(p, a, sign, sew as u32, rm, fl), sew),
+                        false,
+                    )
+                })
+            }
+            // vfncvt.f.xu.w, vfncvt.f.x.w
+            0b1_0010 | 0b1_0011 => {
+                let p = self.vprec(inst, sew)?;
+                let sign = kind & 1 == 1;
+                self.vnarrow(inst, vt, |a, _| {
+                    (fpu::from_int(p, a, sign, 64, rm, fl), false)
+                })
+            }
+            // vfncvt.f.f.w
+            0b1_0100 if sew == 32 => self.vnarrow(inst, vt, |a, _| {
+                let v = fpu::convert(Precision::Double, Precision::Single, a, rm, fl);
+                (v, false)
+            }),
+            // vfncvt.rod.f.f.w
+            0b1_0101 if sew == 32 => self.vnarrow(inst, vt, |a, _| {
+                let v = fpu::convert(Precision::Double, Precision::Single, a, fpu::ROD, fl);
+                (v, false)
+            }),
+            _ => Err(illegal(inst)),
+        };
+        self.accrue_fflags(flags);
+        res
+    }
+}
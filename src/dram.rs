@@ -1,31 +1,128 @@
 use crate::conf::MEM_OFF;
+use std::sync::atomic::{AtomicU64, Ordering};
 
+/// The memory is shared by harts running on host threads.
+/// It is kept in 8-byte atomic words, so aligned accesses are single-copy atomic
+/// as RVWMO requires, and plain loads and stores can be relaxed.
 #[derive(Debug)]
 pub struct Dram {
-    memory: Vec<u8>,
+    memory: Vec<AtomicU64>,
+    size: usize,
 }
 
 impl Dram {
     pub fn new(mem_size: usize) -> Dram {
         Dram {
-            memory: vec![0; mem_size],
+            memory: (0..mem_size.div_ceil(8))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            size: mem_size,
         }
     }
 
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     #[inline(always)]
     fn set_mem(&mut self, idx: usize, data: u8) {
-        if self.memory.len() < idx {
-            panic!("access to invalid address");
-        }
-        self.memory[idx] = data;
+        self.write(idx as u64, 1, data as u64);
     }
 
     #[inline(always)]
     fn get_mem(&self, idx: usize) -> u8 {
-        match self.memory.get(idx) {
-            Some(data) => *data,
-            None => panic!("access to invalid address: 0x{:016X}", idx),
+        self.read(idx as u64, 1) as u8
+    }
+
+    #[inline(always)]
+    fn check_range(&self, addr: u64, size: u64) {
+        if self.size as u64 <= addr || (self.size as u64) < addr + size {
+            panic!("access to invalid address: 0x{:016X}", addr);
+        }
+    }
+
+    /// Read `size` bytes as little endian.
+    /// An access that straddles two words is read byte by byte and is not atomic.
+    fn read(&self, addr: u64, size: u64) -> u64 {
+        self.check_range(addr, size);
+        let off = addr % 8;
+        if off + size > 8 {
+            return (0..size).fold(0, |v, i| v | self.read(addr + i, 1) << (i * 8));
+        }
+        let word = self.memory[(addr / 8) as usize].load(Ordering::Relaxed);
+        (word >> (off * 8)) & mask(size)
+    }
+
+    /// Write the lower `size` bytes as little endian.
+    /// An access that straddles two words is written byte by byte and is not atomic.
+    fn write(&self, addr: u64, size: u64, data: u64) {
+        self.check_range(addr, size);
+        let off = addr % 8;
+        if off + size > 8 {
+            for i in 0..size {
+                self.write(addr + i, 1, data >> (i * 8));
+            }
+            return;
+        }
+        let word = &self.memory[(addr / 8) as usize];
+        if size == 8 {
+            word.store(data, Ordering::Relaxed);
+            return;
         }
+        let mask = mask(size) << (off * 8);
+        let data = (data << (off * 8)) & mask;
+        let _ = word.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |w| {
+            Some(w & !mask | data)
+        });
+    }
+
+    /// Read the naturally aligned `size` bytes with the memory ordering.
+    pub fn load_ordered(&self, addr: u64, size: u64, order: Ordering) -> u64 {
+        self.check_range(addr, size);
+        let word = self.memory[(addr / 8) as usize].load(load_order(order));
+        (word >> ((addr % 8) * 8)) & mask(size)
+    }
+
+    /// Atomically replace the naturally aligned `size` bytes with `f(old)`. Returns the old value.
+    pub fn fetch_update<F>(&self, addr: u64, size: u64, order: Ordering, f: F) -> u64
+    where
+        F: Fn(u64) -> u64,
+    {
+        self.check_range(addr, size);
+        let off = (addr % 8) * 8;
+        let mask = mask(size) << off;
+        let word = self.memory[(addr / 8) as usize]
+            .fetch_update(order, load_order(order), |w| {
+                let new = f((w & mask) >> off) << off & mask;
+                Some(w & !mask | new)
+            })
+            .unwrap();
+        (word & mask) >> off
+    }
+
+    /// Atomically write `new` to the naturally aligned `size` bytes when they still hold `current`.
+    pub fn compare_exchange(
+        &self,
+        addr: u64,
+        size: u64,
+        current: u64,
+        new: u64,
+        order: Ordering,
+    ) -> bool {
+        self.check_range(addr, size);
+        let off = (addr % 8) * 8;
+        let mask = mask(size) << off;
+        let current = current << off & mask;
+        let new = new << off & mask;
+        self.memory[(addr / 8) as usize]
+            .fetch_update(order, load_order(order), |w| {
+                if w & mask == current {
+                    Some(w & !mask | new)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
     }
 
     #[allow(dead_code)]
@@ -61,7 +158,7 @@ impl Dram {
 
     pub fn load(&mut self, data: String) {
         let mut data = to_byte_array(data);
-        if self.memory.len() < data.len() {
+        if self.size < data.len() {
             panic!("data is big");
         }
 
@@ -87,7 +184,7 @@ impl Dram {
         seg_phys_addr: usize,
         seg_size: usize,
     ) {
-        if self.memory.len() < seg_size {
+        if seg_phys_addr < MEM_OFF || self.size < seg_phys_addr - MEM_OFF + seg_size {
             panic!("segment is big");
         }
 
@@ -97,65 +194,51 @@ impl Dram {
     }
 
     pub fn load_byte(&self, addr: u64) -> u8 {
-        self.get_mem(addr as usize)
+        self.read(addr, 1) as u8
     }
 
-    pub fn load_hword(&self, begin: u64) -> u16 {
-        let mut res: u16 = 0;
-        for (i, addr) in (begin..begin + 2).enumerate() {
-            let mut data = self.get_mem(addr as usize) as u16;
-            data <<= (i * 8) as u16;
-            res |= data;
-        }
-        res
+    pub fn load_hword(&self, addr: u64) -> u16 {
+        self.read(addr, 2) as u16
     }
 
     pub fn load_word(&self, addr: u64) -> u32 {
-        let mut res: u32 = 0;
-        for (i, addr) in (addr..addr + 4).enumerate() {
-            let mut data = self.get_mem(addr as usize) as u32;
-            data <<= (i * 8) as u32;
-            res |= data;
-        }
-        res
+        self.read(addr, 4) as u32
     }
 
     pub fn load_dword(&self, addr: u64) -> u64 {
-        let mut res: u64 = 0;
-        for (i, addr) in (addr..addr + 8).enumerate() {
-            let mut data = self.get_mem(addr as usize) as u64;
-            data <<= (i * 8) as u64;
-            res |= data;
-        }
-        res
+        self.read(addr, 8)
     }
 
-    pub fn store_byte(&mut self, addr: u64, data: u8) {
-        self.set_mem(addr as usize, data);
+    pub fn store_byte(&self, addr: u64, data: u8) {
+        self.write(addr, 1, data as u64);
     }
 
-    pub fn store_hword(&mut self, addr: u64, data: u16) {
-        self.set_mem(addr as usize, (data & 0xF) as u8);
-        let addr = addr + 1;
-        self.set_mem(addr as usize, (data >> 8) as u8);
+    pub fn store_hword(&self, addr: u64, data: u16) {
+        self.write(addr, 2, data as u64);
     }
 
-    pub fn store_word(&mut self, mut addr: u64, mut data: u32) {
-        self.set_mem(addr as usize, (data & 0xFF) as u8);
-        for _ in 0..3 {
-            addr = addr + 1;
-            data >>= 8;
-            self.set_mem(addr as usize, (data & 0xFF) as u8);
-        }
+    pub fn store_word(&self, addr: u64, data: u32) {
+        self.write(addr, 4, data as u64);
     }
 
-    pub fn store_dword(&mut self, mut addr: u64, mut data: u64) {
-        self.set_mem(addr as usize, (data & 0xFF) as u8);
-        for _ in 0..7 {
-            addr = addr + 1;
-            data >>= 8;
-            self.set_mem(addr as usize, (data & 0xFF) as u8);
-        }
+    pub fn store_dword(&self, addr: u64, data: u64) {
+        self.write(addr, 8, data);
+    }
+}
+
+fn mask(size: u64) -> u64 {
+    match size {
+        8 => u64::MAX,
+        _ => (1 << (size * 8)) - 1,
+    }
+}
+
+/// The ordering of the load half of a read-modify-write. A load cannot release.
+fn load_order(order: Ordering) -> Ordering {
+    match order {
+        Ordering::Release => Ordering::Relaxed,
+        Ordering::AcqRel => Ordering::Acquire,
+        _ => order,
     }
 }
 
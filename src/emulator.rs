@@ -1,21 +1,36 @@
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
 
-use crate::bus::Bus;
+use crate::bus::{Bus, Irqchip};
+use crate::clint::Clint;
 use crate::cmd::Command;
 use crate::conf::MEM_OFF;
 use crate::cpu::Cpu;
+use crate::dbg::Debug;
 use crate::dram::Dram;
-use crate::plic::Plic;
 use crate::uart::Uart;
 use crate::virtio::Virtio;
 
+const NO_WAKE: u64 = u64::MAX;
+
 pub struct Emulator {
-    cpu: Cpu,
+    cpus: Vec<Cpu>,
+    bus: Arc<Bus>,
+    quantum: u64,
+    threads: bool, // run every hart on its own host thread
     entry_point: usize,
 }
 
+/// Harts halted by WFI when they run on host threads.
+struct Idle {
+    halted: AtomicUsize,
+    wake_time: Vec<AtomicU64>, // per hart. NO_WAKE when the timer cannot wake the hart.
+}
+
 impl Emulator {
     pub fn new(cmd: Command) -> Emulator {
         let mut dram = Dram::new(cmd.mem_size.unwrap());
@@ -27,25 +42,139 @@ impl Emulator {
             entry_point = Emulator::load_elf_to_dram(&mut dram, elf);
         }
 
-        let bus = Bus::new(dram, Uart::new(), Plic::new(), Virtio::new());
+        let irqchip = if cmd.aia {
+            Irqchip::aia(cmd.harts)
+        } else {
+            Irqchip::plic(cmd.harts)
+        };
+        let bus = Arc::new(Bus::new(
+            dram,
+            Clint::new(cmd.harts, cmd.timebase, cmd.wall_clock),
+            Uart::new(),
+            irqchip,
+            Virtio::new(),
+            cmd.harts,
+        ));
+
+        // the debugger follows hart 0
+        let cpus = (0..cmd.harts)
+            .map(|hart| {
+                let dbg = if hart == 0 {
+                    cmd.dbg.clone()
+                } else {
+                    Debug::new(false, 0)
+                };
+                Cpu::new(
+                    bus.clone(),
+                    hart as u64,
+                    dbg,
+                    cmd.busy_wfi,
+                    cmd.trap_misaligned,
+                    cmd.cache_block,
+                    cmd.vlen,
+                )
+            })
+            .collect();
 
         Emulator {
-            cpu: Cpu::new(bus, cmd.mem_size.unwrap(), cmd.dbg.clone()),
+            cpus,
+            bus,
+            quantum: cmd.quantum,
+            threads: cmd.threads,
             entry_point,
         }
     }
 
     pub fn print_cpu(&self) {
-        self.cpu.print();
+        for cpu in self.cpus.iter() {
+            cpu.print();
+        }
     }
 
     pub fn print_dram(&self, begin: usize, end: usize) {
-        self.cpu.pdram_range(begin, end);
+        self.bus.pdram_range(begin, end);
     }
 
     pub fn exec(&mut self) {
-        self.cpu.init(self.entry_point);
-        self.cpu.run();
+        self.init();
+        if self.threads {
+            self.exec_threads();
+        }
+        loop {
+            self.round();
+        }
+    }
+
+    /// Reset every hart to the entry point.
+    pub fn init(&mut self) {
+        for cpu in self.cpus.iter_mut() {
+            cpu.init(self.entry_point);
+        }
+    }
+
+    /// Run `n` deterministic rounds after `init`, ignoring `--threads`. Used by the benchmark.
+    pub fn run_rounds(&mut self, n: u64) {
+        for _ in 0..n {
+            self.round();
+        }
+    }
+
+    /// Instructions retired by all harts.
+    pub fn instret(&self) -> u64 {
+        self.cpus.iter().map(|cpu| cpu.instret()).sum()
+    }
+
+    /// Every hart runs on its own host thread, so runs are not deterministic.
+    fn exec_threads(&mut self) {
+        let idle = Arc::new(Idle {
+            halted: AtomicUsize::new(0),
+            wake_time: self.cpus.iter().map(|_| AtomicU64::new(NO_WAKE)).collect(),
+        });
+        let quantum = self.quantum;
+        let bus = &self.bus;
+        let threads: Vec<_> = self
+            .cpus
+            .drain(..)
+            .enumerate()
+            .map(|(hart, cpu)| {
+                let bus = bus.clone();
+                let idle = idle.clone();
+                thread::spawn(move || run_hart(hart, cpu, bus, idle, quantum))
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
+
+    /// Every hart runs for a quantum in the order of the hart ID, so runs are deterministic
+    /// unless mtime is on the wall clock. mtime advances once per round as if the harts ran in parallel,
+    /// counting the instructions of hart 0.
+    fn round(&mut self) {
+        let events = self.bus.events();
+        for cpu in self.cpus.iter_mut() {
+            cpu.run(self.quantum);
+        }
+        if self.cpus.iter().all(|cpu| cpu.halted()) {
+            self.idle(events);
+        } else {
+            let clint = self.bus.clint();
+            clint.advance_mtime(clint.mtime() + clint.ticks(0, self.quantum));
+        }
+    }
+
+    /// Every hart is halted by WFI.
+    /// Fast-forward mtime to the earliest timer deadline that can wake a hart,
+    /// otherwise block until the deadline on the host clock or an event since `events`.
+    fn idle(&mut self, events: u64) {
+        let clint = self.bus.clint();
+        let wake = self.cpus.iter().filter_map(|cpu| cpu.wake_time()).min();
+        match wake {
+            Some(t) if clint.advance_mtime(t) => (),
+            _ => self
+                .bus
+                .wait_event(events, wake.and_then(|t| clint.wall_time_until(t))),
+        }
     }
 
     fn load_file_to_dram(dram: &mut Dram, in_f: String) {
@@ -83,6 +212,71 @@ impl Emulator {
     }
 }
 
+/// Run the hart on the current host thread forever.
+/// Unless mtime is on the wall clock, it follows the hart that has run the most instructions,
+/// as if the harts ran in lockstep, and skips to the earliest timer deadline while every hart
+/// is halted by WFI. A halted hart blocks until its timer deadline or another event on the bus.
+fn run_hart(hart: usize, mut cpu: Cpu, bus: Arc<Bus>, idle: Arc<Idle>, quantum: u64) {
+    let mut time = 0;
+    let mut halted = false;
+    loop {
+        let events = bus.events();
+        cpu.run(quantum);
+        if cpu.halted() {
+            let wake = cpu.wake_time();
+            idle.wake_time[hart].store(wake.unwrap_or(NO_WAKE), Ordering::Relaxed);
+            if !halted {
+                halted = true;
+                idle.halted.fetch_add(1, Ordering::SeqCst);
+            }
+            if !idle.fast_forward(&bus) {
+                let timeout = wake.and_then(|t| bus.clint().wall_time_until(t));
+                bus.wait_event(events, timeout);
+            }
+            continue;
+        }
+        if halted {
+            halted = false;
+            idle.wake_time[hart].store(NO_WAKE, Ordering::Relaxed);
+            idle.halted.fetch_sub(1, Ordering::SeqCst);
+        }
+        let clint = bus.clint();
+        time = time.max(clint.mtime()) + clint.ticks(hart, quantum);
+        // wake the halted harts when mtime reaches one of their timer deadlines
+        if clint.advance_mtime(time) && time >= idle.earliest_wake() {
+            bus.notify();
+        }
+    }
+}
+
+impl Idle {
+    /// Skip mtime to the earliest timer deadline when every hart is halted, and wake the harts.
+    /// Returns false when no timer can wake a hart or mtime is on the wall clock.
+    fn fast_forward(&self, bus: &Bus) -> bool {
+        if self.halted.load(Ordering::SeqCst) < self.wake_time.len() {
+            return false;
+        }
+        let wake = self.earliest_wake();
+        if wake == NO_WAKE {
+            return false;
+        }
+        if !bus.clint().advance_mtime(wake) {
+            return false;
+        }
+        bus.notify();
+        true
+    }
+
+    /// The earliest timer deadline of the halted harts. NO_WAKE when there is none.
+    fn earliest_wake(&self) -> u64 {
+        self.wake_time
+            .iter()
+            .map(|t| t.load(Ordering::Relaxed))
+            .min()
+            .unwrap()
+    }
+}
+
 /// Returns the `size` byte from the` idx` byte of the `data`
 /// as little endian.
 /// If the `size` is 0, 0 is returned.
@@ -0,0 +1,225 @@
+// https://github.com/riscv/riscv-aia/blob/main/src/IMSIC.adoc
+// An M-level and an S-level interrupt file for each hart, at the addresses of the QEMU virt machine.
+// There are no guest interrupt files.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+pub const IMSIC_M: u64 = 0x2400_0000; // M-level interrupt files. 4 KiB per hart.
+pub const IMSIC_M_END: u64 = IMSIC_M + 0xFF_FFFF;
+pub const IMSIC_S: u64 = 0x2800_0000; // S-level interrupt files. 4 KiB per hart.
+pub const IMSIC_S_END: u64 = IMSIC_S + 0xFF_FFFF;
+
+const FILE_SIZE: u64 = 0x1000;
+const SETEIPNUM_LE: u64 = 0x0;
+const SETEIPNUM_BE: u64 = 0x4;
+
+const IDS: usize = 256; // interrupt identities 1 - 255. Identity 0 does not exist.
+
+// registers accessed through miselect and mireg, or siselect and sireg
+const IPRIO: u64 = 0x30; // iprio0 - iprio15
+const IPRIO_END: u64 = 0x3F;
+const EIDELIVERY: u64 = 0x70;
+const EITHRESHOLD: u64 = 0x72;
+const EIP: u64 = 0x80; // eip0 - eip63
+const EIP_END: u64 = 0xBF;
+const EIE: u64 = 0xC0; // eie0 - eie63
+const EIE_END: u64 = 0xFF;
+
+/// M-level interrupt file of the hart.
+pub fn m_file(hart: usize) -> usize {
+    2 * hart
+}
+
+/// S-level interrupt file of the hart.
+pub fn s_file(hart: usize) -> usize {
+    2 * hart + 1
+}
+
+/// Whether miselect or siselect selects a register.
+/// RV64 has only the even numbered iprio, eip and eie registers.
+pub fn valid_iselect(iselect: u64) -> bool {
+    match iselect {
+        EIDELIVERY | EITHRESHOLD => true,
+        IPRIO..=IPRIO_END | EIP..=EIE_END => iselect & 1 == 0,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Default)]
+struct InterruptFile {
+    eidelivery: u64,
+    eithreshold: u64,
+    eip: [u64; IDS / 64], // 1 bit per identity
+    eie: [u64; IDS / 64],
+}
+
+impl InterruptFile {
+    fn read(&self, iselect: u64) -> u64 {
+        match iselect {
+            EIDELIVERY => self.eidelivery,
+            EITHRESHOLD => self.eithreshold,
+            EIP..=EIP_END => bits(&self.eip, iselect - EIP),
+            EIE..=EIE_END => bits(&self.eie, iselect - EIE),
+            // iprio. The priorities of the major interrupts are not configurable.
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, iselect: u64, value: u64) {
+        match iselect {
+            EIDELIVERY => self.eidelivery = value & 1,
+            EITHRESHOLD => self.eithreshold = value & (IDS as u64 - 1),
+            EIP..=EIP_END => set_bits(&mut self.eip, iselect - EIP, value),
+            EIE..=EIE_END => set_bits(&mut self.eie, iselect - EIE, value),
+            _ => (),
+        }
+    }
+
+    /// The pending and enabled identity below the threshold, as read from mtopei or stopei.
+    /// Lower identities have higher priority. Returns 0 when there is none.
+    ///   26..16 -- interrupt identity
+    ///   10..0  -- interrupt priority, the same as the identity
+    fn topei(&self) -> u64 {
+        for (i, (eip, eie)) in self.eip.iter().zip(self.eie.iter()).enumerate() {
+            let bits = eip & eie;
+            if bits == 0 {
+                continue;
+            }
+            let id = (i * 64) as u64 + bits.trailing_zeros() as u64;
+            if self.eithreshold != 0 && id >= self.eithreshold {
+                return 0;
+            }
+            return (id << 16) | id;
+        }
+        0
+    }
+
+    fn set_pending(&mut self, id: u64, pending: bool) {
+        if id == 0 || id >= IDS as u64 {
+            return;
+        }
+        let bit = 1 << (id % 64);
+        if pending {
+            self.eip[id as usize / 64] |= bit;
+        } else {
+            self.eip[id as usize / 64] &= !bit;
+        }
+    }
+
+    /// The file raises the external interrupt of its level.
+    fn pending_int(&self) -> bool {
+        self.eidelivery == 1 && self.topei() != 0
+    }
+}
+
+/// eip or eie register `k` (even) holds identities 32k to 32k+63.
+fn bits(words: &[u64], k: u64) -> u64 {
+    words.get(k as usize / 2).copied().unwrap_or(0)
+}
+
+/// Identity 0 is read-only zero.
+fn set_bits(words: &mut [u64], k: u64, value: u64) {
+    if let Some(w) = words.get_mut(k as usize / 2) {
+        *w = value;
+    }
+    words[0] &= !1;
+}
+
+/// Interrupt files are written by the APLIC and the harts on any host thread, so each has its own lock.
+/// The external interrupt line of each file is an atomic, so harts poll it without taking the lock.
+#[derive(Debug)]
+pub struct Imsic {
+    files: Vec<Mutex<InterruptFile>>,
+    lines: Vec<AtomicBool>, // per interrupt file
+}
+
+impl Imsic {
+    pub fn new(harts: usize) -> Imsic {
+        Imsic {
+            files: (0..2 * harts)
+                .map(|_| Mutex::new(InterruptFile::default()))
+                .collect(),
+            lines: (0..2 * harts).map(|_| AtomicBool::new(false)).collect(),
+        }
+    }
+
+    /// The interrupt file at the address. None for harts that do not exist.
+    fn file_at(&self, addr: u64) -> Option<usize> {
+        let file = match addr {
+            IMSIC_M..=IMSIC_M_END => m_file(((addr - IMSIC_M) / FILE_SIZE) as usize),
+            _ => s_file(((addr - IMSIC_S) / FILE_SIZE) as usize),
+        };
+        if file < self.files.len() {
+            Some(file)
+        } else {
+            None
+        }
+    }
+
+    /// seteipnum is write-only and reads as 0.
+    pub fn read(&self, addr: u64) -> u64 {
+        if addr & 0b11 != 0 {
+            panic!("invalid reading IMSIC address: 0x{:016X}", addr);
+        }
+        0
+    }
+
+    /// A message signaled interrupt. Writing an identity to seteipnum sets its pending bit.
+    /// Writes to the other registers and to harts that do not exist are ignored.
+    pub fn write(&self, addr: u64, data: u64) {
+        if addr & 0b11 != 0 {
+            panic!("invalid writing IMSIC address: 0x{:016X}", addr);
+        }
+        let file = match self.file_at(addr) {
+            Some(file) => file,
+            None => return,
+        };
+        match addr % FILE_SIZE {
+            SETEIPNUM_LE => self.send(file, data as u32 as u64),
+            SETEIPNUM_BE => self.send(file, (data as u32).swap_bytes() as u64),
+            _ => (),
+        }
+    }
+
+    /// Set the pending bit of the identity in the interrupt file.
+    pub fn send(&self, file: usize, id: u64) {
+        if let Some(f) = self.files.get(file) {
+            let mut f = f.lock().unwrap();
+            f.set_pending(id, true);
+            self.update_line(file, &f);
+        }
+    }
+
+    /// The register of the interrupt file selected by miselect or siselect.
+    pub fn read_ireg(&self, file: usize, iselect: u64) -> u64 {
+        self.files[file].lock().unwrap().read(iselect)
+    }
+
+    pub fn write_ireg(&self, file: usize, iselect: u64, value: u64) {
+        let mut f = self.files[file].lock().unwrap();
+        f.write(iselect, value);
+        self.update_line(file, &f);
+    }
+
+    pub fn topei(&self, file: usize) -> u64 {
+        self.files[file].lock().unwrap().topei()
+    }
+
+    /// A write to mtopei or stopei claims the identity it reports, clearing its pending bit.
+    pub fn claim(&self, file: usize) {
+        let mut f = self.files[file].lock().unwrap();
+        let id = f.topei() >> 16;
+        f.set_pending(id, false);
+        self.update_line(file, &f);
+    }
+
+    fn update_line(&self, file: usize, f: &InterruptFile) {
+        self.lines[file].store(f.pending_int(), Ordering::Relaxed);
+    }
+
+    /// Whether the interrupt file raises the external interrupt of its level.
+    pub fn line(&self, file: usize) -> bool {
+        self.lines[file].load(Ordering::Relaxed)
+    }
+}
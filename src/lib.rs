@@ -1,10 +1,13 @@
+mod aplic;
 mod bus;
+mod clint;
 pub mod cmd;
 mod conf;
 mod cpu;
 mod dbg;
 mod dram;
 pub mod emulator;
+mod imsic;
 mod plic;
 mod uart;
 mod util;
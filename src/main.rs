@@ -1,5 +1,6 @@
 // $ cargo run --release -- --elf kernel/kernel --drive kernel/fs.img
 // $ cargo run --release -- --elf kernel/kernel --debug
+// $ cargo run --release -- --elf kernel/kernel --drive kernel/fs.img --busy-wfi
 
 use kotodori::cmd::Command;
 use kotodori::emulator::Emulator;
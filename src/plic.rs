@@ -7,146 +7,132 @@ pub const PENDING: u64 = PLIC + 0x1000; // 0x1000 - 0x107C
 pub const PENDING_END: u64 = PLIC + 0x107C;
 pub const ENABLE: u64 = PLIC + 0x2000; // 0x2000 - 0x1F_1FFC
 pub const ENABLE_END: u64 = PLIC + 0x1F_1FFC;
-pub const PRIORITY_THR0: u64 = PLIC + 0x20_0000;
-pub const CLAIM0: u64 = PLIC + 0x20_0004;
-pub const PRIORITY_THR: u64 = PLIC + 0x20_1000; // 0x20_1000 - 0x3FF_F000
-pub const CLAIM: u64 = PLIC + 0x20_1004; // 0x20_1004 - 0x3FF_F004
-pub const CLAIM_END: u64 = PLIC + 0x3FF_F004;
+pub const CONTEXT: u64 = PLIC + 0x20_0000; // 0x20_0000 - 0x3FF_FFFC
+pub const CONTEXT_END: u64 = PLIC + 0x3FF_FFFC;
 pub const PLIC_END: u64 = PLIC + 0x3FF_FFFC;
 
+const SOURCES: usize = 1024;
+const ENABLE_STRIDE: u64 = 0x80; // enable bits of a context
+const CONTEXT_STRIDE: u64 = 0x1000; // priority threshold and claim/complete of a context
+const CLAIM_OFF: u64 = 0x4;
+
+/// Context of the M-mode external interrupt of the hart.
+pub fn m_context(hart: usize) -> usize {
+    2 * hart
+}
+
+/// Context of the S-mode external interrupt of the hart.
+pub fn s_context(hart: usize) -> usize {
+    2 * hart + 1
+}
+
 #[derive(Debug)]
 pub struct Plic {
     pub priority: Vec<u32>,
     pub pending: Vec<u32>,
     pub enable: Vec<u32>,
     pub priority_thr: Vec<u32>,
-    pub claim: Vec<u32>,
 }
 
 impl Plic {
-    pub fn new() -> Plic {
+    /// Each hart has an M-mode and an S-mode context.
+    pub fn new(harts: usize) -> Plic {
+        let contexts = 2 * harts;
         Plic {
-            priority: vec![0; 1023],      // Interrupt source 1 - 1023 priority
-            pending: vec![0; 32],         // Interrupt Pending bit 0 - 1023
-            enable: vec![0; 32 * 15872],  // Enable bits for sources. 1024bit. context 0 - 15871
-            priority_thr: vec![0; 15872], // Priority threshold for context 0 - 15871
-            claim: vec![0; 15872],        // Claim/complete for context 0 - 15871
+            priority: vec![0; SOURCES], // Interrupt source 1 - 1023 priority. Source 0 does not exist.
+            pending: vec![0; SOURCES / 32], // Interrupt Pending bit 0 - 1023
+            enable: vec![0; SOURCES / 32 * contexts], // Enable bits for sources. 1024bit per context
+            priority_thr: vec![0; contexts],          // Priority threshold per context
         }
     }
 
-    pub fn read(&self, addr: u64) -> u64 {
+    pub fn read(&mut self, addr: u64) -> u64 {
         if addr % 4 != 0 {
             panic!("invalid reading PLIC address: 0x{:016X}", addr);
         }
 
         match addr {
-            PRIORITY..=PRIORITY_END => self.read_priority(addr),
-            PENDING..=PENDING_END => self.read_pending(addr),
-            ENABLE..=ENABLE_END => self.read_enable(addr),
-            PRIORITY_THR0 => self.read_priority_thr(addr),
-            CLAIM0 => self.read_claim(addr),
-            PRIORITY_THR..=CLAIM_END => {
-                if addr & 0xF == 0x0 || addr & 0xF == 0x8 {
-                    return self.read_priority_thr(addr);
+            PRIORITY..=PRIORITY_END => self.priority[(addr - PLIC) as usize / 4] as u64,
+            PENDING..=PENDING_END => self.pending[(addr - PENDING) as usize / 4] as u64,
+            ENABLE..=ENABLE_END => self.enable[self.enable_idx(addr)] as u64,
+            CONTEXT..=CONTEXT_END => {
+                let ctx = ((addr - CONTEXT) / CONTEXT_STRIDE) as usize;
+                match (addr - CONTEXT) % CONTEXT_STRIDE {
+                    0 => self.priority_thr[ctx] as u64,
+                    CLAIM_OFF => self.claim(ctx) as u64,
+                    _ => panic!("invalid reading PLIC address: 0x{:016X}", addr),
                 }
-                return self.read_claim(addr);
             }
             _ => panic!("invalid reading PLIC address: 0x{:016X}", addr),
         }
     }
 
-    fn read_priority(&self, addr: u64) -> u64 {
-        let addr = addr - PRIORITY;
-        *self.priority.get(addr as usize).unwrap() as u64
-    }
-
-    fn read_pending(&self, addr: u64) -> u64 {
-        let addr = addr - PENDING;
-        *self.pending.get(addr as usize).unwrap() as u64
-    }
-
-    fn read_enable(&self, addr: u64) -> u64 {
-        let addr = addr - ENABLE;
-        *self.enable.get(addr as usize).unwrap() as u64
-    }
-
-    fn read_priority_thr(&self, addr: u64) -> u64 {
-        if CLAIM0 < addr && addr < PRIORITY_THR {
-            panic!("invalid reading PLIC priority thr address: 0x{:016X}", addr);
-        }
-        if addr == PRIORITY_THR0 {
-            return *self.priority_thr.get(0).unwrap() as u64;
-        }
-        let addr = addr - PRIORITY_THR;
-        *self.priority_thr.get(addr as usize).unwrap() as u64
-    }
-
-    fn read_claim(&self, addr: u64) -> u64 {
-        if CLAIM0 < addr && addr < PRIORITY_THR {
-            panic!("invalid reading PLIC claim address: 0x{:016X}", addr);
-        }
-        if addr == CLAIM0 {
-            return *self.priority_thr.get(0).unwrap() as u64;
-        }
-        let addr = addr - CLAIM;
-        *self.priority_thr.get(addr as usize).unwrap() as u64
-    }
-
     pub fn write(&mut self, addr: u64, data: u64) {
         if addr % 4 != 0 {
             panic!("invalid writing PLIC address: 0x{:016X}", addr);
         }
 
         match addr {
-            PRIORITY..=PRIORITY_END => self.write_priority(addr, data as u32),
-            PENDING..=PENDING_END => self.write_pending(addr, data as u32),
-            ENABLE..=ENABLE_END => self.write_enable(addr, data as u32),
-            PRIORITY_THR0 => self.write_priority_thr(addr, data as u32),
-            CLAIM0 => self.write_claim(addr, data as u32),
-            PRIORITY_THR..=CLAIM_END => {
-                if addr & 0xF == 0x0 || addr & 0xF == 0x8 {
-                    return self.write_priority_thr(addr, data as u32);
+            PRIORITY..=PRIORITY_END => self.priority[(addr - PLIC) as usize / 4] = data as u32,
+            PENDING..=PENDING_END => self.pending[(addr - PENDING) as usize / 4] = data as u32,
+            ENABLE..=ENABLE_END => {
+                let idx = self.enable_idx(addr);
+                self.enable[idx] = data as u32;
+            }
+            CONTEXT..=CONTEXT_END => {
+                let ctx = ((addr - CONTEXT) / CONTEXT_STRIDE) as usize;
+                match (addr - CONTEXT) % CONTEXT_STRIDE {
+                    0 => self.priority_thr[ctx] = data as u32,
+                    // complete. Sources have no gateway yet, so there is nothing to re-enable.
+                    CLAIM_OFF => (),
+                    _ => panic!("invalid writing PLIC address: 0x{:016X}", addr),
                 }
-                return self.write_claim(addr, data as u32);
             }
             _ => panic!("invalid writing PLIC address: 0x{:016X}", addr),
         }
     }
 
-    fn write_priority(&mut self, addr: u64, data: u32) {
-        let addr = addr - PRIORITY;
-        self.priority[addr as usize] = data;
-    }
-
-    fn write_pending(&mut self, addr: u64, data: u32) {
-        let addr = addr - PENDING;
-        self.pending[addr as usize] = data;
+    fn enable_idx(&self, addr: u64) -> usize {
+        let ctx = (addr - ENABLE) / ENABLE_STRIDE;
+        let word = (addr - ENABLE) % ENABLE_STRIDE / 4;
+        (ctx * ENABLE_STRIDE / 4 + word) as usize
     }
 
-    fn write_enable(&mut self, addr: u64, data: u32) {
-        let addr = addr - ENABLE;
-        self.enable[addr as usize] = data;
+    /// The pending and enabled source of the context with the highest priority above the threshold.
+    /// Ties go to the lowest source ID.
+    fn max_source(&self, ctx: usize) -> Option<usize> {
+        let enable = &self.enable[ctx * SOURCES / 32..(ctx + 1) * SOURCES / 32];
+        let mut max: Option<usize> = None;
+        for (i, (pending, enable)) in self.pending.iter().zip(enable).enumerate() {
+            let mut bits = pending & enable;
+            while bits != 0 {
+                let src = i * 32 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                if self.priority[src] <= self.priority_thr[ctx] {
+                    continue;
+                }
+                match max {
+                    Some(m) if self.priority[src] <= self.priority[m] => (),
+                    _ => max = Some(src),
+                }
+            }
+        }
+        max
     }
 
-    fn write_priority_thr(&mut self, addr: u64, data: u32) {
-        if CLAIM0 < addr && addr < PRIORITY_THR {
-            panic!("invalid writing PLIC priority thr address: 0x{:016X}", addr);
-        }
-        if addr == PRIORITY_THR0 {
-            self.priority_thr[0] = data;
-        }
-        let addr = addr - PRIORITY_THR;
-        self.priority_thr[addr as usize] = data;
+    /// Whether the context has an interrupt to claim.
+    pub fn pending_int(&self, ctx: usize) -> bool {
+        self.pending.iter().any(|p| *p != 0) && self.max_source(ctx).is_some()
     }
 
-    fn write_claim(&mut self, addr: u64, data: u32) {
-        if CLAIM0 < addr && addr < PRIORITY_THR {
-            panic!("invalid writing PLIC claim address: 0x{:016X}", addr);
-        }
-        if addr == CLAIM0 {
-            self.priority_thr[0] = data;
+    /// Claim the interrupt of the context and clear its pending bit. Returns 0 when there is none.
+    fn claim(&mut self, ctx: usize) -> u32 {
+        match self.max_source(ctx) {
+            Some(src) => {
+                self.pending[src / 32] &= !(1 << (src % 32));
+                src as u32
+            }
+            None => 0,
         }
-        let addr = addr - CLAIM;
-        self.priority_thr[addr as usize] = data;
     }
 }